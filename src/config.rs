@@ -43,6 +43,7 @@ use toml::Value;
 use crate::concrete::BV;
 use crate::ir::{Loc, Name, Reset, Symtab, Val};
 use crate::lexer::Lexer;
+use crate::memory::Endianness;
 use crate::value_parser::{LocParser, ValParser};
 use crate::zencode;
 
@@ -117,6 +118,17 @@ fn get_ifetch_read_kind(config: &Value, symtab: &Symtab) -> Result<Name, String>
     }
 }
 
+/// Get the byte order of memory, which is little-endian unless
+/// specified otherwise.
+fn get_endianness(config: &Value) -> Result<Endianness, String> {
+    match config.get("endianness") {
+        None => Ok(Endianness::Little),
+        Some(Value::String(endianness)) if endianness == "little" => Ok(Endianness::Little),
+        Some(Value::String(endianness)) if endianness == "big" => Ok(Endianness::Big),
+        Some(endianness) => Err(format!("Endianness must be either \"little\" or \"big\", not {}", endianness)),
+    }
+}
+
 fn get_exclusives(config: &Value, exclusives_type: &str, symtab: &Symtab) -> Result<Vec<Name>, String> {
     match config.get(exclusives_type) {
         Some(Value::Array(exclusives)) => exclusives
@@ -382,6 +394,8 @@ pub struct ISAConfig<B> {
     pub pc: Name,
     /// The read_kind for instruction fetch events
     pub ifetch_read_kind: Name,
+    /// The byte order of memory
+    pub endianness: Endianness,
     /// Exlusive read_kinds for the architecture
     pub read_exclusives: Vec<Name>,
     /// Exlusive write_kinds for the architecture
@@ -437,6 +451,7 @@ impl<B: BV> ISAConfig<B> {
         Ok(ISAConfig {
             pc: get_program_counter(&config, symtab)?,
             ifetch_read_kind: get_ifetch_read_kind(&config, symtab)?,
+            endianness: get_endianness(&config)?,
             read_exclusives: get_exclusives(&config, "read_exclusives", symtab)?,
             write_exclusives: get_exclusives(&config, "write_exclusives", symtab)?,
             event_sets: get_event_sets(&config, symtab)?,
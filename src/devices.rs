@@ -0,0 +1,537 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2019, 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module contains reusable models of memory-mapped devices,
+//! implemented using the [CustomRegion] trait. A device can be added
+//! to a [crate::memory::Memory] with
+//! [crate::memory::Memory::add_custom_region].
+//!
+//! All devices record their accesses in the trace as `ReadMem` and
+//! `WriteMem` events with a memory kind of `"device"`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use crate::concrete::BV;
+use crate::error::ExecError;
+use crate::ir::Val;
+use crate::log;
+use crate::memory::{make_bv_bit_pair, Address, CustomRegion};
+use crate::smt::smtlib::{Def, Exp, Ty};
+use crate::smt::{Event, Solver};
+
+static DEVICE_MEMORY_KIND: &str = "device";
+
+fn record_read<B: BV>(
+    read_kind: Val<B>,
+    address: Address,
+    bytes: u32,
+    value: Val<B>,
+    solver: &mut Solver<B>,
+    tag: bool,
+) -> Val<B> {
+    solver.add_event(Event::ReadMem {
+        value: value.clone(),
+        read_kind,
+        address: Val::Bits(B::from_u64(address)),
+        bytes,
+        tag_value: None,
+        kind: DEVICE_MEMORY_KIND.into(),
+    });
+    if tag {
+        make_bv_bit_pair(value, Val::Bits(B::zeros(1)))
+    } else {
+        value
+    }
+}
+
+/// Device writes always succeed, so like writes to concrete memory
+/// the WriteMem event has a success value asserted to be true.
+fn record_write<B: BV>(
+    write_kind: Val<B>,
+    address: Address,
+    data: Val<B>,
+    solver: &mut Solver<B>,
+    tag: Option<Val<B>>,
+) -> Result<Val<B>, ExecError> {
+    let bytes = crate::primop::length_bits(&data, solver)? / 8;
+    let value = solver.declare_const(Ty::Bool);
+    solver.add(Def::Assert(Exp::Var(value)));
+    solver.add_event(Event::WriteMem {
+        value,
+        write_kind,
+        address: Val::Bits(B::from_u64(address)),
+        data,
+        bytes,
+        tag_value: tag,
+        kind: DEVICE_MEMORY_KIND.into(),
+    });
+    Ok(Val::Bool(true))
+}
+
+fn concrete_data<B: BV>(data: &Val<B>) -> Result<B, ExecError> {
+    match data {
+        Val::Bits(bv) => Ok(*bv),
+        _ => Err(ExecError::BadWrite("symbolic data written to device")),
+    }
+}
+
+/// A UART with a transmit register at offset [Uart::TX] and a status
+/// register at offset [Uart::STATUS], which always reads as ready
+/// (1). Bytes written to the transmit register are appended to an
+/// output buffer. The buffer is shared by every clone of the device,
+/// so it collects the output of all paths.
+#[derive(Clone, Debug)]
+pub struct Uart {
+    base: Address,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Uart {
+    pub const TX: Address = 0;
+    pub const STATUS: Address = 4;
+
+    pub fn new(base: Address) -> Self {
+        Uart { base, output: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    pub fn range(&self) -> Range<Address> {
+        self.base..(self.base + 8)
+    }
+
+    /// Returns a handle to the output buffer, which remains valid
+    /// after the device has been added to memory.
+    pub fn output_handle(&self) -> Arc<Mutex<Vec<u8>>> {
+        self.output.clone()
+    }
+
+    pub fn output(&self) -> Vec<u8> {
+        self.output.lock().unwrap().clone()
+    }
+}
+
+impl<B: BV> CustomRegion<B> for Uart {
+    fn read(
+        &mut self,
+        read_kind: Val<B>,
+        address: Address,
+        bytes: u32,
+        solver: &mut Solver<B>,
+        tag: bool,
+    ) -> Result<Val<B>, ExecError> {
+        let value = self.initial_value(address, bytes).ok_or(ExecError::BadRead("bad UART read"))?;
+        Ok(record_read(read_kind, address, bytes, Val::Bits(value), solver, tag))
+    }
+
+    fn write(
+        &mut self,
+        write_kind: Val<B>,
+        address: Address,
+        data: Val<B>,
+        solver: &mut Solver<B>,
+        tag: Option<Val<B>>,
+    ) -> Result<Val<B>, ExecError> {
+        let bv = concrete_data(&data)?;
+        if address - self.base == Uart::TX {
+            log!(log::MEMORY, &format!("UART output: 0x{:02x}", bv.lower_u8()));
+            self.output.lock().unwrap().push(bv.lower_u8())
+        }
+        record_write(write_kind, address, data, solver, tag)
+    }
+
+    fn initial_value(&self, address: Address, bytes: u32) -> Option<B> {
+        if bytes > 8 {
+            return None;
+        }
+        match address - self.base {
+            Uart::TX => Some(B::zeros(8 * bytes)),
+            Uart::STATUS => Some(B::new(1, 8 * bytes)),
+            _ => None,
+        }
+    }
+
+    fn memory_kind(&self) -> &'static str {
+        DEVICE_MEMORY_KIND
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Send + Sync + CustomRegion<B>> {
+        Box::new(self.clone())
+    }
+}
+
+/// A free-running 64-bit counter at offset 0, which advances by
+/// `step` every time it is read. Writing to the counter sets its
+/// value. Unlike the UART the counter is part of each path's state,
+/// so it is copied when the executor forks.
+#[derive(Clone, Debug)]
+pub struct Timer {
+    base: Address,
+    count: u64,
+    step: u64,
+}
+
+impl Timer {
+    pub fn new(base: Address, step: u64) -> Self {
+        Timer { base, count: 0, step }
+    }
+
+    pub fn range(&self) -> Range<Address> {
+        self.base..(self.base + 8)
+    }
+}
+
+impl<B: BV> CustomRegion<B> for Timer {
+    fn read(
+        &mut self,
+        read_kind: Val<B>,
+        address: Address,
+        bytes: u32,
+        solver: &mut Solver<B>,
+        tag: bool,
+    ) -> Result<Val<B>, ExecError> {
+        let value = self.initial_value(address, bytes).ok_or(ExecError::BadRead("bad timer read"))?;
+        self.count = self.count.wrapping_add(self.step);
+        Ok(record_read(read_kind, address, bytes, Val::Bits(value), solver, tag))
+    }
+
+    fn write(
+        &mut self,
+        write_kind: Val<B>,
+        address: Address,
+        data: Val<B>,
+        solver: &mut Solver<B>,
+        tag: Option<Val<B>>,
+    ) -> Result<Val<B>, ExecError> {
+        let bv = concrete_data(&data)?;
+        if address != self.base || bv.len() > 64 {
+            return Err(ExecError::BadWrite("bad timer write"));
+        }
+        self.count = bv.lower_u64();
+        record_write(write_kind, address, data, solver, tag)
+    }
+
+    fn initial_value(&self, address: Address, bytes: u32) -> Option<B> {
+        if address == self.base && (bytes == 4 || bytes == 8) {
+            Some(B::new(self.count, 8 * bytes))
+        } else {
+            None
+        }
+    }
+
+    fn memory_kind(&self) -> &'static str {
+        DEVICE_MEMORY_KIND
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Send + Sync + CustomRegion<B>> {
+        Box::new(self.clone())
+    }
+}
+
+/// Called when a device register is read with the register's current
+/// value, returning the value that is read. The current value can be
+/// updated, e.g. for clear-on-read registers.
+pub type ReadEffect<B> = Arc<dyn Send + Sync + Fn(&mut B) -> B>;
+
+/// Called when a device register is written with the register's
+/// current value and the data written.
+pub type WriteEffect<B> = Arc<dyn Send + Sync + Fn(&mut B, B)>;
+
+/// A register in a [RegisterFile]. By default reads return the
+/// current value and writes replace it.
+#[derive(Clone)]
+pub struct DeviceRegister<B> {
+    pub name: String,
+    pub bytes: u32,
+    value: B,
+    on_read: Option<ReadEffect<B>>,
+    on_write: Option<WriteEffect<B>>,
+}
+
+impl<B: BV> DeviceRegister<B> {
+    pub fn new(name: &str, bytes: u32, reset: u64) -> Self {
+        DeviceRegister { name: name.to_string(), bytes, value: B::new(reset, 8 * bytes), on_read: None, on_write: None }
+    }
+
+    pub fn on_read(mut self, effect: ReadEffect<B>) -> Self {
+        self.on_read = Some(effect);
+        self
+    }
+
+    pub fn on_write(mut self, effect: WriteEffect<B>) -> Self {
+        self.on_write = Some(effect);
+        self
+    }
+
+    pub fn value(&self) -> B {
+        self.value
+    }
+}
+
+impl<B: fmt::Debug> fmt::Debug for DeviceRegister<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DeviceRegister({}, {}, {:?})", self.name, self.bytes, self.value)
+    }
+}
+
+/// A generic device described by a table of registers at offsets
+/// from a base address. Accesses must exactly match a register's
+/// offset and size.
+#[derive(Clone, Debug)]
+pub struct RegisterFile<B> {
+    base: Address,
+    size: u64,
+    registers: BTreeMap<Address, DeviceRegister<B>>,
+}
+
+impl<B: BV> RegisterFile<B> {
+    pub fn new(base: Address, size: u64) -> Self {
+        RegisterFile { base, size, registers: BTreeMap::new() }
+    }
+
+    pub fn add_register(&mut self, offset: Address, register: DeviceRegister<B>) {
+        self.registers.insert(offset, register);
+    }
+
+    pub fn range(&self) -> Range<Address> {
+        self.base..(self.base + self.size)
+    }
+
+    pub fn register(&self, offset: Address) -> Option<&DeviceRegister<B>> {
+        self.registers.get(&offset)
+    }
+
+    fn register_mut(&mut self, address: Address, bytes: u32) -> Option<&mut DeviceRegister<B>> {
+        self.registers.get_mut(&(address - self.base)).filter(|reg| reg.bytes == bytes)
+    }
+}
+
+impl<B: BV + 'static> CustomRegion<B> for RegisterFile<B> {
+    fn read(
+        &mut self,
+        read_kind: Val<B>,
+        address: Address,
+        bytes: u32,
+        solver: &mut Solver<B>,
+        tag: bool,
+    ) -> Result<Val<B>, ExecError> {
+        let reg = self.register_mut(address, bytes).ok_or(ExecError::BadRead("no device register at address"))?;
+        let value = match &reg.on_read {
+            Some(effect) => effect(&mut reg.value),
+            None => reg.value,
+        };
+        log!(log::MEMORY, &format!("Device register {} read: {:?}", reg.name, value));
+        Ok(record_read(read_kind, address, bytes, Val::Bits(value), solver, tag))
+    }
+
+    fn write(
+        &mut self,
+        write_kind: Val<B>,
+        address: Address,
+        data: Val<B>,
+        solver: &mut Solver<B>,
+        tag: Option<Val<B>>,
+    ) -> Result<Val<B>, ExecError> {
+        let bv = concrete_data(&data)?;
+        let reg =
+            self.register_mut(address, bv.len() / 8).ok_or(ExecError::BadWrite("no device register at address"))?;
+        match &reg.on_write {
+            Some(effect) => effect(&mut reg.value, bv),
+            None => reg.value = bv,
+        }
+        log!(log::MEMORY, &format!("Device register {} write: {:?}", reg.name, bv));
+        record_write(write_kind, address, data, solver, tag)
+    }
+
+    fn initial_value(&self, address: Address, bytes: u32) -> Option<B> {
+        self.registers.get(&(address - self.base)).filter(|reg| reg.bytes == bytes).map(|reg| reg.value)
+    }
+
+    fn memory_kind(&self) -> &'static str {
+        DEVICE_MEMORY_KIND
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Send + Sync + CustomRegion<B>> {
+        Box::new(self.clone())
+    }
+}
+
+/// A stub for MMIO regions without a model. Every read returns a
+/// fresh symbolic value, and writes are accepted and logged.
+#[derive(Clone, Debug)]
+pub struct MmioStub {
+    range: Range<Address>,
+}
+
+impl MmioStub {
+    pub fn new(range: Range<Address>) -> Self {
+        MmioStub { range }
+    }
+
+    pub fn range(&self) -> Range<Address> {
+        self.range.clone()
+    }
+}
+
+impl<B: BV> CustomRegion<B> for MmioStub {
+    fn read(
+        &mut self,
+        read_kind: Val<B>,
+        address: Address,
+        bytes: u32,
+        solver: &mut Solver<B>,
+        tag: bool,
+    ) -> Result<Val<B>, ExecError> {
+        let value = solver.declare_const(Ty::BitVec(8 * bytes));
+        log!(log::MEMORY, &format!("MMIO stub read: 0x{:x} = v{}", address, value));
+        Ok(record_read(read_kind, address, bytes, Val::Symbolic(value), solver, tag))
+    }
+
+    fn write(
+        &mut self,
+        write_kind: Val<B>,
+        address: Address,
+        data: Val<B>,
+        solver: &mut Solver<B>,
+        tag: Option<Val<B>>,
+    ) -> Result<Val<B>, ExecError> {
+        log!(log::MEMORY, &format!("MMIO stub write: 0x{:x} = {:?}", address, data));
+        record_write(write_kind, address, data, solver, tag)
+    }
+
+    fn initial_value(&self, _address: Address, _bytes: u32) -> Option<B> {
+        None
+    }
+
+    fn memory_kind(&self) -> &'static str {
+        DEVICE_MEMORY_KIND
+    }
+
+    fn clone_dyn(&self) -> Box<dyn Send + Sync + CustomRegion<B>> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concrete::bitvector64::B64;
+    use crate::memory::Memory;
+    use crate::smt::{Config, Context};
+
+    use super::*;
+
+    fn bits(value: u64, len: u32) -> Val<B64> {
+        Val::Bits(B64::new(value, len))
+    }
+
+    fn read(mem: &mut Memory<B64>, solver: &mut Solver<B64>, address: Address, bytes: i128) -> Result<B64, ExecError> {
+        match mem.read(Val::Unit, bits(address, 64), Val::I128(bytes), solver, false)? {
+            Val::Bits(bv) => Ok(bv),
+            other => panic!("Expected a concrete value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn uart_output() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::new();
+        let uart = Uart::new(0x1000);
+        let output = uart.output_handle();
+        mem.add_custom_region(uart.range(), Box::new(uart));
+
+        for byte in b"hi" {
+            mem.write(Val::Unit, bits(0x1000, 64), bits(*byte as u64, 8), &mut solver, None).unwrap();
+        }
+
+        assert_eq!(*output.lock().unwrap(), b"hi");
+        assert_eq!(read(&mut mem, &mut solver, 0x1004, 4).unwrap(), B64::new(1, 32))
+    }
+
+    #[test]
+    fn timer_counts() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::new();
+        let timer = Timer::new(0x2000, 10);
+        mem.add_custom_region(timer.range(), Box::new(timer));
+
+        assert_eq!(read(&mut mem, &mut solver, 0x2000, 8).unwrap(), B64::new(0, 64));
+        assert_eq!(read(&mut mem, &mut solver, 0x2000, 8).unwrap(), B64::new(10, 64));
+
+        mem.write(Val::Unit, bits(0x2000, 64), bits(100, 64), &mut solver, None).unwrap();
+        assert_eq!(read(&mut mem, &mut solver, 0x2000, 8).unwrap(), B64::new(100, 64))
+    }
+
+    #[test]
+    fn register_file_effects() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::new();
+
+        let mut dev = RegisterFile::new(0x3000, 0x100);
+        dev.add_register(0, DeviceRegister::new("ctrl", 4, 0x5));
+        // A clear-on-read status register, and a write-one-to-set interrupt mask
+        let clear_on_read: ReadEffect<B64> = Arc::new(|value| std::mem::replace(value, B64::zeros(32)));
+        let set_bits: WriteEffect<B64> = Arc::new(|value, data| *value = *value | data);
+        dev.add_register(4, DeviceRegister::new("status", 4, 0xff).on_read(clear_on_read));
+        dev.add_register(8, DeviceRegister::new("mask", 4, 0x1).on_write(set_bits));
+        mem.add_custom_region(dev.range(), Box::new(dev));
+
+        assert!(matches!(mem.read_initial(0x3004, 4), Ok(Val::Bits(bv)) if bv == B64::new(0xff, 32)));
+        assert!(mem.read_initial(0x3004, 1).is_err());
+
+        assert_eq!(read(&mut mem, &mut solver, 0x3000, 4).unwrap(), B64::new(0x5, 32));
+        assert_eq!(read(&mut mem, &mut solver, 0x3004, 4).unwrap(), B64::new(0xff, 32));
+        assert_eq!(read(&mut mem, &mut solver, 0x3004, 4).unwrap(), B64::new(0, 32));
+        assert!(read(&mut mem, &mut solver, 0x300c, 4).is_err());
+
+        mem.write(Val::Unit, bits(0x3008, 64), bits(0x4, 32), &mut solver, None).unwrap();
+        assert_eq!(read(&mut mem, &mut solver, 0x3008, 4).unwrap(), B64::new(0x5, 32));
+        assert!(mem.write(Val::Unit, bits(0x3008, 64), bits(0x4, 64), &mut solver, None).is_err())
+    }
+
+    #[test]
+    fn mmio_stub() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::new();
+        let stub = MmioStub::new(0x4000..0x5000);
+        mem.add_custom_region(stub.range(), Box::new(stub));
+
+        let value = mem.read(Val::Unit, bits(0x4010, 64), Val::I128(4), &mut solver, false).unwrap();
+        assert!(matches!(value, Val::Symbolic(_)));
+        mem.write(Val::Unit, bits(0x4010, 64), value, &mut solver, None).unwrap();
+
+        let events = solver.trace().to_vec();
+        assert!(matches!(&events[0], Event::WriteMem { kind, bytes: 4, .. } if kind == "device"));
+        assert!(events.iter().any(|ev| matches!(ev, Event::ReadMem { kind, .. } if kind == "device")))
+    }
+}
@@ -0,0 +1,401 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2019, 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module loads 64-bit ELF files into a [Memory]. Each `PT_LOAD`
+//! segment is mapped into its own concrete region, the entry point is
+//! made available to the `elf_entry` builtin via the let-bindings,
+//! and the symbol table is kept so that addresses (e.g. for
+//! breakpoints) can be referred to by symbol name. Both little- and
+//! big-endian files are supported.
+
+use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
+use goblin::elf::sym::{STB_GLOBAL, STT_FILE, STT_SECTION};
+use goblin::elf::Elf;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::concrete::BV;
+use crate::error::ExecError;
+use crate::ir::{Bindings, UVal, Val, ELF_ENTRY};
+use crate::memory::{AccessPolicy, Address, Endianness, Memory, Permissions, Region, RegionId};
+use crate::pages::Pages;
+
+/// A loadable segment from the ELF program headers. The contents only
+/// cover the part of the segment present in the file, anything past
+/// that up to the end of the range is zero-initialized.
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub range: Range<Address>,
+    pub permissions: Permissions,
+    contents: Vec<u8>,
+}
+
+impl Segment {
+    pub fn contents(&self) -> &[u8] {
+        &self.contents
+    }
+
+    fn pages(&self) -> Pages {
+        Pages::from_bytes(self.range.start, &self.contents)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub address: Address,
+    pub size: u64,
+}
+
+impl Symbol {
+    // is_none_or would need a newer compiler than this crate supports
+    #[allow(clippy::unnecessary_map_or)]
+    pub fn contains(&self, address: Address) -> bool {
+        self.address <= address && self.address.checked_add(self.size).map_or(true, |end| address < end)
+    }
+}
+
+pub struct ElfFile {
+    /// The entry point from the ELF header
+    pub entry: Address,
+    pub big_endian: bool,
+    pub segments: Vec<Segment>,
+    symbols: Vec<Symbol>,
+    symbol_names: HashMap<String, usize>,
+}
+
+impl ElfFile {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let elf = Elf::parse(bytes).map_err(|e| format!("Failed to parse ELF file: {}", e))?;
+
+        if !elf.is_64 {
+            return Err("Only 64-bit ELF files are supported".to_string());
+        }
+
+        let mut segments = Vec::new();
+        for header in elf.program_headers.iter().filter(|header| header.p_type == PT_LOAD && header.p_memsz > 0) {
+            if header.p_filesz > header.p_memsz {
+                return Err(format!("ELF segment at 0x{:x} has more bytes in the file than in memory", header.p_vaddr));
+            }
+            let contents = bytes
+                .get(header.file_range())
+                .ok_or_else(|| format!("ELF segment at 0x{:x} is outside the file", header.p_vaddr))?;
+            let end = header
+                .p_vaddr
+                .checked_add(header.p_memsz)
+                .ok_or_else(|| format!("ELF segment at 0x{:x} overflows the address space", header.p_vaddr))?;
+            segments.push(Segment {
+                range: header.p_vaddr..end,
+                permissions: Permissions {
+                    read: header.p_flags & PF_R != 0,
+                    write: header.p_flags & PF_W != 0,
+                    execute: header.p_flags & PF_X != 0,
+                },
+                contents: contents.to_vec(),
+            })
+        }
+
+        let mut symbols = Vec::new();
+        let mut symbol_names = HashMap::new();
+        for sym in elf.syms.iter() {
+            if sym.st_type() == STT_SECTION || sym.st_type() == STT_FILE {
+                continue;
+            }
+            let name = match elf.strtab.get(sym.st_name) {
+                Some(Ok(name)) if !name.is_empty() => name.to_string(),
+                Some(Err(e)) => return Err(format!("Failed to read ELF symbol name: {}", e)),
+                _ => continue,
+            };
+            // Global symbols take priority over any local symbols with the same name
+            match symbol_names.get(&name) {
+                Some(_) if sym.st_bind() != STB_GLOBAL => (),
+                _ => {
+                    symbol_names.insert(name.clone(), symbols.len());
+                }
+            }
+            symbols.push(Symbol { name, address: sym.st_value, size: sym.st_size })
+        }
+
+        Ok(ElfFile { entry: elf.entry, big_endian: !elf.little_endian, segments, symbols, symbol_names })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        match fs::read(&path) {
+            Ok(bytes) => Self::parse(&bytes),
+            Err(e) => Err(format!("Error when loading ELF file '{}': {}", path.as_ref().display(), e)),
+        }
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        if self.big_endian {
+            Endianness::Big
+        } else {
+            Endianness::Little
+        }
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbol_names.get(name).map(|i| &self.symbols[*i])
+    }
+
+    pub fn symbol_address(&self, name: &str) -> Option<Address> {
+        self.symbol(name).map(|sym| sym.address)
+    }
+
+    /// Find a symbol whose extent covers the given address
+    pub fn symbol_at(&self, address: Address) -> Option<&Symbol> {
+        self.symbols.iter().find(|sym| sym.contains(address))
+    }
+
+    /// Map each loadable segment into memory as a concrete region
+    /// with the segment's permissions, so accesses the segment does
+    /// not permit will fault and segments with the write flag set are
+    /// updated by concrete writes. The memory's byte order is set to
+    /// that of the file. Fails if a segment overlaps an existing
+    /// region, in which case no regions are added. Returns the handle
+    /// of each region added.
+    pub fn load<B: BV>(&self, memory: &mut Memory<B>) -> Result<Vec<RegionId>, ExecError> {
+        let mut ids = Vec::new();
+        for segment in &self.segments {
+            let region = Region::Concrete(segment.range.clone(), segment.pages());
+            match memory.try_add_region_with_access(region, segment.permissions, AccessPolicy::Fault) {
+                Ok(id) => ids.push(id),
+                Err(e) => {
+                    for id in ids {
+                        memory.remove_region(id);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        memory.set_endianness(self.endianness());
+        Ok(ids)
+    }
+
+    /// Set the entry point let-binding used by the `elf_entry` builtin.
+    pub fn set_entry<B: BV>(&self, lets: &mut Bindings<B>) {
+        lets.insert(ELF_ENTRY, UVal::Init(Val::I128(i128::from(self.entry))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concrete::bitvector64::B64;
+    use crate::memory::AccessKind;
+    use crate::smt::{Config, Context, Solver};
+
+    use super::*;
+
+    struct Writer {
+        big_endian: bool,
+        buf: Vec<u8>,
+    }
+
+    impl Writer {
+        fn bytes(&mut self, bytes: &[u8]) {
+            self.buf.extend_from_slice(bytes)
+        }
+
+        fn u16(&mut self, n: u16) {
+            let bytes = if self.big_endian { n.to_be_bytes() } else { n.to_le_bytes() };
+            self.bytes(&bytes)
+        }
+
+        fn u32(&mut self, n: u32) {
+            let bytes = if self.big_endian { n.to_be_bytes() } else { n.to_le_bytes() };
+            self.bytes(&bytes)
+        }
+
+        fn u64(&mut self, n: u64) {
+            let bytes = if self.big_endian { n.to_be_bytes() } else { n.to_le_bytes() };
+            self.bytes(&bytes)
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn section_header(&mut self, name: u32, ty: u32, offset: u64, size: u64, link: u32, info: u32, entsize: u64) {
+            self.u32(name);
+            self.u32(ty);
+            self.u64(0);
+            self.u64(0);
+            self.u64(offset);
+            self.u64(size);
+            self.u32(link);
+            self.u32(info);
+            self.u64(1);
+            self.u64(entsize);
+        }
+    }
+
+    const CODE: [u8; 8] = [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88];
+
+    // Build a minimal executable with a single loadable segment
+    // containing CODE at 0x400000, and a symbol table defining `main`
+    // and `data`.
+    fn minimal_elf(big_endian: bool) -> Vec<u8> {
+        let strtab = b"\0main\0data\0";
+        let shstrtab = b"\0.symtab\0.strtab\0.shstrtab\0";
+
+        let code_offset = 64 + 56;
+        let strtab_offset = code_offset + CODE.len() as u64;
+        let symtab_offset = strtab_offset + strtab.len() as u64;
+        let shstrtab_offset = symtab_offset + 3 * 24;
+        let shoff = shstrtab_offset + shstrtab.len() as u64;
+
+        let mut w = Writer { big_endian, buf: Vec::new() };
+
+        // ELF header
+        w.bytes(&[0x7f, b'E', b'L', b'F', 2, if big_endian { 2 } else { 1 }, 1, 0]);
+        w.bytes(&[0; 8]);
+        w.u16(2);
+        w.u16(183);
+        w.u32(1);
+        w.u64(0x400004);
+        w.u64(64);
+        w.u64(shoff);
+        w.u32(0);
+        w.u16(64);
+        w.u16(56);
+        w.u16(1);
+        w.u16(64);
+        w.u16(4);
+        w.u16(3);
+
+        // Program header: R+X with 8 bytes in the file and 16 in memory
+        w.u32(PT_LOAD);
+        w.u32(PF_R | PF_X);
+        w.u64(code_offset);
+        w.u64(0x400000);
+        w.u64(0x400000);
+        w.u64(CODE.len() as u64);
+        w.u64(16);
+        w.u64(0x1000);
+
+        w.bytes(&CODE);
+        w.bytes(strtab);
+
+        // Symbol table: null symbol, main (function), data (object)
+        w.bytes(&[0; 24]);
+        for (name, info, value, size) in &[(1, 0x12, 0x400000, 4), (6, 0x11, 0x400008, 8)] {
+            w.u32(*name);
+            w.bytes(&[*info, 0]);
+            w.u16(1);
+            w.u64(*value);
+            w.u64(*size)
+        }
+
+        w.bytes(shstrtab);
+
+        w.bytes(&[0; 64]);
+        w.section_header(1, 2, symtab_offset, 3 * 24, 2, 1, 24);
+        w.section_header(9, 3, strtab_offset, strtab.len() as u64, 0, 0, 0);
+        w.section_header(17, 3, shstrtab_offset, shstrtab.len() as u64, 0, 0, 0);
+
+        w.buf
+    }
+
+    fn check_elf(big_endian: bool) {
+        let elf = ElfFile::parse(&minimal_elf(big_endian)).unwrap();
+        assert_eq!(elf.big_endian, big_endian);
+        assert_eq!(elf.entry, 0x400004);
+
+        assert_eq!(elf.segments.len(), 1);
+        let segment = &elf.segments[0];
+        assert_eq!(segment.range, 0x400000..0x400010);
+        assert_eq!(segment.permissions, Permissions::READ_EXECUTE);
+
+        assert_eq!(elf.symbol_address("main"), Some(0x400000));
+        assert_eq!(elf.symbol_address("data"), Some(0x400008));
+        assert!(elf.symbol("missing").is_none());
+        assert_eq!(elf.symbol_at(0x400003).map(|sym| sym.name.as_str()), Some("main"));
+        assert!(elf.symbol_at(0x400004).is_none());
+
+        let mut memory = Memory::<B64>::new();
+        elf.load(&mut memory).unwrap();
+        assert_eq!(memory.endianness(), elf.endianness());
+        assert!(matches!(memory.read_initial(0x400000, 1), Ok(Val::Bits(bv)) if bv == B64::new(0x11, 8)));
+        assert!(matches!(memory.read_initial(0x400007, 1), Ok(Val::Bits(bv)) if bv == B64::new(0x88, 8)));
+        assert!(matches!(memory.read_initial(0x40000f, 1), Ok(Val::Bits(bv)) if bv.is_zero()));
+        assert!(memory.read_initial(0x400010, 1).is_err());
+        assert!(matches!(elf.load(&mut memory), Err(ExecError::RegionOverlap { .. })));
+
+        let word = if big_endian { 0x1122_3344 } else { 0x4433_2211 };
+        assert!(matches!(memory.read_initial(0x400000, 4), Ok(Val::Bits(bv)) if bv == B64::new(word, 32)));
+
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let addr = Val::Bits(B64::from_u64(0x400000));
+        let write = memory.write(Val::Unit, addr, Val::Bits(B64::new(0, 8)), &mut solver, None);
+        assert!(matches!(write, Err(ExecError::AccessFault { address: 0x400000, kind: AccessKind::Write })));
+
+        let mut lets = HashMap::new();
+        elf.set_entry::<B64>(&mut lets);
+        assert!(matches!(lets.get(&ELF_ENTRY), Some(UVal::Init(Val::I128(0x400004)))));
+    }
+
+    #[test]
+    fn load_little_endian() {
+        check_elf(false)
+    }
+
+    #[test]
+    fn load_big_endian() {
+        check_elf(true)
+    }
+
+    #[test]
+    fn load_writable() {
+        let mut bytes = minimal_elf(false);
+        // Set the write flag on the program header
+        bytes[64 + 4] |= PF_W as u8;
+        let elf = ElfFile::parse(&bytes).unwrap();
+        assert_eq!(elf.segments[0].permissions, Permissions::ALL);
+
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut memory = Memory::<B64>::new();
+        elf.load(&mut memory).unwrap();
+        let addr = Val::Bits(B64::from_u64(0x400008));
+        memory.write(Val::Unit, addr.clone(), Val::Bits(B64::new(0xabcd, 16)), &mut solver, None).unwrap();
+        let value = memory.read(Val::Unit, addr, Val::I128(2), &mut solver, false).unwrap();
+        assert!(matches!(value, Val::Bits(bv) if bv == B64::new(0xabcd, 16)))
+    }
+
+    #[test]
+    fn symbol_at_top_of_memory() {
+        let sym = Symbol { name: "top".to_string(), address: u64::MAX - 1, size: 16 };
+        assert!(sym.contains(u64::MAX));
+        assert!(!sym.contains(u64::MAX - 2))
+    }
+}
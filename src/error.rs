@@ -29,7 +29,10 @@
 
 use std::{error::Error};
 use std::fmt;
+use std::ops::Range;
 
+use crate::memory::{AccessKind, Address};
+use crate::smt::smtlib::Exp;
 use crate::{concrete::BV, executor::Backtrace, ir::SharedState};
 
 #[derive(Debug)]
@@ -41,6 +44,14 @@ pub enum ExecError {
     /// SMTLIB only supports fixed-length bitvectors. This error is
     /// raised if a bitvector width would become symbolic.
     SymbolicLength(&'static str),
+    /// The SMT integer and real theories have no exponentiation, so
+    /// this is raised if an exponent or an integer shift amount would
+    /// be symbolic.
+    SymbolicExponent(&'static str),
+    /// Raised when a real number would be declared in a solver which
+    /// cannot reason about the SMT real theory, see
+    /// `Solver::check_reals`.
+    UnsupportedReals,
     /// Returned when there is no symbolic representation for a
     /// specific type. Certain types like strings are always assumed
     /// to be concrete.
@@ -53,6 +64,24 @@ pub enum ExecError {
     Unmapped,
     BadRead(&'static str),
     BadWrite(&'static str),
+    /// Raised when an access is not permitted by the permissions of
+    /// the memory region containing the address.
+    AccessFault {
+        address: Address,
+        kind: AccessKind,
+    },
+    /// Raised by memory operations using the `Fork` symbolic address
+    /// policy when a symbolic address could fall in more than one
+    /// region, or take more than one value in a region that needs a
+    /// concrete address. Contains a constraint for each possible case,
+    /// and is handled by the executor, which forks on the cases.
+    SymbolicAddressFork(Vec<Exp>),
+    /// Raised when adding or replacing a memory region that would
+    /// overlap an existing region.
+    RegionOverlap {
+        new: Range<Address>,
+        existing: Range<Address>,
+    },
     NoElfEntry,
     OutOfBounds(&'static str),
     MatchFailure,
@@ -62,6 +91,9 @@ pub enum ExecError {
     NoModel,
     Z3Error(String),
     Z3Unknown,
+    /// Raised when communicating with an external SMT solver process
+    /// fails, or the solver reports an error.
+    SolverProcess(String),
     /// Execution stopped because this function is in the stop_functions set
     Stopped(String),
 }
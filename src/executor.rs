@@ -68,10 +68,15 @@ pub fn symbolic<B: BV>(
         Ty::Bits(0) => return Ok(Val::Bits(B::zeros(0))),
 
         Ty::I64 => smtlib::Ty::BitVec(64),
+        Ty::I128 if solver.options().unbounded_ints => smtlib::Ty::Int,
         Ty::I128 => smtlib::Ty::BitVec(128),
         Ty::Bits(sz) => smtlib::Ty::BitVec(*sz),
         Ty::Bool => smtlib::Ty::Bool,
         Ty::Bit => smtlib::Ty::BitVec(1),
+        Ty::Real => {
+            solver.check_reals()?;
+            smtlib::Ty::Real
+        }
 
         Ty::Struct(name) => {
             if let Some(field_types) = shared_state.structs.get(name) {
@@ -540,11 +545,24 @@ impl<'ir, B: BV> LocalFrame<'ir, B> {
         &mut self.memory
     }
 
+    /// Returns the memory along with the current backtrace, which is
+    /// needed for memory accesses that may fire watchpoints.
+    pub fn memory_mut_with_backtrace(&mut self) -> (&mut Memory<B>, &Backtrace) {
+        (&mut self.memory, &self.backtrace)
+    }
+
     pub fn set_memory(&mut self, memory: Memory<B>) -> &mut Self {
         self.memory = memory;
         self
     }
 
+    /// Create a frame for calling a function. The frame starts with
+    /// an empty [Memory::new], which does not know the ISA's byte
+    /// order or instruction fetch read_kind. Callers must use
+    /// [LocalFrame::set_memory] to give it a memory created by
+    /// [Memory::from_isa_config], such as the `memory` returned by
+    /// [crate::init::initialize_architecture], for the `endianness`
+    /// and `ifetch_read_kind` keys of the [crate::config::ISAConfig] to apply.
     pub fn new(
         name: Name,
         args: &[(Name, &'ir Ty<Name>)],
@@ -628,6 +646,9 @@ fn pop_call_stack<'ir, B: BV>(frame: &mut LocalFrame<'ir, B>) {
     }
 }
 
+/// The maximum number of values Monomorphize finds before forking.
+const MONOMORPHIZE_CHUNK: usize = 32;
+
 #[derive(Copy, Clone, Debug)]
 struct Timeout {
     start_time: Instant,
@@ -788,7 +809,43 @@ fn run_loop<'ir, 'task, B: BV>(
                     .iter()
                     .map(|arg| eval_exp(arg, &mut frame.local_state, shared_state, solver))
                     .collect::<Result<_, _>>()?;
-                let value = f(args, solver, frame)?;
+                let value = match f(args, solver, frame) {
+                    // A memory access with a symbolic address that could fall in several
+                    // regions. Fork once per case and retry the primop on each path. The
+                    // cases are numbered by a single selector variable, so the Fork event
+                    // records which case each path took.
+                    Err(ExecError::SymbolicAddressFork(cases)) => {
+                        use smtlib::Def::*;
+                        use smtlib::Exp::*;
+
+                        let width = std::cmp::max(1, 64 - (cases.len() as u64 - 1).leading_zeros());
+                        let last = Bits64(cases.len() as u64 - 1, width);
+                        let selector = cases.iter().enumerate().rev().skip(1).fold(last, |exp, (i, case)| {
+                            Ite(Box::new(case.clone()), Box::new(Bits64(i as u64, width)), Box::new(exp))
+                        });
+
+                        let loc = "Fork @ symbolic address".to_string();
+                        log_from!(tid, log::FORK, loc);
+                        let selector = solver.define_const(selector);
+                        solver.add_event(Event::Fork(frame.forks, selector, loc));
+                        frame.forks += 1;
+
+                        for i in 1..cases.len() {
+                            let point = checkpoint(solver);
+                            queue.push(Task {
+                                id: task_id,
+                                frame: freeze_frame(frame),
+                                checkpoint: point,
+                                fork_cond: Some(Assert(Eq(Box::new(Var(selector)), Box::new(Bits64(i as u64, width))))),
+                                state: task_state,
+                                stop_functions,
+                            })
+                        }
+                        solver.add(Assert(Eq(Box::new(Var(selector)), Box::new(Bits64(0, width)))));
+                        continue;
+                    }
+                    result => result?,
+                };
                 assign(tid, loc, value, &mut frame.local_state, shared_state, solver)?;
                 frame.pc += 1;
             }
@@ -944,51 +1001,58 @@ fn run_loop<'ir, 'task, B: BV>(
             // the possible values for that bitvector and case splits
             // (i.e. forks) on them. This allows us to guarantee that
             // certain bitvectors are non-symbolic, at the cost of
-            // increasing the number of paths.
+            // increasing the number of paths. Each forked task
+            // re-runs the instruction with its value asserted, so it
+            // finds that single value without forking again.
             Instr::Monomorphize(id) => {
                 let val = get_id_and_initialize(*id, &mut frame.local_state, shared_state, solver, &mut Vec::new())?;
                 if let Val::Symbolic(v) = val {
                     use smtlib::Def::*;
                     use smtlib::Exp::*;
-                    use smtlib::Ty::*;
-
-                    let point = checkpoint(solver);
-
-                    let len = solver.length(v).ok_or_else(|| ExecError::Type(format!("_monomorphize {:?}", &v)))?;
-
-                    // For the variable v to appear in the model, there must be some assertion that references it
-                    let sym = solver.declare_const(BitVec(len));
-                    solver.assert_eq(Var(v), Var(sym));
 
-                    if solver.check_sat().is_unsat()? {
-                        return Err(ExecError::Dead);
-                    }
+                    // Values are found in chunks. If there are more, we fork a further task which
+                    // excludes the values found so far and monomorphizes again.
+                    let mut values = solver.enumerate(v, MONOMORPHIZE_CHUNK + 1)?;
+                    let remaining = if values.len() > MONOMORPHIZE_CHUNK { values.pop() } else { None };
 
-                    let (result, size) = {
-                        let mut model = Model::new(solver);
-                        log_from!(tid, log::FORK, format!("Model: {:?}", model));
-                        match model.get_var(v) {
-                            Ok(Some(Bits64(result, size))) => (result, size),
-                            // __monomorphize should have a 'n <= 64 constraint in Sail
-                            Ok(Some(other)) => return Err(ExecError::Type(format!("__monomorphize {:?}", &other))),
-                            Ok(None) => return Err(ExecError::Z3Error(format!("No value for variable v{}", v))),
-                            Err(error) => return Err(error),
-                        }
+                    let (result, size) = match values.first() {
+                        Some(Bits64(result, size)) => (*result, *size),
+                        // __monomorphize should have a 'n <= 64 constraint in Sail
+                        Some(other) => return Err(ExecError::Type(format!("__monomorphize {:?}", &other))),
+                        None => return Err(ExecError::Dead),
                     };
 
-                    let loc = format!("Fork @ monomorphizing v{}", v);
-                    log_from!(tid, log::FORK, loc);
-                    solver.add_event(Event::Fork(frame.forks, v, loc.clone()));
-                    frame.forks += 1;
+                    if values.len() > 1 || remaining.is_some() {
+                        let loc = format!("Fork @ monomorphizing v{}", v);
+                        log_from!(tid, log::FORK, loc);
+                        solver.add_event(Event::Fork(frame.forks, v, loc.clone()));
+                        frame.forks += 1;
 
-                    queue.push(Task {
-                        id: task_id,
-                        frame: freeze_frame(&frame),
-                        checkpoint: point,
-                        fork_cond: Some(Assert(Neq(Box::new(Var(v)), Box::new(Bits64(result, size))))),
-                        state: task_state,
-                        stop_functions,
-                    });
+                        let point = checkpoint(solver);
+                        for value in &values[1..] {
+                            queue.push(Task {
+                                id: task_id,
+                                frame: freeze_frame(frame),
+                                checkpoint: point.clone(),
+                                fork_cond: Some(Assert(Eq(Box::new(Var(v)), Box::new(value.clone())))),
+                                state: task_state,
+                                stop_functions,
+                            })
+                        }
+                        if remaining.is_some() {
+                            let exclude = values.iter().fold(Bool(true), |exclude, value| {
+                                And(Box::new(exclude), Box::new(Neq(Box::new(Var(v)), Box::new(value.clone()))))
+                            });
+                            queue.push(Task {
+                                id: task_id,
+                                frame: freeze_frame(frame),
+                                checkpoint: point,
+                                fork_cond: Some(Assert(exclude)),
+                                state: task_state,
+                                stop_functions,
+                            })
+                        }
+                    }
 
                     solver.assert_eq(Var(v), Bits64(result, size));
 
@@ -1030,22 +1094,106 @@ fn run_loop<'ir, 'task, B: BV>(
 /// takes the result of the execution, which is either a combination of the return value and local
 /// state at the end of the execution or an error, as well as the shared state and the SMT solver
 /// state associated with that execution. It build a final result for all the executions by
-/// collecting the results into a type R.
+/// collecting the results into a type R. The solver is only borrowed, so in incremental mode the
+/// executor can reuse it for the next task. Collectors which need to make further queries can use
+/// `Solver::duplicate`.
 pub type Collector<'ir, B, R> = dyn 'ir
     + Sync
-    + Fn(usize, usize, Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>, &SharedState<'ir, B>, Solver<B>, &R);
+    + Fn(usize, usize, Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>, &SharedState<'ir, B>, &Solver<B>, &R);
 
 pub struct TaskState<B> {
     reset_registers: HashMap<Loc<Name>, Reset<B>>,
+    solver_options: SolverOptions,
+    smt_cache: Option<Arc<SmtCache>>,
+    query_recorder: Option<Arc<QueryRecorder>>,
+    solver_profile: Option<Arc<SolverProfile>>,
+    portfolio: Option<Arc<Portfolio>>,
+    solver_backend: Option<Arc<BackendFactory>>,
 }
 
 impl<B> TaskState<B> {
     pub fn new() -> Self {
-        TaskState { reset_registers: HashMap::new() }
+        TaskState {
+            reset_registers: HashMap::new(),
+            solver_options: SolverOptions::default(),
+            smt_cache: None,
+            query_recorder: None,
+            solver_profile: None,
+            portfolio: None,
+            solver_backend: None,
+        }
     }
 
     pub fn with_reset_registers(reset_registers: HashMap<Loc<Name>, Reset<B>>) -> Self {
-        TaskState { reset_registers }
+        TaskState {
+            reset_registers,
+            solver_options: SolverOptions::default(),
+            smt_cache: None,
+            query_recorder: None,
+            solver_profile: None,
+            portfolio: None,
+            solver_backend: None,
+        }
+    }
+
+    /// In incremental mode, a worker resuming a task forked from the
+    /// path it has just finished reuses its solver by popping back to
+    /// the task's checkpoint, rather than replaying the checkpoint
+    /// into a fresh solver. Tasks stolen from other workers are still
+    /// replayed.
+    pub fn set_incremental(&mut self, incremental: bool) {
+        self.solver_options.incremental = incremental
+    }
+
+    /// Label assertions made at forks and by Sail assertions so the
+    /// solver can report unsat cores for dead paths and failed
+    /// assertions, see `unsat_core_collector`.
+    pub fn set_unsat_cores(&mut self, unsat_cores: bool) {
+        self.solver_options.unsat_cores = unsat_cores
+    }
+
+    /// Model symbolic Sail integers using the SMT integer theory
+    /// rather than 128-bit bitvectors, see `SolverOptions`.
+    pub fn set_unbounded_ints(&mut self, unbounded_ints: bool) {
+        self.solver_options.unbounded_ints = unbounded_ints
+    }
+
+    /// Simplify each definition as it is added to a task's solver,
+    /// see `SolverOptions`.
+    pub fn set_simplify(&mut self, simplify: bool) {
+        self.solver_options.simplify = simplify
+    }
+
+    /// Share a cache of `check_sat_with` results between the solvers
+    /// for all tasks, so feasibility checks repeated by sibling tasks
+    /// are only sent to Z3 once.
+    pub fn set_smt_cache(&mut self, cache: Arc<SmtCache>) {
+        self.smt_cache = Some(cache)
+    }
+
+    /// Write every query sent to the solver into a directory as an
+    /// SMT-LIB2 benchmark tagged with its task, see `QueryRecorder`.
+    pub fn set_query_recorder(&mut self, recorder: Arc<QueryRecorder>) {
+        self.query_recorder = Some(recorder)
+    }
+
+    /// Collect statistics from the solvers for all tasks, with
+    /// queries attributed to fork locations, see `SolverProfile`.
+    pub fn set_solver_profile(&mut self, profile: Arc<SolverProfile>) {
+        self.solver_profile = Some(profile)
+    }
+
+    /// Race the given solver configurations against each task's
+    /// solver for queries it cannot answer quickly, see `Portfolio`.
+    pub fn set_portfolio(&mut self, portfolio: Arc<Portfolio>) {
+        self.portfolio = Some(portfolio)
+    }
+
+    /// Answer every query for a task using a solver backend created
+    /// by `factory`, e.g. an external solver process, rather than
+    /// Z3, see `Solver::set_backend`. Not compatible with unsat cores.
+    pub fn set_solver_backend(&mut self, factory: Arc<BackendFactory>) {
+        self.solver_backend = Some(factory)
     }
 }
 
@@ -1085,26 +1233,12 @@ pub fn start_single<'ir, 'task, B: BV, R>(
 ) {
     let queue = Worker::new_lifo();
     queue.push(task);
+    let mut cfg = Config::new();
+    cfg.set_param_value("model", "true");
+    let ctx = Context::new(cfg);
+    let mut worker = WorkerSolver { model: true, ctx: &ctx, reusable: None };
     while let Some(task) = queue.pop() {
-        let mut cfg = Config::new();
-        cfg.set_param_value("model", "true");
-        let ctx = Context::new(cfg);
-        let mut solver = Solver::from_checkpoint(&ctx, task.checkpoint);
-        if let Some(def) = task.fork_cond {
-            solver.add(def)
-        };
-        let result = run(
-            0,
-            task.id,
-            Timeout::unlimited(),
-            task.stop_functions,
-            &queue,
-            &task.frame,
-            &task.state,
-            shared_state,
-            &mut solver,
-        );
-        collector(0, task.id, result, shared_state, solver, collected)
+        do_work(0, Timeout::unlimited(), &queue, task, shared_state, collected, collector, &mut worker)
     }
 }
 
@@ -1120,7 +1254,30 @@ fn find_task<T>(local: &Worker<T>, global: &Injector<T>, stealers: &RwLock<Vec<S
     })
 }
 
-fn do_work<'ir, 'task, B: BV, R>(
+/// The solver state kept by each worker between tasks.
+struct WorkerSolver<'ctx, B> {
+    /// Whether fresh contexts should produce models
+    model: bool,
+    ctx: &'ctx Context,
+    reusable: Option<Solver<'ctx, B>>,
+}
+
+impl<'ctx, B> WorkerSolver<'ctx, B> {
+    fn fresh_context(&self) -> Context {
+        let mut cfg = Config::new();
+        if self.model {
+            cfg.set_param_value("model", "true")
+        }
+        Context::new(cfg)
+    }
+}
+
+/// Run a task to completion. With an incremental task state the task
+/// is run using the worker's context, and the solver left over from
+/// the worker's previous task is reused if the task's checkpoint can
+/// be restored in it.
+#[allow(clippy::too_many_arguments)]
+fn do_work<'ctx, 'ir, 'task, B: BV, R>(
     tid: usize,
     timeout: Timeout,
     queue: &Worker<Task<'ir, 'task, B>>,
@@ -1128,16 +1285,55 @@ fn do_work<'ir, 'task, B: BV, R>(
     shared_state: &SharedState<'ir, B>,
     collected: &R,
     collector: &Collector<'ir, B, R>,
+    worker: &mut WorkerSolver<'ctx, B>,
 ) {
-    let cfg = Config::new();
-    let ctx = Context::new(cfg);
-    let mut solver = Solver::from_checkpoint(&ctx, task.checkpoint);
-    if let Some(def) = task.fork_cond {
-        solver.add(def)
+    let Task { id, frame, checkpoint, fork_cond, state, stop_functions } = task;
+    let run_task = |solver: &mut Solver<B>| {
+        if let Some(cache) = &state.smt_cache {
+            solver.set_cache(cache.clone())
+        }
+        if let Some(recorder) = &state.query_recorder {
+            solver.set_recorder(recorder.clone(), id)
+        }
+        if let Some(profile) = &state.solver_profile {
+            solver.set_profile(profile.clone())
+        }
+        if let Some(portfolio) = &state.portfolio {
+            solver.set_portfolio(portfolio.clone())
+        }
+        if let Some(factory) = &state.solver_backend {
+            // A reused incremental solver keeps its backend
+            if !solver.has_backend() {
+                if let Err(err) = factory().and_then(|backend| solver.set_backend(backend)) {
+                    return Err((err, Vec::new()));
+                }
+            }
+        }
+        if let Some(def) = fork_cond {
+            solver.add(def)
+        };
+        run(tid, id, timeout, stop_functions, queue, &frame, state, shared_state, solver)
     };
-    let result =
-        run(tid, task.id, timeout, task.stop_functions, queue, &task.frame, &task.state, shared_state, &mut solver);
-    collector(tid, task.id, result, shared_state, solver, collected)
+    if state.solver_options.incremental {
+        let mut solver = match worker.reusable.take() {
+            Some(mut solver) => {
+                if solver.restore_checkpoint(&checkpoint) {
+                    solver
+                } else {
+                    Solver::from_checkpoint_with_options(worker.ctx, checkpoint, state.solver_options)
+                }
+            }
+            None => Solver::from_checkpoint_with_options(worker.ctx, checkpoint, state.solver_options),
+        };
+        let result = run_task(&mut solver);
+        collector(tid, id, result, shared_state, &solver, collected);
+        worker.reusable = Some(solver)
+    } else {
+        let ctx = worker.fresh_context();
+        let mut solver = Solver::from_checkpoint_with_options(&ctx, checkpoint, state.solver_options);
+        let result = run_task(&mut solver);
+        collector(tid, id, result, shared_state, &solver, collected)
+    }
 }
 
 enum Response {
@@ -1190,12 +1386,14 @@ pub fn start_multi<'ir, 'task, B: BV, R>(
                     let mut stealers = stealers.write().unwrap();
                     stealers.push(q.stealer());
                 }
+                let ctx = Context::new(Config::new());
+                let mut worker = WorkerSolver { model: false, ctx: &ctx, reusable: None };
                 loop {
                     if let Some(task) = find_task(&q, &global, &stealers) {
                         thread_tx.send(Activity::Busy(tid)).unwrap();
-                        do_work(tid, timeout, &q, task, &shared_state, collected.as_ref(), collector);
+                        do_work(tid, timeout, &q, task, &shared_state, collected.as_ref(), collector, &mut worker);
                         while let Some(task) = find_task(&q, &global, &stealers) {
-                            do_work(tid, timeout, &q, task, &shared_state, collected.as_ref(), collector)
+                            do_work(tid, timeout, &q, task, &shared_state, collected.as_ref(), collector, &mut worker)
                         }
                     };
                     thread_tx.send(Activity::Idle(tid, poke_tx.clone())).unwrap();
@@ -1268,7 +1466,7 @@ pub fn all_unsat_collector<'ir, B: BV>(
     _: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
     shared_state: &SharedState<'ir, B>,
-    mut solver: Solver<B>,
+    solver: &Solver<B>,
     collected: &AtomicBool,
 ) {
     match result {
@@ -1276,6 +1474,8 @@ pub fn all_unsat_collector<'ir, B: BV>(
             (Val::Symbolic(v), _) => {
                 use smtlib::Def::*;
                 use smtlib::Exp::*;
+                let ctx = Context::new(Config::new());
+                let mut solver = solver.duplicate(&ctx);
                 solver.add(Assert(Not(Box::new(Var(v)))));
                 if solver.check_sat() != SmtResult::Unsat {
                     log_from!(tid, log::VERBOSE, "Got sat");
@@ -1312,12 +1512,27 @@ pub type TraceResultQueue<B> = SegQueue<Result<(usize, bool, Vec<Event<B>>), Str
 
 pub type TraceValueQueue<B> = SegQueue<Result<(usize, Val<B>, Vec<Event<B>>), String>>;
 
+/// Format an error together with a model for the path that raised
+/// it, if there is one.
+fn error_with_model<B: BV>(err: ExecError, solver: &Solver<B>) -> String {
+    let mut cfg = Config::new();
+    cfg.set_param_value("model", "true");
+    let ctx = Context::new(cfg);
+    let mut solver = solver.duplicate(&ctx);
+    if solver.check_sat() == SmtResult::Sat {
+        let model = Model::new(&solver);
+        format!("Error {:?}\n{:?}", err, model)
+    } else {
+        format!("Error {:?}\nno model", err)
+    }
+}
+
 pub fn trace_collector<'ir, B: BV>(
     _: usize,
     task_id: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
     _: &SharedState<'ir, B>,
-    mut solver: Solver<B>,
+    solver: &Solver<B>,
     collected: &TraceQueue<B>,
 ) {
     match result {
@@ -1326,14 +1541,7 @@ pub fn trace_collector<'ir, B: BV>(
             collected.push(Ok((task_id, events.drain(..).cloned().collect())))
         }
         Err((ExecError::Dead, _)) => (),
-        Err((err, _)) => {
-            if solver.check_sat() == SmtResult::Sat {
-                let model = Model::new(&solver);
-                collected.push(Err(format!("Error {:?}\n{:?}", err, model)))
-            } else {
-                collected.push(Err(format!("Error {:?}\nno model", err)))
-            }
-        }
+        Err((err, _)) => collected.push(Err(error_with_model(err, solver))),
     }
 }
 
@@ -1342,7 +1550,7 @@ pub fn trace_value_collector<'ir, B: BV>(
     task_id: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
     _: &SharedState<'ir, B>,
-    mut solver: Solver<B>,
+    solver: &Solver<B>,
     collected: &TraceValueQueue<B>,
 ) {
     match result {
@@ -1351,14 +1559,7 @@ pub fn trace_value_collector<'ir, B: BV>(
             collected.push(Ok((task_id, val, events.drain(..).cloned().collect())))
         }
         Err((ExecError::Dead, _)) => (),
-        Err((err, _)) => {
-            if solver.check_sat() == SmtResult::Sat {
-                let model = Model::new(&solver);
-                collected.push(Err(format!("Error {:?}\n{:?}", err, model)))
-            } else {
-                collected.push(Err(format!("Error {:?}\nno model", err)))
-            }
-        }
+        Err((err, _)) => collected.push(Err(error_with_model(err, solver))),
     }
 }
 
@@ -1367,7 +1568,7 @@ pub fn trace_result_collector<'ir, B: BV>(
     task_id: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
     _: &SharedState<'ir, B>,
-    solver: Solver<B>,
+    solver: &Solver<B>,
     collected: &TraceResultQueue<B>,
 ) {
     match result {
@@ -1386,7 +1587,7 @@ pub fn footprint_collector<'ir, B: BV>(
     task_id: usize,
     result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
     _: &SharedState<'ir, B>,
-    solver: Solver<B>,
+    solver: &Solver<B>,
     collected: &TraceQueue<B>,
 ) {
     match result {
@@ -1404,3 +1605,223 @@ pub fn footprint_collector<'ir, B: BV>(
         Err((err, _)) => collected.push(Err(format!("Error {:?}", err))),
     }
 }
+
+pub type UnsatCoreQueue = SegQueue<(usize, String, Vec<String>)>;
+
+/// This `Collector` reports why paths were discarded when unsat cores
+/// are enabled via `TaskState::set_unsat_cores`. For each dead path
+/// or failed assertion it collects the task id, the error (or the
+/// assertion message), and the fork locations labelling the
+/// conflicting assertions.
+pub fn unsat_core_collector<'ir, B: BV>(
+    _: usize,
+    task_id: usize,
+    result: Result<(Val<B>, LocalFrame<'ir, B>), (ExecError, Backtrace)>,
+    _: &SharedState<'ir, B>,
+    solver: &Solver<B>,
+    collected: &UnsatCoreQueue,
+) {
+    match result {
+        // A path is dead because the last solver call made for it was unsatisfiable
+        Err((ExecError::Dead, _)) => collected.push((task_id, format!("{}", ExecError::Dead), solver.unsat_core())),
+        // The unsat core is from the solver call which found the assertion could not hold
+        Err((ExecError::AssertionFailed(message), _)) => collected.push((task_id, message, solver.unsat_core())),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concrete::bitvector64::B64;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Mutex;
+
+    #[test]
+    fn unbounded_ints() {
+        let mut symtab = Symtab::new();
+        let f = symtab.intern("f");
+        let x = symtab.intern("x");
+        let r = symtab.intern("R");
+        let s = symtab.intern("S");
+        let shared_state = SharedState::<B64>::new(symtab, &[], HashSet::new(), HashMap::new());
+
+        // return (x == R) & (x == S), where x is a fresh symbolic
+        // argument, R an uninitialised register, and S a register
+        // with a concrete initial value
+        let instrs = vec![
+            Instr::Copy(
+                Loc::Id(RETURN),
+                Exp::Call(
+                    Op::And,
+                    vec![
+                        Exp::Call(Op::Eq, vec![Exp::Id(x), Exp::Id(r)]),
+                        Exp::Call(Op::Eq, vec![Exp::Id(x), Exp::Id(s)]),
+                    ],
+                ),
+            ),
+            Instr::End,
+        ];
+        let mut regs = HashMap::new();
+        regs.insert(r, UVal::Uninit(&Ty::I128));
+        regs.insert(s, UVal::Init(Val::I128(3)));
+
+        let mut task_state = TaskState::new();
+        task_state.set_unbounded_ints(true);
+        let task = LocalFrame::new(f, &[(x, &Ty::I128)], None, &instrs).add_regs(&regs).task(0, &task_state);
+
+        let collected = Mutex::new(Vec::new());
+        start_single(task, &shared_state, &collected, &|_, _, result, _, solver, collected| {
+            let result = match result {
+                Ok((Val::Symbolic(v), _)) => {
+                    let ctx = Context::new(Config::new());
+                    let mut solver = solver.duplicate(&ctx);
+                    solver.check_sat_with(&smtlib::Exp::Var(v))
+                }
+                _ => SmtResult::Unknown,
+            };
+            let sorts: Vec<smtlib::Ty> = solver
+                .trace()
+                .to_vec()
+                .iter()
+                .filter_map(|event| match event {
+                    Event::Smt(smtlib::Def::DeclareConst(_, ty)) => Some(ty.clone()),
+                    _ => None,
+                })
+                .collect();
+            collected.lock().unwrap().push((result, sorts))
+        });
+
+        let collected = collected.into_inner().unwrap();
+        assert_eq!(collected.len(), 1);
+        let (result, sorts) = &collected[0];
+        assert!(*result == SmtResult::Sat);
+        assert!(sorts.len() == 2 && sorts.iter().all(|ty| *ty == smtlib::Ty::Int))
+    }
+
+    #[test]
+    fn incremental_matches_replay() {
+        let mut symtab = Symtab::new();
+        let f = symtab.intern("f");
+        let x = symtab.intern("x");
+        let shared_state = SharedState::<B64>::new(symtab, &[], HashSet::new(), HashMap::new());
+
+        // if x == 1 then 1 else if x == 2 then 2 else 0, forking twice
+        let is = |n| Exp::Call(Op::Eq, vec![Exp::Id(x), Exp::Bits(B64::new(n, 4))]);
+        let instrs = vec![
+            Instr::Jump(is(1), 4, String::new()),
+            Instr::Jump(is(2), 6, String::new()),
+            Instr::Copy(Loc::Id(RETURN), Exp::Bits(B64::new(0, 2))),
+            Instr::End,
+            Instr::Copy(Loc::Id(RETURN), Exp::Bits(B64::new(1, 2))),
+            Instr::End,
+            Instr::Copy(Loc::Id(RETURN), Exp::Bits(B64::new(2, 2))),
+            Instr::End,
+        ];
+
+        let results = |incremental: bool| {
+            let profile = Arc::new(SolverProfile::new());
+            let mut task_state = TaskState::new();
+            task_state.set_incremental(incremental);
+            task_state.set_solver_profile(profile.clone());
+            let task = LocalFrame::new(f, &[(x, &Ty::Bits(4))], None, &instrs).task(0, &task_state);
+            let queue = TraceValueQueue::new();
+            start_single(task, &shared_state, &queue, &trace_value_collector);
+            let mut traces = Vec::new();
+            while let Ok(result) = queue.pop() {
+                match result {
+                    Ok((_, Val::Bits(bv), events)) => traces.push((bv.lower_u64(), format!("{:?}", events))),
+                    _ => panic!("Unexpected result"),
+                }
+            }
+            traces.sort();
+            (traces, profile.total().replayed)
+        };
+
+        let (replayed_traces, replayed) = results(false);
+        let (incremental_traces, incremental_replayed) = results(true);
+        assert_eq!(replayed_traces.iter().map(|(value, _)| *value).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(replayed_traces, incremental_traces);
+        // The second fork's task is resumed without replaying
+        assert!(incremental_replayed < replayed)
+    }
+
+    // Answers queries using a Z3 solver of its own, counting them
+    struct CountingBackend {
+        solver: Z3Backend<'static>,
+        queries: Arc<AtomicUsize>,
+    }
+
+    impl SolverBackend for CountingBackend {
+        fn add_def(&mut self, def: &smtlib::Def) -> Result<(), ExecError> {
+            self.solver.add_def(def)
+        }
+
+        fn push(&mut self) -> Result<(), ExecError> {
+            self.solver.push()
+        }
+
+        fn pop(&mut self, n: u32) -> Result<(), ExecError> {
+            self.solver.pop(n)
+        }
+
+        fn check_sat(&mut self) -> Result<SmtResult, ExecError> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            self.solver.check_sat()
+        }
+
+        fn check_sat_with(&mut self, exp: &smtlib::Exp) -> Result<SmtResult, ExecError> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            self.solver.check_sat_with(exp)
+        }
+
+        fn get_value(&mut self, exp: &smtlib::Exp) -> Result<Option<smtlib::Exp>, ExecError> {
+            self.solver.get_value(exp)
+        }
+    }
+
+    #[test]
+    fn solver_backend() {
+        let mut symtab = Symtab::new();
+        let f = symtab.intern("f");
+        let x = symtab.intern("x");
+        let shared_state = SharedState::<B64>::new(symtab, &[], HashSet::new(), HashMap::new());
+
+        // if x == 3 then (if x != 3 then 0 else 1) else 0, so the
+        // executor must ask whether the inner branch is feasible
+        let is_three = |op| Exp::Call(op, vec![Exp::Id(x), Exp::Bits(B64::new(3, 4))]);
+        let instrs = vec![
+            Instr::Jump(is_three(Op::Eq), 3, String::new()),
+            Instr::Copy(Loc::Id(RETURN), Exp::Bits(B64::new(0, 1))),
+            Instr::End,
+            Instr::Jump(is_three(Op::Neq), 1, String::new()),
+            Instr::Copy(Loc::Id(RETURN), Exp::Bits(B64::new(1, 1))),
+            Instr::End,
+        ];
+
+        let results = |task_state: &TaskState<B64>| {
+            let task = LocalFrame::new(f, &[(x, &Ty::Bits(4))], None, &instrs).task(0, task_state);
+            let collected = Mutex::new(Vec::new());
+            start_single(task, &shared_state, &collected, &|_, _, result, _, _, collected| match result {
+                Ok((Val::Bits(bv), _)) => collected.lock().unwrap().push(bv.lower_u64()),
+                _ => panic!("Unexpected result"),
+            });
+            let mut collected = collected.into_inner().unwrap();
+            collected.sort_unstable();
+            collected
+        };
+
+        let queries = Arc::new(AtomicUsize::new(0));
+        let factory_queries = queries.clone();
+        let mut task_state = TaskState::new();
+        task_state.set_solver_backend(Arc::new(move || {
+            let ctx: &'static Context = Box::leak(Box::new(Context::new(Config::new())));
+            let backend = CountingBackend { solver: Z3Backend::new(ctx), queries: factory_queries.clone() };
+            Ok(Box::new(backend) as Box<dyn SolverBackend>)
+        }));
+
+        assert_eq!(results(&TaskState::new()), vec![0, 1]);
+        assert_eq!(results(&task_state), vec![0, 1]);
+        assert!(queries.load(Ordering::SeqCst) >= 3)
+    }
+}
@@ -59,6 +59,7 @@ use crate::config::ISAConfig;
 use crate::executor::{start_single, LocalFrame, TaskState};
 use crate::ir::*;
 use crate::log;
+use crate::memory::Memory;
 use crate::zencode;
 
 fn initialize_letbindings<'ir, B: BV>(
@@ -125,6 +126,9 @@ pub struct Initialized<'ir, B> {
     pub regs: Bindings<'ir, B>,
     pub lets: Bindings<'ir, B>,
     pub shared_state: SharedState<'ir, B>,
+    /// An empty memory using the byte order and instruction fetch
+    /// read_kind from the [ISAConfig], for [LocalFrame::set_memory].
+    pub memory: Memory<B>,
 }
 
 pub fn initialize_architecture<'ir, B: BV>(
@@ -142,5 +146,6 @@ pub fn initialize_architecture<'ir, B: BV>(
 
     initialize_letbindings(arch, &shared_state, &regs, &lets);
 
-    Initialized { regs, lets: lets.into_inner().unwrap(), shared_state }
+    let memory = Memory::from_isa_config(isa_config, &shared_state);
+    Initialized { regs, lets: lets.into_inner().unwrap(), shared_state, memory }
 }
@@ -137,7 +137,7 @@ pub enum Op {
     Tail,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct EnumMember {
     pub enum_id: usize,
     pub member: usize,
@@ -146,7 +146,7 @@ pub struct EnumMember {
 /// A value is either a symbolic value, represented as `Symbolic(n)`
 /// for where n is the identifier of the variable in the SMT solver,
 /// or one of the concrete values in this enum.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Val<B> {
     Symbolic(Sym),
     I64(i64),
@@ -523,6 +523,14 @@ pub const BV_BIT_RIGHT: Name = Name { id: 16 };
 /// values according to the ISA config
 pub const RESET_REGISTERS: Name = Name { id: 17 };
 
+/// [FLOAT_RESULT] is the register the softfloat primops write their
+/// results to, as in the C emulator for the Sail RISC-V model
+pub const FLOAT_RESULT: Name = Name { id: 18 };
+
+/// [FLOAT_FFLAGS] is the register the softfloat primops write their
+/// accrued exception flags to
+pub const FLOAT_FFLAGS: Name = Name { id: 19 };
+
 static GENSYM: &str = "|GENSYM|";
 
 impl<'ir> Symtab<'ir> {
@@ -588,6 +596,8 @@ impl<'ir> Symtab<'ir> {
         symtab.intern("ztuplez3z5bv_z5bit0");
         symtab.intern("ztuplez3z5bv_z5bit1");
         symtab.intern("reset_registers");
+        symtab.intern("zfloat_result");
+        symtab.intern("zfloat_fflags");
         symtab
     }
 
@@ -878,8 +888,8 @@ fn insert_instr_primops<B: BV>(
                     Instr::Call(loc.clone(), false, RESET_REGISTERS, args.clone())
                 } else {
                     // Currently we just warn when we don't have a
-                    // primop. This happens for softfloat based
-                    // floating point in RISC-V right now.
+                    // primop, and leave the call in place, which
+                    // panics if it is ever executed.
                     eprintln!("No primop {} ({:?})", name, f);
                     Instr::Call(loc.clone(), false, *f, args.clone())
                 }
@@ -459,13 +459,16 @@ pub fn self_test<'ir, B: BV>(
         ]
     }));
 
-    let Initialized { regs, lets, shared_state } =
+    let Initialized { regs, lets, shared_state, memory } =
         initialize_architecture(&mut arch, symtab, isa_config, AssertionMode::Optimistic);
 
     let (args, _, instrs) = shared_state.functions.get(&comparison).unwrap();
     let task_state = executor::TaskState::new();
-    let task =
-        executor::LocalFrame::new(comparison, args, None, instrs).add_lets(&lets).add_regs(&regs).task(0, &task_state);
+    let task = executor::LocalFrame::new(comparison, args, None, instrs)
+        .add_lets(&lets)
+        .add_regs(&regs)
+        .set_memory(memory)
+        .task(0, &task_state);
     let result = Arc::new(AtomicBool::new(true));
 
     executor::start_multi(num_threads, None, vec![task], &shared_state, result.clone(), &executor::all_unsat_collector);
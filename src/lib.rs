@@ -43,6 +43,8 @@ lalrpop_mod!(#[allow(clippy::all)] pub value_parser);
 pub mod cache;
 pub mod concrete;
 pub mod config;
+pub mod devices;
+pub mod elf_loader;
 pub mod error;
 pub mod executor;
 pub mod init;
@@ -50,9 +52,12 @@ pub mod ir;
 pub mod ir_lexer;
 pub mod lexer;
 pub mod memory;
+pub mod pages;
 pub mod primop;
 mod probe;
 pub mod simplify;
 pub mod smt;
+pub mod snapshot;
+pub mod softfloat;
 pub mod type_check;
 pub mod zencode;
@@ -36,24 +36,57 @@
 //! so the bevhaior can be imposed later as part of the concurrency
 //! model.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Range;
 use std::sync::Arc;
 
 use crate::concrete::BV;
+use crate::config::ISAConfig;
 use crate::error::ExecError;
+use crate::executor::Backtrace;
 use crate::ir;
 use crate::ir::Val;
 use crate::log;
+use crate::pages::Pages;
 use crate::probe;
 use crate::smt::smtlib::{Def, Exp};
-use crate::smt::{Event, SmtResult, Solver, Sym};
+use crate::smt::{Event, Model, SmtResult, Solver, Sym};
+use crate::snapshot::{MemorySnapshot, RegionSnapshot};
 
-/// For now, we assume that we only deal with 64-bit architectures.
+/// Addresses are always 64-bit, which also covers 32-bit
+/// architectures.
 pub type Address = u64;
 
+/// The byte order used when converting between the bytes stored in
+/// concrete memory and bitvector values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Endianness {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endianness {
+    /// Convert bytes in address order into the most significant byte
+    /// first order used by [BV::from_bytes].
+    fn bytes_to_value_order(self, bytes: &mut [u8]) {
+        if self == Endianness::Little {
+            reverse_endianness(bytes)
+        }
+    }
+
+    /// The bytes of a bitvector in the order they are stored in
+    /// memory.
+    pub fn to_bytes<B: BV>(self, bv: B) -> Vec<u8> {
+        match self {
+            Endianness::Little => bv.to_le_bytes(),
+            Endianness::Big => bv.to_be_bytes(),
+        }
+    }
+}
+
 pub trait CustomRegion<B> {
     fn read(
         &mut self,
@@ -96,8 +129,9 @@ pub enum Region<B> {
     Symbolic(Range<Address>),
     /// A read only region of arbitrary symbolic locations intended for code
     SymbolicCode(Range<Address>),
-    /// A region of concrete read-only memory
-    Concrete(Range<Address>, HashMap<Address, u8>),
+    /// A region of concrete memory, which is updated by concrete
+    /// writes if the region's permissions allow writing
+    Concrete(Range<Address>, Pages),
     /// A custom region
     Custom(Range<Address>, Box<dyn Send + Sync + CustomRegion<B>>),
 }
@@ -115,12 +149,183 @@ impl<B> Clone for Region<B> {
     }
 }
 
+/// The kind of a memory access, used when checking region
+/// permissions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Ifetch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    pub const ALL: Permissions = Permissions { read: true, write: true, execute: true };
+    pub const READ_ONLY: Permissions = Permissions { read: true, write: false, execute: false };
+    pub const READ_WRITE: Permissions = Permissions { read: true, write: true, execute: false };
+    pub const READ_EXECUTE: Permissions = Permissions { read: true, write: false, execute: true };
+
+    pub fn allows(self, kind: AccessKind) -> bool {
+        match kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+            AccessKind::Ifetch => self.execute,
+        }
+    }
+}
+
+/// A callback invoked when an access violates a region's permissions.
+pub type AccessHook<B> = Arc<dyn Send + Sync + Fn(Address, AccessKind, &mut Solver<B>) -> Result<(), ExecError>>;
+
+/// Determines what happens when an access is not permitted by a
+/// region's permissions. `Fault` causes the access to fail with an
+/// [ExecError::AccessFault] error. A `Hook` is called with the
+/// faulting address and access kind, and the access continues as
+/// normal if it returns `Ok`.
+pub enum AccessPolicy<B> {
+    Fault,
+    Hook(AccessHook<B>),
+}
+
+impl<B> Clone for AccessPolicy<B> {
+    fn clone(&self) -> Self {
+        match self {
+            AccessPolicy::Fault => AccessPolicy::Fault,
+            AccessPolicy::Hook(hook) => AccessPolicy::Hook(hook.clone()),
+        }
+    }
+}
+
+impl<B> fmt::Debug for AccessPolicy<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessPolicy::Fault => write!(f, "Fault"),
+            AccessPolicy::Hook(_) => write!(f, "Hook(<closure>)"),
+        }
+    }
+}
+
+/// A handle to a region added to a [Memory], which can be used to
+/// remove or replace it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RegionId(usize);
+
+/// A range of addresses handled by a region, as reported by
+/// [Memory::address_map].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MappedRange {
+    pub range: Range<Address>,
+    pub id: RegionId,
+    pub kind: &'static str,
+    pub permissions: Permissions,
+}
+
+#[derive(Clone, Debug)]
+struct RegionAccess<B> {
+    permissions: Permissions,
+    policy: AccessPolicy<B>,
+}
+
+impl<B> Default for RegionAccess<B> {
+    fn default() -> Self {
+        RegionAccess { permissions: Permissions::ALL, policy: AccessPolicy::Fault }
+    }
+}
+
+/// The information passed to a watchpoint when it fires. `kind` is
+/// the read or write kind argument of the access, and `data` is the
+/// value read or written, which is `None` for a read that faulted. If
+/// the access faulted, `fault` is the error it failed with.
+pub struct WatchHit<'a, B> {
+    pub access: AccessKind,
+    pub kind: &'a Val<B>,
+    pub address: &'a Val<B>,
+    pub bytes: u32,
+    pub data: Option<&'a Val<B>>,
+    pub fault: Option<&'a ExecError>,
+    pub backtrace: &'a Backtrace,
+}
+
+pub type WatchCallback<B> = Arc<dyn Send + Sync + Fn(&WatchHit<B>)>;
+
+/// What to do when a watchpoint fires. `Log` logs the access using
+/// the `log::MEMORY` flag.
+pub enum WatchAction<B> {
+    Log,
+    Callback(WatchCallback<B>),
+}
+
+impl<B> Clone for WatchAction<B> {
+    fn clone(&self) -> Self {
+        match self {
+            WatchAction::Log => WatchAction::Log,
+            WatchAction::Callback(callback) => WatchAction::Callback(callback.clone()),
+        }
+    }
+}
+
+impl<B> fmt::Debug for WatchAction<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchAction::Log => write!(f, "Log"),
+            WatchAction::Callback(_) => write!(f, "Callback(<closure>)"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Watchpoint<B> {
+    range: Range<Address>,
+    kinds: Vec<AccessKind>,
+    action: WatchAction<B>,
+}
+
 pub enum SmtKind {
     ReadData,
     ReadInstr,
     WriteData,
 }
 
+/// Determines how [Memory::read] and [Memory::write] handle symbolic
+/// addresses.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymbolicAddressPolicy {
+    /// Treat the access as an opaque symbolic access, recorded in the
+    /// trace but otherwise unconstrained. This is the default.
+    #[default]
+    Opaque,
+    /// Case split on which region the address falls in. When more
+    /// than one region is possible the access fails with
+    /// [ExecError::SymbolicAddressFork], which the executor handles
+    /// by forking once per region and retrying the access. Regions
+    /// that need a concrete address are handled the same way, by
+    /// forking once per possible address, unless there are more than
+    /// [Memory::set_address_fork_limit] of them, in which case the
+    /// address is concretized as for `Concretize`. An access that
+    /// could straddle the boundary of a region fails on that path.
+    Fork,
+    /// Pick a single concrete address from a model and assume the
+    /// address is equal to it.
+    Concretize,
+    /// Fail if the address could overlap any region that is not
+    /// symbolic, otherwise treat it as opaque.
+    ErrorOnOverlap,
+}
+
+/// The region (by index) a symbolic address is known to fall in, if
+/// any, or whether the access partially overlaps a region.
+enum AddressCase {
+    Region(usize),
+    Straddle,
+    Unmapped,
+}
+
 impl<B> fmt::Debug for Region<B> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use Region::*;
@@ -140,7 +345,7 @@ impl<B> Region<B> {
             Region::Constrained(_, _) => "constrained",
             Region::Symbolic(_) => "symbolic",
             Region::SymbolicCode(_) => "symbolic code",
-            Region::Concrete(_, _) => "concrete",
+            Region::Concrete(_, _) => CONCRETE_MEMORY_KIND,
             Region::Custom(_, contents) => contents.memory_kind(),
         }
     }
@@ -206,7 +411,7 @@ impl<B> Clone for Box<dyn MemoryCallbacks<B>> {
     }
 }
 
-fn make_bv_bit_pair<B>(left: Val<B>, right: Val<B>) -> Val<B> {
+pub(crate) fn make_bv_bit_pair<B>(left: Val<B>, right: Val<B>) -> Val<B> {
     let mut fields = HashMap::new();
     fields.insert(ir::BV_BIT_LEFT, left);
     fields.insert(ir::BV_BIT_RIGHT, right);
@@ -216,14 +421,55 @@ fn make_bv_bit_pair<B>(left: Val<B>, right: Val<B>) -> Val<B> {
 #[derive(Clone, Debug, Default)]
 pub struct Memory<B> {
     regions: Vec<Region<B>>,
+    /// The permissions and access policy for each region, in the
+    /// same order as `regions`
+    access: Vec<RegionAccess<B>>,
+    /// The handle for each region, in the same order as `regions`
+    ids: Vec<RegionId>,
+    next_region_id: usize,
+    /// The enum member of the read_kind used for instruction fetches
+    ifetch_read_kind: Option<usize>,
+    symbolic_address_policy: SymbolicAddressPolicy,
+    /// The most addresses the `Fork` policy will fork over
+    address_fork_limit: usize,
+    endianness: Endianness,
+    watchpoints: Vec<Watchpoint<B>>,
     client_info: Option<Box<dyn MemoryCallbacks<B>>>,
 }
 
 static DEFAULT_MEMORY_KIND: &str = "default";
 
+static CONCRETE_MEMORY_KIND: &str = "concrete";
+
+pub const DEFAULT_ADDRESS_FORK_LIMIT: usize = 16;
+
 impl<B: BV> Memory<B> {
+    /// Create an empty little-endian memory. It has no instruction
+    /// fetch read_kind, so ifetch permissions are never checked;
+    /// [Memory::from_isa_config] takes both from the ISA
+    /// configuration instead.
     pub fn new() -> Self {
-        Memory { regions: Vec::new(), client_info: None }
+        Memory {
+            regions: Vec::new(),
+            access: Vec::new(),
+            ids: Vec::new(),
+            next_region_id: 0,
+            ifetch_read_kind: None,
+            symbolic_address_policy: SymbolicAddressPolicy::Opaque,
+            address_fork_limit: DEFAULT_ADDRESS_FORK_LIMIT,
+            endianness: Endianness::Little,
+            watchpoints: Vec::new(),
+            client_info: None,
+        }
+    }
+
+    /// Create an empty memory using the byte order and instruction
+    /// fetch read_kind from the ISA configuration.
+    pub fn from_isa_config(isa_config: &ISAConfig<B>, shared_state: &ir::SharedState<B>) -> Self {
+        let mut memory = Memory::new();
+        memory.endianness = isa_config.endianness;
+        memory.ifetch_read_kind = shared_state.enum_member(isa_config.ifetch_read_kind);
+        memory
     }
 
     pub fn kind_at(&self, addr: Address) -> &'static str {
@@ -267,24 +513,311 @@ impl<B: BV> Memory<B> {
         None
     }
 
-    pub fn add_region(&mut self, region: Region<B>) {
-        self.regions.push(region)
+    /// Add a region which permits all accesses. Regions may overlap,
+    /// in which case the region added first handles the addresses
+    /// they share.
+    pub fn add_region(&mut self, region: Region<B>) -> RegionId {
+        self.add_region_with_access(region, Permissions::ALL, AccessPolicy::Fault)
+    }
+
+    /// Add a region with the given permissions. Accesses that are not
+    /// permitted are handled according to the access policy. As with
+    /// [Memory::add_region], an overlapping region is shadowed by
+    /// those added before it.
+    pub fn add_region_with_access(
+        &mut self,
+        region: Region<B>,
+        permissions: Permissions,
+        policy: AccessPolicy<B>,
+    ) -> RegionId {
+        if let Some(existing) = self.overlapping_region(region.region_range(), None) {
+            log!(
+                log::MEMORY,
+                &format!("Region {:?} is shadowed by existing region {:?}", region.region_range(), existing)
+            )
+        }
+        let id = RegionId(self.next_region_id);
+        self.next_region_id += 1;
+        self.regions.push(region);
+        self.access.push(RegionAccess { permissions, policy });
+        self.ids.push(id);
+        id
+    }
+
+    /// Like [Memory::add_region], but fails with a `RegionOverlap`
+    /// error rather than adding a region which overlaps an existing
+    /// region.
+    pub fn try_add_region(&mut self, region: Region<B>) -> Result<RegionId, ExecError> {
+        self.try_add_region_with_access(region, Permissions::ALL, AccessPolicy::Fault)
+    }
+
+    /// Like [Memory::add_region_with_access], but fails with a
+    /// `RegionOverlap` error rather than adding a region which
+    /// overlaps an existing region.
+    pub fn try_add_region_with_access(
+        &mut self,
+        region: Region<B>,
+        permissions: Permissions,
+        policy: AccessPolicy<B>,
+    ) -> Result<RegionId, ExecError> {
+        if let Some(existing) = self.overlapping_region(region.region_range(), None) {
+            return Err(ExecError::RegionOverlap { new: region.region_range().clone(), existing });
+        }
+        Ok(self.add_region_with_access(region, permissions, policy))
+    }
+
+    /// Returns the range of the first region (ignoring `except`)
+    /// which overlaps the given range.
+    fn overlapping_region(&self, range: &Range<Address>, except: Option<usize>) -> Option<Range<Address>> {
+        self.regions
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != except)
+            .map(|(_, region)| region.region_range())
+            .find(|existing| existing.start < range.end && range.start < existing.end)
+            .cloned()
+    }
+
+    fn region_index(&self, id: RegionId) -> Option<usize> {
+        self.ids.iter().position(|other| *other == id)
+    }
+
+    pub fn region(&self, id: RegionId) -> Option<&Region<B>> {
+        self.region_index(id).map(|i| &self.regions[i])
+    }
+
+    /// Remove a region, returning it if it was present.
+    pub fn remove_region(&mut self, id: RegionId) -> Option<Region<B>> {
+        let i = self.region_index(id)?;
+        self.access.remove(i);
+        self.ids.remove(i);
+        Some(self.regions.remove(i))
+    }
+
+    /// Replace a region with a new region, which keeps the same
+    /// handle, precedence and permissions. Returns the old region.
+    /// Fails if the new region would overlap any other region, or
+    /// with an `Unmapped` error if there is no region with the handle.
+    pub fn replace_region(&mut self, id: RegionId, region: Region<B>) -> Result<Region<B>, ExecError> {
+        let i = self.region_index(id).ok_or(ExecError::Unmapped)?;
+        if let Some(existing) = self.overlapping_region(region.region_range(), Some(i)) {
+            return Err(ExecError::RegionOverlap { new: region.region_range().clone(), existing });
+        }
+        Ok(std::mem::replace(&mut self.regions[i], region))
+    }
+
+    /// Returns the effective address map, i.e. which region handles
+    /// each address once regions shadowed by earlier regions are
+    /// taken into account, in address order. Addresses not covered by
+    /// any range are not mapped by any region.
+    pub fn address_map(&self) -> impl Iterator<Item = MappedRange> {
+        let mut covered: Vec<Range<Address>> = Vec::new();
+        let mut map = Vec::new();
+
+        for (i, region) in self.regions.iter().enumerate() {
+            let mut uncovered = vec![region.region_range().clone()];
+            for used in &covered {
+                uncovered = uncovered
+                    .drain(..)
+                    .flat_map(|r| vec![r.start..u64::min(r.end, used.start), u64::max(r.start, used.end)..r.end])
+                    .filter(|r| r.start < r.end)
+                    .collect()
+            }
+            for range in uncovered {
+                map.push(MappedRange {
+                    range,
+                    id: self.ids[i],
+                    kind: region.memory_kind(),
+                    permissions: self.access[i].permissions,
+                })
+            }
+            covered.push(region.region_range().clone())
+        }
+
+        map.sort_by_key(|mapped| mapped.range.start);
+        map.into_iter()
+    }
+
+    // The region helpers below permit all accesses, except for
+    // symbolic code and the read-only variants, which fault on
+    // writes. Like add_region they do not check for overlaps.
+    // Instruction fetches are only checked once the ifetch read_kind
+    // is set, either by set_ifetch_read_kind or from_isa_config.
+
+    pub fn add_symbolic_region(&mut self, range: Range<Address>) -> RegionId {
+        self.add_region(Region::Symbolic(range))
+    }
+
+    /// Add a read-only symbolic region for code, which faults on
+    /// writes.
+    pub fn add_symbolic_code_region(&mut self, range: Range<Address>) -> RegionId {
+        self.add_region_with_access(Region::SymbolicCode(range), Permissions::READ_EXECUTE, AccessPolicy::Fault)
+    }
+
+    /// Add a concrete region whose contents are updated by concrete
+    /// writes.
+    pub fn add_concrete_region(&mut self, range: Range<Address>, contents: HashMap<Address, u8>) -> RegionId {
+        self.add_region(Region::Concrete(range, contents.into()))
+    }
+
+    /// Add a read-only concrete region, which faults on writes.
+    pub fn add_read_only_concrete_region(&mut self, range: Range<Address>, contents: HashMap<Address, u8>) -> RegionId {
+        let region = Region::Concrete(range, contents.into());
+        self.add_region_with_access(region, Permissions::READ_EXECUTE, AccessPolicy::Fault)
+    }
+
+    pub fn add_custom_region(
+        &mut self,
+        range: Range<Address>,
+        contents: Box<dyn Send + Sync + CustomRegion<B>>,
+    ) -> RegionId {
+        self.add_region(Region::Custom(range, contents))
+    }
+
+    /// Add a concrete region where every byte is initially zero.
+    pub fn add_zero_region(&mut self, range: Range<Address>) -> RegionId {
+        self.add_region(Region::Concrete(range, Pages::new()))
+    }
+
+    /// Add a read-only concrete region where every byte is zero,
+    /// which faults on writes.
+    pub fn add_read_only_zero_region(&mut self, range: Range<Address>) -> RegionId {
+        let region = Region::Concrete(range, Pages::new());
+        self.add_region_with_access(region, Permissions::READ_EXECUTE, AccessPolicy::Fault)
+    }
+
+    /// Set the read_kind enum member that identifies instruction
+    /// fetches, which must be permitted to execute from the region
+    /// they read. [Memory::from_isa_config] sets this from the
+    /// [ISAConfig].
+    pub fn set_ifetch_read_kind(&mut self, member: usize) {
+        self.ifetch_read_kind = Some(member)
+    }
+
+    /// Set the byte order used for concrete memory. [Memory::from_isa_config]
+    /// sets this from [ISAConfig::endianness].
+    pub fn set_endianness(&mut self, endianness: Endianness) {
+        self.endianness = endianness
+    }
+
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+
+    pub fn set_symbolic_address_policy(&mut self, policy: SymbolicAddressPolicy) {
+        self.symbolic_address_policy = policy
     }
 
-    pub fn add_symbolic_region(&mut self, range: Range<Address>) {
-        self.regions.push(Region::Symbolic(range))
+    /// Set the most addresses the `Fork` policy will fork over when
+    /// an access to a region needs a concrete address. The default is
+    /// [DEFAULT_ADDRESS_FORK_LIMIT].
+    pub fn set_address_fork_limit(&mut self, limit: usize) {
+        self.address_fork_limit = limit
     }
 
-    pub fn add_symbolic_code_region(&mut self, range: Range<Address>) {
-        self.regions.push(Region::SymbolicCode(range))
+    fn is_ifetch(&self, read_kind: &Val<B>) -> bool {
+        match (self.ifetch_read_kind, read_kind) {
+            (Some(ifetch), Val::Enum(e)) => e.member == ifetch,
+            _ => false,
+        }
+    }
+
+    /// Check the permissions of every region an access of `bytes`
+    /// bytes at `address` touches. The region that applies to each
+    /// byte can only change at a region boundary, so we check the
+    /// region for the first byte of each stretch between boundaries.
+    fn check_access(
+        &self,
+        address: Address,
+        bytes: u32,
+        kind: AccessKind,
+        solver: &mut Solver<B>,
+    ) -> Result<(), ExecError> {
+        let end = address.saturating_add(u64::from(bytes.max(1)));
+        let mut current = address;
+        while current < end {
+            let region = self.regions.iter().zip(self.access.iter()).find(|(r, _)| r.region_range().contains(&current));
+            if let Some((_, access)) = region {
+                if !access.permissions.allows(kind) {
+                    log!(log::MEMORY, &format!("Access fault: {:?} at 0x{:x}", kind, current));
+                    match &access.policy {
+                        AccessPolicy::Fault => return Err(ExecError::AccessFault { address: current, kind }),
+                        AccessPolicy::Hook(hook) => hook(current, kind, solver)?,
+                    }
+                }
+            }
+            current = self
+                .regions
+                .iter()
+                .flat_map(|r| [r.region_range().start, r.region_range().end])
+                .filter(|boundary| *boundary > current)
+                .fold(end, u64::min)
+        }
+        Ok(())
+    }
+
+    /// Add a watchpoint which fires whenever an access of one of the
+    /// given kinds overlaps the range, after the access has been
+    /// performed or has faulted. Accesses with a symbolic address fire
+    /// the watchpoint if they could overlap the range.
+    pub fn add_watchpoint(&mut self, range: Range<Address>, kinds: &[AccessKind], action: WatchAction<B>) {
+        self.watchpoints.push(Watchpoint { range, kinds: kinds.to_vec(), action })
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear()
     }
 
-    pub fn add_concrete_region(&mut self, range: Range<Address>, contents: HashMap<Address, u8>) {
-        self.regions.push(Region::Concrete(range, contents))
+    fn watch_overlaps(range: &Range<Address>, address: &Val<B>, bytes: u32, solver: &mut Solver<B>) -> bool {
+        use Exp::*;
+        match address {
+            Val::Bits(bv) => bv.lower_u64() < range.end && range.start < bv.lower_u64().saturating_add(bytes as u64),
+            Val::Symbolic(v) => {
+                let v = &match address_64(*v, solver) {
+                    Ok(v) => v,
+                    Err(_) => return true,
+                };
+                let in_range = And(
+                    Box::new(Bvult(Box::new(Var(*v)), Box::new(Bits64(range.end, 64)))),
+                    Box::new(Bvult(
+                        Box::new(ZeroExtend(1, Box::new(Bits64(range.start, 64)))),
+                        Box::new(Bvadd(
+                            Box::new(ZeroExtend(1, Box::new(Var(*v)))),
+                            Box::new(ZeroExtend(1, Box::new(Bits64(bytes as u64, 64)))),
+                        )),
+                    )),
+                );
+                // If the solver can't decide we treat it as a possible overlap
+                solver.check_sat_with(&in_range) != SmtResult::Unsat
+            }
+            _ => false,
+        }
     }
 
-    pub fn add_zero_region(&mut self, range: Range<Address>) {
-        self.regions.push(Region::Concrete(range, HashMap::new()))
+    fn fire_watchpoints(&self, hit: &WatchHit<B>, solver: &mut Solver<B>) {
+        for watchpoint in &self.watchpoints {
+            if watchpoint.kinds.contains(&hit.access)
+                && Self::watch_overlaps(&watchpoint.range, hit.address, hit.bytes, solver)
+            {
+                match &watchpoint.action {
+                    WatchAction::Log => log!(
+                        log::MEMORY,
+                        &format!(
+                            "Watchpoint [0x{:x}, 0x{:x}) {:?}: {:?} {:?} {:?} fault: {:?} backtrace: {:?}",
+                            watchpoint.range.start,
+                            watchpoint.range.end,
+                            hit.access,
+                            hit.kind,
+                            hit.address,
+                            hit.data,
+                            hit.fault,
+                            hit.backtrace
+                        )
+                    ),
+                    WatchAction::Callback(callback) => callback(hit),
+                }
+            }
+        }
     }
 
     pub fn set_client_info(&mut self, info: Box<dyn MemoryCallbacks<B>>) {
@@ -295,13 +828,14 @@ impl<B: BV> Memory<B> {
         for region in &mut self.regions {
             match region {
                 Region::Concrete(range, contents) if range.contains(&address) => {
-                    contents.insert(address, byte);
+                    contents.write_byte(address, byte);
                     return;
                 }
                 _ => (),
             }
         }
-        self.regions.push(Region::Concrete(address..address, vec![(address, byte)].into_iter().collect()))
+        let region = Region::Concrete(address..address, vec![(address, byte)].into_iter().collect());
+        self.add_region(region);
     }
 
     fn read_initial_byte(&self, address: Address) -> Result<u8, ExecError> {
@@ -312,7 +846,10 @@ impl<B: BV> Memory<B> {
                     return Err(ExecError::BadRead("symbolic initial byte"))
                 }
                 Concrete(range, contents) if range.contains(&address) => {
-                    return Ok(contents.get(&address).copied().unwrap_or(0))
+                    return match contents.symbolic_byte(address) {
+                        Some(_) => Err(ExecError::BadRead("symbolic initial byte")),
+                        None => Ok(contents.read_byte(address)),
+                    }
                 }
                 Custom(range, contents) if range.contains(&address) => {
                     return contents
@@ -326,34 +863,79 @@ impl<B: BV> Memory<B> {
         Err(ExecError::BadRead("symbolic initial byte (no region)"))
     }
 
-    pub fn read_initial(&self, address: Address, bytes: u32) -> Result<Val<B>, ExecError> {
+    fn read_initial_bytes(&self, address: Address, bytes: u32) -> Result<Vec<u8>, ExecError> {
+        let end = address.checked_add(u64::from(bytes)).ok_or(ExecError::BadRead("initial read wraps around"))?;
+
+        // Custom regions such as device registers may only provide
+        // initial values for whole accesses, so ask for the value in
+        // one go rather than byte by byte.
+        for region in &self.regions {
+            if let Region::Custom(range, contents) = region {
+                if range.contains(&address) {
+                    if end > range.end {
+                        return Err(ExecError::BadRead("initial read crosses the end of a custom region"));
+                    }
+                    return contents
+                        .initial_value(address, bytes)
+                        .filter(|bv| bv.len() == 8 * bytes)
+                        .map(B::to_be_bytes)
+                        .ok_or(ExecError::BadRead("read of initial value from custom region failed"));
+                }
+            }
+        }
+
         let mut byte_vec: Vec<u8> = Vec::with_capacity(bytes as usize);
-        for i in address..(address + u64::from(bytes)) {
+        for i in address..end {
             byte_vec.push(self.read_initial_byte(i)?)
         }
 
-        reverse_endianness(&mut byte_vec);
+        self.endianness.bytes_to_value_order(&mut byte_vec);
+        Ok(byte_vec)
+    }
+
+    /// Read the initial value of memory at an address. Reads from
+    /// custom regions ask the region for the initial value of the
+    /// whole access. The result is a concrete bitvector, so reads
+    /// wider than `B::MAX_WIDTH` bits are not supported and return
+    /// `BadRead`; use [Memory::read_initial_wide] for those, which
+    /// assembles the bytes into an SMT bitvector.
+    pub fn read_initial(&self, address: Address, bytes: u32) -> Result<Val<B>, ExecError> {
+        let byte_vec = self.read_initial_bytes(address, bytes)?;
 
-        if byte_vec.len() <= 8 {
+        if byte_vec.len() * 8 <= B::MAX_WIDTH as usize {
             Ok(Val::Bits(B::from_bytes(&byte_vec)))
         } else {
-            Err(ExecError::BadRead("initial read greater than 8 bytes"))
+            Err(ExecError::BadRead("initial read wider than the maximum concrete bitvector width"))
         }
     }
 
-    fn check_overlap(&self, address: Sym, error: ExecError, solver: &mut Solver<B>) -> Result<(), ExecError> {
+    /// Like [Memory::read_initial], but values wider than
+    /// `B::MAX_WIDTH` are returned as a symbolic variable defined in
+    /// the solver to be the concatenation of the bytes read.
+    pub fn read_initial_wide(&self, address: Address, bytes: u32, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+        let byte_vec = self.read_initial_bytes(address, bytes)?;
+        Ok(bytes_to_val(&byte_vec, solver))
+    }
+
+    /// Fail with the given error if an access of `bytes` bytes at the
+    /// symbolic address could overlap a region that is not symbolic.
+    fn check_overlap(
+        &self,
+        address: Sym,
+        bytes: u32,
+        error: ExecError,
+        solver: &mut Solver<B>,
+    ) -> Result<(), ExecError> {
         use Exp::*;
         use SmtResult::*;
 
         let mut region_constraints = Vec::new();
 
         for region in &self.regions {
-            let Range { start, end } = region.region_range();
-
-            region_constraints.push(And(
-                Box::new(Bvule(Box::new(Bits64(*start, 64)), Box::new(Var(address)))),
-                Box::new(Bvult(Box::new(Var(address)), Box::new(Bits64(*end, 64)))),
-            ))
+            if let Region::Symbolic(_) | Region::SymbolicCode(_) = region {
+                continue;
+            }
+            region_constraints.push(smt_overlap_constraint(address, bytes, region.region_range()))
         }
 
         if let Some(r) = region_constraints.pop() {
@@ -371,9 +953,117 @@ impl<B: BV> Memory<B> {
         Ok(())
     }
 
+    /// Get a possible value for a 64-bit symbolic address from a model.
+    fn address_value(&self, address: Sym, solver: &mut Solver<B>) -> Result<Address, ExecError> {
+        if solver.check_sat().is_unsat()? {
+            return Err(ExecError::Dead);
+        }
+
+        let mut model = Model::new(solver);
+        match model.get_var(address)? {
+            Some(value) => value_address(&value),
+            None => Err(ExecError::Z3Error(format!("No value for address v{}", address))),
+        }
+    }
+
+    /// Replace a 64-bit symbolic address with a concrete value from a
+    /// model, assuming the address is equal to that value from now on.
+    fn concretize_address(&self, address: Sym, solver: &mut Solver<B>) -> Result<B, ExecError> {
+        use Exp::*;
+
+        let value = self.address_value(address, solver)?;
+        log!(log::MEMORY, &format!("Concretized address v{} to 0x{:x}", address, value));
+        solver.assert_eq(Var(address), Bits64(value, 64));
+        Ok(B::from_u64(value))
+    }
+
+    /// Like [Memory::concretize_address], but if the address could
+    /// take several values this returns an
+    /// [ExecError::SymbolicAddressFork] error with a case for each of
+    /// them. If there are more than the address fork limit the
+    /// address is concretized instead.
+    fn fork_address(&self, address: Sym, solver: &mut Solver<B>) -> Result<B, ExecError> {
+        use Exp::*;
+
+        let mut values = solver.enumerate(address, self.address_fork_limit + 1)?;
+        match values.len() {
+            0 => Err(ExecError::Dead),
+            1 => Ok(B::from_u64(value_address(&values[0])?)),
+            n if n <= self.address_fork_limit => Err(ExecError::SymbolicAddressFork(
+                values.drain(..).map(|value| Eq(Box::new(Var(address)), Box::new(value))).collect(),
+            )),
+            _ => {
+                log!(log::MEMORY, &format!("Too many possible values for address v{} to fork", address));
+                self.concretize_address(address, solver)
+            }
+        }
+    }
+
+    /// Determine which region a symbolic address falls in. Each
+    /// region is a separate case (with earlier regions taking
+    /// precedence when they overlap), as is the access partially
+    /// overlapping a region without being contained in any, and the
+    /// address falling outside every region. If more than one case is
+    /// possible this returns an [ExecError::SymbolicAddressFork] error
+    /// with the constraint for each case.
+    fn address_case(&self, address: Sym, bytes: u32, solver: &mut Solver<B>) -> Result<AddressCase, ExecError> {
+        use Exp::*;
+
+        let mut cases = Vec::new();
+        let mut earlier = Bool(false);
+
+        for (i, region) in self.regions.iter().enumerate() {
+            let in_region = smt_address_constraint(
+                std::slice::from_ref(region),
+                &Var(address),
+                bytes,
+                SmtKind::ReadData,
+                solver,
+                None,
+            );
+            let case = And(Box::new(in_region.clone()), Box::new(Not(Box::new(earlier.clone()))));
+            if solver.check_sat_with(&case).is_sat()? {
+                cases.push((AddressCase::Region(i), case))
+            }
+            earlier = Or(Box::new(earlier), Box::new(in_region))
+        }
+
+        let overlaps = self
+            .regions
+            .iter()
+            .map(|region| smt_overlap_constraint(address, bytes, region.region_range()))
+            .fold(Bool(false), |acc, overlap| Or(Box::new(acc), Box::new(overlap)));
+
+        let straddle = And(Box::new(overlaps.clone()), Box::new(Not(Box::new(earlier))));
+        if solver.check_sat_with(&straddle).is_sat()? {
+            cases.push((AddressCase::Straddle, straddle))
+        }
+
+        let unmapped = Not(Box::new(overlaps));
+        if solver.check_sat_with(&unmapped).is_sat()? {
+            cases.push((AddressCase::Unmapped, unmapped))
+        }
+
+        match cases.len() {
+            0 => Err(ExecError::Dead),
+            1 => Ok(cases.pop().unwrap().0),
+            _ => Err(ExecError::SymbolicAddressFork(cases.drain(..).map(|(_, case)| case).collect())),
+        }
+    }
+
+    /// Under the `Fork` policy, accesses to regions other than
+    /// symbolic regions with full permissions need a concrete address.
+    fn needs_concrete_address(&self, region: usize, kind: AccessKind) -> bool {
+        match self.regions[region] {
+            Region::Symbolic(_) | Region::SymbolicCode(_) => !self.access[region].permissions.allows(kind),
+            _ => true,
+        }
+    }
+
     /// Read from the memory region determined by the address. If the address is symbolic the read
     /// value is always also symbolic. The number of bytes must be concrete otherwise will return a
-    /// SymbolicLength error.
+    /// SymbolicLength error. Reads wider than `B::MAX_WIDTH` from concrete memory are returned as
+    /// a symbolic variable defined to be the concatenation of the bytes read.
     ///
     /// # Panics
     ///
@@ -386,6 +1076,87 @@ impl<B: BV> Memory<B> {
         bytes: Val<B>,
         solver: &mut Solver<B>,
         tag: bool,
+    ) -> Result<Val<B>, ExecError> {
+        self.read_with_backtrace(read_kind, address, bytes, solver, tag, &Vec::new())
+    }
+
+    /// Like [Memory::read], but with the current backtrace which is
+    /// passed to any watchpoints that fire.
+    pub fn read_with_backtrace(
+        &mut self,
+        read_kind: Val<B>,
+        address: Val<B>,
+        bytes: Val<B>,
+        solver: &mut Solver<B>,
+        tag: bool,
+        backtrace: &Backtrace,
+    ) -> Result<Val<B>, ExecError> {
+        if self.watchpoints.is_empty() {
+            return self.read_unwatched(read_kind, address, bytes, solver, tag);
+        }
+
+        let result = self.read_unwatched(read_kind.clone(), address.clone(), bytes.clone(), solver, tag);
+        let (data, fault) = match &result {
+            Ok(value) => (Some(value), None),
+            Err(err) if is_fault(err) => (None, Some(err)),
+            Err(_) => return result,
+        };
+        let access = if self.is_ifetch(&read_kind) { AccessKind::Ifetch } else { AccessKind::Read };
+        let bytes = match bytes {
+            Val::I128(bytes) => bytes as u32,
+            _ => unreachable!("read with a symbolic length did not fail with SymbolicLength"),
+        };
+        let hit = WatchHit { access, kind: &read_kind, address: &address, bytes, data, fault, backtrace };
+        self.fire_watchpoints(&hit, solver);
+        result
+    }
+
+    pub fn write(
+        &mut self,
+        write_kind: Val<B>,
+        address: Val<B>,
+        data: Val<B>,
+        solver: &mut Solver<B>,
+        tag: Option<Val<B>>,
+    ) -> Result<Val<B>, ExecError> {
+        self.write_with_backtrace(write_kind, address, data, solver, tag, &Vec::new())
+    }
+
+    /// Like [Memory::write], but with the current backtrace which is
+    /// passed to any watchpoints that fire.
+    pub fn write_with_backtrace(
+        &mut self,
+        write_kind: Val<B>,
+        address: Val<B>,
+        data: Val<B>,
+        solver: &mut Solver<B>,
+        tag: Option<Val<B>>,
+        backtrace: &Backtrace,
+    ) -> Result<Val<B>, ExecError> {
+        if self.watchpoints.is_empty() {
+            return self.write_unwatched(write_kind, address, data, solver, tag);
+        }
+
+        let result = self.write_unwatched(write_kind.clone(), address.clone(), data.clone(), solver, tag);
+        let fault = match &result {
+            Ok(_) => None,
+            Err(err) if is_fault(err) => Some(err),
+            Err(_) => return result,
+        };
+        let bytes = crate::primop::length_bits(&data, solver)? / 8;
+        let access = AccessKind::Write;
+        let hit = WatchHit { access, kind: &write_kind, address: &address, bytes, data: Some(&data), fault, backtrace };
+        self.fire_watchpoints(&hit, solver);
+        result
+    }
+
+    fn read_unwatched(
+        &mut self,
+        read_kind: Val<B>,
+        address: Val<B>,
+        bytes: Val<B>,
+        solver: &mut Solver<B>,
+        tag: bool,
     ) -> Result<Val<B>, ExecError> {
         log!(log::MEMORY, &format!("Read: {:?} {:?} {:?} {:?}", read_kind, address, bytes, tag));
 
@@ -393,67 +1164,115 @@ impl<B: BV> Memory<B> {
             let bytes = u32::try_from(bytes).expect("Bytes did not fit in u32 in memory read");
 
             match address {
-                Val::Bits(concrete_addr) => {
-                    for region in &mut self.regions {
-                        let memory_kind = region.memory_kind();
-                        match region {
-                            Region::Constrained(range, generator) if range.contains(&concrete_addr.lower_u64()) => {
-                                return read_constrained(
-                                    range,
-                                    generator.as_ref(),
-                                    read_kind,
-                                    concrete_addr.lower_u64(),
-                                    bytes,
-                                    solver,
-                                    tag,
-                                    memory_kind,
-                                )
-                            }
+                Val::Bits(concrete_addr) => self.read_concrete_address(read_kind, concrete_addr, bytes, solver, tag),
 
-                            Region::Symbolic(range) if range.contains(&concrete_addr.lower_u64()) => {
-                                return self.read_symbolic(read_kind, address, bytes, solver, tag, memory_kind)
-                            }
+                Val::Symbolic(symbolic_addr) => match self.symbolic_address_policy {
+                    SymbolicAddressPolicy::Opaque => {
+                        self.read_symbolic(read_kind, address, bytes, solver, tag, DEFAULT_MEMORY_KIND)
+                    }
 
-                            Region::SymbolicCode(range) if range.contains(&concrete_addr.lower_u64()) => {
-                                return self.read_symbolic(read_kind, address, bytes, solver, tag, memory_kind)
-                            }
+                    SymbolicAddressPolicy::ErrorOnOverlap => {
+                        let symbolic_addr = address_64(symbolic_addr, solver)?;
+                        let error = ExecError::BadRead("possible symbolic address overlap");
+                        self.check_overlap(symbolic_addr, bytes, error, solver)?;
+                        self.read_symbolic(read_kind, address, bytes, solver, tag, DEFAULT_MEMORY_KIND)
+                    }
 
-                            Region::Concrete(range, contents) if range.contains(&concrete_addr.lower_u64()) => {
-                                return read_concrete(
-                                    contents,
-                                    read_kind,
-                                    concrete_addr.lower_u64(),
-                                    bytes,
-                                    solver,
-                                    tag,
-                                    memory_kind,
-                                )
-                            }
+                    SymbolicAddressPolicy::Concretize => {
+                        let symbolic_addr = address_64(symbolic_addr, solver)?;
+                        let concrete_addr = self.concretize_address(symbolic_addr, solver)?;
+                        self.read_concrete_address(read_kind, concrete_addr, bytes, solver, tag)
+                    }
 
-                            Region::Custom(range, contents) if range.contains(&concrete_addr.lower_u64()) => {
-                                return contents.read(read_kind, concrete_addr.lower_u64(), bytes, solver, tag)
+                    SymbolicAddressPolicy::Fork => {
+                        let symbolic_addr = address_64(symbolic_addr, solver)?;
+                        let access_kind =
+                            if self.is_ifetch(&read_kind) { AccessKind::Ifetch } else { AccessKind::Read };
+                        match self.address_case(symbolic_addr, bytes, solver)? {
+                            AddressCase::Region(i) if self.needs_concrete_address(i, access_kind) => {
+                                let concrete_addr = self.fork_address(symbolic_addr, solver)?;
+                                self.read_concrete_address(read_kind, concrete_addr, bytes, solver, tag)
+                            }
+                            AddressCase::Region(i) => {
+                                let memory_kind = self.regions[i].memory_kind();
+                                self.read_symbolic(read_kind, address, bytes, solver, tag, memory_kind)
+                            }
+                            AddressCase::Straddle => Err(ExecError::BadRead("access straddles a region boundary")),
+                            AddressCase::Unmapped => {
+                                self.read_symbolic(read_kind, address, bytes, solver, tag, DEFAULT_MEMORY_KIND)
                             }
-
-                            _ => continue,
                         }
                     }
+                },
+
+                _ => Err(ExecError::Type("Non bitvector address in read".to_string())),
+            }
+        } else {
+            Err(ExecError::SymbolicLength("read_symbolic"))
+        }
+    }
+
+    fn read_concrete_address(
+        &mut self,
+        read_kind: Val<B>,
+        concrete_addr: B,
+        bytes: u32,
+        solver: &mut Solver<B>,
+        tag: bool,
+    ) -> Result<Val<B>, ExecError> {
+        let access_kind = if self.is_ifetch(&read_kind) { AccessKind::Ifetch } else { AccessKind::Read };
+        self.check_access(concrete_addr.lower_u64(), bytes, access_kind, solver)?;
+
+        let endianness = self.endianness;
+        for region in &mut self.regions {
+            let memory_kind = region.memory_kind();
+            match region {
+                Region::Constrained(range, generator) if range.contains(&concrete_addr.lower_u64()) => {
+                    return read_constrained(
+                        range,
+                        generator.as_ref(),
+                        read_kind,
+                        concrete_addr.lower_u64(),
+                        bytes,
+                        solver,
+                        tag,
+                        memory_kind,
+                    )
+                }
 
-                    self.read_symbolic(read_kind, address, bytes, solver, tag, DEFAULT_MEMORY_KIND)
+                Region::Symbolic(range) if range.contains(&concrete_addr.lower_u64()) => {
+                    return self.read_symbolic(read_kind, Val::Bits(concrete_addr), bytes, solver, tag, memory_kind)
                 }
 
-                Val::Symbolic(symbolic_addr) => {
-                    //self.check_overlap(symbolic_addr, ExecError::BadRead("possible symbolic address overlap"), solver)?;
-                    self.read_symbolic(read_kind, address, bytes, solver, tag, DEFAULT_MEMORY_KIND)
+                Region::SymbolicCode(range) if range.contains(&concrete_addr.lower_u64()) => {
+                    return self.read_symbolic(read_kind, Val::Bits(concrete_addr), bytes, solver, tag, memory_kind)
                 }
 
-                _ => Err(ExecError::Type("Non bitvector address in read".to_string())),
+                Region::Concrete(range, contents) if range.contains(&concrete_addr.lower_u64()) => {
+                    return read_concrete(
+                        contents,
+                        endianness,
+                        read_kind,
+                        concrete_addr.lower_u64(),
+                        bytes,
+                        solver,
+                        tag,
+                        memory_kind,
+                    )
+                }
+
+                Region::Custom(range, contents) if range.contains(&concrete_addr.lower_u64()) => {
+                    return contents.read(read_kind, concrete_addr.lower_u64(), bytes, solver, tag)
+                }
+
+                _ => continue,
             }
-        } else {
-            Err(ExecError::SymbolicLength("read_symbolic"))
         }
+
+        self.read_symbolic(read_kind, Val::Bits(concrete_addr), bytes, solver, tag, DEFAULT_MEMORY_KIND)
     }
 
-    pub fn write(
+    fn write_unwatched(
         &mut self,
         write_kind: Val<B>,
         address: Val<B>,
@@ -463,29 +1282,93 @@ impl<B: BV> Memory<B> {
     ) -> Result<Val<B>, ExecError> {
         log!(log::MEMORY, &format!("Write: {:?} {:?} {:?} {:?}", write_kind, address, data, tag));
         match address {
-            Val::Bits(concrete_addr) => {
-                for region in self.regions.iter_mut() {
-                    match region {
-                        Region::Custom(range, contents) if range.contains(&concrete_addr.lower_u64()) => {
-                            return contents.write(write_kind, concrete_addr.lower_u64(), data, solver, tag)
-                        }
+            Val::Bits(concrete_addr) => self.write_concrete_address(write_kind, concrete_addr, data, solver, tag),
 
-                        _ => continue,
-                    }
+            Val::Symbolic(symbolic_addr) => match self.symbolic_address_policy {
+                SymbolicAddressPolicy::Opaque => {
+                    self.write_symbolic(write_kind, address, data, solver, tag, DEFAULT_MEMORY_KIND)
                 }
 
-                self.write_symbolic(write_kind, address, data, solver, tag, DEFAULT_MEMORY_KIND)
-            }
+                SymbolicAddressPolicy::ErrorOnOverlap => {
+                    let symbolic_addr = address_64(symbolic_addr, solver)?;
+                    let bytes = crate::primop::length_bits(&data, solver)? / 8;
+                    let error = ExecError::BadWrite("possible symbolic address overlap");
+                    self.check_overlap(symbolic_addr, bytes, error, solver)?;
+                    self.write_symbolic(write_kind, address, data, solver, tag, DEFAULT_MEMORY_KIND)
+                }
 
-            Val::Symbolic(symbolic_addr) => {
-                //self.check_overlap(symbolic_addr, ExecError::BadWrite("possible symbolic address overlap"), solver)?;
-                self.write_symbolic(write_kind, address, data, solver, tag, DEFAULT_MEMORY_KIND)
-            }
+                SymbolicAddressPolicy::Concretize => {
+                    let symbolic_addr = address_64(symbolic_addr, solver)?;
+                    let concrete_addr = self.concretize_address(symbolic_addr, solver)?;
+                    self.write_concrete_address(write_kind, concrete_addr, data, solver, tag)
+                }
+
+                SymbolicAddressPolicy::Fork => {
+                    let symbolic_addr = address_64(symbolic_addr, solver)?;
+                    let bytes = crate::primop::length_bits(&data, solver)? / 8;
+                    match self.address_case(symbolic_addr, bytes, solver)? {
+                        AddressCase::Region(i) if self.needs_concrete_address(i, AccessKind::Write) => {
+                            let concrete_addr = self.fork_address(symbolic_addr, solver)?;
+                            self.write_concrete_address(write_kind, concrete_addr, data, solver, tag)
+                        }
+                        AddressCase::Region(i) => {
+                            let memory_kind = self.regions[i].memory_kind();
+                            self.write_symbolic(write_kind, address, data, solver, tag, memory_kind)
+                        }
+                        AddressCase::Straddle => Err(ExecError::BadWrite("access straddles a region boundary")),
+                        AddressCase::Unmapped => {
+                            self.write_symbolic(write_kind, address, data, solver, tag, DEFAULT_MEMORY_KIND)
+                        }
+                    }
+                }
+            },
 
             _ => Err(ExecError::Type("Non bitvector address in write".to_string())),
         }
     }
 
+    fn write_concrete_address(
+        &mut self,
+        write_kind: Val<B>,
+        concrete_addr: B,
+        data: Val<B>,
+        solver: &mut Solver<B>,
+        tag: Option<Val<B>>,
+    ) -> Result<Val<B>, ExecError> {
+        let bytes = crate::primop::length_bits(&data, solver)? / 8;
+        self.check_access(concrete_addr.lower_u64(), bytes, AccessKind::Write, solver)?;
+
+        let endianness = self.endianness;
+        for (region, access) in self.regions.iter_mut().zip(self.access.iter()) {
+            let memory_kind = region.memory_kind();
+            match region {
+                Region::Concrete(range, contents)
+                    if access.permissions.write && range.contains(&concrete_addr.lower_u64()) =>
+                {
+                    return write_concrete(
+                        contents,
+                        range.end,
+                        endianness,
+                        write_kind,
+                        concrete_addr.lower_u64(),
+                        data,
+                        solver,
+                        tag,
+                        memory_kind,
+                    )
+                }
+
+                Region::Custom(range, contents) if range.contains(&concrete_addr.lower_u64()) => {
+                    return contents.write(write_kind, concrete_addr.lower_u64(), data, solver, tag)
+                }
+
+                _ => continue,
+            }
+        }
+
+        self.write_symbolic(write_kind, Val::Bits(concrete_addr), data, solver, tag, DEFAULT_MEMORY_KIND)
+    }
+
     /// The simplest read is to symbolically read a memory location. In
     /// that case we just return a fresh SMT bitvector of the appropriate
     /// size, and add a ReadMem event to the trace. For this we need the
@@ -531,7 +1414,7 @@ impl<B: BV> Memory<B> {
             address,
             bytes,
             tag_value: tag_ir_value.clone(),
-            kind,
+            kind: kind.into(),
         });
 
         log!(log::MEMORY, &format!("Read symbolic: {} {:?}", value, tag_value));
@@ -573,11 +1456,75 @@ impl<B: BV> Memory<B> {
             Some(c) => c.symbolic_write(&self.regions, solver, value, &write_kind, &address, &data, bytes, &tag),
             None => (),
         };
-        solver.add_event(Event::WriteMem { value, write_kind, address, data, bytes, tag_value: tag, kind });
+        solver.add_event(Event::WriteMem {
+            value,
+            write_kind,
+            address,
+            data,
+            bytes,
+            tag_value: tag,
+            kind: kind.into(),
+        });
 
         Ok(Val::Symbolic(value))
     }
 
+    /// Take a snapshot of the memory after executing a path, given the
+    /// events of that path in the order they occurred (i.e. the
+    /// reverse of [crate::smt::Trace::to_vec]). Writes to concrete
+    /// writable regions are already part of the region contents, and
+    /// writes to custom regions are ignored. A write with symbolic
+    /// data makes the bytes it writes unknown, and as we have no
+    /// solver to tell which addresses a symbolic address could alias,
+    /// a write to a symbolic address makes every byte written before
+    /// it unknown.
+    pub fn snapshot<'ev, I>(&self, events: I) -> MemorySnapshot<B>
+    where
+        I: IntoIterator<Item = &'ev Event<B>>,
+        B: 'ev,
+    {
+        let regions = self
+            .regions
+            .iter()
+            .map(|region| {
+                let contents = match region {
+                    Region::Concrete(_, contents) => Some(contents.clone()),
+                    _ => None,
+                };
+                RegionSnapshot { range: region.region_range().clone(), kind: region.memory_kind(), contents }
+            })
+            .collect();
+
+        let mut written = BTreeMap::new();
+        let mut unresolved = Vec::new();
+        for event in events {
+            match event {
+                Event::WriteMem { address: Val::Bits(address), .. }
+                    if self.in_custom_region(address.lower_u64()).is_some() => {}
+                Event::WriteMem { kind, .. } if kind == CONCRETE_MEMORY_KIND => (),
+                Event::WriteMem { address: Val::Bits(address), data: Val::Bits(data), .. } => {
+                    for (i, byte) in self.endianness.to_bytes(*data).iter().enumerate() {
+                        written.insert(address.lower_u64() + i as u64, *byte);
+                    }
+                }
+                Event::WriteMem { address: Val::Bits(address), bytes, .. } => {
+                    let address = address.lower_u64();
+                    for i in 0..*bytes {
+                        written.remove(&address.wrapping_add(i as u64));
+                    }
+                    unresolved.push(event.clone())
+                }
+                Event::WriteMem { .. } => {
+                    written.clear();
+                    unresolved.push(event.clone())
+                }
+                _ => (),
+            }
+        }
+
+        MemorySnapshot { regions, written, unresolved }
+    }
+
     pub fn smt_address_constraint(
         &self,
         address: &Exp,
@@ -646,6 +1593,48 @@ pub fn smt_address_constraint<B: BV>(
         })
 }
 
+/// Errors which mean an access failed, rather than needing to be
+/// retried (as for [ExecError::SymbolicAddressFork]) or being
+/// malformed.
+fn is_fault(err: &ExecError) -> bool {
+    matches!(err, ExecError::AccessFault { .. } | ExecError::BadRead(_) | ExecError::BadWrite(_))
+}
+
+/// Addresses may be narrower than 64 bits, e.g. on 32-bit
+/// architectures, so zero-extend a symbolic address to 64 bits before
+/// building any constraints on it.
+fn address_64<B: BV>(address: Sym, solver: &mut Solver<B>) -> Result<Sym, ExecError> {
+    match solver.length(address) {
+        Some(64) => Ok(address),
+        Some(width) if width < 64 => Ok(solver.define_const(Exp::ZeroExtend(64 - width, Box::new(Exp::Var(address))))),
+        _ => Err(ExecError::Type(format!("symbolic address v{} is not a bitvector of at most 64 bits", address))),
+    }
+}
+
+/// The concrete address given by a bitvector literal from a model.
+fn value_address(value: &Exp) -> Result<Address, ExecError> {
+    match value {
+        Exp::Bits64(address, _) => Ok(*address),
+        _ => Err(ExecError::Type(format!("address_value {:?}", value))),
+    }
+}
+
+/// The access [address, address + bytes) overlaps [start, end), using
+/// an extra bit to prevent wrapping
+fn smt_overlap_constraint(address: Sym, bytes: u32, range: &Range<Address>) -> Exp {
+    use Exp::*;
+    And(
+        Box::new(Bvult(
+            Box::new(ZeroExtend(1, Box::new(Bits64(range.start, 64)))),
+            Box::new(Bvadd(
+                Box::new(ZeroExtend(1, Box::new(Var(address)))),
+                Box::new(ZeroExtend(1, Box::new(Bits64(bytes as u64, 64)))),
+            )),
+        )),
+        Box::new(Bvult(Box::new(Var(address)), Box::new(Bits64(range.end, 64)))),
+    )
+}
+
 fn reverse_endianness(bytes: &mut [u8]) {
     if bytes.len() <= 2 {
         bytes.reverse()
@@ -675,7 +1664,7 @@ fn read_constrained<B: BV>(
             address: Val::Bits(B::from_u64(address)),
             bytes,
             tag_value: None,
-            kind,
+            kind: kind.into(),
         });
         if tag {
             Ok(make_bv_bit_pair(Val::Symbolic(region), Val::Bits(B::zeros(1))))
@@ -687,8 +1676,10 @@ fn read_constrained<B: BV>(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn read_concrete<B: BV>(
-    region: &HashMap<Address, u8>,
+    region: &Pages,
+    endianness: Endianness,
     read_kind: Val<B>,
     address: Address,
     bytes: u32,
@@ -696,32 +1687,857 @@ fn read_concrete<B: BV>(
     tag: bool,
     kind: &'static str,
 ) -> Result<Val<B>, ExecError> {
-    let mut byte_vec: Vec<u8> = Vec::with_capacity(bytes as usize);
-    for i in address..(address + u64::from(bytes)) {
-        byte_vec.push(*region.get(&i).unwrap_or(&0))
+    let mut byte_vec: Vec<u8> = vec![0; bytes as usize];
+    region.read(address, &mut byte_vec);
+
+    let value = if region.any_symbolic(address, bytes as u64) {
+        // Some of the bytes were written with symbolic data, so the
+        // value is the concatenation of the concrete and symbolic bytes
+        let mut byte_exps: Vec<Exp> = byte_vec
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| match region.symbolic_byte(address + i as u64) {
+                Some(v) => Exp::Var(v),
+                None => Exp::Bits64(u64::from(*byte), 8),
+            })
+            .collect();
+        if endianness == Endianness::Little {
+            byte_exps.reverse()
+        }
+        log!(log::MEMORY, &format!("Read concrete with symbolic bytes: {:?}", byte_exps));
+        let exp = byte_exps
+            .drain(..)
+            .fold(None, |acc, byte| match acc {
+                None => Some(byte),
+                Some(prefix) => Some(Exp::Concat(Box::new(prefix), Box::new(byte))),
+            })
+            .unwrap();
+        Val::Symbolic(solver.define_const(exp))
+    } else {
+        endianness.bytes_to_value_order(&mut byte_vec);
+        log!(log::MEMORY, &format!("Read concrete: {:?}", byte_vec));
+        bytes_to_val(&byte_vec, solver)
+    };
+    solver.add_event(Event::ReadMem {
+        value: value.clone(),
+        read_kind,
+        address: Val::Bits(B::from_u64(address)),
+        bytes,
+        tag_value: None,
+        kind: kind.into(),
+    });
+    if tag {
+        Ok(make_bv_bit_pair(value, Val::Bits(B::zeros(1))))
+    } else {
+        Ok(value)
     }
+}
 
-    reverse_endianness(&mut byte_vec);
+/// Write data into a writable concrete region. The write always
+/// succeeds, but we still record a WriteMem event in the trace with a
+/// success value that is asserted to be true. Symbolic data, such as
+/// values wider than `B::MAX_WIDTH`, is stored as concrete bytes if
+/// its value is fixed, and otherwise each byte is stored as an 8-bit
+/// SMT variable extracted from the data. A write cannot run past the
+/// end of the region, so such writes raise a BadWrite error.
+#[allow(clippy::too_many_arguments)]
+fn write_concrete<B: BV>(
+    region: &mut Pages,
+    region_end: Address,
+    endianness: Endianness,
+    write_kind: Val<B>,
+    address: Address,
+    data: Val<B>,
+    solver: &mut Solver<B>,
+    tag: Option<Val<B>>,
+    kind: &'static str,
+) -> Result<Val<B>, ExecError> {
+    use crate::smt::smtlib::*;
 
-    if byte_vec.len() <= 8 {
-        log!(log::MEMORY, &format!("Read concrete: {:?}", byte_vec));
+    let data_length = crate::primop::length_bits(&data, solver)?;
+    if data_length % 8 != 0 {
+        return Err(ExecError::Type(format!("write_concrete {:?}", data_length)));
+    }
+    let bytes = data_length / 8;
 
-        let value = Val::Bits(B::from_bytes(&byte_vec));
-        solver.add_event(Event::ReadMem {
-            value,
-            read_kind,
-            address: Val::Bits(B::from_u64(address)),
-            bytes,
-            tag_value: None,
-            kind,
-        });
-        if tag {
-            Ok(make_bv_bit_pair(Val::Bits(B::from_bytes(&byte_vec)), Val::Bits(B::zeros(1))))
-        } else {
-            Ok(Val::Bits(B::from_bytes(&byte_vec)))
+    match address.checked_add(u64::from(bytes)) {
+        Some(end) if end <= region_end => (),
+        _ => return Err(ExecError::BadWrite("write crosses the end of a concrete region")),
+    }
+
+    match &data {
+        Val::Bits(bv) => {
+            let byte_vec = endianness.to_bytes(*bv);
+            log!(log::MEMORY, &format!("Write concrete: {:?}", byte_vec));
+            region.write(address, &byte_vec)
         }
-    } else {
-        // TODO: Handle reads > 64 bits
-        Err(ExecError::BadRead("concrete read more than 8 bytes"))
+        Val::Symbolic(v) => match solver.eval_closed(&Exp::Var(*v)).ok().and_then(|exp| exp_to_bytes(&exp)) {
+            Some(mut byte_vec) => {
+                // Reversing the byte order is its own inverse
+                endianness.bytes_to_value_order(&mut byte_vec);
+                log!(log::MEMORY, &format!("Write concrete: {:?}", byte_vec));
+                region.write(address, &byte_vec)
+            }
+            None => {
+                log!(log::MEMORY, &format!("Write symbolic bytes: v{}", v));
+                for i in 0..bytes {
+                    let byte = solver.define_const(Exp::Extract(8 * i + 7, 8 * i, Box::new(Exp::Var(*v))));
+                    let offset = match endianness {
+                        Endianness::Little => i,
+                        Endianness::Big => bytes - i - 1,
+                    };
+                    region.write_symbolic_byte(address + u64::from(offset), byte)
+                }
+            }
+        },
+        _ => return Err(ExecError::Type(format!("write_concrete {:?}", &data))),
+    }
+
+    let value = solver.declare_const(Ty::Bool);
+    solver.add(Def::Assert(Exp::Var(value)));
+    solver.add_event(Event::WriteMem {
+        value,
+        write_kind,
+        address: Val::Bits(B::from_u64(address)),
+        data,
+        bytes,
+        tag_value: tag,
+        kind: kind.into(),
+    });
+
+    Ok(Val::Bool(true))
+}
+
+/// The bytes of a bitvector literal, most significant byte first, or
+/// `None` if the expression is not a literal or its length is not a
+/// multiple of 8.
+fn exp_to_bytes(exp: &Exp) -> Option<Vec<u8>> {
+    match exp {
+        Exp::Bits64(bits, len) if len % 8 == 0 => Some((0..len / 8).rev().map(|i| (bits >> (8 * i)) as u8).collect()),
+        Exp::Bits(bits) if bits.len() % 8 == 0 => Some(
+            bits.chunks(8)
+                .rev()
+                .map(|byte| byte.iter().rev().fold(0, |acc, bit| (acc << 1) | u8::from(*bit)))
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+/// Turn a big-endian sequence of bytes into a value. If the bytes fit
+/// within `B::MAX_WIDTH` the result is a concrete bitvector,
+/// otherwise we define a symbolic variable equal to the concatenation
+/// of the bytes in 64-bit chunks.
+fn bytes_to_val<B: BV>(byte_vec: &[u8], solver: &mut Solver<B>) -> Val<B> {
+    if byte_vec.len() * 8 <= B::MAX_WIDTH as usize {
+        return Val::Bits(B::from_bytes(byte_vec));
+    }
+
+    let exp = byte_vec
+        .chunks(8)
+        .map(|chunk| {
+            let bits = chunk.iter().fold(0, |bits, byte| (bits << 8) | u64::from(*byte));
+            Exp::Bits64(bits, chunk.len() as u32 * 8)
+        })
+        .fold(None, |acc, chunk| match acc {
+            None => Some(chunk),
+            Some(prefix) => Some(Exp::Concat(Box::new(prefix), Box::new(chunk))),
+        })
+        .unwrap();
+
+    Val::Symbolic(solver.define_const(exp))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concrete::bitvector64::B64;
+    use crate::ir::EnumMember;
+    use crate::smt::smtlib::Exp::*;
+    use crate::smt::smtlib::Ty;
+    use crate::smt::{Config, Context};
+    use crate::snapshot::ByteDiff;
+
+    use super::*;
+
+    fn concrete_memory(base: Address, len: u64) -> Memory<B64> {
+        let contents: HashMap<Address, u8> = (0..len).map(|i| (base + i, i as u8)).collect();
+        let mut mem = Memory::new();
+        mem.add_concrete_region(base..(base + len), contents);
+        mem
+    }
+
+    #[test]
+    fn read_concrete_wide() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = concrete_memory(0x1000, 32);
+
+        let value = mem.read(Val::Unit, Val::Bits(B64::from_u64(0x1000)), Val::I128(16), &mut solver, false).unwrap();
+
+        match value {
+            Val::Symbolic(v) => {
+                let expected =
+                    Concat(Box::new(Bits64(0x0f0e_0d0c_0b0a_0908, 64)), Box::new(Bits64(0x0706_0504_0302_0100, 64)));
+                assert!(solver.check_sat_with(&Neq(Box::new(Var(v)), Box::new(expected))) == SmtResult::Unsat)
+            }
+            _ => panic!("Expected a symbolic value for a 16 byte read"),
+        }
+    }
+
+    fn assert_equal(solver: &mut Solver<B64>, value: &Val<B64>, expected: Exp) {
+        match value {
+            Val::Symbolic(v) => {
+                assert!(solver.check_sat_with(&Neq(Box::new(Var(*v)), Box::new(expected))) == SmtResult::Unsat)
+            }
+            _ => panic!("Expected a symbolic value, got {:?}", value),
+        }
+    }
+
+    /// Store 16 bytes at 0x1000 and load them back, returning the
+    /// value loaded.
+    fn round_trip(mem: &mut Memory<B64>, solver: &mut Solver<B64>, data: Exp) -> Val<B64> {
+        let addr = Val::Bits(B64::from_u64(0x1000));
+        let data = Val::Symbolic(solver.define_const(data));
+        mem.write(Val::Unit, addr.clone(), data, solver, None).unwrap();
+        let value = mem.read(Val::Unit, addr, Val::I128(16), solver, false).unwrap();
+        assert!(crate::primop::length_bits(&value, solver).unwrap() == 128);
+        value
+    }
+
+    fn wide_data() -> Exp {
+        Concat(Box::new(Bits64(0x0f0e_0d0c_0b0a_0908, 64)), Box::new(Bits64(0x0706_0504_0302_0100, 64)))
+    }
+
+    #[test]
+    fn wide_round_trip_concrete() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        for endianness in [Endianness::Little, Endianness::Big].iter() {
+            let mut mem = Memory::<B64>::new();
+            mem.set_endianness(*endianness);
+            mem.add_concrete_region(0x1000..0x1020, HashMap::new());
+
+            // Data with a fixed value is stored as concrete bytes
+            let value = round_trip(&mut mem, &mut solver, wide_data());
+            assert_equal(&mut solver, &value, wide_data());
+            let low = mem.read(Val::Unit, Val::Bits(B64::from_u64(0x1000)), Val::I128(1), &mut solver, false);
+            let expected = if *endianness == Endianness::Little { 0x00 } else { 0x0f };
+            assert!(matches!(low, Ok(Val::Bits(bv)) if bv == B64::new(expected, 8)));
+
+            // Otherwise each byte is stored symbolically
+            let x = solver.declare_const(Ty::BitVec(128));
+            let value = round_trip(&mut mem, &mut solver, Var(x));
+            assert_equal(&mut solver, &value, Var(x));
+            let word = mem.read(Val::Unit, Val::Bits(B64::from_u64(0x1004)), Val::I128(4), &mut solver, false).unwrap();
+            let expected = if *endianness == Endianness::Little {
+                Extract(63, 32, Box::new(Var(x)))
+            } else {
+                Extract(95, 64, Box::new(Var(x)))
+            };
+            assert_equal(&mut solver, &word, expected);
+            assert!(mem.read_initial(0x1000, 1).is_err());
+
+            // and a concrete write replaces the symbolic bytes
+            let data = B64::new(0xaabb_ccdd, 32);
+            mem.write(Val::Unit, Val::Bits(B64::from_u64(0x1004)), Val::Bits(data), &mut solver, None).unwrap();
+            let word = mem.read(Val::Unit, Val::Bits(B64::from_u64(0x1004)), Val::I128(4), &mut solver, false);
+            assert!(matches!(word, Ok(Val::Bits(bv)) if bv == data))
+        }
+    }
+
+    #[test]
+    fn read_only_concrete() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        let contents: HashMap<Address, u8> = (0..32).map(|i| (0x1000 + i, i as u8)).collect();
+        mem.add_read_only_concrete_region(0x1000..0x1020, contents);
+        mem.add_read_only_zero_region(0x2000..0x2020);
+        // Writes to read-only concrete memory fault and do not change
+        // its contents
+        let zero = Val::Bits(B64::from_u64(0x2000));
+        assert!(matches!(
+            mem.write(Val::Unit, zero, Val::Bits(B64::new(1, 8)), &mut solver, None),
+            Err(ExecError::AccessFault { address: 0x2000, kind: AccessKind::Write })
+        ));
+        let addr = Val::Bits(B64::from_u64(0x1000));
+        let x = solver.declare_const(Ty::BitVec(128));
+        assert!(matches!(
+            mem.write(Val::Unit, addr.clone(), Val::Symbolic(x), &mut solver, None),
+            Err(ExecError::AccessFault { address: 0x1000, kind: AccessKind::Write })
+        ));
+        let value = mem.read(Val::Unit, addr, Val::I128(16), &mut solver, false).unwrap();
+        assert_equal(&mut solver, &value, wide_data())
+    }
+
+    #[test]
+    fn wide_round_trip_symbolic() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        mem.add_symbolic_region(0x1000..0x1020);
+        round_trip(&mut mem, &mut solver, wide_data());
+        let events = solver.trace().to_vec();
+        assert!(matches!(events[0], Event::ReadMem { bytes: 16, .. }));
+        assert!(matches!(events.iter().find(|ev| ev.is_memory_write()), Some(Event::WriteMem { bytes: 16, .. })))
+    }
+
+    #[test]
+    fn symbolic_code_is_read_only() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        mem.add_symbolic_code_region(0x1000..0x1020);
+        let addr = Val::Bits(B64::from_u64(0x1000));
+        assert!(matches!(
+            mem.write(Val::Unit, addr.clone(), Val::Bits(B64::new(0, 32)), &mut solver, None),
+            Err(ExecError::AccessFault { address: 0x1000, kind: AccessKind::Write })
+        ));
+        assert!(mem.read(Val::Unit, addr, Val::I128(16), &mut solver, false).is_ok());
+        assert!(matches!(solver.trace().to_vec()[0], Event::ReadMem { bytes: 16, .. }))
+    }
+
+    #[test]
+    fn wide_round_trip_constrained() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        let generator = |solver: &mut Solver<B64>| {
+            let v = solver.declare_const(Ty::BitVec(128));
+            solver.add(Def::Assert(Eq(Box::new(Var(v)), Box::new(wide_data()))));
+            v
+        };
+        mem.add_region(Region::Constrained(0x1000..0x1010, Arc::new(generator)));
+        let value = round_trip(&mut mem, &mut solver, wide_data());
+        assert_equal(&mut solver, &value, wide_data())
+    }
+
+    #[test]
+    fn wide_round_trip_custom() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        let stub = crate::devices::MmioStub::new(0x1000..0x1020);
+        mem.add_custom_region(stub.range(), Box::new(stub));
+        round_trip(&mut mem, &mut solver, wide_data());
+        assert!(matches!(solver.trace().to_vec()[0], Event::ReadMem { bytes: 16, .. }))
+    }
+
+    #[test]
+    fn read_concrete_narrow() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = concrete_memory(0x1000, 32);
+
+        let value = mem.read(Val::Unit, Val::Bits(B64::from_u64(0x1004)), Val::I128(4), &mut solver, false).unwrap();
+        assert!(matches!(value, Val::Bits(bv) if bv == B64::new(0x0706_0504, 32)))
+    }
+
+    #[test]
+    fn access_faults() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        mem.add_region_with_access(
+            Region::Concrete(0x1000..0x2000, Pages::new()),
+            Permissions::READ_ONLY,
+            AccessPolicy::Fault,
+        );
+        mem.add_region_with_access(Region::Symbolic(0x2000..0x3000), Permissions::READ_EXECUTE, AccessPolicy::Fault);
+        mem.set_ifetch_read_kind(1);
+
+        let ifetch = Val::Enum(EnumMember { enum_id: 0, member: 1 });
+        let data_read = Val::Enum(EnumMember { enum_id: 0, member: 0 });
+        let addr = Val::Bits(B64::from_u64(0x1000));
+        let code_addr = Val::Bits(B64::from_u64(0x2000));
+        let data = Val::Bits(B64::new(0, 32));
+
+        assert!(mem.read(data_read.clone(), addr.clone(), Val::I128(4), &mut solver, false).is_ok());
+        assert!(matches!(
+            mem.read(ifetch.clone(), addr.clone(), Val::I128(4), &mut solver, false),
+            Err(ExecError::AccessFault { address: 0x1000, kind: AccessKind::Ifetch })
+        ));
+        assert!(matches!(
+            mem.write(data_read.clone(), addr, data.clone(), &mut solver, None),
+            Err(ExecError::AccessFault { address: 0x1000, kind: AccessKind::Write })
+        ));
+        assert!(mem.read(ifetch, code_addr.clone(), Val::I128(4), &mut solver, false).is_ok());
+        assert!(matches!(
+            mem.write(data_read, code_addr, data, &mut solver, None),
+            Err(ExecError::AccessFault { address: 0x2000, kind: AccessKind::Write })
+        ));
+    }
+
+    #[test]
+    fn access_faults_across_regions() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        mem.add_region_with_access(Region::Symbolic(0x1000..0x1004), Permissions::READ_WRITE, AccessPolicy::Fault);
+        mem.add_region_with_access(Region::Symbolic(0x1004..0x2000), Permissions::READ_ONLY, AccessPolicy::Fault);
+        mem.add_region_with_access(Region::Symbolic(0x0..0x1000), Permissions::ALL, AccessPolicy::Fault);
+        mem.set_ifetch_read_kind(1);
+
+        let ifetch = Val::Enum(EnumMember { enum_id: 0, member: 1 });
+        let addr = Val::Bits(B64::from_u64(0x1000));
+        let data = Val::Bits(B64::new(0, 64));
+
+        let word = Val::Bits(B64::new(0, 32));
+        assert!(mem.write(Val::Unit, Val::Bits(B64::from_u64(0xffc)), word, &mut solver, None).is_ok());
+        assert!(matches!(
+            mem.write(Val::Unit, addr.clone(), data, &mut solver, None),
+            Err(ExecError::AccessFault { address: 0x1004, kind: AccessKind::Write })
+        ));
+        assert!(matches!(
+            mem.read(ifetch.clone(), Val::Bits(B64::from_u64(0xffc)), Val::I128(8), &mut solver, false),
+            Err(ExecError::AccessFault { address: 0x1000, kind: AccessKind::Ifetch })
+        ));
+        assert!(mem.read(Val::Unit, addr, Val::I128(8), &mut solver, false).is_ok())
+    }
+
+    #[test]
+    fn isa_config() {
+        let mut symtab = ir::Symtab::new();
+        let read_kind = symtab.intern("zread_kind");
+        let plain = symtab.intern("zRead_plain");
+        let ifetch = symtab.intern("zRead_ifetch");
+        symtab.intern("zPC");
+        let config = r#"
+            pc = "PC"
+            ifetch = "Read_ifetch"
+            endianness = "big"
+            read_exclusives = []
+            write_exclusives = []
+            assembler = "sh"
+            objdump = "sh"
+            linker = "sh"
+            [reads]
+            [writes]
+            [cache_ops]
+            [mmu]
+            page_table_base = "0x300000"
+            page_size = "4096"
+            s2_page_table_base = "0x400000"
+            s2_page_size = "4096"
+            [threads]
+            base = "0x400000"
+            top = "0x500000"
+            stride = "0x1000"
+            [symbolic_addrs]
+            base = "0x600000"
+            stride = "0x10"
+        "#;
+        let isa_config = ISAConfig::<B64>::parse(config, &symtab).unwrap();
+        let defs = vec![ir::Def::Enum(read_kind, vec![plain, ifetch])];
+        let shared_state = ir::SharedState::new(symtab, &defs, std::collections::HashSet::new(), HashMap::new());
+
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::from_isa_config(&isa_config, &shared_state);
+        mem.add_region_with_access(
+            Region::Concrete(0x1000..0x2000, [(0x1000, 0x01), (0x1001, 0x02)].iter().copied().collect()),
+            Permissions::READ_ONLY,
+            AccessPolicy::Fault,
+        );
+
+        let plain = Val::Enum(EnumMember { enum_id: 0, member: 0 });
+        let ifetch = Val::Enum(EnumMember { enum_id: 0, member: 1 });
+        let addr = Val::Bits(B64::from_u64(0x1000));
+        let value = mem.read(plain, addr.clone(), Val::I128(2), &mut solver, false).unwrap();
+        assert!(matches!(value, Val::Bits(bv) if bv == B64::new(0x0102, 16)));
+        assert!(matches!(
+            mem.read(ifetch, addr, Val::I128(2), &mut solver, false),
+            Err(ExecError::AccessFault { address: 0x1000, kind: AccessKind::Ifetch })
+        ))
+    }
+
+    #[test]
+    fn access_hook() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        let hook: AccessHook<B64> =
+            Arc::new(|address, _, _| if address < 0x1800 { Ok(()) } else { Err(ExecError::BadWrite("hook")) });
+        mem.add_region_with_access(Region::Symbolic(0x1000..0x2000), Permissions::READ_ONLY, AccessPolicy::Hook(hook));
+
+        let data = Val::Bits(B64::new(0, 32));
+        assert!(mem.write(Val::Unit, Val::Bits(B64::from_u64(0x1000)), data.clone(), &mut solver, None).is_ok());
+        assert!(matches!(
+            mem.write(Val::Unit, Val::Bits(B64::from_u64(0x1800)), data, &mut solver, None),
+            Err(ExecError::BadWrite("hook"))
+        ))
+    }
+
+    #[test]
+    fn read_initial_wide() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mem = concrete_memory(0x1000, 32);
+
+        assert!(mem.read_initial(0x1000, 12).is_err());
+        match mem.read_initial_wide(0x1000, 12, &mut solver).unwrap() {
+            Val::Symbolic(v) => {
+                let expected = Concat(Box::new(Bits64(0x0b0a_0908, 32)), Box::new(Bits64(0x0706_0504_0302_0100, 64)));
+                assert!(solver.check_sat_with(&Neq(Box::new(Var(v)), Box::new(expected))) == SmtResult::Unsat)
+            }
+            _ => panic!("Expected a symbolic value for a 12 byte read"),
+        }
+    }
+
+    fn symbolic_address(solver: &mut Solver<B64>, lo: Address, hi: Address) -> Sym {
+        let addr = solver.declare_const(Ty::BitVec(64));
+        solver.add(Def::Assert(Bvule(Box::new(Bits64(lo, 64)), Box::new(Var(addr)))));
+        solver.add(Def::Assert(Bvult(Box::new(Var(addr)), Box::new(Bits64(hi, 64)))));
+        addr
+    }
+
+    #[test]
+    fn symbolic_address_concretize() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = concrete_memory(0x1000, 32);
+        mem.set_symbolic_address_policy(SymbolicAddressPolicy::Concretize);
+
+        let addr = symbolic_address(&mut solver, 0x1008, 0x1009);
+        let value = mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(1), &mut solver, false).unwrap();
+        assert!(matches!(value, Val::Bits(bv) if bv == B64::new(0x08, 8)))
+    }
+
+    #[test]
+    fn symbolic_address_overlap() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = concrete_memory(0x1000, 32);
+        mem.add_symbolic_region(0x2000..0x3000);
+        mem.set_symbolic_address_policy(SymbolicAddressPolicy::ErrorOnOverlap);
+
+        // An access ending just inside the concrete region overlaps it
+        let addr = symbolic_address(&mut solver, 0xffd, 0xffe);
+        let read = mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(4), &mut solver, false);
+        assert!(matches!(read, Err(ExecError::BadRead(_))));
+
+        let addr = symbolic_address(&mut solver, 0x2000, 0x3000);
+        assert!(mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(4), &mut solver, false).is_ok())
+    }
+
+    #[test]
+    fn symbolic_address_fork() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = concrete_memory(0x1000, 32);
+        mem.add_symbolic_region(0x2000..0x3000);
+        mem.set_symbolic_address_policy(SymbolicAddressPolicy::Fork);
+
+        let addr = symbolic_address(&mut solver, 0x1000, 0x3000);
+        let cases = match mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(4), &mut solver, false) {
+            Err(ExecError::SymbolicAddressFork(cases)) => cases,
+            other => panic!("Expected a fork, got {:?}", other),
+        };
+        // The concrete region, the symbolic region, an access straddling
+        // the end of the concrete region, and the gap between them
+        assert_eq!(cases.len(), 4);
+
+        solver.add(Def::Assert(cases[1].clone()));
+        let value = mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(4), &mut solver, false).unwrap();
+        assert!(matches!(value, Val::Symbolic(_)));
+        assert!(matches!(solver.trace().head.last(), Some(Event::ReadMem { kind, .. }) if kind == "symbolic"))
+    }
+
+    #[test]
+    fn symbolic_address_fork_straddle() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = concrete_memory(0x1000, 32);
+        mem.set_symbolic_address_policy(SymbolicAddressPolicy::Fork);
+
+        let addr = symbolic_address(&mut solver, 0x101e, 0x101f);
+        let read = mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(4), &mut solver, false);
+        assert!(matches!(read, Err(ExecError::BadRead(_))));
+        let write = mem.write(Val::Unit, Val::Symbolic(addr), Val::Bits(B64::new(0, 32)), &mut solver, None);
+        assert!(matches!(write, Err(ExecError::BadWrite(_))))
+    }
+
+    #[test]
+    fn symbolic_address_fork_concrete() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = concrete_memory(0x1000, 32);
+        mem.set_symbolic_address_policy(SymbolicAddressPolicy::Fork);
+
+        // Every address is in the concrete region, but the read forks
+        // on the value of the address rather than picking just one
+        let addr = symbolic_address(&mut solver, 0x1008, 0x100a);
+        let cases = match mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(1), &mut solver, false) {
+            Err(ExecError::SymbolicAddressFork(cases)) => cases,
+            other => panic!("Expected a fork, got {:?}", other),
+        };
+        assert_eq!(cases.len(), 2);
+
+        solver.add(Def::Assert(cases[1].clone()));
+        let value = mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(1), &mut solver, false).unwrap();
+        assert!(matches!(value, Val::Bits(bv) if bv == B64::new(0x08, 8) || bv == B64::new(0x09, 8)))
+    }
+
+    #[test]
+    fn symbolic_address_fork_limit() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = concrete_memory(0x1000, 32);
+        mem.set_symbolic_address_policy(SymbolicAddressPolicy::Fork);
+        mem.set_address_fork_limit(4);
+
+        let addr = symbolic_address(&mut solver, 0x1000, 0x1004);
+        let read = mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(1), &mut solver, false);
+        assert!(matches!(read, Err(ExecError::SymbolicAddressFork(cases)) if cases.len() == 4));
+
+        // With more possible addresses than the limit we concretize
+        let addr = symbolic_address(&mut solver, 0x1000, 0x1010);
+        let value = mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(1), &mut solver, false).unwrap();
+        let value = match value {
+            Val::Bits(bv) => bv.lower_u64(),
+            other => panic!("Expected a concrete value, got {:?}", other),
+        };
+        let other = Neq(Box::new(Var(addr)), Box::new(Bits64(0x1000 + value, 64)));
+        assert!(solver.check_sat_with(&other) == SmtResult::Unsat)
+    }
+
+    #[test]
+    fn symbolic_address_32_bit() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let policies =
+            [SymbolicAddressPolicy::Fork, SymbolicAddressPolicy::Concretize, SymbolicAddressPolicy::ErrorOnOverlap];
+        for policy in policies.iter() {
+            let mut mem = concrete_memory(0x1000, 32);
+            mem.add_symbolic_region(0x2000..0x3000);
+            mem.set_symbolic_address_policy(*policy);
+
+            let addr = solver.declare_const(Ty::BitVec(32));
+            solver.add(Def::Assert(Eq(Box::new(Var(addr)), Box::new(Bits64(0x2004, 32)))));
+            assert!(mem.read(Val::Unit, Val::Symbolic(addr), Val::I128(4), &mut solver, false).is_ok());
+            assert!(mem.write(Val::Unit, Val::Symbolic(addr), Val::Bits(B64::new(0, 32)), &mut solver, None).is_ok())
+        }
+    }
+
+    #[test]
+    fn watchpoints() {
+        use std::sync::Mutex;
+
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        mem.add_concrete_region(0x1000..0x2000, HashMap::new());
+
+        let hits = Arc::new(Mutex::new(Vec::new()));
+        let hits_cb = hits.clone();
+        let callback: WatchCallback<B64> = Arc::new(move |hit| {
+            hits_cb.lock().unwrap().push((hit.access, hit.bytes, hit.backtrace.len()));
+        });
+        mem.add_watchpoint(0x1008..0x1010, &[AccessKind::Write], WatchAction::Callback(callback));
+        mem.add_watchpoint(0x1008..0x1010, &[AccessKind::Read], WatchAction::Log);
+
+        let backtrace = vec![(ir::RETURN, 0)];
+        let data = Val::Bits(B64::new(0xff, 32));
+        // Overlaps the start of the watched range
+        let addr = Val::Bits(B64::from_u64(0x1006));
+        mem.write_with_backtrace(Val::Unit, addr, data.clone(), &mut solver, None, &backtrace).unwrap();
+        // Ends just before the watched range
+        let addr = Val::Bits(B64::from_u64(0x1004));
+        mem.write_with_backtrace(Val::Unit, addr, data, &mut solver, None, &backtrace).unwrap();
+        mem.read(Val::Unit, Val::Bits(B64::from_u64(0x1008)), Val::I128(4), &mut solver, false).unwrap();
+
+        let hits = hits.lock().unwrap();
+        assert_eq!(*hits, vec![(AccessKind::Write, 4, 1)])
+    }
+
+    #[test]
+    fn watchpoints_top_of_memory() {
+        use std::sync::Mutex;
+
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+
+        let hits = Arc::new(Mutex::new(0));
+        let hits_cb = hits.clone();
+        let callback: WatchCallback<B64> = Arc::new(move |_| *hits_cb.lock().unwrap() += 1);
+        mem.add_watchpoint((u64::MAX - 8)..u64::MAX, &[AccessKind::Read], WatchAction::Callback(callback));
+
+        // The end of this access is past the top of the address space
+        mem.read(Val::Unit, Val::Bits(B64::from_u64(u64::MAX - 1)), Val::I128(4), &mut solver, false).unwrap();
+        let hits = *hits.lock().unwrap();
+        assert_eq!(hits, 1)
+    }
+
+    #[test]
+    fn watchpoints_on_faults() {
+        use std::sync::Mutex;
+
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        mem.add_region_with_access(
+            Region::Concrete(0x1000..0x2000, Pages::new()),
+            Permissions::READ_ONLY,
+            AccessPolicy::Fault,
+        );
+        mem.set_ifetch_read_kind(1);
+
+        let hits = Arc::new(Mutex::new(Vec::new()));
+        let hits_cb = hits.clone();
+        let callback: WatchCallback<B64> = Arc::new(move |hit| {
+            let fault = matches!(hit.fault, Some(ExecError::AccessFault { address: 0x1008, .. }));
+            hits_cb.lock().unwrap().push((hit.access, hit.data.is_some(), fault));
+        });
+        let kinds = [AccessKind::Read, AccessKind::Write, AccessKind::Ifetch];
+        mem.add_watchpoint(0x1008..0x1010, &kinds, WatchAction::Callback(callback));
+
+        let addr = Val::Bits(B64::from_u64(0x1008));
+        let ifetch = Val::Enum(EnumMember { enum_id: 0, member: 1 });
+        let write = mem.write(Val::Unit, addr.clone(), Val::Bits(B64::new(0, 32)), &mut solver, None);
+        assert!(matches!(write, Err(ExecError::AccessFault { .. })));
+        let read = mem.read(ifetch, addr, Val::I128(4), &mut solver, false);
+        assert!(matches!(read, Err(ExecError::AccessFault { .. })));
+
+        let hits = hits.lock().unwrap();
+        assert_eq!(*hits, vec![(AccessKind::Write, true, true), (AccessKind::Ifetch, false, true)])
+    }
+
+    #[test]
+    fn snapshot_diff() {
+        let ctx = Context::new(Config::new());
+        let mut mem = concrete_memory(0x1000, 32);
+        mem.add_concrete_region(0x2000..0x2010, HashMap::new());
+        mem.add_symbolic_region(0x3000..0x4000);
+
+        let snapshot_path = |writes: &[(Address, u64)]| {
+            let mut mem = mem.clone();
+            let mut solver = Solver::<B64>::new(&ctx);
+            let addr = solver.declare_const(Ty::BitVec(64));
+            mem.write(Val::Unit, Val::Symbolic(addr), Val::Bits(B64::new(0, 8)), &mut solver, None).unwrap();
+            for (address, data) in writes {
+                let data = Val::Bits(B64::new(*data, 16));
+                mem.write(Val::Unit, Val::Bits(B64::from_u64(*address)), data, &mut solver, None).unwrap();
+            }
+            let mut events = solver.trace().to_vec();
+            events.reverse();
+            mem.snapshot(events)
+        };
+
+        let left = snapshot_path(&[(0x2000, 0xabcd), (0x3000, 0x1234)]);
+        let right = snapshot_path(&[(0x2000, 0xab00), (0x3002, 0x5678)]);
+
+        assert_eq!(left.unresolved.len(), 1);
+        assert_eq!(left.region_bytes(1).unwrap().take(2).collect::<Vec<_>>(), [0xcd, 0xab]);
+        assert!(left.region_bytes(2).is_err());
+        assert!(left.region_bytes(3).is_err());
+        assert_eq!(
+            left.diff(&right),
+            vec![
+                ByteDiff { address: 0x2000, left: Some(0xcd), right: Some(0x00) },
+                ByteDiff { address: 0x3000, left: Some(0x34), right: None },
+                ByteDiff { address: 0x3001, left: Some(0x12), right: None },
+                ByteDiff { address: 0x3002, left: None, right: Some(0x78) },
+                ByteDiff { address: 0x3003, left: None, right: Some(0x56) },
+            ]
+        )
+    }
+
+    #[test]
+    fn snapshot_unresolved_writes() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        mem.add_symbolic_region(0x3000..0x4000);
+
+        let write = |mem: &mut Memory<B64>, solver: &mut Solver<B64>, address: Val<B64>, data: Val<B64>| {
+            mem.write(Val::Unit, address, data, solver, None).unwrap();
+        };
+        write(&mut mem, &mut solver, Val::Bits(B64::from_u64(0x3000)), Val::Bits(B64::new(0x1234, 16)));
+        let data = solver.declare_const(Ty::BitVec(8));
+        write(&mut mem, &mut solver, Val::Bits(B64::from_u64(0x3001)), Val::Symbolic(data));
+        let snapshot = |mem: &Memory<B64>, solver: &Solver<B64>| {
+            let mut events = solver.trace().to_vec();
+            events.reverse();
+            mem.snapshot(events)
+        };
+
+        // Symbolic data only affects the bytes it writes
+        let before = snapshot(&mem, &solver);
+        assert_eq!(before.byte(0x3000), Some(0x34));
+        assert_eq!(before.byte(0x3001), None);
+
+        // A symbolic address could alias any byte written before it
+        write(&mut mem, &mut solver, Val::Bits(B64::from_u64(0x3008)), Val::Bits(B64::new(0xab, 8)));
+        let addr = solver.declare_const(Ty::BitVec(64));
+        write(&mut mem, &mut solver, Val::Symbolic(addr), Val::Bits(B64::new(0, 8)));
+        write(&mut mem, &mut solver, Val::Bits(B64::from_u64(0x3010)), Val::Bits(B64::new(0xcd, 8)));
+        let after = snapshot(&mem, &solver);
+        assert_eq!(after.byte(0x3000), None);
+        assert_eq!(after.byte(0x3008), None);
+        assert_eq!(after.byte(0x3010), Some(0xcd));
+        assert_eq!(after.unresolved.len(), 2)
+    }
+
+    #[test]
+    fn write_past_region_end() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        mem.add_concrete_region(0x1000..0x1004, HashMap::new());
+
+        let data = Val::Bits(B64::new(0x0102_0304, 32));
+        assert!(mem.write(Val::Unit, Val::Bits(B64::from_u64(0x1000)), data.clone(), &mut solver, None).is_ok());
+        let write = mem.write(Val::Unit, Val::Bits(B64::from_u64(0x1002)), data, &mut solver, None);
+        assert!(matches!(write, Err(ExecError::BadWrite(_))))
+    }
+
+    #[test]
+    fn big_endian() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut mem = Memory::<B64>::new();
+        mem.add_concrete_region(0x1000..0x2000, HashMap::new());
+        mem.set_endianness(Endianness::Big);
+
+        let addr = Val::Bits(B64::from_u64(0x1000));
+        mem.write(Val::Unit, addr.clone(), Val::Bits(B64::new(0x0102_0304, 32)), &mut solver, None).unwrap();
+        assert!(matches!(mem.read_initial(0x1000, 1), Ok(Val::Bits(bv)) if bv == B64::new(0x01, 8)));
+        let value = mem.read(Val::Unit, addr, Val::I128(2), &mut solver, false).unwrap();
+        assert!(matches!(value, Val::Bits(bv) if bv == B64::new(0x0102, 16)))
+    }
+
+    #[test]
+    fn region_handles() {
+        let mut mem = Memory::<B64>::new();
+        let code = mem.add_symbolic_code_region(0x1000..0x2000);
+        assert!(matches!(
+            mem.try_add_region(Region::Concrete(0x1800..0x3000, Pages::new())),
+            Err(ExecError::RegionOverlap { existing, .. }) if existing == (0x1000..0x2000)
+        ));
+        let data = mem.try_add_region(Region::Concrete(0x2000..0x3000, Pages::new())).unwrap();
+        assert!(matches!(
+            mem.try_add_region(Region::Symbolic(0x2fff..0x4000)),
+            Err(ExecError::RegionOverlap { existing, .. }) if existing == (0x2000..0x3000)
+        ));
+
+        let map: Vec<(Range<Address>, RegionId)> = mem.address_map().map(|m| (m.range, m.id)).collect();
+        assert_eq!(map, vec![(0x1000..0x2000, code), (0x2000..0x3000, data)]);
+
+        // Unchecked regions are shadowed by those added before them
+        let shadowed = mem.add_symbolic_region(0x2800..0x3800);
+        assert_eq!(mem.kind_at(0x2800), "concrete");
+        assert_eq!(mem.kind_at(0x3000), "symbolic");
+        assert!(mem.remove_region(shadowed).is_some());
+
+        let old = mem.replace_region(code, Region::Symbolic(0x0..0x1000)).unwrap();
+        assert_eq!(old.region_range(), &(0x1000..0x2000));
+        assert!(mem.replace_region(code, Region::Symbolic(0x0..0x2001)).is_err());
+        assert_eq!(mem.kind_at(0x800), "symbolic");
+
+        assert!(mem.remove_region(data).is_some());
+        assert!(mem.remove_region(data).is_none());
+        assert!(mem.region(data).is_none());
+        assert_eq!(mem.address_map().count(), 1)
     }
 }
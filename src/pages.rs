@@ -0,0 +1,280 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2019, 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements a sparse, page-based store for the
+//! contents of concrete memory regions. Pages are shared between
+//! clones using reference counting and only copied when written, so
+//! cloning a [crate::memory::Memory] containing a large firmware
+//! image when the executor forks is cheap. Bytes which have been
+//! overwritten with symbolic data are kept separately as 8-bit SMT
+//! variables which shadow the page contents.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+use crate::memory::Address;
+use crate::smt::Sym;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+type Page = [u8; PAGE_SIZE as usize];
+
+/// A sparse store of bytes split into [PAGE_SIZE] pages. Bytes that
+/// have never been written read as zero. Accesses which run past the
+/// top of the address space wrap around to address zero.
+///
+/// Both the page table and the pages are reference counted, so a
+/// clone only copies the table when one of the clones is first
+/// written, and then only copies the pages that are written.
+#[derive(Clone, Default)]
+pub struct Pages {
+    pages: Arc<HashMap<u64, Arc<Page>>>,
+    symbolic: Arc<HashMap<Address, Sym>>,
+}
+
+fn split(address: Address) -> (u64, usize) {
+    (address / PAGE_SIZE, (address % PAGE_SIZE) as usize)
+}
+
+impl Pages {
+    pub fn new() -> Self {
+        Pages { pages: Arc::new(HashMap::new()), symbolic: Arc::new(HashMap::new()) }
+    }
+
+    /// Create pages containing `bytes` starting at `base`.
+    pub fn from_bytes(base: Address, bytes: &[u8]) -> Self {
+        let mut pages = Pages::new();
+        pages.write(base, bytes);
+        pages
+    }
+
+    /// The number of pages that have been allocated.
+    pub fn num_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn read_byte(&self, address: Address) -> u8 {
+        let (page, offset) = split(address);
+        self.pages.get(&page).map(|p| p[offset]).unwrap_or(0)
+    }
+
+    /// Fill `buf` with the bytes starting at `address`.
+    pub fn read(&self, address: Address, buf: &mut [u8]) {
+        let mut done = 0;
+        while done < buf.len() {
+            let (page, offset) = split(address.wrapping_add(done as u64));
+            let n = usize::min(buf.len() - done, PAGE_SIZE as usize - offset);
+            match self.pages.get(&page) {
+                Some(p) => buf[done..done + n].copy_from_slice(&p[offset..offset + n]),
+                None => buf[done..done + n].iter_mut().for_each(|b| *b = 0),
+            }
+            done += n
+        }
+    }
+
+    fn page_mut(&mut self, page: u64) -> &mut Page {
+        Arc::make_mut(Arc::make_mut(&mut self.pages).entry(page).or_insert_with(|| Arc::new([0; PAGE_SIZE as usize])))
+    }
+
+    pub fn write_byte(&mut self, address: Address, byte: u8) {
+        if self.symbolic.contains_key(&address) {
+            Arc::make_mut(&mut self.symbolic).remove(&address);
+        }
+        let (page, offset) = split(address);
+        self.page_mut(page)[offset] = byte
+    }
+
+    /// The symbolic byte stored at an address, if any.
+    pub fn symbolic_byte(&self, address: Address) -> Option<Sym> {
+        self.symbolic.get(&address).copied()
+    }
+
+    /// Returns true if any of the `len` bytes starting at `address`
+    /// are symbolic.
+    pub fn any_symbolic(&self, address: Address, len: u64) -> bool {
+        !self.symbolic.is_empty() && (0..len).any(|i| self.symbolic.contains_key(&address.wrapping_add(i)))
+    }
+
+    /// Store a symbolic byte, which must be an 8-bit SMT variable. The
+    /// underlying concrete byte is cleared, so it reads as zero.
+    pub fn write_symbolic_byte(&mut self, address: Address, byte: Sym) {
+        let (page, offset) = split(address);
+        self.page_mut(page)[offset] = 0;
+        Arc::make_mut(&mut self.symbolic).insert(address, byte);
+    }
+
+    /// Write `bytes` starting at `address`. Any pages shared with
+    /// another clone are copied first.
+    pub fn write(&mut self, address: Address, bytes: &[u8]) {
+        if self.any_symbolic(address, bytes.len() as u64) {
+            let symbolic = Arc::make_mut(&mut self.symbolic);
+            for i in 0..bytes.len() {
+                symbolic.remove(&address.wrapping_add(i as u64));
+            }
+        }
+        let mut done = 0;
+        while done < bytes.len() {
+            let (page, offset) = split(address.wrapping_add(done as u64));
+            let n = usize::min(bytes.len() - done, PAGE_SIZE as usize - offset);
+            self.page_mut(page)[offset..offset + n].copy_from_slice(&bytes[done..done + n]);
+            done += n
+        }
+    }
+
+    /// Returns the addresses of all bytes which differ between two
+    /// sets of pages, along with every symbolic byte in either. Pages
+    /// which are still shared are skipped without comparing their
+    /// contents.
+    pub fn differences(&self, other: &Pages) -> Vec<Address> {
+        let zero_page = [0; PAGE_SIZE as usize];
+        let mut page_numbers: Vec<u64> = self.pages.keys().chain(other.pages.keys()).copied().collect();
+        page_numbers.sort_unstable();
+        page_numbers.dedup();
+
+        let mut addresses: Vec<Address> = self.symbolic.keys().chain(other.symbolic.keys()).copied().collect();
+        for page in page_numbers {
+            let (left, right) = match (self.pages.get(&page), other.pages.get(&page)) {
+                (Some(l), Some(r)) if Arc::ptr_eq(l, r) => continue,
+                (l, r) => (l.map(|p| &**p).unwrap_or(&zero_page), r.map(|p| &**p).unwrap_or(&zero_page)),
+            };
+            for offset in 0..PAGE_SIZE as usize {
+                if left[offset] != right[offset] {
+                    addresses.push(page * PAGE_SIZE + offset as u64)
+                }
+            }
+        }
+        addresses.sort_unstable();
+        addresses.dedup();
+        addresses
+    }
+}
+
+impl fmt::Debug for Pages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut pages: Vec<u64> = self.pages.keys().map(|page| page * PAGE_SIZE).collect();
+        pages.sort_unstable();
+        f.debug_list().entries(pages.iter().map(|addr| format!("0x{:x}", addr))).finish()
+    }
+}
+
+impl FromIterator<(Address, u8)> for Pages {
+    fn from_iter<I: IntoIterator<Item = (Address, u8)>>(iter: I) -> Self {
+        let mut pages = Pages::new();
+        for (address, byte) in iter {
+            pages.write_byte(address, byte)
+        }
+        pages
+    }
+}
+
+impl From<HashMap<Address, u8>> for Pages {
+    fn from(bytes: HashMap<Address, u8>) -> Self {
+        bytes.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_across_pages() {
+        let bytes: Vec<u8> = (0..32).collect();
+        let pages = Pages::from_bytes(PAGE_SIZE - 16, &bytes);
+        assert_eq!(pages.num_pages(), 2);
+
+        let mut buf = [0xff; 40];
+        pages.read(PAGE_SIZE - 20, &mut buf);
+        assert_eq!(&buf[0..4], &[0, 0, 0, 0]);
+        assert_eq!(&buf[4..36], &bytes[..]);
+        assert_eq!(&buf[36..], &[0, 0, 0, 0]);
+        assert_eq!(pages.read_byte(PAGE_SIZE), 16)
+    }
+
+    #[test]
+    fn copy_on_write() {
+        let mut pages = Pages::from_bytes(0, &[1; 2 * PAGE_SIZE as usize]);
+        let shared = pages.clone();
+        pages.write_byte(1, 2);
+
+        assert_eq!(pages.read_byte(1), 2);
+        assert_eq!(shared.read_byte(1), 1);
+        assert!(!Arc::ptr_eq(&pages.pages[&0], &shared.pages[&0]));
+        assert!(Arc::ptr_eq(&pages.pages[&1], &shared.pages[&1]))
+    }
+
+    #[test]
+    fn top_of_memory() {
+        let mut pages = Pages::new();
+        pages.write(u64::MAX - 3, &[1, 2, 3, 4]);
+        pages.write(u64::MAX, &[5, 6]);
+
+        let mut buf = [0; 4];
+        pages.read(u64::MAX - 3, &mut buf);
+        assert_eq!(buf, [1, 2, 3, 5]);
+        assert_eq!(pages.read_byte(0), 6)
+    }
+
+    #[test]
+    fn clone_shares_page_table() {
+        let mut pages = Pages::from_bytes(0, &[1; 2 * PAGE_SIZE as usize]);
+        let shared = pages.clone();
+        assert!(Arc::ptr_eq(&pages.pages, &shared.pages));
+        pages.write_byte(0, 2);
+        assert!(!Arc::ptr_eq(&pages.pages, &shared.pages))
+    }
+
+    #[test]
+    fn differences() {
+        let mut pages = Pages::from_bytes(0, &[1; 2 * PAGE_SIZE as usize]);
+        let mut other = pages.clone();
+        other.write(PAGE_SIZE - 1, &[3, 3]);
+        other.write_byte(4 * PAGE_SIZE, 0);
+        pages.write_byte(8 * PAGE_SIZE, 2);
+
+        assert_eq!(pages.differences(&other), vec![PAGE_SIZE - 1, PAGE_SIZE, 8 * PAGE_SIZE])
+    }
+
+    #[test]
+    fn symbolic_bytes() {
+        let mut pages = Pages::from_bytes(0, &[1; 8]);
+        pages.write_symbolic_byte(2, Sym::from_u32(0));
+        pages.write_symbolic_byte(3, Sym::from_u32(1));
+        assert!(pages.any_symbolic(0, 3) && !pages.any_symbolic(4, 4));
+        assert_eq!(pages.symbolic_byte(3), Some(Sym::from_u32(1)));
+        assert_eq!(pages.read_byte(2), 0);
+
+        let other = pages.clone();
+        pages.write(3, &[1]);
+        assert_eq!(pages.symbolic_byte(3), None);
+        assert_eq!(pages.differences(&other), vec![2, 3])
+    }
+}
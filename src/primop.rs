@@ -43,6 +43,7 @@
 
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
+use std::num::IntErrorKind;
 use std::ops::{BitAnd, BitOr, Not, Shl, Shr};
 use std::str::FromStr;
 
@@ -52,6 +53,7 @@ use crate::executor::LocalFrame;
 use crate::ir::{UVal, Val, ELF_ENTRY};
 use crate::smt::smtlib::*;
 use crate::smt::*;
+use crate::softfloat;
 
 pub type Unary<B> = fn(Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError>;
 pub type Binary<B> = fn(Val<B>, Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError>;
@@ -119,6 +121,225 @@ pub fn smt_sbits<B: BV>(bv: B) -> Exp {
     }
 }
 
+/// Convert a concrete integer into an SMT expression of the sort the
+/// solver uses for Sail integers, which is either a 128-bit bitvector
+/// or, with the `unbounded_ints` option, the SMT integer sort.
+pub fn smt_int<B: BV>(i: i128, solver: &Solver<B>) -> Exp {
+    if solver.options().unbounded_ints {
+        Exp::Int(i)
+    } else {
+        smt_i128(i)
+    }
+}
+
+/// The SMT sort used for symbolic Sail integers
+fn int_ty<B: BV>(solver: &Solver<B>) -> Ty {
+    if solver.options().unbounded_ints {
+        Ty::Int
+    } else {
+        Ty::BitVec(128)
+    }
+}
+
+/// Like [smt_value], but converting integers with [smt_int]
+fn solver_value<B: BV>(v: &Val<B>, solver: &Solver<B>) -> Result<Exp, ExecError> {
+    match v {
+        Val::I128(n) => Ok(smt_int(*n, solver)),
+        _ => smt_value(v),
+    }
+}
+
+fn int_exp<B: BV>(v: &Val<B>, solver: &Solver<B>) -> Option<Exp> {
+    match v {
+        Val::I128(n) => Some(smt_int(*n, solver)),
+        Val::Symbolic(v) => Some(Exp::Var(*v)),
+        _ => None,
+    }
+}
+
+/// Operations on bitvectors treat symbolic integers as 128-bit
+/// bitvectors, so convert any integer variable that uses the SMT
+/// integer sort. Variables that are already bitvectors (such as
+/// 64-bit machine integers) are left as-is.
+fn int_bits<B: BV>(v: Sym, solver: &Solver<B>) -> Exp {
+    if solver.options().unbounded_ints && solver.length(v).is_none() {
+        Exp::Int2BV(128, Box::new(Exp::Var(v)))
+    } else {
+        Exp::Var(v)
+    }
+}
+
+/// Convert a bitvector expression of the given length into a Sail integer
+fn bits_int<B: BV>(bits: Exp, length: u32, signed: bool, solver: &Solver<B>) -> Exp {
+    if !solver.options().unbounded_ints {
+        assert!(length < 128);
+        if signed {
+            Exp::SignExtend(128 - length, Box::new(bits))
+        } else {
+            Exp::ZeroExtend(128 - length, Box::new(bits))
+        }
+    } else if signed {
+        signed_bits_to_int(bits, length)
+    } else {
+        Exp::BV2Nat(Box::new(bits))
+    }
+}
+
+/// Interpret a bitvector as a two's complement SMT integer
+fn signed_bits_to_int(bits: Exp, length: u32) -> Exp {
+    // The sign bit is worth -2^(length - 1), which we compute
+    // with a shift so it cannot overflow for 128-bit values
+    let sign_bit =
+        Exp::Eq(Box::new(Exp::Extract(length - 1, length - 1, Box::new(bits.clone()))), Box::new(Exp::Bits64(1, 1)));
+    let sign = Exp::Ite(Box::new(sign_bit), Box::new(Exp::Int(i128::MIN >> (128 - length))), Box::new(Exp::Int(0)));
+    if length == 1 {
+        sign
+    } else {
+        let rest = Exp::BV2Nat(Box::new(Exp::Extract(length - 2, 0, Box::new(bits))));
+        Exp::IntAdd(Box::new(rest), Box::new(sign))
+    }
+}
+
+/// Generate functions which build SMT operations on Sail integers
+/// using either bitvector or integer operations.
+macro_rules! int_op {
+    ($f:ident, $bv_op:path, $int_op:path) => {
+        fn $f<B: BV>(x: Exp, y: Exp, solver: &Solver<B>) -> Exp {
+            if solver.options().unbounded_ints {
+                $int_op(Box::new(x), Box::new(y))
+            } else {
+                $bv_op(Box::new(x), Box::new(y))
+            }
+        }
+    };
+}
+
+int_op!(smt_int_add, Exp::Bvadd, Exp::IntAdd);
+int_op!(smt_int_sub, Exp::Bvsub, Exp::IntSub);
+int_op!(smt_int_mul, Exp::Bvmul, Exp::IntMul);
+int_op!(smt_int_lt, Exp::Bvslt, Exp::IntLt);
+int_op!(smt_int_le, Exp::Bvsle, Exp::IntLe);
+int_op!(smt_int_gt, Exp::Bvsgt, Exp::IntGt);
+int_op!(smt_int_ge, Exp::Bvsge, Exp::IntGe);
+
+fn smt_int_eq<B: BV>(x: Exp, y: Exp, _: &Solver<B>) -> Exp {
+    Exp::Eq(Box::new(x), Box::new(y))
+}
+
+fn smt_int_neg<B: BV>(x: Exp, solver: &Solver<B>) -> Exp {
+    if solver.options().unbounded_ints {
+        Exp::IntNeg(Box::new(x))
+    } else {
+        Exp::Bvneg(Box::new(x))
+    }
+}
+
+fn smt_int_abs<B: BV>(x: Exp, solver: &Solver<B>) -> Exp {
+    Exp::Ite(
+        Box::new(smt_int_lt(x.clone(), smt_int(0, solver), solver)),
+        Box::new(smt_int_neg(x.clone(), solver)),
+        Box::new(x),
+    )
+}
+
+/// Division rounding towards zero. SMT integer division is Euclidean,
+/// so we divide the absolute values and then fix up the sign.
+fn smt_int_tdiv<B: BV>(x: Exp, y: Exp, solver: &Solver<B>) -> Exp {
+    if solver.options().unbounded_ints {
+        let quotient = Exp::IntDiv(Box::new(smt_int_abs(x.clone(), solver)), Box::new(smt_int_abs(y.clone(), solver)));
+        let negative = Exp::Neq(
+            Box::new(smt_int_lt(x, smt_int(0, solver), solver)),
+            Box::new(smt_int_lt(y, smt_int(0, solver), solver)),
+        );
+        Exp::Ite(Box::new(negative), Box::new(Exp::IntNeg(Box::new(quotient.clone()))), Box::new(quotient))
+    } else {
+        Exp::Bvsdiv(Box::new(x), Box::new(y))
+    }
+}
+
+/// `udiv_int` truncates like `wrapping_div` when its arguments are
+/// concrete, so unbounded integers use truncating division rather
+/// than Euclidean SMT integer division.
+fn smt_int_udiv<B: BV>(x: Exp, y: Exp, solver: &Solver<B>) -> Exp {
+    if solver.options().unbounded_ints {
+        smt_int_tdiv(x, y, solver)
+    } else {
+        Exp::Bvudiv(Box::new(x), Box::new(y))
+    }
+}
+
+fn smt_int_tmod<B: BV>(x: Exp, y: Exp, solver: &Solver<B>) -> Exp {
+    if solver.options().unbounded_ints {
+        let quotient = smt_int_tdiv(x.clone(), y.clone(), solver);
+        Exp::IntSub(Box::new(x), Box::new(Exp::IntMul(Box::new(y), Box::new(quotient))))
+    } else {
+        Exp::Bvsmod(Box::new(x), Box::new(y))
+    }
+}
+
+/// There is no exponentiation in the SMT integer theory, so shifts of
+/// unbounded integers multiply or divide by a power of two, which
+/// requires the shift amount to be concrete.
+fn smt_int_pow2<B: BV>(y: &Exp, op: &'static str, solver: &Solver<B>) -> Result<Exp, ExecError> {
+    match solver.eval_closed(y) {
+        Ok(Exp::Int(n)) if (0..127).contains(&n) => Ok(Exp::Int(1 << n)),
+        Ok(Exp::Int(_)) => Err(ExecError::Overflow),
+        _ => Err(ExecError::SymbolicExponent(op)),
+    }
+}
+
+fn smt_int_shl<B: BV>(x: Exp, y: Exp, solver: &Solver<B>) -> Result<Exp, ExecError> {
+    if solver.options().unbounded_ints {
+        Ok(Exp::IntMul(Box::new(x), Box::new(smt_int_pow2(&y, "shl_int", solver)?)))
+    } else {
+        Ok(Exp::Bvshl(Box::new(x), Box::new(y)))
+    }
+}
+
+/// Euclidean division by a positive divisor rounds down, so this is
+/// an arithmetic shift
+fn smt_int_shr<B: BV>(x: Exp, y: Exp, solver: &Solver<B>) -> Result<Exp, ExecError> {
+    if solver.options().unbounded_ints {
+        Ok(Exp::IntDiv(Box::new(x), Box::new(smt_int_pow2(&y, "shr_int", solver)?)))
+    } else {
+        Ok(Exp::Bvashr(Box::new(x), Box::new(y)))
+    }
+}
+
+/// Integer primops take an additional concrete operation, which is
+/// used when both arguments are concrete.
+macro_rules! int_primop {
+    ($f:ident, $name:expr, $wrap:path, $concrete_op:expr, $smt_op:path) => {
+        pub(crate) fn $f<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+            match (&x, &y) {
+                (Val::I128(x), Val::I128(y)) => Ok($wrap(($concrete_op)(*x, *y))),
+                _ => match (int_exp(&x, solver), int_exp(&y, solver)) {
+                    (Some(x), Some(y)) => solver.define_const($smt_op(x, y, solver)).into(),
+                    _ => Err(ExecError::Type(format!("{} {:?} {:?}", $name, &x, &y))),
+                },
+            }
+        }
+    };
+}
+
+/// As `int_primop`, but for SMT operations which can fail
+macro_rules! fallible_int_primop {
+    ($f:ident, $name:expr, $wrap:path, $concrete_op:expr, $smt_op:path) => {
+        pub(crate) fn $f<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+            match (&x, &y) {
+                (Val::I128(x), Val::I128(y)) => Ok($wrap(($concrete_op)(*x, *y))),
+                _ => match (int_exp(&x, solver), int_exp(&y, solver)) {
+                    (Some(x), Some(y)) => {
+                        let exp = $smt_op(x, y, solver)?;
+                        solver.define_const(exp).into()
+                    }
+                    _ => Err(ExecError::Type(format!("{} {:?} {:?}", $name, &x, &y))),
+                },
+            }
+        }
+    };
+}
+
 macro_rules! unary_primop_copy {
     ($f:ident, $name:expr, $unwrap:path, $wrap:path, $concrete_op:path, $smt_op:path) => {
         pub(crate) fn $f<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
@@ -200,7 +421,7 @@ fn optimistic_assert<B: BV>(x: Val<B>, message: Val<B>, solver: &mut Solver<B>)
             let test_true = Box::new(Exp::Var(v));
             let can_be_true = solver.check_sat_with(&test_true).is_sat()?;
             if can_be_true {
-                solver.add(Def::Assert(Exp::Var(v)));
+                solver.add_labelled(Exp::Var(v), message);
                 Ok(Val::Unit)
             } else {
                 Err(ExecError::AssertionFailed(message))
@@ -249,7 +470,7 @@ fn pessimistic_assert<B: BV>(x: Val<B>, message: Val<B>, solver: &mut Solver<B>)
 fn i64_to_i128<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
     match x {
         Val::I64(x) => Ok(Val::I128(i128::from(x))),
-        Val::Symbolic(x) => solver.define_const(Exp::SignExtend(64, Box::new(Exp::Var(x)))).into(),
+        Val::Symbolic(x) => solver.define_const(bits_int(Exp::Var(x), 64, true, solver)).into(),
         _ => Err(ExecError::Type(format!("%i64->%i {:?}", &x))),
     }
 }
@@ -260,7 +481,7 @@ fn i128_to_i64<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecE
             Ok(y) => Ok(Val::I64(y)),
             Err(_) => Err(ExecError::Overflow),
         },
-        Val::Symbolic(x) => solver.define_const(Exp::Extract(63, 0, Box::new(Exp::Var(x)))).into(),
+        Val::Symbolic(x) => solver.define_const(Exp::Extract(63, 0, Box::new(int_bits(x, solver)))).into(),
         _ => Err(ExecError::Type(format!("%i->%i64 {:?}", &x))),
     }
 }
@@ -362,60 +583,56 @@ pub(crate) fn op_signed<B: BV>(bits: Val<B>, solver: &mut Solver<B>) -> Result<V
 unary_primop_copy!(not_bool, "not".to_string(), Val::Bool, Val::Bool, bool::not, Exp::Not);
 binary_primop_copy!(and_bool, "and_bool".to_string(), Val::Bool, Val::Bool, bool::bitand, Exp::And, Exp::Bool);
 binary_primop_copy!(or_bool, "or_bool".to_string(), Val::Bool, Val::Bool, bool::bitor, Exp::Or, Exp::Bool);
-binary_primop!(eq_int, "eq_int".to_string(), Val::I128, Val::Bool, i128::eq, Exp::Eq, smt_i128);
+int_primop!(eq_int, "eq_int", Val::Bool, |x, y| x == y, smt_int_eq);
 binary_primop!(eq_bool, "eq_bool".to_string(), Val::Bool, Val::Bool, bool::eq, Exp::Eq, Exp::Bool);
-binary_primop!(lteq_int, "lteq".to_string(), Val::I128, Val::Bool, i128::le, Exp::Bvsle, smt_i128);
-binary_primop!(gteq_int, "gteq".to_string(), Val::I128, Val::Bool, i128::ge, Exp::Bvsge, smt_i128);
-binary_primop!(lt_int, "lt".to_string(), Val::I128, Val::Bool, i128::lt, Exp::Bvslt, smt_i128);
-binary_primop!(gt_int, "gt".to_string(), Val::I128, Val::Bool, i128::gt, Exp::Bvsgt, smt_i128);
+int_primop!(lteq_int, "lteq", Val::Bool, |x, y| x <= y, smt_int_le);
+int_primop!(gteq_int, "gteq", Val::Bool, |x, y| x >= y, smt_int_ge);
+int_primop!(lt_int, "lt", Val::Bool, |x, y| x < y, smt_int_lt);
+int_primop!(gt_int, "gt", Val::Bool, |x, y| x > y, smt_int_gt);
 
 fn abs_int<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
     match x {
         Val::I128(x) => Ok(Val::I128(x.abs())),
-        Val::Symbolic(x) => {
-            let y = solver.fresh();
-            solver.add(Def::DefineConst(
-                y,
-                Exp::Ite(
-                    Box::new(Exp::Bvslt(Box::new(Exp::Var(x)), Box::new(smt_i128(0)))),
-                    Box::new(Exp::Bvneg(Box::new(Exp::Var(x)))),
-                    Box::new(Exp::Var(x)),
-                ),
-            ));
-            Ok(Val::Symbolic(y))
-        }
+        Val::Symbolic(x) => solver.define_const(smt_int_abs(Exp::Var(x), solver)).into(),
         _ => Err(ExecError::Type(format!("abs_int {:?}", &x))),
     }
 }
 
 // Arithmetic operations
 
-binary_primop_copy!(sub_int, "sub_int".to_string(), Val::I128, Val::I128, i128::wrapping_sub, Exp::Bvsub, smt_i128);
-binary_primop_copy!(mult_int, "mult_int".to_string(), Val::I128, Val::I128, i128::wrapping_mul, Exp::Bvmul, smt_i128);
-unary_primop_copy!(neg_int, "neg_int".to_string(), Val::I128, Val::I128, i128::wrapping_neg, Exp::Bvneg);
-binary_primop_copy!(tdiv_int, "tdiv_int".to_string(), Val::I128, Val::I128, i128::wrapping_div, Exp::Bvsdiv, smt_i128);
-binary_primop_copy!(tmod_int, "tmod_int".to_string(), Val::I128, Val::I128, i128::wrapping_rem, Exp::Bvsmod, smt_i128);
-binary_primop_copy!(shl_int, "shl_int".to_string(), Val::I128, Val::I128, i128::shl, Exp::Bvshl, smt_i128);
-binary_primop_copy!(shr_int, "shr_int".to_string(), Val::I128, Val::I128, i128::shr, Exp::Bvashr, smt_i128);
+int_primop!(sub_int, "sub_int", Val::I128, i128::wrapping_sub, smt_int_sub);
+int_primop!(mult_int, "mult_int", Val::I128, i128::wrapping_mul, smt_int_mul);
+int_primop!(tdiv_int, "tdiv_int", Val::I128, i128::wrapping_div, smt_int_tdiv);
+int_primop!(tmod_int, "tmod_int", Val::I128, i128::wrapping_rem, smt_int_tmod);
+fallible_int_primop!(shl_int, "shl_int", Val::I128, i128::shl, smt_int_shl);
+fallible_int_primop!(shr_int, "shr_int", Val::I128, i128::shr, smt_int_shr);
+
+fn neg_int<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    match x {
+        Val::Symbolic(x) => solver.define_const(smt_int_neg(Exp::Var(x), solver)).into(),
+        Val::I128(x) => Ok(Val::I128(x.wrapping_neg())),
+        _ => Err(ExecError::Type(format!("neg_int {:?}", &x))),
+    }
+}
 binary_primop_copy!(shl_mach_int, "shl_mach_int".to_string(), Val::I64, Val::I64, i64::shl, Exp::Bvshl, smt_i64);
 binary_primop_copy!(shr_mach_int, "shr_mach_int".to_string(), Val::I64, Val::I64, i64::shr, Exp::Bvashr, smt_i64);
-binary_primop_copy!(udiv_int, "udiv_int".to_string(), Val::I128, Val::I128, i128::wrapping_div, Exp::Bvudiv, smt_i128);
+int_primop!(udiv_int, "udiv_int", Val::I128, i128::wrapping_div, smt_int_udiv);
 
 pub(crate) fn add_int<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
     match (x, y) {
         (Val::Symbolic(x), Val::Symbolic(y)) => {
-            solver.define_const(Exp::Bvadd(Box::new(Exp::Var(x)), Box::new(Exp::Var(y)))).into()
+            solver.define_const(smt_int_add(Exp::Var(x), Exp::Var(y), solver)).into()
         }
         (Val::Symbolic(x), Val::I128(y)) => {
             if y != 0 {
-                solver.define_const(Exp::Bvadd(Box::new(Exp::Var(x)), Box::new(smt_i128(y)))).into()
+                solver.define_const(smt_int_add(Exp::Var(x), smt_int(y, solver), solver)).into()
             } else {
                 Ok(Val::Symbolic(x))
             }
         }
         (Val::I128(x), Val::Symbolic(y)) => {
             if x != 0 {
-                solver.define_const(Exp::Bvadd(Box::new(smt_i128(x)), Box::new(Exp::Var(y)))).into()
+                solver.define_const(smt_int_add(smt_int(x, solver), Exp::Var(y), solver)).into()
             } else {
                 Ok(Val::Symbolic(y))
             }
@@ -427,19 +644,18 @@ pub(crate) fn add_int<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Re
 
 macro_rules! symbolic_compare {
     ($op: path, $x: expr, $y: expr, $solver: ident) => {{
-        let z = $solver.fresh();
-        $solver
-            .add(Def::DefineConst(z, Exp::Ite(Box::new($op(Box::new($x), Box::new($y))), Box::new($x), Box::new($y))));
-        Ok(Val::Symbolic(z))
+        let (x, y) = ($x, $y);
+        let cond = $op(x.clone(), y.clone(), $solver);
+        $solver.define_const(Exp::Ite(Box::new(cond), Box::new(x), Box::new(y))).into()
     }};
 }
 
 fn max_int<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
     match (x, y) {
         (Val::I128(x), Val::I128(y)) => Ok(Val::I128(i128::max(x, y))),
-        (Val::I128(x), Val::Symbolic(y)) => symbolic_compare!(Exp::Bvsgt, smt_i128(x), Exp::Var(y), solver),
-        (Val::Symbolic(x), Val::I128(y)) => symbolic_compare!(Exp::Bvsgt, Exp::Var(x), smt_i128(y), solver),
-        (Val::Symbolic(x), Val::Symbolic(y)) => symbolic_compare!(Exp::Bvsgt, Exp::Var(x), Exp::Var(y), solver),
+        (Val::I128(x), Val::Symbolic(y)) => symbolic_compare!(smt_int_gt, smt_int(x, solver), Exp::Var(y), solver),
+        (Val::Symbolic(x), Val::I128(y)) => symbolic_compare!(smt_int_gt, Exp::Var(x), smt_int(y, solver), solver),
+        (Val::Symbolic(x), Val::Symbolic(y)) => symbolic_compare!(smt_int_gt, Exp::Var(x), Exp::Var(y), solver),
         (x, y) => Err(ExecError::Type(format!("max_int {:?} {:?}", &x, &y))),
     }
 }
@@ -447,9 +663,9 @@ fn max_int<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>
 fn min_int<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
     match (x, y) {
         (Val::I128(x), Val::I128(y)) => Ok(Val::I128(i128::min(x, y))),
-        (Val::I128(x), Val::Symbolic(y)) => symbolic_compare!(Exp::Bvslt, smt_i128(x), Exp::Var(y), solver),
-        (Val::Symbolic(x), Val::I128(y)) => symbolic_compare!(Exp::Bvslt, Exp::Var(x), smt_i128(y), solver),
-        (Val::Symbolic(x), Val::Symbolic(y)) => symbolic_compare!(Exp::Bvslt, Exp::Var(x), Exp::Var(y), solver),
+        (Val::I128(x), Val::Symbolic(y)) => symbolic_compare!(smt_int_lt, smt_int(x, solver), Exp::Var(y), solver),
+        (Val::Symbolic(x), Val::I128(y)) => symbolic_compare!(smt_int_lt, Exp::Var(x), smt_int(y, solver), solver),
+        (Val::Symbolic(x), Val::Symbolic(y)) => symbolic_compare!(smt_int_lt, Exp::Var(x), Exp::Var(y), solver),
         (x, y) => Err(ExecError::Type(format!("max_int {:?} {:?}", &x, &y))),
     }
 }
@@ -457,7 +673,10 @@ fn min_int<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>
 fn pow2<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
     match x {
         Val::I128(x) => Ok(Val::I128(1 << x)),
-        Val::Symbolic(x) => solver.define_const(Exp::Bvshl(Box::new(smt_i128(1)), Box::new(Exp::Var(x)))).into(),
+        Val::Symbolic(x) => {
+            let exp = smt_int_shl(smt_int(1, solver), Exp::Var(x), solver)?;
+            solver.define_const(exp).into()
+        }
         _ => Err(ExecError::Type(format!("pow2 {:?}", &x))),
     }
 }
@@ -470,18 +689,12 @@ fn pow_int<B: BV>(x: Val<B>, y: Val<B>, _solver: &mut Solver<B>) -> Result<Val<B
 }
 
 fn sub_nat<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
-    match (x, y) {
+    match (&x, &y) {
         (Val::I128(x), Val::I128(y)) => Ok(Val::I128(i128::max(x - y, 0))),
-        (Val::I128(x), Val::Symbolic(y)) => {
-            symbolic_compare!(Exp::Bvsgt, Exp::Bvsub(Box::new(smt_i128(x)), Box::new(Exp::Var(y))), smt_i128(0), solver)
-        }
-        (Val::Symbolic(x), Val::I128(y)) => {
-            symbolic_compare!(Exp::Bvsgt, Exp::Bvsub(Box::new(Exp::Var(x)), Box::new(smt_i128(y))), smt_i128(0), solver)
-        }
-        (Val::Symbolic(x), Val::Symbolic(y)) => {
-            symbolic_compare!(Exp::Bvsgt, Exp::Bvsub(Box::new(Exp::Var(x)), Box::new(Exp::Var(y))), smt_i128(0), solver)
-        }
-        (x, y) => Err(ExecError::Type(format!("sub_nat {:?} {:?}", &x, &y))),
+        _ => match (int_exp(&x, solver), int_exp(&y, solver)) {
+            (Some(x), Some(y)) => symbolic_compare!(smt_int_gt, smt_int_sub(x, y, solver), smt_int(0, solver), solver),
+            _ => Err(ExecError::Type(format!("sub_nat {:?} {:?}", &x, &y))),
+        },
     }
 }
 
@@ -542,7 +755,7 @@ fn add_bits_int<B: BV>(bits: Val<B>, n: Val<B>, solver: &mut Solver<B>) -> Resul
             assert!(len <= 128);
             solver.add(Def::DefineConst(
                 result,
-                Exp::Bvadd(Box::new(Exp::Var(bits)), Box::new(Exp::Extract(len - 1, 0, Box::new(Exp::Var(n))))),
+                Exp::Bvadd(Box::new(Exp::Var(bits)), Box::new(Exp::Extract(len - 1, 0, Box::new(int_bits(n, solver))))),
             ));
             Ok(Val::Symbolic(result))
         }
@@ -585,7 +798,7 @@ fn sub_bits_int<B: BV>(bits: Val<B>, n: Val<B>, solver: &mut Solver<B>) -> Resul
             assert!(len <= 128);
             solver.add(Def::DefineConst(
                 result,
-                Exp::Bvsub(Box::new(Exp::Var(bits)), Box::new(Exp::Extract(len - 1, 0, Box::new(Exp::Var(n))))),
+                Exp::Bvsub(Box::new(Exp::Var(bits)), Box::new(Exp::Extract(len - 1, 0, Box::new(int_bits(n, solver))))),
             ));
             Ok(Val::Symbolic(result))
         }
@@ -733,11 +946,11 @@ macro_rules! slice {
                 // 0. We therefore need to make from the correct
                 // length so the bvlshr is type-correct.
                 let shift = if $bits_length > 128 {
-                    Exp::ZeroExtend($bits_length - 128, Box::new(Exp::Var(from)))
+                    Exp::ZeroExtend($bits_length - 128, Box::new(int_bits(from, $solver)))
                 } else if $bits_length < 128 {
-                    Exp::Extract($bits_length - 1, 0, Box::new(Exp::Var(from)))
+                    Exp::Extract($bits_length - 1, 0, Box::new(int_bits(from, $solver)))
                 } else {
-                    Exp::Var(from)
+                    int_bits(from, $solver)
                 };
                 $solver.add(Def::DefineConst(
                     sliced,
@@ -892,10 +1105,7 @@ fn sail_unsigned<B: BV>(bits: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>,
     match bits {
         Val::Bits(bits) => Ok(Val::I128(bits.unsigned())),
         Val::Symbolic(bits) => match solver.length(bits) {
-            Some(length) => {
-                assert!(length < 128);
-                solver.define_const(Exp::ZeroExtend(128 - length, Box::new(Exp::Var(bits)))).into()
-            }
+            Some(length) => solver.define_const(bits_int(Exp::Var(bits), length, false, solver)).into(),
             None => Err(ExecError::Type(format!("sail_unsigned (solver cannot determine length) {:?}", &bits))),
         },
         _ => Err(ExecError::Type(format!("sail_unsigned {:?}", &bits))),
@@ -906,10 +1116,7 @@ fn sail_signed<B: BV>(bits: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, Ex
     match bits {
         Val::Bits(bits) => Ok(Val::I128(bits.signed())),
         Val::Symbolic(bits) => match solver.length(bits) {
-            Some(length) => {
-                assert!(length < 128);
-                solver.define_const(Exp::SignExtend(128 - length, Box::new(Exp::Var(bits)))).into()
-            }
+            Some(length) => solver.define_const(bits_int(Exp::Var(bits), length, true, solver)).into(),
             None => Err(ExecError::Type(format!("sail_signed (solver cannot determine length) {:?}", &bits))),
         },
         _ => Err(ExecError::Type(format!("sail_signed {:?}", &bits))),
@@ -921,11 +1128,11 @@ fn shiftr<B: BV>(bits: Val<B>, shift: Val<B>, solver: &mut Solver<B>) -> Result<
         (Val::Symbolic(x), Val::Symbolic(y)) => match solver.length(x) {
             Some(length) => {
                 let shift = if length < 128 {
-                    Exp::Extract(length - 1, 0, Box::new(Exp::Var(y)))
+                    Exp::Extract(length - 1, 0, Box::new(int_bits(y, solver)))
                 } else if length > 128 {
-                    Exp::ZeroExtend(length - 128, Box::new(Exp::Var(y)))
+                    Exp::ZeroExtend(length - 128, Box::new(int_bits(y, solver)))
                 } else {
-                    Exp::Var(y)
+                    int_bits(y, solver)
                 };
                 solver.define_const(Exp::Bvlshr(Box::new(Exp::Var(x)), Box::new(shift))).into()
             }
@@ -948,7 +1155,7 @@ fn shiftr<B: BV>(bits: Val<B>, shift: Val<B>, solver: &mut Solver<B>) -> Result<
         (Val::Bits(x), Val::Symbolic(y)) => solver
             .define_const(Exp::Bvlshr(
                 Box::new(smt_sbits(x)),
-                Box::new(Exp::Extract(x.len() - 1, 0, Box::new(Exp::Var(y)))),
+                Box::new(Exp::Extract(x.len() - 1, 0, Box::new(int_bits(y, solver)))),
             ))
             .into(),
         (Val::Bits(x), Val::I128(y)) => Ok(Val::Bits(x.shiftr(y))),
@@ -961,11 +1168,11 @@ fn arith_shiftr<B: BV>(bits: Val<B>, shift: Val<B>, solver: &mut Solver<B>) -> R
         (Val::Symbolic(x), Val::Symbolic(y)) => match solver.length(x) {
             Some(length) => {
                 let shift = if length < 128 {
-                    Exp::Extract(length - 1, 0, Box::new(Exp::Var(y)))
+                    Exp::Extract(length - 1, 0, Box::new(int_bits(y, solver)))
                 } else if length > 128 {
-                    Exp::ZeroExtend(length - 128, Box::new(Exp::Var(y)))
+                    Exp::ZeroExtend(length - 128, Box::new(int_bits(y, solver)))
                 } else {
-                    Exp::Var(y)
+                    int_bits(y, solver)
                 };
                 solver.define_const(Exp::Bvashr(Box::new(Exp::Var(x)), Box::new(shift))).into()
             }
@@ -988,7 +1195,7 @@ fn arith_shiftr<B: BV>(bits: Val<B>, shift: Val<B>, solver: &mut Solver<B>) -> R
         (Val::Bits(x), Val::Symbolic(y)) => solver
             .define_const(Exp::Bvashr(
                 Box::new(smt_sbits(x)),
-                Box::new(Exp::Extract(x.len() - 1, 0, Box::new(Exp::Var(y)))),
+                Box::new(Exp::Extract(x.len() - 1, 0, Box::new(int_bits(y, solver)))),
             ))
             .into(),
         (Val::Bits(x), Val::I128(y)) => Ok(Val::Bits(x.arith_shiftr(y))),
@@ -1001,11 +1208,11 @@ fn shiftl<B: BV>(bits: Val<B>, len: Val<B>, solver: &mut Solver<B>) -> Result<Va
         (Val::Symbolic(x), Val::Symbolic(y)) => match solver.length(x) {
             Some(length) => {
                 let shift = if length < 128 {
-                    Exp::Extract(length - 1, 0, Box::new(Exp::Var(y)))
+                    Exp::Extract(length - 1, 0, Box::new(int_bits(y, solver)))
                 } else if length > 128 {
-                    Exp::ZeroExtend(length - 128, Box::new(Exp::Var(y)))
+                    Exp::ZeroExtend(length - 128, Box::new(int_bits(y, solver)))
                 } else {
-                    Exp::Var(y)
+                    int_bits(y, solver)
                 };
                 solver.define_const(Exp::Bvshl(Box::new(Exp::Var(x)), Box::new(shift))).into()
             }
@@ -1028,7 +1235,7 @@ fn shiftl<B: BV>(bits: Val<B>, len: Val<B>, solver: &mut Solver<B>) -> Result<Va
         (Val::Bits(x), Val::Symbolic(y)) => solver
             .define_const(Exp::Bvshl(
                 Box::new(smt_sbits(x)),
-                Box::new(Exp::Extract(x.len() - 1, 0, Box::new(Exp::Var(y)))),
+                Box::new(Exp::Extract(x.len() - 1, 0, Box::new(int_bits(y, solver)))),
             ))
             .into(),
         (Val::Bits(x), Val::I128(y)) => Ok(Val::Bits(x.shiftl(y))),
@@ -1112,11 +1319,11 @@ pub(crate) fn vector_access<B: BV>(vec: Val<B>, n: Val<B>, solver: &mut Solver<B
         (Val::Symbolic(bits), Val::Symbolic(n)) => match solver.length(bits) {
             Some(length) => {
                 let shift = if length < 128 {
-                    Exp::Extract(length - 1, 0, Box::new(Exp::Var(n)))
+                    Exp::Extract(length - 1, 0, Box::new(int_bits(n, solver)))
                 } else if length > 128 {
-                    Exp::ZeroExtend(length - 128, Box::new(Exp::Var(n)))
+                    Exp::ZeroExtend(length - 128, Box::new(int_bits(n, solver)))
                 } else {
-                    Exp::Var(n)
+                    int_bits(n, solver)
                 };
                 solver
                     .define_const(Exp::Extract(0, 0, Box::new(Exp::Bvlshr(Box::new(Exp::Var(bits)), Box::new(shift)))))
@@ -1140,7 +1347,7 @@ pub(crate) fn vector_access<B: BV>(vec: Val<B>, n: Val<B>, solver: &mut Solver<B
             None => Err(ExecError::Type(format!("vector_access {:?} {:?}", &bits, &n))),
         },
         (Val::Bits(bits), Val::Symbolic(n)) => {
-            let shift = Exp::Extract(bits.len() - 1, 0, Box::new(Exp::Var(n)));
+            let shift = Exp::Extract(bits.len() - 1, 0, Box::new(int_bits(n, solver)));
             solver
                 .define_const(Exp::Extract(0, 0, Box::new(Exp::Bvlshr(Box::new(smt_sbits(bits)), Box::new(shift)))))
                 .into()
@@ -1234,10 +1441,10 @@ fn set_slice_internal<B: BV>(
     let update_length = length_bits(&update, solver)?;
     match (bits, n, update) {
         (Val::Symbolic(bits), Val::Symbolic(n), Val::Symbolic(update)) => {
-            set_slice!(bits_length, update_length, Exp::Var(bits), Exp::Var(n), Exp::Var(update), solver)
+            set_slice!(bits_length, update_length, Exp::Var(bits), int_bits(n, solver), Exp::Var(update), solver)
         }
         (Val::Symbolic(bits), Val::Symbolic(n), Val::Bits(update)) => {
-            set_slice!(bits_length, update_length, Exp::Var(bits), Exp::Var(n), smt_sbits(update), solver)
+            set_slice!(bits_length, update_length, Exp::Var(bits), int_bits(n, solver), smt_sbits(update), solver)
         }
         (Val::Symbolic(bits), Val::I128(n), Val::Symbolic(update)) => {
             if n == 0 {
@@ -1258,10 +1465,10 @@ fn set_slice_internal<B: BV>(
             }
         }
         (Val::Bits(bits), Val::Symbolic(n), Val::Symbolic(update)) => {
-            set_slice!(bits_length, update_length, smt_sbits(bits), Exp::Var(n), Exp::Var(update), solver)
+            set_slice!(bits_length, update_length, smt_sbits(bits), int_bits(n, solver), Exp::Var(update), solver)
         }
         (Val::Bits(bits), Val::Symbolic(n), Val::Bits(update)) => {
-            set_slice!(bits_length, update_length, smt_sbits(bits), Exp::Var(n), smt_sbits(update), solver)
+            set_slice!(bits_length, update_length, smt_sbits(bits), int_bits(n, solver), smt_sbits(update), solver)
         }
         (Val::Bits(bits), Val::I128(n), Val::Symbolic(update)) => {
             if n == 0 {
@@ -1288,32 +1495,32 @@ fn set_slice_int_internal<B: BV>(
     solver: &mut Solver<B>,
 ) -> Result<Val<B>, ExecError> {
     let update_length = length_bits(&update, solver)?;
-    match (int, n, update) {
+    let result = match (int, n, update) {
         (Val::Symbolic(int), Val::Symbolic(n), Val::Symbolic(update)) => {
-            set_slice!(128, update_length, Exp::Var(int), Exp::Var(n), Exp::Var(update), solver)
+            set_slice!(128, update_length, int_bits(int, solver), int_bits(n, solver), Exp::Var(update), solver)
         }
         (Val::Symbolic(int), Val::Symbolic(n), Val::Bits(update)) => {
-            set_slice!(128, update_length, Exp::Var(int), Exp::Var(n), smt_sbits(update), solver)
+            set_slice!(128, update_length, int_bits(int, solver), int_bits(n, solver), smt_sbits(update), solver)
         }
         (Val::Symbolic(int), Val::I128(n), Val::Symbolic(update)) => {
             if n == 0 {
-                set_slice_n0!(128, update_length, Exp::Var(int), Exp::Var(update), solver)
+                set_slice_n0!(128, update_length, int_bits(int, solver), Exp::Var(update), solver)
             } else {
-                set_slice!(128, update_length, Exp::Var(int), smt_i128(n), Exp::Var(update), solver)
+                set_slice!(128, update_length, int_bits(int, solver), smt_i128(n), Exp::Var(update), solver)
             }
         }
         (Val::Symbolic(int), Val::I128(n), Val::Bits(update)) => {
             if n == 0 {
-                set_slice_n0!(128, update_length, Exp::Var(int), smt_sbits(update), solver)
+                set_slice_n0!(128, update_length, int_bits(int, solver), smt_sbits(update), solver)
             } else {
-                set_slice!(128, update_length, Exp::Var(int), smt_i128(n), smt_sbits(update), solver)
+                set_slice!(128, update_length, int_bits(int, solver), smt_i128(n), smt_sbits(update), solver)
             }
         }
         (Val::I128(int), Val::Symbolic(n), Val::Symbolic(update)) => {
-            set_slice!(128, update_length, smt_i128(int), Exp::Var(n), Exp::Var(update), solver)
+            set_slice!(128, update_length, smt_i128(int), int_bits(n, solver), Exp::Var(update), solver)
         }
         (Val::I128(int), Val::Symbolic(n), Val::Bits(update)) => {
-            set_slice!(128, update_length, smt_i128(int), Exp::Var(n), smt_sbits(update), solver)
+            set_slice!(128, update_length, smt_i128(int), int_bits(n, solver), smt_sbits(update), solver)
         }
         (Val::I128(int), Val::I128(n), Val::Symbolic(update)) => {
             if n == 0 {
@@ -1324,6 +1531,13 @@ fn set_slice_int_internal<B: BV>(
         }
         (Val::I128(int), Val::I128(n), Val::Bits(update)) => Ok(Val::I128(B::set_slice_int(int, n as u32, update))),
         (int, n, update) => Err(ExecError::Type(format!("set_slice_int {:?} {:?} {:?}", &int, &n, &update))),
+    }?;
+    // The slice is computed on a 128-bit bitvector, so convert the result back to an integer
+    match result {
+        Val::Symbolic(v) if solver.options().unbounded_ints => {
+            solver.define_const(bits_int(Exp::Var(v), 128, true, solver)).into()
+        }
+        result => Ok(result),
     }
 }
 
@@ -1406,9 +1620,9 @@ pub fn vector_update<B: BV>(
                     solver.add(Def::DefineConst(
                         var,
                         Exp::Ite(
-                            Box::new(Exp::Eq(Box::new(Exp::Var(n)), Box::new(Exp::Bits64(i as u64, 128)))),
-                            Box::new(smt_value(&args[2])?),
-                            Box::new(smt_value(&item)?),
+                            Box::new(Exp::Eq(Box::new(int_bits(n, solver)), Box::new(Exp::Bits64(i as u64, 128)))),
+                            Box::new(solver_value(&args[2], solver)?),
+                            Box::new(solver_value(&item, solver)?),
                         ),
                     ));
                     *item = Val::Symbolic(var);
@@ -1468,7 +1682,7 @@ fn get_slice_int_internal<B: BV>(
 ) -> Result<Val<B>, ExecError> {
     match length {
         Val::I128(length) => match n {
-            Val::Symbolic(n) => slice!(128, Exp::Var(n), from, length, solver),
+            Val::Symbolic(n) => slice!(128, int_bits(n, solver), from, length, solver),
             Val::I128(n) => match from {
                 Val::I128(from) if length <= B::MAX_WIDTH as i128 => {
                     Ok(Val::Bits(B::get_slice_int(length as u32, n, from as u32)))
@@ -1486,10 +1700,6 @@ fn get_slice_int<B: BV>(args: Vec<Val<B>>, solver: &mut Solver<B>, _: &mut Local
     get_slice_int_internal(args[0].clone(), args[1].clone(), args[2].clone(), solver)
 }
 
-fn unimplemented<B: BV>(_: Vec<Val<B>>, _: &mut Solver<B>, _: &mut LocalFrame<B>) -> Result<Val<B>, ExecError> {
-    Err(ExecError::Unimplemented)
-}
-
 fn eq_string<B: BV>(lhs: Val<B>, rhs: Val<B>, _: &mut Solver<B>) -> Result<Val<B>, ExecError> {
     match (lhs, rhs) {
         (Val::String(lhs), Val::String(rhs)) => Ok(Val::Bool(lhs == rhs)),
@@ -1543,10 +1753,10 @@ fn eq_anything<B: BV>(lhs: Val<B>, rhs: Val<B>, solver: &mut Solver<B>) -> Resul
             solver.define_const(Exp::Eq(Box::new(Exp::Var(lhs)), Box::new(Exp::Var(rhs)))).into()
         }
         (lhs, Val::Symbolic(rhs)) => {
-            solver.define_const(Exp::Eq(Box::new(smt_value(&lhs)?), Box::new(Exp::Var(rhs)))).into()
+            solver.define_const(Exp::Eq(Box::new(solver_value(&lhs, solver)?), Box::new(Exp::Var(rhs)))).into()
         }
         (Val::Symbolic(lhs), rhs) => {
-            solver.define_const(Exp::Eq(Box::new(Exp::Var(lhs)), Box::new(smt_value(&rhs)?))).into()
+            solver.define_const(Exp::Eq(Box::new(Exp::Var(lhs)), Box::new(solver_value(&rhs, solver)?))).into()
         }
 
         (Val::Bits(lhs), Val::Bits(rhs)) => Ok(Val::Bool(lhs == rhs)),
@@ -1735,21 +1945,21 @@ fn undefined_bool<B: BV>(_: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, Ex
 }
 
 fn undefined_int<B: BV>(_: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
-    solver.declare_const(Ty::BitVec(128)).into()
+    solver.declare_const(int_ty(solver)).into()
 }
 
 fn undefined_nat<B: BV>(_: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
     let sym = solver.fresh();
-    solver.add(Def::DeclareConst(sym, Ty::BitVec(128)));
-    solver.add(Def::Assert(Exp::Bvsge(Box::new(Exp::Var(sym)), Box::new(smt_i128(0)))));
+    solver.add(Def::DeclareConst(sym, int_ty(solver)));
+    solver.add(Def::Assert(smt_int_ge(Exp::Var(sym), smt_int(0, solver), solver)));
     Ok(Val::Symbolic(sym))
 }
 
 fn undefined_range<B: BV>(lo: Val<B>, hi: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
     let sym = solver.fresh();
-    solver.add(Def::DeclareConst(sym, Ty::BitVec(128)));
-    solver.add(Def::Assert(Exp::Bvsle(Box::new(smt_value(&lo)?), Box::new(Exp::Var(sym)))));
-    solver.add(Def::Assert(Exp::Bvsle(Box::new(Exp::Var(sym)), Box::new(smt_value(&hi)?))));
+    solver.add(Def::DeclareConst(sym, int_ty(solver)));
+    solver.add(Def::Assert(smt_int_le(solver_value(&lo, solver)?, Exp::Var(sym), solver)));
+    solver.add(Def::Assert(smt_int_le(Exp::Var(sym), solver_value(&hi, solver)?, solver)));
     Ok(Val::Symbolic(sym))
 }
 
@@ -1812,15 +2022,15 @@ pub fn smt_value<B: BV>(v: &Val<B>) -> Result<Exp, ExecError> {
     })
 }
 
-fn choice_chain<B: BV>(sym: Sym, n: u64, sz: u32, mut xs: Vec<Val<B>>) -> Result<Exp, ExecError> {
+fn choice_chain<B: BV>(sym: Sym, n: u64, sz: u32, mut xs: Vec<Val<B>>, solver: &Solver<B>) -> Result<Exp, ExecError> {
     if xs.len() == 1 {
-        smt_value(&xs[0])
+        solver_value(&xs[0], solver)
     } else {
         let x = xs.pop().unwrap();
         Ok(Exp::Ite(
             Box::new(Exp::Eq(Box::new(Exp::Var(sym)), Box::new(Exp::Bits64(n, sz)))),
-            Box::new(smt_value(&x)?),
-            Box::new(choice_chain(sym, n + 1, sz, xs)?),
+            Box::new(solver_value(&x, solver)?),
+            Box::new(choice_chain(sym, n + 1, sz, xs, solver)?),
         ))
     }
 }
@@ -1836,7 +2046,7 @@ fn choice<B: BV>(xs: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError
             let sym = solver.fresh();
             let choice = solver.fresh();
             solver.add(Def::DeclareConst(sym, Ty::BitVec(sz)));
-            solver.add(Def::DefineConst(choice, choice_chain(sym, 0, sz, xs)?));
+            solver.add(Def::DefineConst(choice, choice_chain(sym, 0, sz, xs, solver)?));
             Ok(Val::Symbolic(choice))
         }
         _ => Err(ExecError::Type(format!("choice {:?}", &xs))),
@@ -1844,11 +2054,13 @@ fn choice<B: BV>(xs: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError
 }
 
 fn read_mem<B: BV>(args: Vec<Val<B>>, solver: &mut Solver<B>, frame: &mut LocalFrame<B>) -> Result<Val<B>, ExecError> {
-    frame.memory_mut().read(args[0].clone(), args[2].clone(), args[3].clone(), solver, false)
+    let (memory, backtrace) = frame.memory_mut_with_backtrace();
+    memory.read_with_backtrace(args[0].clone(), args[2].clone(), args[3].clone(), solver, false, backtrace)
 }
 
 fn read_memt<B: BV>(args: Vec<Val<B>>, solver: &mut Solver<B>, frame: &mut LocalFrame<B>) -> Result<Val<B>, ExecError> {
-    frame.memory_mut().read(args[0].clone(), args[1].clone(), args[2].clone(), solver, true)
+    let (memory, backtrace) = frame.memory_mut_with_backtrace();
+    memory.read_with_backtrace(args[0].clone(), args[1].clone(), args[2].clone(), solver, true, backtrace)
 }
 
 fn bad_read<B: BV>(_: Val<B>, _: &mut Solver<B>) -> Result<Val<B>, ExecError> {
@@ -1856,7 +2068,8 @@ fn bad_read<B: BV>(_: Val<B>, _: &mut Solver<B>) -> Result<Val<B>, ExecError> {
 }
 
 fn write_mem<B: BV>(args: Vec<Val<B>>, solver: &mut Solver<B>, frame: &mut LocalFrame<B>) -> Result<Val<B>, ExecError> {
-    frame.memory_mut().write(args[0].clone(), args[2].clone(), args[4].clone(), solver, None)
+    let (memory, backtrace) = frame.memory_mut_with_backtrace();
+    memory.write_with_backtrace(args[0].clone(), args[2].clone(), args[4].clone(), solver, None, backtrace)
 }
 
 fn write_memt<B: BV>(
@@ -1864,7 +2077,9 @@ fn write_memt<B: BV>(
     solver: &mut Solver<B>,
     frame: &mut LocalFrame<B>,
 ) -> Result<Val<B>, ExecError> {
-    frame.memory_mut().write(args[0].clone(), args[1].clone(), args[3].clone(), solver, Some(args[4].clone()))
+    let (memory, backtrace) = frame.memory_mut_with_backtrace();
+    let tag = Some(args[4].clone());
+    memory.write_with_backtrace(args[0].clone(), args[1].clone(), args[3].clone(), solver, tag, backtrace)
 }
 
 fn bad_write<B: BV>(_: Val<B>, _: &mut Solver<B>) -> Result<Val<B>, ExecError> {
@@ -2035,7 +2250,12 @@ fn count_leading_zeros<B: BV>(bv: Val<B>, solver: &mut Solver<B>) -> Result<Val<
         Val::Bits(bv) => Ok(Val::I128(bv.leading_zeros() as i128)),
         Val::Symbolic(bv) => {
             if let Some(len) = solver.length(bv) {
-                smt_clz(bv, len, solver).into()
+                let clz = smt_clz(bv, len, solver);
+                if solver.options().unbounded_ints {
+                    solver.define_const(Exp::BV2Nat(Box::new(Exp::Var(clz)))).into()
+                } else {
+                    clz.into()
+                }
             } else {
                 Err(ExecError::Type("count_leading_zeros (solver could not determine length)".to_string()))
             }
@@ -2057,7 +2277,11 @@ fn build_ite<B: BV>(b: Sym, lhs: &Val<B>, rhs: &Val<B>, solver: &mut Solver<B>)
             Ok(Val::Struct(fields?))
         }
         _ => solver
-            .define_const(Exp::Ite(Box::new(Exp::Var(b)), Box::new(smt_value(lhs)?), Box::new(smt_value(rhs)?)))
+            .define_const(Exp::Ite(
+                Box::new(Exp::Var(b)),
+                Box::new(solver_value(lhs, solver)?),
+                Box::new(solver_value(rhs, solver)?),
+            ))
             .into(),
     }
 }
@@ -2071,6 +2295,175 @@ fn ite<B: BV>(args: Vec<Val<B>>, solver: &mut Solver<B>, _: &mut LocalFrame<B>)
     }
 }
 
+// Real numbers
+//
+// Reals have no concrete representation as a value, so they are
+// always bound to variables of the SMT real sort. Reals built from
+// literals are defined by closed expressions, so operations on them
+// are still evaluated to concrete integers and booleans where the
+// result has one. Reasoning about symbolic reals requires a general
+// purpose solver, i.e. the `unbounded_ints` solver option.
+
+fn real_exp<B: BV>(x: &Val<B>, op: &str) -> Result<Exp, ExecError> {
+    match x {
+        Val::Symbolic(v) => Ok(Exp::Var(*v)),
+        _ => Err(ExecError::Type(format!("{} {:?}", op, x))),
+    }
+}
+
+fn smt_real(n: i128) -> Exp {
+    Exp::IntToReal(Box::new(Exp::Int(n)))
+}
+
+/// Bind a real-valued expression to a variable, replacing it by its
+/// value if it is closed
+fn real_value<B: BV>(exp: Exp, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    solver.check_reals()?;
+    let exp = solver.eval_closed(&exp).unwrap_or(exp);
+    solver.define_const(exp).into()
+}
+
+fn real_compare<B: BV>(exp: Exp, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    match solver.eval_closed(&exp) {
+        Ok(Exp::Bool(b)) => Ok(Val::Bool(b)),
+        _ => solver.define_const(exp).into(),
+    }
+}
+
+/// Convert an integer-valued SMT expression into a Sail integer
+fn real_int_value<B: BV>(exp: Exp, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    match solver.eval_closed(&exp) {
+        Ok(Exp::Int(n)) => Ok(Val::I128(n)),
+        _ if solver.options().unbounded_ints => solver.define_const(exp).into(),
+        _ => solver.define_const(Exp::Int2BV(128, Box::new(exp))).into(),
+    }
+}
+
+fn string_to_real<B: BV>(s: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    let s = match s {
+        Val::String(s) => s,
+        _ => return Err(ExecError::Type(format!("%string->%real {:?}", &s))),
+    };
+    let (whole, fraction) = s.split_once('.').unwrap_or((&s, ""));
+    if !fraction.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(ExecError::Type(format!("%string->%real {:?}", s)));
+    }
+    let numerator = i128::from_str(&format!("{}{}", whole, fraction)).map_err(|err| match err.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow => ExecError::Overflow,
+        _ => ExecError::Type(format!("%string->%real {:?}", s)),
+    })?;
+    let denominator =
+        u32::try_from(fraction.len()).ok().and_then(|n| 10_i128.checked_pow(n)).ok_or(ExecError::Overflow)?;
+    real_value(Exp::RealDiv(Box::new(smt_real(numerator)), Box::new(smt_real(denominator))), solver)
+}
+
+/// Convert a Sail integer into an expression of the SMT integer
+/// sort, whether or not the solver is using unbounded integers
+fn smt_int_exp<B: BV>(x: &Val<B>, op: &str, solver: &Solver<B>) -> Result<Exp, ExecError> {
+    match x {
+        Val::I128(n) => Ok(Exp::Int(*n)),
+        Val::Symbolic(v) => match solver.length(*v) {
+            Some(len) => Ok(signed_bits_to_int(Exp::Var(*v), len)),
+            None => Ok(Exp::Var(*v)),
+        },
+        _ => Err(ExecError::Type(format!("{} {:?}", op, x))),
+    }
+}
+
+fn to_real<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    real_value(Exp::IntToReal(Box::new(smt_int_exp(&x, "to_real", solver)?)), solver)
+}
+
+fn neg_real<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    real_value(Exp::IntNeg(Box::new(real_exp(&x, "neg_real")?)), solver)
+}
+
+fn abs_real<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    let x = real_exp(&x, "abs_real")?;
+    let negative = Exp::IntLt(Box::new(x.clone()), Box::new(smt_real(0)));
+    real_value(Exp::Ite(Box::new(negative), Box::new(Exp::IntNeg(Box::new(x.clone()))), Box::new(x)), solver)
+}
+
+/// The square root is unconstrained for negative arguments
+fn sqrt_real<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    let x = real_exp(&x, "sqrt_real")?;
+    solver.check_reals()?;
+    let root = Exp::Var(solver.declare_const(Ty::Real));
+    let is_root = Exp::And(
+        Box::new(Exp::IntGe(Box::new(root.clone()), Box::new(smt_real(0)))),
+        Box::new(Exp::Eq(Box::new(Exp::IntMul(Box::new(root.clone()), Box::new(root.clone()))), Box::new(x.clone()))),
+    );
+    let negative = Exp::IntLt(Box::new(x), Box::new(smt_real(0)));
+    solver.add(Def::Assert(Exp::Or(Box::new(negative), Box::new(is_root))));
+    solver.define_const(root).into()
+}
+
+fn round_down<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    real_int_value(Exp::RealToInt(Box::new(real_exp(&x, "round_down")?)), solver)
+}
+
+fn round_up<B: BV>(x: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    let x = Exp::IntNeg(Box::new(real_exp(&x, "round_up")?));
+    real_int_value(Exp::IntNeg(Box::new(Exp::RealToInt(Box::new(x)))), solver)
+}
+
+fn undefined_real<B: BV>(_: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    solver.check_reals()?;
+    solver.declare_const(Ty::Real).into()
+}
+
+macro_rules! real_binop {
+    ($f:ident, $name:expr, $op:path, $result:ident) => {
+        fn $f<B: BV>(x: Val<B>, y: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+            let exp = $op(Box::new(real_exp(&x, $name)?), Box::new(real_exp(&y, $name)?));
+            $result(exp, solver)
+        }
+    };
+}
+
+real_binop!(add_real, "add_real", Exp::IntAdd, real_value);
+real_binop!(sub_real, "sub_real", Exp::IntSub, real_value);
+real_binop!(mult_real, "mult_real", Exp::IntMul, real_value);
+real_binop!(div_real, "div_real", Exp::RealDiv, real_value);
+real_binop!(eq_real, "eq_real", Exp::Eq, real_compare);
+real_binop!(lt_real, "lt_real", Exp::IntLt, real_compare);
+real_binop!(gt_real, "gt_real", Exp::IntGt, real_compare);
+real_binop!(lteq_real, "lteq_real", Exp::IntLe, real_compare);
+real_binop!(gteq_real, "gteq_real", Exp::IntGe, real_compare);
+
+/// Raise a real to an integer power by repeated squaring. The
+/// exponent must be concrete.
+fn real_power<B: BV>(x: Val<B>, n: Val<B>, solver: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    let n = match solver.eval_closed(&smt_int_exp(&n, "real_power", solver)?) {
+        Ok(Exp::Int(n)) => n,
+        _ => return Err(ExecError::SymbolicExponent("real_power")),
+    };
+    let mut base = real_exp(&x, "real_power")?;
+    let mut result = smt_real(1);
+    let mut k = n.unsigned_abs();
+    while k > 0 {
+        if k & 1 == 1 {
+            result = Exp::IntMul(Box::new(result), Box::new(base.clone()))
+        }
+        k >>= 1;
+        if k > 0 {
+            base = Exp::Var(solver.define_const(Exp::IntMul(Box::new(base.clone()), Box::new(base))))
+        }
+    }
+    if n < 0 {
+        result = Exp::RealDiv(Box::new(smt_real(1)), Box::new(result))
+    }
+    real_value(result, solver)
+}
+
+fn print_real<B: BV>(_prefix: Val<B>, _x: Val<B>, _: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    Ok(Val::Unit)
+}
+
+fn prerr_real<B: BV>(_prefix: Val<B>, _x: Val<B>, _: &mut Solver<B>) -> Result<Val<B>, ExecError> {
+    Ok(Val::Unit)
+}
+
 pub fn unary_primops<B: BV>() -> HashMap<String, Unary<B>> {
     let mut primops = HashMap::new();
     primops.insert("%i64->%i".to_string(), i64_to_i128 as Unary<B>);
@@ -2120,6 +2513,14 @@ pub fn unary_primops<B: BV>() -> HashMap<String, Unary<B>> {
     primops.insert("platform_instr_announce".to_string(), instr_announce as Unary<B>);
     primops.insert("platform_barrier".to_string(), barrier as Unary<B>);
     primops.insert("monomorphize".to_string(), monomorphize as Unary<B>);
+    primops.insert("%string->%real".to_string(), string_to_real as Unary<B>);
+    primops.insert("to_real".to_string(), to_real as Unary<B>);
+    primops.insert("neg_real".to_string(), neg_real as Unary<B>);
+    primops.insert("abs_real".to_string(), abs_real as Unary<B>);
+    primops.insert("sqrt_real".to_string(), sqrt_real as Unary<B>);
+    primops.insert("round_down".to_string(), round_down as Unary<B>);
+    primops.insert("round_up".to_string(), round_up as Unary<B>);
+    primops.insert("undefined_real".to_string(), undefined_real as Unary<B>);
     primops
 }
 
@@ -2194,6 +2595,18 @@ pub fn binary_primops<B: BV>() -> HashMap<String, Binary<B>> {
     primops.insert("prerr_bits".to_string(), prerr_bits as Binary<B>);
     primops.insert("platform_branch_announce".to_string(), branch_announce as Binary<B>);
     primops.insert("mark_register".to_string(), mark_register as Binary<B>);
+    primops.insert("add_real".to_string(), add_real as Binary<B>);
+    primops.insert("sub_real".to_string(), sub_real as Binary<B>);
+    primops.insert("mult_real".to_string(), mult_real as Binary<B>);
+    primops.insert("div_real".to_string(), div_real as Binary<B>);
+    primops.insert("eq_real".to_string(), eq_real as Binary<B>);
+    primops.insert("lt_real".to_string(), lt_real as Binary<B>);
+    primops.insert("gt_real".to_string(), gt_real as Binary<B>);
+    primops.insert("lteq_real".to_string(), lteq_real as Binary<B>);
+    primops.insert("gteq_real".to_string(), gteq_real as Binary<B>);
+    primops.insert("real_power".to_string(), real_power as Binary<B>);
+    primops.insert("print_real".to_string(), print_real as Binary<B>);
+    primops.insert("prerr_real".to_string(), prerr_real as Binary<B>);
     primops
 }
 
@@ -2216,27 +2629,7 @@ pub fn variadic_primops<B: BV>() -> HashMap<String, Variadic<B>> {
     primops.insert("elf_entry".to_string(), elf_entry as Variadic<B>);
     primops.insert("ite".to_string(), ite as Variadic<B>);
     primops.insert("mark_register_pair".to_string(), mark_register_pair as Variadic<B>);
-    // We explicitly don't handle anything real number related right now
-    primops.insert("%string->%real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("neg_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("mult_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("sub_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("add_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("div_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("sqrt_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("abs_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("round_down".to_string(), unimplemented as Variadic<B>);
-    primops.insert("round_up".to_string(), unimplemented as Variadic<B>);
-    primops.insert("to_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("eq_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("lt_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("gt_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("lteq_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("gteq_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("real_power".to_string(), unimplemented as Variadic<B>);
-    primops.insert("print_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("prerr_real".to_string(), unimplemented as Variadic<B>);
-    primops.insert("undefined_real".to_string(), unimplemented as Variadic<B>);
+    primops.extend(softfloat::softfloat_primops());
     primops
 }
 
@@ -31,12 +31,14 @@
 use std::borrow::Borrow;
 use std::collections::{HashMap, HashSet};
 use std::io::Write;
+use std::str::FromStr;
 
 use crate::concrete::{write_bits64, BV};
-use crate::ir::{Name, Symtab, Val, HAVE_EXCEPTION};
+use crate::ir::{EnumMember, Name, Symtab, Val, HAVE_EXCEPTION};
+use crate::smt::process::{parse_sexp, parse_value, Sexp};
 use crate::smt::smtlib::*;
 use crate::smt::Event::*;
-use crate::smt::{Accessor, Event, Sym};
+use crate::smt::{exp_to_val, rewrite, Accessor, Event, Sym};
 use crate::zencode;
 
 /// `renumber_event` Renumbers all the symbolic variables in an event such that multiple event
@@ -118,10 +120,20 @@ fn uses_in_exp(uses: &mut HashMap<Sym, u32>, exp: &Exp) {
         Var(v) => {
             uses.insert(*v, uses.get(&v).unwrap_or(&0) + 1);
         }
-        Bits(_) | Bits64(_, _) | Enum(_) | Bool(_) => (),
-        Not(exp) | Bvnot(exp) | Bvneg(exp) | Extract(_, _, exp) | ZeroExtend(_, exp) | SignExtend(_, exp) => {
-            uses_in_exp(uses, exp)
-        }
+        Bits(_) | Bits64(_, _) | Enum(_) | Bool(_) | RoundingMode(_) | Int(_) => (),
+        Not(exp)
+        | Bvnot(exp)
+        | Bvneg(exp)
+        | Extract(_, _, exp)
+        | ZeroExtend(_, exp)
+        | SignExtend(_, exp)
+        | FPUnary(_, exp)
+        | FPFromIEEE(_, _, exp)
+        | IntNeg(exp)
+        | Int2BV(_, exp)
+        | BV2Nat(exp)
+        | IntToReal(exp)
+        | RealToInt(exp) => uses_in_exp(uses, exp),
         Eq(lhs, rhs)
         | Neq(lhs, rhs)
         | And(lhs, rhs)
@@ -151,10 +163,34 @@ fn uses_in_exp(uses: &mut HashMap<Sym, u32>, exp: &Exp) {
         | Bvshl(lhs, rhs)
         | Bvlshr(lhs, rhs)
         | Bvashr(lhs, rhs)
-        | Concat(lhs, rhs) => {
+        | Concat(lhs, rhs)
+        | FPRoundingUnary(_, lhs, rhs)
+        | FPBinary(_, lhs, rhs)
+        | FPConvert(_, lhs, rhs)
+        | IntAdd(lhs, rhs)
+        | IntSub(lhs, rhs)
+        | IntMul(lhs, rhs)
+        | IntDiv(lhs, rhs)
+        | IntMod(lhs, rhs)
+        | RealDiv(lhs, rhs)
+        | IntLt(lhs, rhs)
+        | IntLe(lhs, rhs)
+        | IntGt(lhs, rhs)
+        | IntGe(lhs, rhs) => {
             uses_in_exp(uses, lhs);
             uses_in_exp(uses, rhs)
         }
+        FPRoundingBinary(_, rm, lhs, rhs) => {
+            uses_in_exp(uses, rm);
+            uses_in_exp(uses, lhs);
+            uses_in_exp(uses, rhs)
+        }
+        FPfma(rm, x, y, z) => {
+            uses_in_exp(uses, rm);
+            uses_in_exp(uses, x);
+            uses_in_exp(uses, y);
+            uses_in_exp(uses, z)
+        }
         Ite(cond, then_exp, else_exp) => {
             uses_in_exp(uses, cond);
             uses_in_exp(uses, then_exp);
@@ -421,6 +457,59 @@ pub fn remove_unused<B: BV, E: Borrow<Event<B>>>(events: &mut Vec<E>) {
     }
 }
 
+/// Simplify the SMT definitions in a trace using
+/// [crate::smt::rewrite], additionally propagating variables that are
+/// defined as literals into later expressions. The events should be
+/// in the order they occurred (i.e. reversed from
+/// [crate::smt::Trace::to_vec]). Assertions that simplify to true are
+/// removed, and [remove_unused] can be used afterwards to remove any
+/// definitions that are no longer referenced.
+pub fn simplify_smt<B: BV>(events: &mut Vec<Event<B>>) {
+    let mut tcx: HashMap<Sym, Ty> = HashMap::new();
+    let mut ftcx: HashMap<Sym, (Vec<Ty>, Ty)> = HashMap::new();
+    let mut literals: HashMap<Sym, Exp> = HashMap::new();
+
+    for event in events.iter_mut() {
+        if let Smt(def) = event {
+            let var_width = |v| match tcx.get(&v) {
+                Some(Ty::BitVec(sz)) => Some(*sz),
+                _ => None,
+            };
+            let mut propagated = std::mem::replace(def, Def::Assert(Exp::Bool(true)));
+            if let Def::DefineConst(_, exp) | Def::Assert(exp) = &mut propagated {
+                exp.modify(&|exp: &mut Exp| {
+                    if let Exp::Var(v) = exp {
+                        if let Some(literal) = literals.get(v) {
+                            *exp = literal.clone()
+                        }
+                    }
+                })
+            }
+            *def = rewrite::simplify_def(propagated, &var_width);
+
+            match def {
+                Def::DeclareConst(v, ty) => {
+                    tcx.insert(*v, ty.clone());
+                }
+                Def::DeclareFun(v, arg_tys, result_ty) => {
+                    ftcx.insert(*v, (arg_tys.clone(), result_ty.clone()));
+                }
+                Def::DefineConst(v, exp) => {
+                    if let Some(ty) = exp.infer(&tcx, &ftcx) {
+                        tcx.insert(*v, ty);
+                    }
+                    if let Exp::Bits(_) | Exp::Bits64(_, _) | Exp::Bool(_) | Exp::Enum(_) = exp {
+                        literals.insert(*v, exp.clone());
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    events.retain(|event| !matches!(event, Smt(Def::Assert(Exp::Bool(true)))))
+}
+
 fn accessor_to_string(acc: &[Accessor], symtab: &Symtab) -> String {
     acc.iter()
         .map(|elem| elem.to_string(symtab))
@@ -441,6 +530,10 @@ pub struct WriteOpts {
     /// Print the sizes of enumerations declared during symbolic
     /// evaluation.
     pub define_enum: bool,
+    /// Print the variable for the condition of each fork, as
+    /// `(branch n "loc" v)` rather than `(branch n "loc")`, so
+    /// [parse_events] can recover it.
+    pub fork_vars: bool,
 }
 
 impl WriteOpts {
@@ -451,6 +544,7 @@ impl WriteOpts {
             types: true,
             just_smt: true,
             define_enum: false,
+            fork_vars: false,
         }
     }
 }
@@ -463,6 +557,7 @@ impl Default for WriteOpts {
             types: false,
             just_smt: false,
             define_enum: true,
+            fork_vars: false,
         }
     }
 }
@@ -491,7 +586,7 @@ fn write_bits(buf: &mut dyn Write, bits: &[bool]) -> std::io::Result<()> {
     Ok(())
 }
 
-fn write_exp(buf: &mut dyn Write, exp: &Exp, opts: &WriteOpts, enums: &[usize]) -> std::io::Result<()> {
+pub(crate) fn write_exp(buf: &mut dyn Write, exp: &Exp, opts: &WriteOpts, enums: &[usize]) -> std::io::Result<()> {
     use Exp::*;
     match exp {
         Var(v) => write!(buf, "{}{}", opts.variable_prefix, v),
@@ -578,6 +673,49 @@ fn write_exp(buf: &mut dyn Write, exp: &Exp, opts: &WriteOpts, enums: &[usize])
             write_exp(buf, val, opts, enums)?;
             write!(buf, ")")
         }
+        RoundingMode(rm) => write!(buf, "{}", rm),
+        FPUnary(op, exp) => write_unop(buf, &op.to_string(), exp, opts, enums),
+        FPRoundingUnary(op, rm, exp) => write_binop(buf, &op.to_string(), rm, exp, opts, enums),
+        FPBinary(op, lhs, rhs) => write_binop(buf, &op.to_string(), lhs, rhs, opts, enums),
+        FPRoundingBinary(op, rm, lhs, rhs) => {
+            write!(buf, "({} ", op)?;
+            write_exp(buf, rm, opts, enums)?;
+            write!(buf, " ")?;
+            write_exp(buf, lhs, opts, enums)?;
+            write!(buf, " ")?;
+            write_exp(buf, rhs, opts, enums)?;
+            write!(buf, ")")
+        }
+        FPfma(rm, x, y, z) => {
+            write!(buf, "(fp.fma ")?;
+            write_exp(buf, rm, opts, enums)?;
+            write!(buf, " ")?;
+            write_exp(buf, x, opts, enums)?;
+            write!(buf, " ")?;
+            write_exp(buf, y, opts, enums)?;
+            write!(buf, " ")?;
+            write_exp(buf, z, opts, enums)?;
+            write!(buf, ")")
+        }
+        FPFromIEEE(ebits, sbits, exp) => write_unop(buf, &format!("(_ to_fp {} {})", ebits, sbits), exp, opts, enums),
+        FPConvert(conversion, rm, exp) => write_binop(buf, &conversion.to_string(), rm, exp, opts, enums),
+        Int(i) if *i < 0 => write!(buf, "(- {})", i.unsigned_abs()),
+        Int(i) => write!(buf, "{}", i),
+        IntNeg(exp) => write_unop(buf, "-", exp, opts, enums),
+        IntAdd(lhs, rhs) => write_binop(buf, "+", lhs, rhs, opts, enums),
+        IntSub(lhs, rhs) => write_binop(buf, "-", lhs, rhs, opts, enums),
+        IntMul(lhs, rhs) => write_binop(buf, "*", lhs, rhs, opts, enums),
+        IntDiv(lhs, rhs) => write_binop(buf, "div", lhs, rhs, opts, enums),
+        IntMod(lhs, rhs) => write_binop(buf, "mod", lhs, rhs, opts, enums),
+        IntLt(lhs, rhs) => write_binop(buf, "<", lhs, rhs, opts, enums),
+        IntLe(lhs, rhs) => write_binop(buf, "<=", lhs, rhs, opts, enums),
+        IntGt(lhs, rhs) => write_binop(buf, ">", lhs, rhs, opts, enums),
+        IntGe(lhs, rhs) => write_binop(buf, ">=", lhs, rhs, opts, enums),
+        Int2BV(sz, exp) => write_unop(buf, &format!("(_ int2bv {})", sz), exp, opts, enums),
+        BV2Nat(exp) => write_unop(buf, "bv2nat", exp, opts, enums),
+        IntToReal(exp) => write_unop(buf, "to_real", exp, opts, enums),
+        RealToInt(exp) => write_unop(buf, "to_int", exp, opts, enums),
+        RealDiv(lhs, rhs) => write_binop(buf, "/", lhs, rhs, opts, enums),
     }
 }
 
@@ -618,6 +756,7 @@ pub fn write_events_with_opts<B: BV>(
     for event in events.iter().filter(|ev| !opts.just_smt || ev.is_smt()) {
         (match event {
             // TODO: rename this
+            Fork(n, v, loc) if opts.fork_vars => write!(buf, "\n  (branch {} \"{}\" v{})", n, loc, v),
             Fork(n, _, loc) => write!(buf, "\n  (branch {} \"{}\")", n, loc),
 
             Smt(Def::DefineEnum(_, size)) if !opts.define_enum => {
@@ -756,3 +895,577 @@ pub fn write_events_with_opts<B: BV>(
 pub fn write_events<B: BV>(buf: &mut dyn Write, events: &[Event<B>], symtab: &Symtab) {
     write_events_with_opts(buf, events, symtab, &WriteOpts::default()).unwrap()
 }
+
+const FP_UNOPS: [FPUnop; 10] = [
+    FPUnop::Abs,
+    FPUnop::Neg,
+    FPUnop::IsNormal,
+    FPUnop::IsSubnormal,
+    FPUnop::IsZero,
+    FPUnop::IsInfinite,
+    FPUnop::IsNaN,
+    FPUnop::IsNegative,
+    FPUnop::IsPositive,
+    FPUnop::ToIEEE,
+];
+
+const FP_ROUNDING_UNOPS: [FPRoundingUnop; 2] = [FPRoundingUnop::Sqrt, FPRoundingUnop::RoundToIntegral];
+
+const FP_BINOPS: [FPBinop; 5] = [FPBinop::Eq, FPBinop::Lt, FPBinop::Leq, FPBinop::Gt, FPBinop::Geq];
+
+const FP_ROUNDING_BINOPS: [FPRoundingBinop; 4] =
+    [FPRoundingBinop::Add, FPRoundingBinop::Sub, FPRoundingBinop::Mul, FPRoundingBinop::Div];
+
+const ROUNDING_MODES: [FPRoundingMode; 5] =
+    [FPRoundingMode::RNE, FPRoundingMode::RNA, FPRoundingMode::RTP, FPRoundingMode::RTN, FPRoundingMode::RTZ];
+
+/// Find the operation that is printed as `name`
+fn find_op<Op: Copy + ToString>(ops: &[Op], name: &str) -> Option<Op> {
+    ops.iter().find(|op| op.to_string() == name).copied()
+}
+
+fn parse_error(what: &str, sexp: &Sexp) -> String {
+    format!("Could not parse {} in trace: {:?}", what, sexp)
+}
+
+fn atom(sexp: &Sexp) -> Option<&str> {
+    match sexp {
+        Sexp::Atom(a) => Some(a),
+        Sexp::List(_) => None,
+    }
+}
+
+/// Get the contents of a `|quoted|` symbol or `"string"` literal
+fn quoted(sexp: &Sexp, delimiter: char) -> Option<&str> {
+    atom(sexp)?.strip_prefix(delimiter)?.strip_suffix(delimiter)
+}
+
+fn number<T: FromStr>(sexp: &Sexp) -> Result<T, String> {
+    atom(sexp).and_then(|a| a.parse().ok()).ok_or_else(|| parse_error("number", sexp))
+}
+
+fn variable(a: &str) -> Option<Sym> {
+    a.strip_prefix('v')?.parse().ok().map(Sym::from_u32)
+}
+
+/// Keeps track of the information needed to parse a trace that is
+/// not printed explicitly in each event
+struct TraceParser<'a, 'ir> {
+    symtab: &'a Symtab<'ir>,
+    tcx: HashMap<Sym, Ty>,
+    ftcx: HashMap<Sym, (Vec<Ty>, Ty)>,
+    // The sizes of each enumeration, indexed by enum_id
+    enums: Vec<usize>,
+    // One more than the largest variable seen so far
+    next_var: u32,
+    // Indices of forks written without their condition variable
+    conditionless_forks: Vec<usize>,
+}
+
+impl<'a, 'ir> TraceParser<'a, 'ir> {
+    fn new(symtab: &'a Symtab<'ir>) -> Self {
+        TraceParser {
+            symtab,
+            tcx: HashMap::new(),
+            ftcx: HashMap::new(),
+            enums: Vec::new(),
+            next_var: 0,
+            conditionless_forks: Vec::new(),
+        }
+    }
+
+    fn seen(&mut self, v: Sym) -> Sym {
+        self.next_var = std::cmp::max(self.next_var, v.id + 1);
+        v
+    }
+
+    fn sym(&mut self, sexp: &Sexp) -> Result<Sym, String> {
+        let v = atom(sexp).and_then(variable).ok_or_else(|| parse_error("variable", sexp))?;
+        Ok(self.seen(v))
+    }
+
+    fn name(&self, sexp: &Sexp) -> Result<Name, String> {
+        let name = quoted(sexp, '|').ok_or_else(|| parse_error("identifier", sexp))?;
+        self.symtab.get(&zencode::encode(name)).ok_or_else(|| format!("Unknown identifier {} in trace", name))
+    }
+
+    fn ty(&self, sexp: &Sexp) -> Result<Ty, String> {
+        match sexp {
+            Sexp::Atom(a) => match a.as_str() {
+                "Bool" => Ok(Ty::Bool),
+                "RoundingMode" => Ok(Ty::RoundingMode),
+                "Int" => Ok(Ty::Int),
+                "Real" => Ok(Ty::Real),
+                _ => a
+                    .strip_prefix("Enum")
+                    .and_then(|e| e.parse().ok())
+                    .map(Ty::Enum)
+                    .ok_or_else(|| parse_error("type", sexp)),
+            },
+            Sexp::List(items) => match items.as_slice() {
+                [Sexp::Atom(a), dom, codom] if a == "Array" => {
+                    Ok(Ty::Array(Box::new(self.ty(dom)?), Box::new(self.ty(codom)?)))
+                }
+                [Sexp::Atom(u), Sexp::Atom(bv), sz] if u == "_" && bv == "BitVec" => Ok(Ty::BitVec(number(sz)?)),
+                [Sexp::Atom(u), Sexp::Atom(fp), ebits, sbits] if u == "_" && fp == "FloatingPoint" => {
+                    Ok(Ty::Float(number(ebits)?, number(sbits)?))
+                }
+                _ => Err(parse_error("type", sexp)),
+            },
+        }
+    }
+
+    fn exp_atom(&mut self, a: &str) -> Option<Exp> {
+        if let Some(v) = variable(a) {
+            return Some(Exp::Var(self.seen(v)));
+        }
+        // Enumeration members are printed using the size of the
+        // enumeration, which is unique for each enumeration
+        if let Some(member) = a.strip_prefix('e') {
+            let mut parts = member.splitn(2, '_');
+            let size: usize = parts.next()?.parse().ok()?;
+            let member = parts.next()?.parse().ok()?;
+            let enum_id = self.enums.iter().position(|sz| *sz == size)?;
+            return Some(Exp::Enum(EnumMember { enum_id, member }));
+        }
+        if let Some(rm) = find_op(&ROUNDING_MODES, a) {
+            return Some(Exp::RoundingMode(rm));
+        }
+        if let Ok(i) = a.parse() {
+            return Some(Exp::Int(i));
+        }
+        parse_value(&Sexp::Atom(a.to_string()))
+    }
+
+    fn exp_op(&mut self, op: &str, args: Vec<Exp>) -> Option<Exp> {
+        use Exp::*;
+        if let Some(f) = variable(op) {
+            return Some(App(self.seen(f), args));
+        }
+        let n = args.len();
+        let mut args = args.into_iter().map(Box::new);
+        let mut arg = || args.next().unwrap();
+        Some(match (op, n) {
+            ("=", 2) => Eq(arg(), arg()),
+            // Not(Eq(..)) and Neq(..) are printed identically
+            ("not", 1) => match *arg() {
+                Eq(lhs, rhs) => Neq(lhs, rhs),
+                exp => Not(Box::new(exp)),
+            },
+            ("and", 2) => And(arg(), arg()),
+            ("or", 2) => Or(arg(), arg()),
+            ("bvnot", 1) => Bvnot(arg()),
+            ("bvand", 2) => Bvand(arg(), arg()),
+            ("bvor", 2) => Bvor(arg(), arg()),
+            ("bvxor", 2) => Bvxor(arg(), arg()),
+            ("bvnand", 2) => Bvnand(arg(), arg()),
+            ("bvnor", 2) => Bvnor(arg(), arg()),
+            ("bvxnor", 2) => Bvxnor(arg(), arg()),
+            ("bvneg", 1) => Bvneg(arg()),
+            ("bvadd", 2) => Bvadd(arg(), arg()),
+            ("bvsub", 2) => Bvsub(arg(), arg()),
+            ("bvmul", 2) => Bvmul(arg(), arg()),
+            ("bvudiv", 2) => Bvudiv(arg(), arg()),
+            ("bvsdiv", 2) => Bvsdiv(arg(), arg()),
+            ("bvurem", 2) => Bvurem(arg(), arg()),
+            ("bvsrem", 2) => Bvsrem(arg(), arg()),
+            ("bvsmod", 2) => Bvsmod(arg(), arg()),
+            ("bvult", 2) => Bvult(arg(), arg()),
+            ("bvslt", 2) => Bvslt(arg(), arg()),
+            ("bvule", 2) => Bvule(arg(), arg()),
+            ("bvsle", 2) => Bvsle(arg(), arg()),
+            ("bvuge", 2) => Bvuge(arg(), arg()),
+            ("bvsge", 2) => Bvsge(arg(), arg()),
+            ("bvugt", 2) => Bvugt(arg(), arg()),
+            ("bvsgt", 2) => Bvsgt(arg(), arg()),
+            ("bvshl", 2) => Bvshl(arg(), arg()),
+            ("bvlshr", 2) => Bvlshr(arg(), arg()),
+            ("bvashr", 2) => Bvashr(arg(), arg()),
+            ("concat", 2) => Concat(arg(), arg()),
+            ("ite", 3) => Ite(arg(), arg(), arg()),
+            ("select", 2) => Select(arg(), arg()),
+            ("store", 3) => Store(arg(), arg(), arg()),
+            ("fp.fma", 4) => FPfma(arg(), arg(), arg(), arg()),
+            // Negative integer literals are printed as (- n)
+            ("-", 1) => match *arg() {
+                Int(i) => Int(-i),
+                exp => IntNeg(Box::new(exp)),
+            },
+            ("+", 2) => IntAdd(arg(), arg()),
+            ("-", 2) => IntSub(arg(), arg()),
+            ("*", 2) => IntMul(arg(), arg()),
+            ("div", 2) => IntDiv(arg(), arg()),
+            ("mod", 2) => IntMod(arg(), arg()),
+            ("<", 2) => IntLt(arg(), arg()),
+            ("<=", 2) => IntLe(arg(), arg()),
+            (">", 2) => IntGt(arg(), arg()),
+            (">=", 2) => IntGe(arg(), arg()),
+            ("bv2nat", 1) => BV2Nat(arg()),
+            ("to_real", 1) => IntToReal(arg()),
+            ("to_int", 1) => RealToInt(arg()),
+            ("/", 2) => RealDiv(arg(), arg()),
+            (_, 1) => FPUnary(find_op(&FP_UNOPS, op)?, arg()),
+            (_, 2) => match find_op(&FP_ROUNDING_UNOPS, op) {
+                Some(fpop) => FPRoundingUnary(fpop, arg(), arg()),
+                None => FPBinary(find_op(&FP_BINOPS, op)?, arg(), arg()),
+            },
+            (_, 3) => FPRoundingBinary(find_op(&FP_ROUNDING_BINOPS, op)?, arg(), arg(), arg()),
+            _ => return None,
+        })
+    }
+
+    /// Parse an application of an indexed operator such as `(_ extract i j)`
+    fn exp_indexed(&self, op: &str, indices: &[u32], args: Vec<Exp>) -> Option<Exp> {
+        use Exp::*;
+        use FPConversion::*;
+        let n = args.len();
+        let mut args = args.into_iter().map(Box::new);
+        let mut arg = || args.next().unwrap();
+        Some(match (op, indices, n) {
+            ("extract", [i, j], 1) => Extract(*i, *j, arg()),
+            ("zero_extend", [sz], 1) => ZeroExtend(*sz, arg()),
+            ("sign_extend", [sz], 1) => SignExtend(*sz, arg()),
+            ("int2bv", [sz], 1) => Int2BV(*sz, arg()),
+            ("to_fp", [ebits, sbits], 1) => FPFromIEEE(*ebits, *sbits, arg()),
+            // Conversions from floats and signed bitvectors are both
+            // printed as to_fp, so use the type of the argument
+            ("to_fp", [ebits, sbits], 2) => {
+                let rm = arg();
+                let exp = arg();
+                match exp.infer(&self.tcx, &self.ftcx) {
+                    Some(Ty::BitVec(_)) => FPConvert(FromSigned(*ebits, *sbits), rm, exp),
+                    _ => FPConvert(ToFP(*ebits, *sbits), rm, exp),
+                }
+            }
+            ("to_fp_unsigned", [ebits, sbits], 2) => FPConvert(FromUnsigned(*ebits, *sbits), arg(), arg()),
+            ("fp.to_sbv", [sz], 2) => FPConvert(ToSigned(*sz), arg(), arg()),
+            ("fp.to_ubv", [sz], 2) => FPConvert(ToUnsigned(*sz), arg(), arg()),
+            _ => return None,
+        })
+    }
+
+    fn exp(&mut self, sexp: &Sexp) -> Result<Exp, String> {
+        let exp = match sexp {
+            Sexp::Atom(a) => self.exp_atom(a),
+            Sexp::List(items) => match items.split_first() {
+                Some((op, args)) => {
+                    let args = args.iter().map(|arg| self.exp(arg)).collect::<Result<_, _>>()?;
+                    match op {
+                        Sexp::Atom(op) => self.exp_op(op, args),
+                        Sexp::List(indexed) => match indexed.as_slice() {
+                            [Sexp::Atom(u), Sexp::Atom(op), indices @ ..] if u == "_" => {
+                                let indices: Vec<u32> = indices.iter().map(number).collect::<Result<_, _>>()?;
+                                self.exp_indexed(op, &indices, args)
+                            }
+                            _ => None,
+                        },
+                    }
+                }
+                None => return Err(parse_error("expression", sexp)),
+            },
+        };
+        exp.ok_or_else(|| parse_error("expression", sexp))
+    }
+
+    fn vals<B: BV>(&mut self, sexps: &[Sexp]) -> Result<Vec<Val<B>>, String> {
+        match sexps {
+            [Sexp::Atom(nil)] if nil == "nil" => Ok(Vec::new()),
+            _ => sexps.iter().map(|sexp| self.val(sexp)).collect(),
+        }
+    }
+
+    fn val<B: BV>(&mut self, sexp: &Sexp) -> Result<Val<B>, String> {
+        match sexp {
+            Sexp::Atom(a) => {
+                if let Some(v) = variable(a) {
+                    Ok(Val::Symbolic(self.seen(v)))
+                } else if let Some(s) = quoted(sexp, '"') {
+                    Ok(Val::String(s.to_string()))
+                } else {
+                    let exp = parse_value(sexp).ok_or_else(|| parse_error("value", sexp))?;
+                    exp_to_val(&exp).map_err(|err| format!("{}", err))
+                }
+            }
+            Sexp::List(items) => match items.as_slice() {
+                [Sexp::Atom(u), Sexp::Atom(kind), rest @ ..] if u == "_" => match (kind.as_str(), rest) {
+                    ("unit", []) => Ok(Val::Unit),
+                    ("poison", []) => Ok(Val::Poison),
+                    ("list", elems) => Ok(Val::List(self.vals(elems)?)),
+                    ("vec", elems) => Ok(Val::Vector(self.vals(elems)?)),
+                    ("reg", [reg]) => Ok(Val::Ref(self.name(reg)?)),
+                    ("struct", fields) => {
+                        let mut struct_fields = HashMap::new();
+                        for field in fields {
+                            match field {
+                                Sexp::List(kv) if kv.len() == 2 => {
+                                    struct_fields.insert(self.name(&kv[0])?, self.val(&kv[1])?);
+                                }
+                                _ => return Err(parse_error("struct field", field)),
+                            }
+                        }
+                        Ok(Val::Struct(struct_fields))
+                    }
+                    (bv, [Sexp::Atom(len)]) if bv.starts_with("bv") && len == "64" => {
+                        Ok(Val::I64(bv[2..].parse().map_err(|_| parse_error("value", sexp))?))
+                    }
+                    (bv, [Sexp::Atom(len)]) if bv.starts_with("bv") && len == "128" => {
+                        Ok(Val::I128(bv[2..].parse().map_err(|_| parse_error("value", sexp))?))
+                    }
+                    _ => Err(parse_error("value", sexp)),
+                },
+                [ctor, v] => Ok(Val::Ctor(self.name(ctor)?, Box::new(self.val(v)?))),
+                _ => Err(parse_error("value", sexp)),
+            },
+        }
+    }
+
+    fn accessors(&self, sexp: &Sexp) -> Result<Vec<Accessor>, String> {
+        match sexp {
+            Sexp::Atom(nil) if nil == "nil" => Ok(Vec::new()),
+            Sexp::List(accessors) => accessors
+                .iter()
+                .map(|acc| match acc {
+                    Sexp::List(items) => match items.as_slice() {
+                        [Sexp::Atom(u), Sexp::Atom(field), name] if u == "_" && field == "field" => {
+                            Ok(Accessor::Field(self.name(name)?))
+                        }
+                        _ => Err(parse_error("accessor", acc)),
+                    },
+                    _ => Err(parse_error("accessor", acc)),
+                })
+                .collect(),
+            _ => Err(parse_error("accessors", sexp)),
+        }
+    }
+
+    fn tag_value<B: BV>(&mut self, sexps: &[Sexp]) -> Result<Option<Val<B>>, String> {
+        match sexps {
+            [Sexp::Atom(none)] if none == "None" => Ok(None),
+            [Sexp::Atom(some), Sexp::List(v)] if some == "Some" && v.len() == 1 => Ok(Some(self.val(&v[0])?)),
+            _ => Err(format!("Could not parse tag value in trace: {:?}", sexps)),
+        }
+    }
+
+    fn def(&mut self, def: &str, args: &[Sexp]) -> Result<Option<Def>, String> {
+        Ok(Some(match (def, args) {
+            ("declare-const", [v, ty]) => {
+                let v = self.sym(v)?;
+                let ty = self.ty(ty)?;
+                self.tcx.insert(v, ty.clone());
+                Def::DeclareConst(v, ty)
+            }
+            ("declare-fun", [f, Sexp::List(arg_tys), result_ty])
+            | ("declare_fun", [f, Sexp::List(arg_tys), result_ty]) => {
+                let f = self.sym(f)?;
+                let arg_tys = arg_tys.iter().map(|ty| self.ty(ty)).collect::<Result<Vec<_>, _>>()?;
+                let result_ty = self.ty(result_ty)?;
+                self.ftcx.insert(f, (arg_tys.clone(), result_ty.clone()));
+                Def::DeclareFun(f, arg_tys, result_ty)
+            }
+            ("define-const", [v, exp]) | ("define-const", [v, _, exp]) => {
+                let v = self.sym(v)?;
+                let exp = self.exp(exp)?;
+                if let Some(ty) = exp.infer(&self.tcx, &self.ftcx) {
+                    self.tcx.insert(v, ty);
+                }
+                Def::DefineConst(v, exp)
+            }
+            // The solver names each enumeration and its members with
+            // fresh variables, which are not printed
+            ("define-enum", [size]) => {
+                let size: usize = number(size)?;
+                let name = Sym::from_u32(self.next_var);
+                self.next_var += size as u32 + 1;
+                self.enums.push(size);
+                Def::DefineEnum(name, size)
+            }
+            ("assert", [exp]) => Def::Assert(self.exp(exp)?),
+            _ => return Ok(None),
+        }))
+    }
+
+    fn event<B: BV>(&mut self, i: usize, sexp: &Sexp) -> Result<Event<B>, String> {
+        let (head, args) = match sexp {
+            Sexp::List(items) => match items.split_first() {
+                Some((Sexp::Atom(head), args)) => (head.as_str(), args),
+                _ => return Err(parse_error("event", sexp)),
+            },
+            Sexp::Atom(_) => return Err(parse_error("event", sexp)),
+        };
+
+        if let Some(def) = self.def(head, args)? {
+            return Ok(Smt(def));
+        }
+
+        Ok(match (head, args) {
+            ("branch", [n, loc, v]) => {
+                let loc = quoted(loc, '"').ok_or_else(|| parse_error("branch location", sexp))?;
+                Fork(number(n)?, self.sym(v)?, loc.to_string())
+            }
+            ("branch", [n, loc]) => {
+                let loc = quoted(loc, '"').ok_or_else(|| parse_error("branch location", sexp))?;
+                self.conditionless_forks.push(i);
+                Fork(number(n)?, Sym::from_u32(0), loc.to_string())
+            }
+            ("read-mem", [value, read_kind, address, bytes, tag_value @ ..]) => ReadMem {
+                value: self.val(value)?,
+                read_kind: self.val(read_kind)?,
+                address: self.val(address)?,
+                bytes: number(bytes)?,
+                tag_value: self.tag_value(tag_value)?,
+                kind: "".into(),
+            },
+            ("write-mem", [value, write_kind, address, data, bytes, tag_value @ ..]) => WriteMem {
+                value: self.sym(value)?,
+                write_kind: self.val(write_kind)?,
+                address: self.val(address)?,
+                data: self.val(data)?,
+                bytes: number(bytes)?,
+                tag_value: self.tag_value(tag_value)?,
+                kind: "".into(),
+            },
+            ("branch-address", [address]) => Branch { address: self.val(address)? },
+            ("barrier", [barrier_kind]) => Barrier { barrier_kind: self.val(barrier_kind)? },
+            ("cache-op", [cache_op_kind, address]) => {
+                CacheOp { cache_op_kind: self.val(cache_op_kind)?, address: self.val(address)? }
+            }
+            ("write-reg", [reg, acc, v]) => WriteReg(self.name(reg)?, self.accessors(acc)?, self.val(v)?),
+            ("read-reg", [reg, acc, v]) => ReadReg(self.name(reg)?, self.accessors(acc)?, self.val(v)?),
+            ("mark-reg", [reg, mark]) => {
+                let mark = quoted(mark, '"').ok_or_else(|| parse_error("register mark", sexp))?;
+                MarkReg { regs: vec![self.name(reg)?], mark: mark.to_string() }
+            }
+            ("cycle", []) => Cycle,
+            ("instr", [value]) => Instr(self.val(value)?),
+            ("sleeping", [v]) => Sleeping(self.sym(v)?),
+            ("sleep-request", []) => SleepRequest,
+            ("wake-request", []) => WakeupRequest,
+            _ => return Err(parse_error("event", sexp)),
+        })
+    }
+}
+
+/// Parse a trace printed by [write_events_with_opts] back into a
+/// sequence of events, with register, field, and constructor names
+/// looked up in the given symbol table. Both `(trace ...)` wrapped
+/// traces and sequences of bare SMT definitions are accepted, as long
+/// as they were printed with the default variable and enumeration
+/// prefixes.
+///
+/// Some information is not printed, so is reconstructed as follows:
+///
+/// * The names of enumerations are taken to be fresh variables, as
+///   they would be when created by the solver.
+/// * Memory events have an empty `kind`.
+/// * Each `mark-reg` becomes a separate event with a single register.
+/// * `(not (= x y))` is parsed as `Neq(x, y)`, and bitvector literals
+///   are `Bits64` if they are at most 64 bits wide.
+/// * Forks printed without the variable for the fork condition, which
+///   is only printed with [WriteOpts::fork_vars], are given fresh
+///   variables which are not used elsewhere in the trace.
+pub fn parse_events<B: BV>(input: &str, symtab: &Symtab) -> Result<Vec<Event<B>>, String> {
+    let mut chars = input.chars().peekable();
+    let mut sexps = Vec::new();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+        sexps.push(parse_sexp(&mut chars).ok_or_else(|| "Could not parse S-expression in trace".to_string())?)
+    }
+
+    let events = match sexps.as_slice() {
+        [Sexp::List(items)] if matches!(items.first(), Some(Sexp::Atom(trace)) if trace == "trace") => &items[1..],
+        _ => &sexps,
+    };
+
+    let mut parser = TraceParser::new(symtab);
+    let mut events: Vec<Event<B>> =
+        events.iter().enumerate().map(|(i, event)| parser.event(i, event)).collect::<Result<_, _>>()?;
+    for i in parser.conditionless_forks {
+        if let Fork(_, v, _) = &mut events[i] {
+            *v = Sym::from_u32(parser.next_var);
+            parser.next_var += 1
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concrete::bitvector64::B64;
+    use crate::smt::{Config, Context, Solver};
+
+    use super::*;
+
+    fn trace_string(events: &[Event<B64>], symtab: &Symtab) -> String {
+        let mut buf = Vec::new();
+        let opts = WriteOpts { fork_vars: true, ..WriteOpts::default() };
+        write_events_with_opts(&mut buf, events, symtab, &opts).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn parse_trace() {
+        let mut symtab = Symtab::new();
+        let pc = symtab.intern("zPC");
+        let field = symtab.intern("zfield");
+        let ctor = symtab.intern("zSome");
+
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(64));
+        let y = solver.define_const(Exp::Bvadd(Box::new(Exp::Var(x)), Box::new(Exp::Bits64(4, 64))));
+        solver.add_event(Fork(0, y, "location".to_string()));
+        let enum_id = solver.get_enum(3);
+        let e = solver.declare_const(Ty::Enum(enum_id));
+        let member = Box::new(Exp::Enum(EnumMember { enum_id, member: 2 }));
+        solver.add(Def::Assert(Exp::Neq(Box::new(Exp::Var(e)), member)));
+        solver.add_event(Fork(1, x, "earlier".to_string()));
+        solver.add_event(ReadReg(pc, vec![], Val::Symbolic(x)));
+        solver.add_event(WriteReg(pc, vec![Accessor::Field(field)], Val::Ctor(ctor, Box::new(Val::I64(-3)))));
+        solver.add_event(ReadMem {
+            value: Val::Symbolic(y),
+            read_kind: Val::Enum(EnumMember { enum_id: 1, member: 0 }),
+            address: Val::Bits(B64::new(0x1000, 64)),
+            bytes: 8,
+            tag_value: None,
+            kind: "".into(),
+        });
+        solver.add_event(MarkReg { regs: vec![pc], mark: "ignore".to_string() });
+        solver.add_event(Instr(Val::List(vec![Val::Bool(true), Val::Unit, Val::String("str".to_string())])));
+        solver.add_event(Cycle);
+        let events: Vec<Event<B64>> = solver.trace().to_vec().drain(..).rev().cloned().collect();
+
+        let trace = trace_string(&events, &symtab);
+        let parsed = parse_events::<B64>(&trace, &symtab).unwrap();
+        assert_eq!(trace, trace_string(&parsed, &symtab));
+        let forks: Vec<&Event<B64>> = parsed.iter().filter(|ev| ev.is_fork()).collect();
+        assert!(matches!(forks[..], [Fork(0, v, _), Fork(1, w, _)] if *v == y && *w == x));
+        assert!(parsed.iter().any(|ev| matches!(ev, Smt(Def::DefineEnum(v, 3)) if v.id == 2)));
+
+        // By default forks are printed without their variables
+        let mut buf = Vec::new();
+        write_events(&mut buf, &events, &symtab);
+        let default = String::from_utf8(buf).unwrap();
+        assert!(default.contains("(branch 0 \"location\")") && !default.contains("(branch 0 \"location\" v"));
+
+        let mut replay = Solver::<B64>::new(&ctx);
+        for event in parsed {
+            if let Smt(def) = event {
+                replay.add(def)
+            }
+        }
+        assert!(replay.check_sat().is_sat().unwrap());
+
+        assert!(parse_events::<B64>("(trace (read-reg |unknown| nil v0))", &symtab).is_err());
+        assert!(parse_events::<B64>("(trace (declare-const v0 Bool)", &symtab).is_err());
+        let old_fork = parse_events::<B64>("(trace (declare-const v3 Bool) (branch 0 \"loc\"))", &symtab).unwrap();
+        assert!(matches!(&old_fork[..], [Smt(_), Fork(0, v, loc)] if v.id == 4 && loc == "loc"));
+        assert!(parse_events::<B64>("(trace (declare-const v0 Bool) (branch 0 loc))", &symtab).is_err());
+        assert!(parse_events::<B64>("(trace (assert ()))", &symtab).is_err());
+        assert!(parse_events::<B64>("(trace (assert (not ())))", &symtab).is_err());
+        assert!(parse_events::<B64>("(trace (assert ((_ extract 0 0))))", &symtab).is_err())
+    }
+}
@@ -37,28 +37,41 @@
 //! these traces can be snapshotted and shared between threads via the
 //! [Checkpoint] type.
 
+use crossbeam::thread;
 use libc::{c_int, c_uint};
-use serde::{Deserialize, Serialize};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use z3_sys::*;
 
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
 use std::convert::TryInto;
 use std::error::Error;
 use std::ffi::{CStr, CString};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::mem;
+use std::path::{Path, PathBuf};
 use std::ptr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+use crate::cache::{Cacheable, Cachekey};
 use crate::concrete::BV;
 use crate::error::ExecError;
-use crate::ir::{EnumMember, Name, Symtab, Val};
+use crate::ir;
+use crate::ir::{Bindings, EnumMember, Name, Symtab, UVal, Val};
+use crate::log;
 use crate::zencode;
 
 /// A newtype wrapper for symbolic variables, which are `u32` under
 /// the hood.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Sym {
     pub(crate) id: u32,
 }
@@ -87,15 +100,24 @@ impl fmt::Display for Sym {
 pub mod smtlib {
     use super::Sym;
     use crate::ir::EnumMember;
+    use serde::{Deserialize, Serialize};
     use std::collections::HashMap;
     use std::fmt;
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
     pub enum Ty {
         Bool,
         BitVec(u32),
         Enum(usize),
         Array(Box<Ty>, Box<Ty>),
+        /// A floating point sort with the given number of exponent
+        /// and significand bits (including the hidden bit)
+        Float(u32, u32),
+        RoundingMode,
+        /// Unbounded mathematical integers
+        Int,
+        /// Mathematical real numbers
+        Real,
     }
 
     impl fmt::Display for Ty {
@@ -112,11 +134,151 @@ pub mod smtlib {
                     codom.fmt(f)?;
                     write!(f, ")")
                 }
+                Float(ebits, sbits) => write!(f, "(_ FloatingPoint {} {})", ebits, sbits),
+                RoundingMode => write!(f, "RoundingMode"),
+                Int => write!(f, "Int"),
+                Real => write!(f, "Real"),
             }
         }
     }
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub enum FPRoundingMode {
+        RNE,
+        RNA,
+        RTP,
+        RTN,
+        RTZ,
+    }
+
+    impl fmt::Display for FPRoundingMode {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub enum FPUnop {
+        Abs,
+        Neg,
+        IsNormal,
+        IsSubnormal,
+        IsZero,
+        IsInfinite,
+        IsNaN,
+        IsNegative,
+        IsPositive,
+        /// Convert to an IEEE bitvector. This is unspecified for NaNs,
+        /// which have no unique representation.
+        ToIEEE,
+    }
+
+    impl fmt::Display for FPUnop {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            use FPUnop::*;
+            let name = match self {
+                Abs => "fp.abs",
+                Neg => "fp.neg",
+                IsNormal => "fp.isNormal",
+                IsSubnormal => "fp.isSubnormal",
+                IsZero => "fp.isZero",
+                IsInfinite => "fp.isInfinite",
+                IsNaN => "fp.isNaN",
+                IsNegative => "fp.isNegative",
+                IsPositive => "fp.isPositive",
+                ToIEEE => "fp.to_ieee_bv",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub enum FPRoundingUnop {
+        Sqrt,
+        RoundToIntegral,
+    }
+
+    impl fmt::Display for FPRoundingUnop {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                FPRoundingUnop::Sqrt => write!(f, "fp.sqrt"),
+                FPRoundingUnop::RoundToIntegral => write!(f, "fp.roundToIntegral"),
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub enum FPBinop {
+        Eq,
+        Lt,
+        Leq,
+        Gt,
+        Geq,
+    }
+
+    impl fmt::Display for FPBinop {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            use FPBinop::*;
+            let name = match self {
+                Eq => "fp.eq",
+                Lt => "fp.lt",
+                Leq => "fp.leq",
+                Gt => "fp.gt",
+                Geq => "fp.geq",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub enum FPRoundingBinop {
+        Add,
+        Sub,
+        Mul,
+        Div,
+    }
+
+    impl fmt::Display for FPRoundingBinop {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            use FPRoundingBinop::*;
+            let name = match self {
+                Add => "fp.add",
+                Sub => "fp.sub",
+                Mul => "fp.mul",
+                Div => "fp.div",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    /// Conversions between floating point formats, and to and from
+    /// signed and unsigned bitvectors, which all take a rounding
+    /// mode. Converting a NaN, an infinity, or an out of range value
+    /// to a bitvector is unspecified.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+    pub enum FPConversion {
+        ToFP(u32, u32),
+        FromSigned(u32, u32),
+        FromUnsigned(u32, u32),
+        ToSigned(u32),
+        ToUnsigned(u32),
+    }
+
+    impl fmt::Display for FPConversion {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            use FPConversion::*;
+            match self {
+                ToFP(ebits, sbits) | FromSigned(ebits, sbits) => write!(f, "(_ to_fp {} {})", ebits, sbits),
+                FromUnsigned(ebits, sbits) => write!(f, "(_ to_fp_unsigned {} {})", ebits, sbits),
+                ToSigned(sz) => write!(f, "(_ fp.to_sbv {})", sz),
+                ToUnsigned(sz) => write!(f, "(_ fp.to_ubv {})", sz),
+            }
+        }
+    }
+
+    /// SMT expressions. Equality and hashing are structural, so for
+    /// example `Bits64` and `Bits` values are never equal.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
     pub enum Exp {
         Var(Sym),
         Bits(Vec<bool>),
@@ -163,6 +325,42 @@ pub mod smtlib {
         App(Sym, Vec<Exp>),
         Select(Box<Exp>, Box<Exp>),
         Store(Box<Exp>, Box<Exp>, Box<Exp>),
+        RoundingMode(FPRoundingMode),
+        FPUnary(FPUnop, Box<Exp>),
+        FPRoundingUnary(FPRoundingUnop, Box<Exp>, Box<Exp>),
+        FPBinary(FPBinop, Box<Exp>, Box<Exp>),
+        FPRoundingBinary(FPRoundingBinop, Box<Exp>, Box<Exp>, Box<Exp>),
+        FPfma(Box<Exp>, Box<Exp>, Box<Exp>, Box<Exp>),
+        /// Reinterpret an IEEE bitvector as a floating point value
+        /// with the given exponent and significand widths
+        FPFromIEEE(u32, u32, Box<Exp>),
+        FPConvert(FPConversion, Box<Exp>, Box<Exp>),
+        Int(i128),
+        /// The arithmetic and comparison operations on integers below
+        /// (excluding `IntDiv` and `IntMod`) are also used for reals,
+        /// as in SMT-LIB where `+`, `<`, etc. are overloaded
+        IntNeg(Box<Exp>),
+        IntAdd(Box<Exp>, Box<Exp>),
+        IntSub(Box<Exp>, Box<Exp>),
+        IntMul(Box<Exp>, Box<Exp>),
+        /// Euclidean division, as in SMT-LIB's `div`
+        IntDiv(Box<Exp>, Box<Exp>),
+        /// Euclidean remainder, as in SMT-LIB's `mod`
+        IntMod(Box<Exp>, Box<Exp>),
+        IntLt(Box<Exp>, Box<Exp>),
+        IntLe(Box<Exp>, Box<Exp>),
+        IntGt(Box<Exp>, Box<Exp>),
+        IntGe(Box<Exp>, Box<Exp>),
+        /// Convert an integer to a bitvector of the given width, modulo 2^width
+        Int2BV(u32, Box<Exp>),
+        /// Interpret a bitvector as an unsigned integer
+        BV2Nat(Box<Exp>),
+        /// Convert an integer to a real, as in SMT-LIB's `to_real`
+        IntToReal(Box<Exp>),
+        /// The largest integer not greater than a real, as in SMT-LIB's `to_int`
+        RealToInt(Box<Exp>),
+        /// Real division, as in SMT-LIB's `/`
+        RealDiv(Box<Exp>, Box<Exp>),
     }
 
     impl Exp {
@@ -173,10 +371,20 @@ pub mod smtlib {
         {
             use Exp::*;
             match self {
-                Var(_) | Bits(_) | Bits64(_, _) | Enum { .. } | Bool(_) => (),
-                Not(exp) | Bvnot(exp) | Bvneg(exp) | Extract(_, _, exp) | ZeroExtend(_, exp) | SignExtend(_, exp) => {
-                    exp.modify(f)
-                }
+                Var(_) | Bits(_) | Bits64(_, _) | Enum { .. } | Bool(_) | RoundingMode(_) | Int(_) => (),
+                Not(exp)
+                | Bvnot(exp)
+                | Bvneg(exp)
+                | Extract(_, _, exp)
+                | ZeroExtend(_, exp)
+                | SignExtend(_, exp)
+                | FPUnary(_, exp)
+                | FPFromIEEE(_, _, exp)
+                | IntNeg(exp)
+                | Int2BV(_, exp)
+                | BV2Nat(exp)
+                | IntToReal(exp)
+                | RealToInt(exp) => exp.modify(f),
                 Eq(lhs, rhs)
                 | Neq(lhs, rhs)
                 | And(lhs, rhs)
@@ -206,10 +414,34 @@ pub mod smtlib {
                 | Bvshl(lhs, rhs)
                 | Bvlshr(lhs, rhs)
                 | Bvashr(lhs, rhs)
-                | Concat(lhs, rhs) => {
+                | Concat(lhs, rhs)
+                | FPRoundingUnary(_, lhs, rhs)
+                | FPBinary(_, lhs, rhs)
+                | FPConvert(_, lhs, rhs)
+                | IntAdd(lhs, rhs)
+                | IntSub(lhs, rhs)
+                | IntMul(lhs, rhs)
+                | IntDiv(lhs, rhs)
+                | IntMod(lhs, rhs)
+                | RealDiv(lhs, rhs)
+                | IntLt(lhs, rhs)
+                | IntLe(lhs, rhs)
+                | IntGt(lhs, rhs)
+                | IntGe(lhs, rhs) => {
                     lhs.modify(f);
                     rhs.modify(f);
                 }
+                FPRoundingBinary(_, rm, lhs, rhs) => {
+                    rm.modify(f);
+                    lhs.modify(f);
+                    rhs.modify(f);
+                }
+                FPfma(rm, x, y, z) => {
+                    rm.modify(f);
+                    x.modify(f);
+                    y.modify(f);
+                    z.modify(f);
+                }
                 Ite(cond, then_exp, else_exp) => {
                     cond.modify(f);
                     then_exp.modify(f);
@@ -254,7 +486,11 @@ pub mod smtlib {
                 | Bvuge(_, _)
                 | Bvsge(_, _)
                 | Bvugt(_, _)
-                | Bvsgt(_, _) => Some(Ty::Bool),
+                | Bvsgt(_, _)
+                | IntLt(_, _)
+                | IntLe(_, _)
+                | IntGt(_, _)
+                | IntGe(_, _) => Some(Ty::Bool),
                 Bvnot(exp) | Bvneg(exp) => exp.infer(tcx, ftcx),
                 Extract(i, j, _) => Some(Ty::BitVec((i - j) + 1)),
                 ZeroExtend(ext, exp) | SignExtend(ext, exp) => match exp.infer(tcx, ftcx) {
@@ -289,11 +525,33 @@ pub mod smtlib {
                     _ => None,
                 },
                 Store(array, _, _) => array.infer(tcx, ftcx),
+                RoundingMode(_) => Some(Ty::RoundingMode),
+                FPUnary(FPUnop::Abs, exp) | FPUnary(FPUnop::Neg, exp) => exp.infer(tcx, ftcx),
+                FPUnary(FPUnop::ToIEEE, exp) => match exp.infer(tcx, ftcx) {
+                    Some(Ty::Float(ebits, sbits)) => Some(Ty::BitVec(ebits + sbits)),
+                    _ => None,
+                },
+                FPUnary(_, _) | FPBinary(_, _, _) => Some(Ty::Bool),
+                FPRoundingUnary(_, _, exp) | FPRoundingBinary(_, _, exp, _) | FPfma(_, exp, _, _) => {
+                    exp.infer(tcx, ftcx)
+                }
+                FPFromIEEE(ebits, sbits, _) => Some(Ty::Float(*ebits, *sbits)),
+                FPConvert(conversion, _, _) => match conversion {
+                    FPConversion::ToFP(ebits, sbits)
+                    | FPConversion::FromSigned(ebits, sbits)
+                    | FPConversion::FromUnsigned(ebits, sbits) => Some(Ty::Float(*ebits, *sbits)),
+                    FPConversion::ToSigned(sz) | FPConversion::ToUnsigned(sz) => Some(Ty::BitVec(*sz)),
+                },
+                IntNeg(exp) => exp.infer(tcx, ftcx),
+                IntAdd(lhs, _) | IntSub(lhs, _) | IntMul(lhs, _) => lhs.infer(tcx, ftcx),
+                Int(_) | IntDiv(_, _) | IntMod(_, _) | BV2Nat(_) | RealToInt(_) => Some(Ty::Int),
+                Int2BV(sz, _) => Some(Ty::BitVec(*sz)),
+                IntToReal(_) | RealDiv(_, _) => Some(Ty::Real),
             }
         }
     }
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
     pub enum Def {
         DeclareConst(Sym, Ty),
         DeclareFun(Sym, Vec<Ty>, Ty),
@@ -303,6 +561,9 @@ pub mod smtlib {
     }
 }
 
+pub mod process;
+pub mod rewrite;
+
 use smtlib::*;
 
 /// Snapshot of interaction with underlying solver that can be
@@ -347,18 +608,43 @@ impl Accessor {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Event<B> {
     Smt(Def),
     Fork(u32, Sym, String),
     ReadReg(Name, Vec<Accessor>, Val<B>),
     WriteReg(Name, Vec<Accessor>, Val<B>),
-    ReadMem { value: Val<B>, read_kind: Val<B>, address: Val<B>, bytes: u32, tag_value: Option<Val<B>>, kind: &'static str },
-    WriteMem { value: Sym, write_kind: Val<B>, address: Val<B>, data: Val<B>, bytes: u32, tag_value: Option<Val<B>>, kind: &'static str },
-    Branch { address: Val<B> },
-    Barrier { barrier_kind: Val<B> },
-    CacheOp { cache_op_kind: Val<B>, address: Val<B> },
-    MarkReg { regs: Vec<Name>, mark: String },
+    ReadMem {
+        value: Val<B>,
+        read_kind: Val<B>,
+        address: Val<B>,
+        bytes: u32,
+        tag_value: Option<Val<B>>,
+        kind: Cow<'static, str>,
+    },
+    WriteMem {
+        value: Sym,
+        write_kind: Val<B>,
+        address: Val<B>,
+        data: Val<B>,
+        bytes: u32,
+        tag_value: Option<Val<B>>,
+        kind: Cow<'static, str>,
+    },
+    Branch {
+        address: Val<B>,
+    },
+    Barrier {
+        barrier_kind: Val<B>,
+    },
+    CacheOp {
+        cache_op_kind: Val<B>,
+        address: Val<B>,
+    },
+    MarkReg {
+        regs: Vec<Name>,
+        mark: String,
+    },
     Cycle,
     Instr(Val<B>),
     Sleeping(Sym),
@@ -451,25 +737,60 @@ pub type EvPath<B> = Vec<Event<B>>;
 #[derive(Debug)]
 pub struct Trace<B> {
     checkpoints: usize,
+    /// Identifies this trace when it is the tail of a checkpoint,
+    /// unique for the lifetime of the program
+    id: u64,
     pub head: Vec<Event<B>>,
     pub tail: Arc<Option<Trace<B>>>,
 }
 
+static NEXT_TRACE_ID: AtomicU64 = AtomicU64::new(1);
+
+fn fresh_trace_id() -> u64 {
+    NEXT_TRACE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 impl<B: BV> Trace<B> {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        Trace { checkpoints: 0, head: Vec::new(), tail: Arc::new(None) }
+        Trace { checkpoints: 0, id: fresh_trace_id(), head: Vec::new(), tail: Arc::new(None) }
+    }
+
+    /// The identifier of the checkpoint this trace continues from, or
+    /// 0 if it has no checkpoints.
+    fn checkpoint_id(&self) -> u64 {
+        match &*self.tail {
+            Some(trace) => trace.id,
+            None => 0,
+        }
     }
 
     pub fn checkpoint(&mut self, next_var: u32) -> Checkpoint<B> {
         let mut head = Vec::new();
         mem::swap(&mut self.head, &mut head);
-        let tail = Arc::new(Some(Trace { checkpoints: self.checkpoints, head, tail: self.tail.clone() }));
+        let tail = Arc::new(Some(Trace {
+            checkpoints: self.checkpoints,
+            id: fresh_trace_id(),
+            head,
+            tail: self.tail.clone(),
+        }));
         self.checkpoints += 1;
         self.tail = tail.clone();
         Checkpoint { num: self.checkpoints, trace: tail, next_var }
     }
 
+    /// Like `checkpoint`, but copies the current head rather than
+    /// moving it, so the trace itself is left unchanged.
+    fn to_checkpoint(&self, next_var: u32) -> Checkpoint<B> {
+        let tail = Arc::new(Some(Trace {
+            checkpoints: self.checkpoints,
+            id: fresh_trace_id(),
+            head: self.head.clone(),
+            tail: self.tail.clone(),
+        }));
+        Checkpoint { num: self.checkpoints + 1, trace: tail, next_var }
+    }
+
     pub fn to_vec<'a>(&'a self) -> Vec<&'a Event<B>> {
         let mut vec: Vec<&'a Event<B>> = Vec::new();
 
@@ -490,6 +811,69 @@ impl<B: BV> Trace<B> {
     }
 }
 
+/// A trace is serialized as the sequence of event vectors between
+/// each checkpoint, oldest first, rather than as the nested structure
+/// used in memory, so long traces do not cause deep recursion.
+impl<B: BV> Serialize for Trace<B> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut segments: Vec<&[Event<B>]> = vec![&self.head];
+        let mut next = &*self.tail;
+        while let Some(trace) = next {
+            segments.push(&trace.head);
+            next = &*trace.tail
+        }
+        segments.reverse();
+        segments.serialize(serializer)
+    }
+}
+
+impl<'de, B: BV> Deserialize<'de> for Trace<B> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut segments: Vec<Vec<Event<B>>> = Vec::deserialize(deserializer)?;
+        let head = segments.pop().ok_or_else(|| D::Error::custom("A serialized trace must have a head"))?;
+        let mut trace = Trace::new();
+        for segment in segments {
+            trace.head = segment;
+            trace.checkpoint(0);
+        }
+        trace.head = head;
+        Ok(trace)
+    }
+}
+
+/// The name a trace is stored under in a cache directory, e.g. a
+/// hash of the opcode or litmus test which produced it.
+pub struct TraceKey(pub String);
+
+impl Cachekey for TraceKey {
+    fn key(&self) -> String {
+        format!("trace_{}", self.0)
+    }
+}
+
+impl<B: BV> Cacheable for Trace<B> {
+    type Key = TraceKey;
+}
+
+impl<B: BV> Serialize for Checkpoint<B> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.num, self.next_var, &*self.trace).serialize(serializer)
+    }
+}
+
+/// The number of checkpoints is checked against the trace, as
+/// replaying a checkpoint relies on it.
+impl<'de, B: BV> Deserialize<'de> for Checkpoint<B> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (num, next_var, trace) = <(usize, u32, Option<Trace<B>>)>::deserialize(deserializer)?;
+        let depth = trace.as_ref().map_or(0, |trace| trace.checkpoints + 1);
+        if num != depth {
+            return Err(D::Error::custom(format!("Checkpoint has {} checkpoints but its trace has {}", num, depth)));
+        }
+        Ok(Checkpoint { num, next_var, trace: Arc::new(trace) })
+    }
+}
+
 /// Config is a wrapper around the `Z3_config` type from the C
 /// API. `Z3_del_config` is called when it is dropped.
 pub struct Config {
@@ -571,13 +955,16 @@ impl<'ctx> Enums<'ctx> {
         Enums { enums: Vec::new(), ctx }
     }
 
-    fn add_enum(&mut self, name: Sym, members: &[Sym]) {
+    fn add_enum(&mut self, name: Sym, size: usize) {
         unsafe {
             let ctx = self.ctx.z3_ctx;
-            let size = members.len();
 
+            // Members are named by strings, so they cannot clash with
+            // the integer symbols used for variables
+            let member_names: Vec<CString> =
+                (0..size).map(|i| CString::new(format!("e{}_{}", name, i)).unwrap()).collect();
             let name = Z3_mk_int_symbol(ctx, name.id as c_int);
-            let members: Vec<Z3_symbol> = members.iter().map(|m| Z3_mk_int_symbol(ctx, m.id as c_int)).collect();
+            let members: Vec<Z3_symbol> = member_names.iter().map(|m| Z3_mk_string_symbol(ctx, m.as_ptr())).collect();
 
             let mut consts = mem::ManuallyDrop::new(Vec::with_capacity(size));
             let mut testers = mem::ManuallyDrop::new(Vec::with_capacity(size));
@@ -634,6 +1021,22 @@ impl<'ctx> Sort<'ctx> {
         }
     }
 
+    fn float(ctx: &'ctx Context, ebits: u32, sbits: u32) -> Self {
+        unsafe {
+            let z3_sort = Z3_mk_fpa_sort(ctx.z3_ctx, ebits, sbits);
+            Z3_inc_ref(ctx.z3_ctx, Z3_sort_to_ast(ctx.z3_ctx, z3_sort));
+            Sort { z3_sort, ctx }
+        }
+    }
+
+    fn int(ctx: &'ctx Context) -> Self {
+        unsafe {
+            let z3_sort = Z3_mk_int_sort(ctx.z3_ctx);
+            Z3_inc_ref(ctx.z3_ctx, Z3_sort_to_ast(ctx.z3_ctx, z3_sort));
+            Sort { z3_sort, ctx }
+        }
+    }
+
     fn new(ctx: &'ctx Context, enums: &Enums<'ctx>, ty: &Ty) -> Self {
         unsafe {
             match ty {
@@ -648,6 +1051,18 @@ impl<'ctx> Sort<'ctx> {
                     Z3_inc_ref(ctx.z3_ctx, Z3_sort_to_ast(ctx.z3_ctx, z3_sort));
                     Sort { z3_sort, ctx }
                 }
+                Ty::Float(ebits, sbits) => Self::float(ctx, *ebits, *sbits),
+                Ty::Int => Self::int(ctx),
+                Ty::Real => {
+                    let z3_sort = Z3_mk_real_sort(ctx.z3_ctx);
+                    Z3_inc_ref(ctx.z3_ctx, Z3_sort_to_ast(ctx.z3_ctx, z3_sort));
+                    Sort { z3_sort, ctx }
+                }
+                Ty::RoundingMode => {
+                    let z3_sort = Z3_mk_fpa_rounding_mode_sort(ctx.z3_ctx);
+                    Z3_inc_ref(ctx.z3_ctx, Z3_sort_to_ast(ctx.z3_ctx, z3_sort));
+                    Sort { z3_sort, ctx }
+                }
                 Ty::Array(dom, codom) => {
                     let dom_s = Self::new(ctx, enums, dom);
                     let codom_s = Self::new(ctx, enums, codom);
@@ -966,6 +1381,178 @@ impl<'ctx> Ast<'ctx> {
         }
     }
 
+    fn mk_rounding_mode(ctx: &'ctx Context, rm: FPRoundingMode) -> Self {
+        unsafe {
+            let z3_ast = match rm {
+                FPRoundingMode::RNE => Z3_mk_fpa_rne(ctx.z3_ctx),
+                FPRoundingMode::RNA => Z3_mk_fpa_rna(ctx.z3_ctx),
+                FPRoundingMode::RTP => Z3_mk_fpa_rtp(ctx.z3_ctx),
+                FPRoundingMode::RTN => Z3_mk_fpa_rtn(ctx.z3_ctx),
+                FPRoundingMode::RTZ => Z3_mk_fpa_rtz(ctx.z3_ctx),
+            };
+            Z3_inc_ref(ctx.z3_ctx, z3_ast);
+            Ast { z3_ast, ctx }
+        }
+    }
+
+    fn mk_fp_unary(&self, op: FPUnop) -> Self {
+        match op {
+            FPUnop::Abs => z3_unary_op!(Z3_mk_fpa_abs, self),
+            FPUnop::Neg => z3_unary_op!(Z3_mk_fpa_neg, self),
+            FPUnop::IsNormal => z3_unary_op!(Z3_mk_fpa_is_normal, self),
+            FPUnop::IsSubnormal => z3_unary_op!(Z3_mk_fpa_is_subnormal, self),
+            FPUnop::IsZero => z3_unary_op!(Z3_mk_fpa_is_zero, self),
+            FPUnop::IsInfinite => z3_unary_op!(Z3_mk_fpa_is_infinite, self),
+            FPUnop::IsNaN => z3_unary_op!(Z3_mk_fpa_is_nan, self),
+            FPUnop::IsNegative => z3_unary_op!(Z3_mk_fpa_is_negative, self),
+            FPUnop::IsPositive => z3_unary_op!(Z3_mk_fpa_is_positive, self),
+            FPUnop::ToIEEE => z3_unary_op!(Z3_mk_fpa_to_ieee_bv, self),
+        }
+    }
+
+    fn mk_fp_rounding_unary(rm: &Ast<'ctx>, op: FPRoundingUnop, exp: &Ast<'ctx>) -> Self {
+        match op {
+            FPRoundingUnop::Sqrt => z3_binary_op!(Z3_mk_fpa_sqrt, rm, exp),
+            FPRoundingUnop::RoundToIntegral => z3_binary_op!(Z3_mk_fpa_round_to_integral, rm, exp),
+        }
+    }
+
+    fn mk_fp_binary(&self, op: FPBinop, rhs: &Ast<'ctx>) -> Self {
+        match op {
+            FPBinop::Eq => z3_binary_op!(Z3_mk_fpa_eq, self, rhs),
+            FPBinop::Lt => z3_binary_op!(Z3_mk_fpa_lt, self, rhs),
+            FPBinop::Leq => z3_binary_op!(Z3_mk_fpa_leq, self, rhs),
+            FPBinop::Gt => z3_binary_op!(Z3_mk_fpa_gt, self, rhs),
+            FPBinop::Geq => z3_binary_op!(Z3_mk_fpa_geq, self, rhs),
+        }
+    }
+
+    fn mk_fp_rounding_binary(rm: &Ast<'ctx>, op: FPRoundingBinop, lhs: &Ast<'ctx>, rhs: &Ast<'ctx>) -> Self {
+        let f = match op {
+            FPRoundingBinop::Add => Z3_mk_fpa_add,
+            FPRoundingBinop::Sub => Z3_mk_fpa_sub,
+            FPRoundingBinop::Mul => Z3_mk_fpa_mul,
+            FPRoundingBinop::Div => Z3_mk_fpa_div,
+        };
+        unsafe {
+            let z3_ast = f(rm.ctx.z3_ctx, rm.z3_ast, lhs.z3_ast, rhs.z3_ast);
+            Z3_inc_ref(rm.ctx.z3_ctx, z3_ast);
+            Ast { z3_ast, ctx: rm.ctx }
+        }
+    }
+
+    fn mk_fp_fma(rm: &Ast<'ctx>, x: &Ast<'ctx>, y: &Ast<'ctx>, z: &Ast<'ctx>) -> Self {
+        unsafe {
+            let z3_ast = Z3_mk_fpa_fma(rm.ctx.z3_ctx, rm.z3_ast, x.z3_ast, y.z3_ast, z.z3_ast);
+            Z3_inc_ref(rm.ctx.z3_ctx, z3_ast);
+            Ast { z3_ast, ctx: rm.ctx }
+        }
+    }
+
+    fn mk_int(ctx: &'ctx Context, i: i128) -> Self {
+        unsafe {
+            let sort = Sort::int(ctx);
+            let numeral = CString::new(i.to_string()).unwrap();
+            let z3_ast = Z3_mk_numeral(ctx.z3_ctx, numeral.as_ptr(), sort.z3_sort);
+            Z3_inc_ref(ctx.z3_ctx, z3_ast);
+            Ast { z3_ast, ctx }
+        }
+    }
+
+    fn mk_int_neg(&self) -> Self {
+        z3_unary_op!(Z3_mk_unary_minus, self)
+    }
+
+    fn mk_int_arith(
+        &self,
+        op: unsafe extern "C" fn(Z3_context, c_uint, *const Z3_ast) -> Z3_ast,
+        rhs: &Ast<'ctx>,
+    ) -> Self {
+        unsafe {
+            let z3_ast = op(self.ctx.z3_ctx, 2, &[self.z3_ast, rhs.z3_ast] as *const Z3_ast);
+            Z3_inc_ref(self.ctx.z3_ctx, z3_ast);
+            Ast { z3_ast, ctx: self.ctx }
+        }
+    }
+
+    fn mk_int_div(&self, rhs: &Ast<'ctx>) -> Self {
+        z3_binary_op!(Z3_mk_div, self, rhs)
+    }
+
+    fn mk_int_mod(&self, rhs: &Ast<'ctx>) -> Self {
+        z3_binary_op!(Z3_mk_mod, self, rhs)
+    }
+
+    fn mk_int_lt(&self, rhs: &Ast<'ctx>) -> Self {
+        z3_binary_op!(Z3_mk_lt, self, rhs)
+    }
+
+    fn mk_int_le(&self, rhs: &Ast<'ctx>) -> Self {
+        z3_binary_op!(Z3_mk_le, self, rhs)
+    }
+
+    fn mk_int_gt(&self, rhs: &Ast<'ctx>) -> Self {
+        z3_binary_op!(Z3_mk_gt, self, rhs)
+    }
+
+    fn mk_int_ge(&self, rhs: &Ast<'ctx>) -> Self {
+        z3_binary_op!(Z3_mk_ge, self, rhs)
+    }
+
+    fn mk_int2bv(&self, sz: u32) -> Self {
+        unsafe {
+            let z3_ast = Z3_mk_int2bv(self.ctx.z3_ctx, sz, self.z3_ast);
+            Z3_inc_ref(self.ctx.z3_ctx, z3_ast);
+            Ast { z3_ast, ctx: self.ctx }
+        }
+    }
+
+    fn mk_int2real(&self) -> Self {
+        z3_unary_op!(Z3_mk_int2real, self)
+    }
+
+    fn mk_real2int(&self) -> Self {
+        z3_unary_op!(Z3_mk_real2int, self)
+    }
+
+    fn mk_bv2nat(&self) -> Self {
+        unsafe {
+            let z3_ast = Z3_mk_bv2int(self.ctx.z3_ctx, self.z3_ast, false);
+            Z3_inc_ref(self.ctx.z3_ctx, z3_ast);
+            Ast { z3_ast, ctx: self.ctx }
+        }
+    }
+
+    fn mk_fp_from_ieee(&self, ebits: u32, sbits: u32) -> Self {
+        unsafe {
+            let sort = Sort::float(self.ctx, ebits, sbits);
+            let z3_ast = Z3_mk_fpa_to_fp_bv(self.ctx.z3_ctx, self.z3_ast, sort.z3_sort);
+            Z3_inc_ref(self.ctx.z3_ctx, z3_ast);
+            Ast { z3_ast, ctx: self.ctx }
+        }
+    }
+
+    fn mk_fp_convert(rm: &Ast<'ctx>, conversion: FPConversion, exp: &Ast<'ctx>) -> Self {
+        unsafe {
+            let z3_ctx = rm.ctx.z3_ctx;
+            let z3_ast = match conversion {
+                FPConversion::ToFP(ebits, sbits) => {
+                    Z3_mk_fpa_to_fp_float(z3_ctx, rm.z3_ast, exp.z3_ast, Sort::float(rm.ctx, ebits, sbits).z3_sort)
+                }
+                FPConversion::FromSigned(ebits, sbits) => {
+                    Z3_mk_fpa_to_fp_signed(z3_ctx, rm.z3_ast, exp.z3_ast, Sort::float(rm.ctx, ebits, sbits).z3_sort)
+                }
+                FPConversion::FromUnsigned(ebits, sbits) => {
+                    Z3_mk_fpa_to_fp_unsigned(z3_ctx, rm.z3_ast, exp.z3_ast, Sort::float(rm.ctx, ebits, sbits).z3_sort)
+                }
+                FPConversion::ToSigned(sz) => Z3_mk_fpa_to_sbv(z3_ctx, rm.z3_ast, exp.z3_ast, sz),
+                FPConversion::ToUnsigned(sz) => Z3_mk_fpa_to_ubv(z3_ctx, rm.z3_ast, exp.z3_ast, sz),
+            };
+            Z3_inc_ref(z3_ctx, z3_ast);
+            Ast { z3_ast, ctx: rm.ctx }
+        }
+    }
+
     fn get_bool_value(&self) -> Option<bool> {
         unsafe {
             match Z3_get_bool_value(self.ctx.z3_ctx, self.z3_ast) {
@@ -976,6 +1563,29 @@ impl<'ctx> Ast<'ctx> {
         }
     }
 
+    fn get_numeral_i128(&self) -> Result<i128, ExecError> {
+        unsafe {
+            let s = CStr::from_ptr(Z3_get_numeral_string(self.ctx.z3_ctx, self.z3_ast));
+            s.to_string_lossy().parse().map_err(|_| ExecError::Overflow)
+        }
+    }
+
+    /// Get a real numeral as the quotient of two integers
+    fn get_numeral_real(&self) -> Result<Exp, ExecError> {
+        let part = |z3_ast| unsafe {
+            Z3_inc_ref(self.ctx.z3_ctx, z3_ast);
+            Ast { z3_ast, ctx: self.ctx }.get_numeral_i128()
+        };
+        let numerator = part(unsafe { Z3_get_numerator(self.ctx.z3_ctx, self.z3_ast) })?;
+        let denominator = part(unsafe { Z3_get_denominator(self.ctx.z3_ctx, self.z3_ast) })?;
+        let numerator = Exp::IntToReal(Box::new(Exp::Int(numerator)));
+        if denominator == 1 {
+            Ok(numerator)
+        } else {
+            Ok(Exp::RealDiv(Box::new(numerator), Box::new(Exp::IntToReal(Box::new(Exp::Int(denominator))))))
+        }
+    }
+
     fn get_numeral_u64(&self) -> Result<u64, ExecError> {
         let mut v: u64 = 0;
         unsafe {
@@ -1043,84 +1653,1014 @@ impl<'ctx> Drop for Ast<'ctx> {
 /// let ctx = Context::new(cfg);
 /// let mut solver = Solver::from_checkpoint(&ctx, point);
 /// assert!(solver.check_sat() == SmtResult::Unsat);
+/// ```
+///
+/// Replaying a checkpoint re-translates every definition in its
+/// trace. In incremental mode the solver instead opens a Z3 scope at
+/// each checkpoint it creates, so it can be returned to any of those
+/// checkpoints by popping scopes with `restore_checkpoint`.
+///
+/// When unsat cores are enabled, each assertion made immediately
+/// after an `Event::Fork` is labelled with the fork's location, other
+/// assertions can be given a label with `add_labelled`, and
+/// `unsat_core` reports the labels of conflicting assertions.
 pub struct Solver<'ctx, B> {
     trace: Trace<B>,
     next_var: u32,
     cycles: i128,
-    decls: HashMap<Sym, Ast<'ctx>>,
-    func_decls: HashMap<Sym, FuncDecl<'ctx>>,
-    enums: Enums<'ctx>,
-    enum_map: HashMap<usize, usize>,
-    z3_solver: Z3_solver,
+    options: SolverOptions,
+    scopes: Vec<Scope<B>>,
+    /// The number of open scopes holding assertions made with
+    /// `assert_untraced`
+    untraced_scopes: u32,
+    pending_label: Option<String>,
+    last_unsat: bool,
+    head_hash: u64,
+    cache: Option<Arc<SmtCache>>,
+    fork_location: Option<String>,
+    recorder: Option<(Arc<QueryRecorder>, usize)>,
+    portfolio: Option<Arc<Portfolio>>,
+    stats: SolverStats,
+    profile: Option<Arc<SolverProfile>>,
+    backend: Option<RefCell<Box<dyn SolverBackend>>>,
+    backend_failed: bool,
+    z3: Z3Backend<'ctx>,
     ctx: &'ctx Context,
 }
 
-impl<'ctx, B> Drop for Solver<'ctx, B> {
-    fn drop(&mut self) {
-        unsafe {
-            Z3_solver_dec_ref(self.ctx.z3_ctx, self.z3_solver);
-        }
-    }
+/// Options which must be chosen when a solver is created, as they
+/// affect how the definitions in a checkpoint are replayed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SolverOptions {
+    pub incremental: bool,
+    pub unsat_cores: bool,
+    /// Model symbolic Sail `int` values using the SMT integer theory,
+    /// rather than as 128-bit bitvectors which silently wrap on
+    /// overflow. Integer shifts by a symbolic amount are not
+    /// supported in this mode.
+    pub unbounded_ints: bool,
+    /// Simplify each definition as it is added to the solver (see
+    /// [rewrite]). Otherwise traces are only simplified afterwards by
+    /// [crate::simplify::simplify_smt].
+    pub simplify: bool,
 }
 
-/// Interface for extracting information from Z3 models.
-///
-/// Model generation should be turned on in advance.  This is
-/// currently Z3's default, but it's best to make sure:
-///
-/// ```
-/// # use isla_lib::concrete::bitvector64::B64;
-/// # use isla_lib::smt::smtlib::Exp::*;
-/// # use isla_lib::smt::smtlib::Def::*;
-/// # use isla_lib::smt::smtlib::*;
-/// # use isla_lib::smt::*;
-/// # let x = Sym::from_u32(0);
-/// let mut cfg = Config::new();
-/// cfg.set_param_value("model", "true");
-/// let ctx = Context::new(cfg);
-/// let mut solver = Solver::<B64>::new(&ctx);
-/// solver.add(DeclareConst(x, Ty::BitVec(4)));
-/// solver.add(Assert(Bvsgt(Box::new(Var(x)), Box::new(Bits(vec![false,false,true,false])))));
-/// assert!(solver.check_sat() == SmtResult::Sat);
-/// let mut model = Model::new(&solver);
-/// let var0 = model.get_var(x).unwrap().unwrap();
-/// ```
-pub struct Model<'ctx, B> {
-    z3_model: Z3_model,
-    solver: &'ctx Solver<'ctx, B>,
-    ctx: &'ctx Context,
+/// A Z3 scope opened when an incremental solver created a checkpoint.
+struct Scope<B> {
+    point: Arc<Option<Trace<B>>>,
+    enums: usize,
+    fork_location: Option<String>,
 }
 
-impl<'ctx, B> Drop for Model<'ctx, B> {
-    fn drop(&mut self) {
-        unsafe {
-            Z3_model_dec_ref(self.ctx.z3_ctx, self.z3_model);
-        }
-    }
+/// A thread-safe cache of `check_sat_with` results which can be
+/// shared between solvers, so sibling tasks asking the same question
+/// of the same solver state only call Z3 once. A solver state is the
+/// checkpoint the solver continues from, plus the definitions added
+/// since, so every solver replaying or restoring a checkpoint on any
+/// thread shares the states that extend it. States are looked up by
+/// the checkpoint and a hash of the later definitions, which are
+/// compared in full on a hit. Once the cache holds `capacity` states,
+/// the oldest state is evicted to make room for a new one.
+pub struct SmtCache {
+    capacity: usize,
+    states: RwLock<CacheStates>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
 }
 
-// This implements Debug rather than Display because it displays the internal
-// variable names (albeit with the same numbers that appear in the trace).
-impl<'ctx, B> fmt::Debug for Model<'ctx, B> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        unsafe {
-            let z3_string = CStr::from_ptr(Z3_model_to_string(self.ctx.z3_ctx, self.z3_model));
-            write!(f, "{}", z3_string.to_string_lossy())
-        }
-    }
+#[derive(Default)]
+struct CacheStates {
+    by_key: HashMap<CacheKey, Vec<CachedState>>,
+    /// The states in the order they were added, for eviction
+    order: VecDeque<(CacheKey, Arc<[Def]>)>,
 }
 
-impl<'ctx, B: BV> Model<'ctx, B> {
-    pub fn new(solver: &'ctx Solver<'ctx, B>) -> Self {
-        unsafe {
-            let z3_model = Z3_solver_get_model(solver.ctx.z3_ctx, solver.z3_solver);
-            Z3_model_inc_ref(solver.ctx.z3_ctx, z3_model);
-            Model { z3_model, solver, ctx: solver.ctx }
-        }
+/// A checkpoint identifier and a hash of the definitions since
+type CacheKey = (u64, u64);
+
+struct CachedState {
+    /// The definitions since the checkpoint, oldest first
+    defs: Arc<[Def]>,
+    results: HashMap<Exp, SmtResult>,
+}
+
+impl CachedState {
+    fn is(&self, defs: &[&Def]) -> bool {
+        self.defs.len() == defs.len() && self.defs.iter().zip(defs).all(|(lhs, rhs)| lhs == *rhs)
     }
+}
 
-    #[allow(clippy::needless_range_loop)]
-    fn get_large_bv(&mut self, ast: Ast, size: u32) -> Result<Vec<bool>, ExecError> {
+impl SmtCache {
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    pub fn new() -> Self {
+        Self::with_capacity(Self::DEFAULT_CAPACITY)
+    }
+
+    /// Create a cache holding results for at most `capacity` solver
+    /// states.
+    pub fn with_capacity(capacity: usize) -> Self {
+        SmtCache {
+            capacity,
+            states: RwLock::new(CacheStates::default()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    fn get(&self, key: CacheKey, defs: &[&Def], exp: &Exp) -> Option<SmtResult> {
+        let states = self.states.read().unwrap();
+        let result = states
+            .by_key
+            .get(&key)
+            .and_then(|states| states.iter().find(|state| state.is(defs)))
+            .and_then(|state| state.results.get(exp))
+            .copied();
+        if result.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    fn insert(&self, key: CacheKey, defs: &[&Def], exp: &Exp, result: SmtResult) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut states = self.states.write().unwrap();
+        let CacheStates { by_key, order } = &mut *states;
+
+        if let Some(state) = by_key.get_mut(&key).and_then(|states| states.iter_mut().find(|state| state.is(defs))) {
+            state.results.insert(exp.clone(), result);
+            return;
+        }
+
+        if order.len() >= self.capacity {
+            if let Some((oldest_key, oldest)) = order.pop_front() {
+                if let Some(states) = by_key.get_mut(&oldest_key) {
+                    states.retain(|state| !Arc::ptr_eq(&state.defs, &oldest));
+                    if states.is_empty() {
+                        by_key.remove(&oldest_key);
+                    }
+                }
+            }
+        }
+
+        let defs: Arc<[Def]> = defs.iter().map(|def| (*def).clone()).collect();
+        let mut results = HashMap::new();
+        results.insert(exp.clone(), result);
+        by_key.entry(key).or_default().push(CachedState { defs: defs.clone(), results });
+        order.push_back((key, defs))
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// The number of solver states with cached results.
+    pub fn len(&self) -> usize {
+        self.states.read().unwrap().order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        let mut states = self.states.write().unwrap();
+        states.by_key.clear();
+        states.order.clear()
+    }
+}
+
+impl Default for SmtCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes every query a solver sends to Z3 via `check_sat` or
+/// `check_sat_with` into a directory as a self-contained SMT-LIB2
+/// benchmark, so hard queries from real runs can be reproduced and
+/// profiled outside isla. Files are named `<task>_<n>.smt2`, and
+/// begin with comments giving the task, the location of the most
+/// recent fork, the result, and how long Z3 took. Results served
+/// from an `SmtCache` are not recorded. Recording does not stop
+/// execution if a file cannot be written, but such queries are
+/// counted by `failures`.
+pub struct QueryRecorder {
+    dir: PathBuf,
+    queries: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+impl QueryRecorder {
+    /// Create a recorder writing into `dir`, creating the directory
+    /// if it does not exist.
+    pub fn new<P: AsRef<Path>>(dir: P) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(QueryRecorder {
+            dir: dir.as_ref().to_path_buf(),
+            queries: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn queries(&self) -> usize {
+        self.queries.load(Ordering::Relaxed)
+    }
+
+    /// The number of queries which could not be written.
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    fn record(&self, task_id: usize, location: Option<&str>, result: SmtResult, time: Duration, benchmark: &str) {
+        if self.write_query(task_id, location, result, time, benchmark).is_err() {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn write_query(
+        &self,
+        task_id: usize,
+        location: Option<&str>,
+        result: SmtResult,
+        time: Duration,
+        benchmark: &str,
+    ) -> std::io::Result<()> {
+        let n = self.queries.fetch_add(1, Ordering::Relaxed);
+        let mut file = std::fs::File::create(self.dir.join(format!("{}_{}.smt2", task_id, n)))?;
+        writeln!(file, "; task: {}", task_id)?;
+        if let Some(location) = location {
+            writeln!(file, "; location: {}", location.replace('\n', " "))?
+        }
+        writeln!(file, "; result: {}", result.status())?;
+        writeln!(file, "; time: {:.6}s", time.as_secs_f64())?;
+        file.write_all(benchmark.as_bytes())
+    }
+}
+
+/// A Z3 solver configuration which can be raced against a solver's
+/// own configuration, see [Portfolio].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SolverConfig {
+    /// A solver built from the named Z3 tactic, for example `qfaufbv`
+    /// (which isla uses by default) or `qfbv`, which bit-blasts.
+    /// Tactics which cannot handle a query return unknown.
+    Tactic(String),
+    /// Z3's general purpose solver
+    General,
+}
+
+impl SolverConfig {
+    unsafe fn mk_solver(&self, ctx: &Context) -> Z3_solver {
+        match self {
+            SolverConfig::Tactic(name) => {
+                let name = CString::new(name.as_str()).unwrap();
+                let tactic = Z3_mk_tactic(ctx.z3_ctx, name.as_ptr());
+                Z3_tactic_inc_ref(ctx.z3_ctx, tactic);
+                let z3_solver = Z3_mk_solver_from_tactic(ctx.z3_ctx, tactic);
+                Z3_tactic_dec_ref(ctx.z3_ctx, tactic);
+                z3_solver
+            }
+            SolverConfig::General => Z3_mk_solver(ctx.z3_ctx),
+        }
+    }
+}
+
+/// A set of solver configurations which are raced against a solver's
+/// own configuration by `check_sat_with`. The solver first gets a head
+/// start, running alone with the head start as its timeout, and only
+/// if it has not answered by then does each configuration get its own
+/// thread and Z3 context, into which a checkpoint of the solver is
+/// replayed, while the solver retries the query. The first definitive
+/// answer is used, and the remaining solvers are interrupted. As
+/// replaying the checkpoint takes time this is only worthwhile for
+/// hard queries, which the head start picks out. The portfolio is not
+/// used while the solver has assertions which are not in its trace,
+/// as the other configurations would not see them.
+///
+/// If another configuration answers first the solver is interrupted,
+/// so it has no model for the query, and `check_sat` should be used
+/// before building a `Model`.
+#[derive(Clone, Debug)]
+pub struct Portfolio {
+    configs: Vec<SolverConfig>,
+    head_start: Duration,
+}
+
+impl Portfolio {
+    pub fn new(configs: Vec<SolverConfig>) -> Self {
+        Portfolio { configs, head_start: Duration::from_millis(100) }
+    }
+
+    pub fn configs(&self) -> &[SolverConfig] {
+        &self.configs
+    }
+
+    /// How long the solver's own configuration runs alone before the
+    /// others are started, 100ms by default. With a head start of less
+    /// than a millisecond they are started immediately.
+    pub fn set_head_start(&mut self, head_start: Duration) {
+        self.head_start = head_start
+    }
+
+    pub fn head_start(&self) -> Duration {
+        self.head_start
+    }
+}
+
+impl Default for Portfolio {
+    fn default() -> Self {
+        Portfolio::new(vec![SolverConfig::General, SolverConfig::Tactic("qfbv".to_string())])
+    }
+}
+
+/// A Z3 context which another thread can interrupt
+#[derive(Clone, Copy)]
+struct Interrupt(Z3_context);
+
+// Z3_interrupt is designed to be called from other threads
+unsafe impl Send for Interrupt {}
+
+struct RaceState {
+    finished: bool,
+    running: Vec<Option<Interrupt>>,
+}
+
+/// Shared state for the solvers racing on a query. A solver must
+/// register its context before it starts, and deregister it once the
+/// query is done, so only running queries are interrupted.
+struct Race {
+    state: Mutex<RaceState>,
+}
+
+impl Race {
+    fn new(solvers: usize) -> Self {
+        Race { state: Mutex::new(RaceState { finished: false, running: vec![None; solvers] }) }
+    }
+
+    fn finished(&self) -> bool {
+        self.state.lock().unwrap().finished
+    }
+
+    fn run<'ctx>(&self, i: usize, z3: &mut Z3Backend<'ctx>, ast: &Ast<'ctx>) -> SmtResult {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.finished {
+                return Unknown;
+            }
+            state.running[i] = Some(Interrupt(z3.ctx.z3_ctx))
+        }
+        let result = z3.check_assumption(ast);
+        let mut state = self.state.lock().unwrap();
+        state.running[i] = None;
+        if result != Unknown && !state.finished {
+            state.finished = true;
+            for Interrupt(z3_ctx) in state.running.iter().flatten() {
+                unsafe { Z3_interrupt(*z3_ctx) }
+            }
+        }
+        result
+    }
+}
+
+/// Counts of the work a solver has done. Each solver keeps its own
+/// statistics, see `Solver::statistics`, and a `SolverProfile` can
+/// aggregate them across many solvers.
+#[derive(Clone, Debug, Default)]
+pub struct SolverStats {
+    /// Constants and functions declared or defined
+    pub declarations: usize,
+    pub assertions: usize,
+    /// Declarations and assertions replayed from a checkpoint by
+    /// `from_checkpoint`, which are not counted above
+    pub replayed: usize,
+    /// Calls to `check_sat` and `check_sat_with` answered by Z3
+    pub queries: usize,
+    /// Calls to `check_sat_with` answered from an `SmtCache`
+    pub cached: usize,
+    pub sat: usize,
+    pub unsat: usize,
+    pub unknown: usize,
+    /// Total time spent in Z3 answering queries
+    pub time: Duration,
+    /// Time taken by the slowest query
+    pub max_time: Duration,
+}
+
+impl SolverStats {
+    fn record(&mut self, result: SmtResult, time: Duration) {
+        self.queries += 1;
+        match result {
+            Sat => self.sat += 1,
+            Unsat => self.unsat += 1,
+            Unknown => self.unknown += 1,
+        }
+        self.time += time;
+        self.max_time = std::cmp::max(self.max_time, time)
+    }
+
+    pub fn merge(&mut self, other: &SolverStats) {
+        self.declarations += other.declarations;
+        self.assertions += other.assertions;
+        self.replayed += other.replayed;
+        self.queries += other.queries;
+        self.cached += other.cached;
+        self.sat += other.sat;
+        self.unsat += other.unsat;
+        self.unknown += other.unknown;
+        self.time += other.time;
+        self.max_time = std::cmp::max(self.max_time, other.max_time)
+    }
+}
+
+/// Aggregates solver statistics across threads. Queries are
+/// attributed to the location of the most recent fork (the location
+/// string from the `Instr::Jump` that created it) so expensive parts
+/// of a specification can be identified. The totals include each
+/// solver's declarations and assertions, which are added when the
+/// solver is dropped.
+#[derive(Default)]
+pub struct SolverProfile {
+    total: Mutex<SolverStats>,
+    locations: Mutex<HashMap<Option<String>, SolverStats>>,
+}
+
+impl SolverProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, location: &Option<String>, result: SmtResult, time: Duration) {
+        self.locations.lock().unwrap().entry(location.clone()).or_default().record(result, time)
+    }
+
+    fn record_cached(&self, location: &Option<String>) {
+        self.locations.lock().unwrap().entry(location.clone()).or_default().cached += 1
+    }
+
+    /// The statistics of every solver using this profile that has
+    /// been dropped
+    pub fn total(&self) -> SolverStats {
+        self.total.lock().unwrap().clone()
+    }
+
+    /// Query statistics for each fork location, ordered by the total
+    /// time spent in Z3, most expensive first. Queries made before
+    /// any fork have no location.
+    pub fn locations(&self) -> Vec<(Option<String>, SolverStats)> {
+        let mut locations: Vec<_> =
+            self.locations.lock().unwrap().iter().map(|(loc, stats)| (loc.clone(), stats.clone())).collect();
+        locations.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.time));
+        locations
+    }
+}
+
+impl<'ctx, B> Drop for Solver<'ctx, B> {
+    fn drop(&mut self) {
+        if let Some(profile) = &self.profile {
+            profile.total.lock().unwrap().merge(&self.stats)
+        }
+    }
+}
+
+/// Interface for extracting information from Z3 models.
+///
+/// Model generation should be turned on in advance.  This is
+/// currently Z3's default, but it's best to make sure:
+///
+/// ```
+/// # use isla_lib::concrete::bitvector64::B64;
+/// # use isla_lib::smt::smtlib::Exp::*;
+/// # use isla_lib::smt::smtlib::Def::*;
+/// # use isla_lib::smt::smtlib::*;
+/// # use isla_lib::smt::*;
+/// # let x = Sym::from_u32(0);
+/// let mut cfg = Config::new();
+/// cfg.set_param_value("model", "true");
+/// let ctx = Context::new(cfg);
+/// let mut solver = Solver::<B64>::new(&ctx);
+/// solver.add(DeclareConst(x, Ty::BitVec(4)));
+/// solver.add(Assert(Bvsgt(Box::new(Var(x)), Box::new(Bits(vec![false,false,true,false])))));
+/// assert!(solver.check_sat() == SmtResult::Sat);
+/// let mut model = Model::new(&solver);
+/// let var0 = model.get_var(x).unwrap().unwrap();
+/// ```
+pub struct Model<'ctx, B> {
+    z3_model: Z3_model,
+    solver: &'ctx Solver<'ctx, B>,
+    ctx: &'ctx Context,
+}
+
+impl<'ctx, B> Drop for Model<'ctx, B> {
+    fn drop(&mut self) {
+        if !self.z3_model.is_null() {
+            unsafe { Z3_model_dec_ref(self.ctx.z3_ctx, self.z3_model) }
+        }
+    }
+}
+
+// This implements Debug rather than Display because it displays the internal
+// variable names (albeit with the same numbers that appear in the trace).
+impl<'ctx, B> fmt::Debug for Model<'ctx, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.z3_model.is_null() {
+            return write!(f, "(model in solver backend)");
+        }
+        unsafe {
+            let z3_string = CStr::from_ptr(Z3_model_to_string(self.ctx.z3_ctx, self.z3_model));
+            write!(f, "{}", z3_string.to_string_lossy())
+        }
+    }
+}
+
+impl<'ctx, B: BV> Model<'ctx, B> {
+    /// With a solver backend (see `Solver::set_backend`) values are
+    /// taken from the backend's model for its most recent query.
+    pub fn new(solver: &'ctx Solver<'ctx, B>) -> Self {
+        if solver.has_backend() {
+            return Model { z3_model: ptr::null_mut(), solver, ctx: solver.ctx };
+        }
+        unsafe {
+            let z3_model = Z3_solver_get_model(solver.ctx.z3_ctx, solver.z3.z3_solver);
+            Z3_model_inc_ref(solver.ctx.z3_ctx, z3_model);
+            Model { z3_model, solver, ctx: solver.ctx }
+        }
+    }
+
+    pub fn get_var(&mut self, var: Sym) -> Result<Option<Exp>, ExecError> {
+        self.get_var_with_completion(var, false)
+    }
+
+    // With model completion, variables the model did not need to
+    // assign an interpretation to are given an arbitrary value.
+    fn get_var_with_completion(&mut self, var: Sym, completion: bool) -> Result<Option<Exp>, ExecError> {
+        if self.z3_model.is_null() {
+            return self.solver.backend_value(&Exp::Var(var));
+        }
+        let var_ast = match self.solver.z3.decls.get(&var) {
+            None => return Err(ExecError::Type(format!("Unbound variable {:?}", &var))),
+            Some(ast) => ast.clone(),
+        };
+        self.get_ast(var_ast, completion)
+    }
+
+    pub fn get_exp(&mut self, exp: &Exp) -> Result<Option<Exp>, ExecError> {
+        if self.z3_model.is_null() {
+            return self.solver.backend_value(exp);
+        }
+        let ast = self.solver.z3.translate_exp(exp);
+        self.get_ast(ast, false)
+    }
+
+    /// Concretise a value by replacing every symbolic variable it
+    /// contains with its interpretation in the model. Variables the
+    /// model leaves unconstrained are given an arbitrary value.
+    ///
+    /// Bitvectors are returned as `Val::Bits`, so this fails for
+    /// variables wider than `B::MAX_WIDTH`. Note that without the
+    /// `unbounded_ints` solver option symbolic integers are 128-bit
+    /// bitvectors, and will be returned as such, so use
+    /// `eval_val_with_ty` when the type of the value is known.
+    pub fn eval_val(&mut self, val: &Val<B>) -> Result<Val<B>, ExecError> {
+        match val {
+            Val::Symbolic(v) => match self.get_var_with_completion(*v, true)? {
+                Some(exp) => exp_to_val(&exp),
+                None => Err(ExecError::Z3Error(format!("No value for variable v{}", v))),
+            },
+            Val::Vector(vals) => Ok(Val::Vector(vals.iter().map(|val| self.eval_val(val)).collect::<Result<_, _>>()?)),
+            Val::List(vals) => Ok(Val::List(vals.iter().map(|val| self.eval_val(val)).collect::<Result<_, _>>()?)),
+            Val::Struct(fields) => {
+                let fields: Result<_, ExecError> =
+                    fields.iter().map(|(name, val)| Ok((*name, self.eval_val(val)?))).collect();
+                Ok(Val::Struct(fields?))
+            }
+            Val::Ctor(ctor, val) => Ok(Val::Ctor(*ctor, Box::new(self.eval_val(val)?))),
+            _ => Ok(val.clone()),
+        }
+    }
+
+    /// Like `eval_val`, but using the type of the value to convert
+    /// each variable, so symbolic Sail integers are returned as
+    /// `Val::I128` or `Val::I64` whichever SMT sort represents them.
+    /// Struct fields and constructor arguments are converted as by
+    /// `eval_val`.
+    pub fn eval_val_with_ty(&mut self, val: &Val<B>, ty: &ir::Ty<Name>) -> Result<Val<B>, ExecError> {
+        match (val, ty) {
+            (Val::Symbolic(v), ir::Ty::I128) => match self.get_var_with_completion(*v, true)? {
+                Some(Exp::Int(n)) => Ok(Val::I128(n)),
+                Some(Exp::Bits(bits)) if bits.len() == 128 => {
+                    Ok(Val::I128(bits.iter().rev().fold(0, |acc, bit| (acc << 1) | *bit as u128) as i128))
+                }
+                Some(exp) => Err(ExecError::Type(format!("eval_val_with_ty {:?} is not an integer", exp))),
+                None => Err(ExecError::Z3Error(format!("No value for variable v{}", v))),
+            },
+            (Val::Symbolic(v), ir::Ty::I64) => match self.get_var_with_completion(*v, true)? {
+                Some(Exp::Bits64(n, 64)) => Ok(Val::I64(n as i64)),
+                Some(exp) => Err(ExecError::Type(format!("eval_val_with_ty {:?} is not a 64-bit integer", exp))),
+                None => Err(ExecError::Z3Error(format!("No value for variable v{}", v))),
+            },
+            (Val::Vector(vals), ir::Ty::Vector(ty) | ir::Ty::FixedVector(_, ty)) => {
+                Ok(Val::Vector(vals.iter().map(|val| self.eval_val_with_ty(val, ty)).collect::<Result<_, _>>()?))
+            }
+            (Val::List(vals), ir::Ty::List(ty)) => {
+                Ok(Val::List(vals.iter().map(|val| self.eval_val_with_ty(val, ty)).collect::<Result<_, _>>()?))
+            }
+            _ => self.eval_val(val),
+        }
+    }
+
+    /// Concretise every initialized value in a map of bindings, such
+    /// as the registers of a [crate::executor::LocalFrame], using the
+    /// declared types of the bindings (such as the types of registers
+    /// from `Def::Register`) where they are given.
+    pub fn eval_bindings<'ir>(
+        &mut self,
+        bindings: &Bindings<'ir, B>,
+        types: &HashMap<Name, ir::Ty<Name>>,
+    ) -> Result<Bindings<'ir, B>, ExecError> {
+        bindings
+            .iter()
+            .map(|(name, uval)| match uval {
+                UVal::Init(val) => {
+                    let val = match types.get(name) {
+                        Some(ty) => self.eval_val_with_ty(val, ty)?,
+                        None => self.eval_val(val)?,
+                    };
+                    Ok((*name, UVal::Init(val)))
+                }
+                UVal::Uninit(ty) => Ok((*name, UVal::Uninit(ty))),
+            })
+            .collect()
+    }
+
+    // Requiring the model to be mutable as I expect Z3 will alter the underlying data
+    fn get_ast(&mut self, var_ast: Ast<'ctx>, completion: bool) -> Result<Option<Exp>, ExecError> {
+        self.solver.z3.model_value(self.z3_model, var_ast, completion)
+    }
+}
+
+fn bits_to_bv<B: BV>(bits: &[bool]) -> Result<B, ExecError> {
+    let len: u32 = bits.len().try_into().map_err(|_| ExecError::Overflow)?;
+    if len > B::MAX_WIDTH {
+        return Err(ExecError::Type(format!("{}-bit value is wider than the maximum concrete bitvector width", len)));
+    }
+    let mut bv = B::zeros(len);
+    for (i, chunk) in bits.chunks(64).enumerate() {
+        let value = chunk.iter().rev().fold(0, |value, bit| (value << 1) | (*bit as u64));
+        bv = bv.set_slice(i as u32 * 64, B::new(value, chunk.len() as u32))
+    }
+    Ok(bv)
+}
+
+/// Convert a concrete value from a model into a value
+pub(crate) fn exp_to_val<B: BV>(exp: &Exp) -> Result<Val<B>, ExecError> {
+    match exp {
+        Exp::Bits64(value, len) if *len <= B::MAX_WIDTH => Ok(Val::Bits(B::new(*value, *len))),
+        Exp::Bits64(_, len) => {
+            Err(ExecError::Type(format!("{}-bit value is wider than the maximum concrete bitvector width", len)))
+        }
+        Exp::Bits(bits) => Ok(Val::Bits(bits_to_bv(bits)?)),
+        Exp::Bool(b) => Ok(Val::Bool(*b)),
+        Exp::Int(n) => Ok(Val::I128(*n)),
+        Exp::Enum(member) => Ok(Val::Enum(*member)),
+        _ => Err(ExecError::Type(format!("exp_to_val {:?}", exp))),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmtResult {
+    Sat,
+    Unsat,
+    Unknown,
+}
+
+use SmtResult::*;
+
+impl SmtResult {
+    pub fn is_sat(self) -> Result<bool, ExecError> {
+        match self {
+            Sat => Ok(true),
+            Unsat => Ok(false),
+            Unknown => Err(ExecError::Z3Unknown),
+        }
+    }
+
+    pub fn is_unsat(self) -> Result<bool, ExecError> {
+        match self {
+            Sat => Ok(false),
+            Unsat => Ok(true),
+            Unknown => Err(ExecError::Z3Unknown),
+        }
+    }
+
+    pub fn is_unknown(self) -> bool {
+        self == Unknown
+    }
+
+    /// The result as an SMT-LIB status string
+    pub fn status(self) -> &'static str {
+        match self {
+            Sat => "sat",
+            Unsat => "unsat",
+            Unknown => "unknown",
+        }
+    }
+}
+
+/// The operations isla needs from an SMT solver. [Z3Backend]
+/// implements this trait using the Z3 C API, while
+/// [process::SmtLibProcess] talks SMT-LIB v2 to an external solver
+/// binary, so code written against this trait can be run (and
+/// benchmarked) with either.
+///
+/// To run symbolic execution using another backend, give it to
+/// `Solver::set_backend` (or to `TaskState::set_solver_backend` in
+/// the executor), and the solver will send it every definition and
+/// query.
+pub trait SolverBackend {
+    /// Declare, define or assert according to an SMT definition.
+    fn add_def(&mut self, def: &Def) -> Result<(), ExecError>;
+
+    /// Open a new scope, which is discarded along with every
+    /// definition made in it by the matching `pop`.
+    fn push(&mut self) -> Result<(), ExecError>;
+
+    /// Discard the `n` most recently opened scopes.
+    fn pop(&mut self, n: u32) -> Result<(), ExecError>;
+
+    fn check_sat(&mut self) -> Result<SmtResult, ExecError>;
+
+    /// Check satisfiability assuming `exp` holds, without asserting it.
+    fn check_sat_with(&mut self, exp: &Exp) -> Result<SmtResult, ExecError>;
+
+    /// Evaluate an expression in the model found by the most recent
+    /// satisfiable check.
+    fn get_value(&mut self, exp: &Exp) -> Result<Option<Exp>, ExecError>;
+
+    /// Add all the SMT definitions from a trace, oldest first.
+    fn add_trace<B: BV>(&mut self, trace: &Trace<B>) -> Result<(), ExecError>
+    where
+        Self: Sized,
+    {
+        for event in trace.to_vec().iter().rev() {
+            if let Event::Smt(def) = event {
+                self.add_def(def)?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates the backend for each solver started by the executor, see
+/// `TaskState::set_solver_backend`.
+pub type BackendFactory = dyn Send + Sync + Fn() -> Result<Box<dyn SolverBackend>, ExecError>;
+
+static QFAUFBV_STR: &[u8] = b"qfaufbv\0";
+static TIMEOUT_STR: &[u8] = b"timeout\0";
+static LABEL_STR: &[u8] = b"label\0";
+
+/// The Z3 solver behind every [Solver], which answers its queries
+/// unless it is given another backend with `Solver::set_backend`.
+/// Z3 is still told about every declaration in that case, so it can
+/// give us the sorts of variables and evaluate closed expressions.
+pub struct Z3Backend<'ctx> {
+    decls: HashMap<Sym, Ast<'ctx>>,
+    func_decls: HashMap<Sym, FuncDecl<'ctx>>,
+    enums: Enums<'ctx>,
+    enum_map: HashMap<usize, usize>,
+    labels: HashMap<c_uint, (Ast<'ctx>, String)>,
+    /// False if the solver is built from the QF_AUFBV tactic
+    reals: bool,
+    z3_solver: Z3_solver,
+    ctx: &'ctx Context,
+}
+
+impl<'ctx> Drop for Z3Backend<'ctx> {
+    fn drop(&mut self) {
+        unsafe { Z3_solver_dec_ref(self.ctx.z3_ctx, self.z3_solver) }
+    }
+}
+
+impl<'ctx> Z3Backend<'ctx> {
+    pub fn new(ctx: &'ctx Context) -> Self {
+        Self::with_config(ctx, SolverOptions::default(), None)
+    }
+
+    fn with_config(ctx: &'ctx Context, options: SolverOptions, config: Option<&SolverConfig>) -> Self {
+        unsafe {
+            // The QF_AUFBV solver has good performance on our problems, but we need to initialise it
+            // using a tactic rather than the logic name to ensure that the enumerations are supported,
+            // otherwise Z3 may crash.
+            // Solvers created from tactics cannot produce unsat cores,
+            // and the QF_AUFBV tactic does not handle integers or reals,
+            // so we use Z3's general purpose solver in those cases.
+            let reals = config.is_some() || options.unsat_cores || options.unbounded_ints;
+            let z3_solver = if let Some(config) = config {
+                config.mk_solver(ctx)
+            } else if options.unsat_cores || options.unbounded_ints {
+                Z3_mk_solver(ctx.z3_ctx)
+            } else {
+                let qfaufbv_tactic =
+                    Z3_mk_tactic(ctx.z3_ctx, CStr::from_bytes_with_nul_unchecked(QFAUFBV_STR).as_ptr());
+                Z3_tactic_inc_ref(ctx.z3_ctx, qfaufbv_tactic);
+                Z3_mk_solver_from_tactic(ctx.z3_ctx, qfaufbv_tactic)
+            };
+            Z3_solver_inc_ref(ctx.z3_ctx, z3_solver);
+
+            Z3Backend {
+                decls: HashMap::new(),
+                func_decls: HashMap::new(),
+                enums: Enums::new(ctx),
+                enum_map: HashMap::new(),
+                labels: HashMap::new(),
+                reals,
+                z3_solver,
+                ctx,
+            }
+        }
+    }
+
+    fn translate_exp(&self, exp: &Exp) -> Ast<'ctx> {
+        use Exp::*;
+        match exp {
+            Var(v) => match self.decls.get(v) {
+                None => panic!("Could not get Z3 func_decl {}", *v),
+                Some(ast) => ast.clone(),
+            },
+            Bits(bv) => Ast::mk_bv(self.ctx, bv.len().try_into().unwrap(), &bv),
+            Bits64(bv, len) => Ast::mk_bv_u64(self.ctx, *len, *bv),
+            Enum(e) => Ast::mk_enum_member(&self.enums, e.enum_id, e.member),
+            Bool(b) => Ast::mk_bool(self.ctx, *b),
+            Not(exp) => Ast::mk_not(&self.translate_exp(exp)),
+            Eq(lhs, rhs) => Ast::mk_eq(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Neq(lhs, rhs) => Ast::mk_not(&Ast::mk_eq(&self.translate_exp(lhs), &self.translate_exp(rhs))),
+            And(lhs, rhs) => Ast::mk_and(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Or(lhs, rhs) => Ast::mk_or(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvnot(exp) => Ast::mk_bvnot(&self.translate_exp(exp)),
+            Bvand(lhs, rhs) => Ast::mk_bvand(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvor(lhs, rhs) => Ast::mk_bvor(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvxor(lhs, rhs) => Ast::mk_bvxor(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvnand(lhs, rhs) => Ast::mk_bvnand(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvnor(lhs, rhs) => Ast::mk_bvnor(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvxnor(lhs, rhs) => Ast::mk_bvxnor(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvneg(exp) => Ast::mk_bvneg(&self.translate_exp(exp)),
+            Bvadd(lhs, rhs) => Ast::mk_bvadd(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvsub(lhs, rhs) => Ast::mk_bvsub(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvmul(lhs, rhs) => Ast::mk_bvmul(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvudiv(lhs, rhs) => Ast::mk_bvudiv(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvsdiv(lhs, rhs) => Ast::mk_bvsdiv(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvurem(lhs, rhs) => Ast::mk_bvurem(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvsrem(lhs, rhs) => Ast::mk_bvsrem(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvsmod(lhs, rhs) => Ast::mk_bvsmod(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvult(lhs, rhs) => Ast::mk_bvult(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvslt(lhs, rhs) => Ast::mk_bvslt(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvule(lhs, rhs) => Ast::mk_bvule(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvsle(lhs, rhs) => Ast::mk_bvsle(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvuge(lhs, rhs) => Ast::mk_bvuge(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvsge(lhs, rhs) => Ast::mk_bvsge(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvugt(lhs, rhs) => Ast::mk_bvugt(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvsgt(lhs, rhs) => Ast::mk_bvsgt(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Extract(hi, lo, bv) => self.translate_exp(bv).extract(*hi, *lo),
+            ZeroExtend(i, bv) => self.translate_exp(bv).zero_extend(*i),
+            SignExtend(i, bv) => self.translate_exp(bv).sign_extend(*i),
+            Bvshl(lhs, rhs) => Ast::mk_bvshl(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvlshr(lhs, rhs) => Ast::mk_bvlshr(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Bvashr(lhs, rhs) => Ast::mk_bvashr(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Concat(lhs, rhs) => Ast::mk_concat(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Ite(cond, t, f) => self.translate_exp(cond).ite(&self.translate_exp(t), &self.translate_exp(f)),
+            App(f, args) => {
+                let args_ast: Vec<_> = args.iter().map(|arg| self.translate_exp(arg)).collect();
+                match self.func_decls.get(f) {
+                    None => panic!("Could not get Z3 func_decl {}", *f),
+                    Some(fd) => Ast::mk_app(&fd, &args_ast),
+                }
+            }
+            Select(array, index) => Ast::mk_select(&self.translate_exp(array), &self.translate_exp(index)),
+            Store(array, index, val) => {
+                Ast::mk_store(&self.translate_exp(array), &self.translate_exp(index), &self.translate_exp(val))
+            }
+            RoundingMode(rm) => Ast::mk_rounding_mode(self.ctx, *rm),
+            FPUnary(op, exp) => self.translate_exp(exp).mk_fp_unary(*op),
+            FPRoundingUnary(op, rm, exp) => {
+                Ast::mk_fp_rounding_unary(&self.translate_exp(rm), *op, &self.translate_exp(exp))
+            }
+            FPBinary(op, lhs, rhs) => self.translate_exp(lhs).mk_fp_binary(*op, &self.translate_exp(rhs)),
+            FPRoundingBinary(op, rm, lhs, rhs) => Ast::mk_fp_rounding_binary(
+                &self.translate_exp(rm),
+                *op,
+                &self.translate_exp(lhs),
+                &self.translate_exp(rhs),
+            ),
+            FPfma(rm, x, y, z) => Ast::mk_fp_fma(
+                &self.translate_exp(rm),
+                &self.translate_exp(x),
+                &self.translate_exp(y),
+                &self.translate_exp(z),
+            ),
+            FPFromIEEE(ebits, sbits, bv) => self.translate_exp(bv).mk_fp_from_ieee(*ebits, *sbits),
+            Int(i) => Ast::mk_int(self.ctx, *i),
+            IntNeg(exp) => Ast::mk_int_neg(&self.translate_exp(exp)),
+            IntAdd(lhs, rhs) => Ast::mk_int_arith(&self.translate_exp(lhs), Z3_mk_add, &self.translate_exp(rhs)),
+            IntSub(lhs, rhs) => Ast::mk_int_arith(&self.translate_exp(lhs), Z3_mk_sub, &self.translate_exp(rhs)),
+            IntMul(lhs, rhs) => Ast::mk_int_arith(&self.translate_exp(lhs), Z3_mk_mul, &self.translate_exp(rhs)),
+            IntDiv(lhs, rhs) => Ast::mk_int_div(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            IntMod(lhs, rhs) => Ast::mk_int_mod(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            IntLt(lhs, rhs) => Ast::mk_int_lt(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            IntLe(lhs, rhs) => Ast::mk_int_le(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            IntGt(lhs, rhs) => Ast::mk_int_gt(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            IntGe(lhs, rhs) => Ast::mk_int_ge(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            Int2BV(sz, exp) => Ast::mk_int2bv(&self.translate_exp(exp), *sz),
+            BV2Nat(exp) => Ast::mk_bv2nat(&self.translate_exp(exp)),
+            IntToReal(exp) => Ast::mk_int2real(&self.translate_exp(exp)),
+            RealToInt(exp) => Ast::mk_real2int(&self.translate_exp(exp)),
+            RealDiv(lhs, rhs) => Ast::mk_int_div(&self.translate_exp(lhs), &self.translate_exp(rhs)),
+            FPConvert(conversion, rm, exp) => {
+                Ast::mk_fp_convert(&self.translate_exp(rm), *conversion, &self.translate_exp(exp))
+            }
+        }
+    }
+
+    /// Make a declaration or definition, which must not be an
+    /// assertion.
+    fn declare(&mut self, def: &Def) {
+        match def {
+            Def::Assert(_) => panic!("Z3Backend::declare called with an assertion"),
+            Def::DeclareConst(v, ty) => {
+                let fd = FuncDecl::new(&self.ctx, *v, &self.enums, &[], ty);
+                self.decls.insert(*v, Ast::mk_constant(&fd));
+            }
+            Def::DeclareFun(v, arg_tys, result_ty) => {
+                let fd = FuncDecl::new(&self.ctx, *v, &self.enums, arg_tys, result_ty);
+                self.func_decls.insert(*v, fd);
+            }
+            Def::DefineConst(v, exp) => {
+                let ast = self.translate_exp(exp);
+                self.decls.insert(*v, ast);
+            }
+            Def::DefineEnum(name, size) => {
+                self.enums.add_enum(*name, *size);
+                self.enum_map.insert(*size, self.enums.enums.len() - 1);
+            }
+        }
+    }
+
+    fn assert(&mut self, exp: &Exp) {
+        let ast = self.translate_exp(exp);
+        unsafe { Z3_solver_assert(self.ctx.z3_ctx, self.z3_solver, ast.z3_ast) }
+    }
+
+    /// Assert an expression, tracking it with a fresh label so it can
+    /// be reported in unsat cores. The solver must not have been
+    /// created from a tactic.
+    fn assert_labelled(&mut self, exp: &Exp, label: String) {
+        let ast = self.translate_exp(exp);
+        unsafe {
+            let z3_ctx = self.ctx.z3_ctx;
+            let bool_sort = Sort::new(self.ctx, &self.enums, &Ty::Bool);
+            let z3_label = Z3_mk_fresh_const(z3_ctx, LABEL_STR.as_ptr() as Z3_string, bool_sort.z3_sort);
+            Z3_inc_ref(z3_ctx, z3_label);
+            let label_ast = Ast { z3_ast: z3_label, ctx: self.ctx };
+            Z3_solver_assert_and_track(z3_ctx, self.z3_solver, ast.z3_ast, label_ast.z3_ast);
+            self.labels.insert(Z3_get_ast_id(z3_ctx, z3_label), (label_ast, label));
+        }
+    }
+
+    fn check_assumption(&mut self, ast: &Ast<'ctx>) -> SmtResult {
+        smt_result(unsafe { Z3_solver_check_assumptions(self.ctx.z3_ctx, self.z3_solver, 1, &ast.z3_ast) })
+    }
+
+    /// Like `check_assumption`, but give up with `Unknown` after `timeout`.
+    fn check_assumption_timeout(&mut self, ast: &Ast<'ctx>, timeout: Duration) -> SmtResult {
+        let millis = timeout.as_millis().try_into().unwrap_or(u32::MAX);
+        self.set_timeout(millis);
+        let result = self.check_assumption(ast);
+        // Z3 treats the largest timeout as no timeout
+        self.set_timeout(u32::MAX);
+        result
+    }
+
+    fn set_timeout(&mut self, millis: u32) {
+        unsafe {
+            let z3_ctx = self.ctx.z3_ctx;
+            let params = Z3_mk_params(z3_ctx);
+            Z3_params_inc_ref(z3_ctx, params);
+            let timeout = Z3_mk_string_symbol(z3_ctx, CStr::from_bytes_with_nul_unchecked(TIMEOUT_STR).as_ptr());
+            Z3_params_set_uint(z3_ctx, params, timeout, millis);
+            Z3_solver_set_params(z3_ctx, self.z3_solver, params);
+            Z3_params_dec_ref(z3_ctx, params)
+        }
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn get_large_bv(&self, z3_model: Z3_model, ast: Ast<'ctx>, size: u32) -> Result<Vec<bool>, ExecError> {
         let mut i = 0;
         let size = size.try_into().unwrap();
         let mut result = vec![false; size];
@@ -1132,7 +2672,7 @@ impl<'ctx, B: BV> Model<'ctx, B> {
 
             unsafe {
                 let mut result_z3_ast: Z3_ast = ptr::null_mut();
-                if !Z3_model_eval(self.ctx.z3_ctx, self.z3_model, extract_ast.z3_ast, true, &mut result_z3_ast) {
+                if !Z3_model_eval(self.ctx.z3_ctx, z3_model, extract_ast.z3_ast, true, &mut result_z3_ast) {
                     return Err(self.ctx.error());
                 }
                 Z3_inc_ref(self.ctx.z3_ctx, result_z3_ast);
@@ -1140,32 +2680,20 @@ impl<'ctx, B: BV> Model<'ctx, B> {
             }
             let v = result_ast.get_numeral_u64()?;
             for j in i..hi {
-                result[j] = (v >> (j - i) & 1) == 1;
-            }
-            i += 64;
-        }
-        Ok(result)
-    }
-
-    pub fn get_var(&mut self, var: Sym) -> Result<Option<Exp>, ExecError> {
-        let var_ast = match self.solver.decls.get(&var) {
-            None => return Err(ExecError::Type(format!("Unbound variable {:?}", &var))),
-            Some(ast) => ast.clone(),
-        };
-        self.get_ast(var_ast)
-    }
-
-    pub fn get_exp(&mut self, exp: &Exp) -> Result<Option<Exp>, ExecError> {
-        let ast = self.solver.translate_exp(exp);
-        self.get_ast(ast)
+                result[j] = (v >> (j - i) & 1) == 1;
+            }
+            i += 64;
+        }
+        Ok(result)
     }
 
-    // Requiring the model to be mutable as I expect Z3 will alter the underlying data
-    fn get_ast(&mut self, var_ast: Ast) -> Result<Option<Exp>, ExecError> {
+    /// Evaluate an expression in a model of this solver, returning
+    /// `None` if the model does not need to assign it a value.
+    fn model_value(&self, z3_model: Z3_model, var_ast: Ast<'ctx>, completion: bool) -> Result<Option<Exp>, ExecError> {
         unsafe {
             let z3_ctx = self.ctx.z3_ctx;
             let mut z3_ast: Z3_ast = ptr::null_mut();
-            if !Z3_model_eval(z3_ctx, self.z3_model, var_ast.z3_ast, false, &mut z3_ast) {
+            if !Z3_model_eval(z3_ctx, z3_model, var_ast.z3_ast, completion, &mut z3_ast) {
                 return Err(self.ctx.error());
             }
             Z3_inc_ref(z3_ctx, z3_ast);
@@ -1179,7 +2707,7 @@ impl<'ctx, B: BV> Model<'ctx, B> {
             let result = if sort_kind == SortKind::BV && Z3_is_numeral_ast(z3_ctx, z3_ast) {
                 let size = Z3_get_bv_sort_size(z3_ctx, sort);
                 if size > 64 {
-                    let v = self.get_large_bv(ast, size)?;
+                    let v = self.get_large_bv(z3_model, ast, size)?;
                     Ok(Some(Exp::Bits(v)))
                 } else {
                     let result = ast.get_numeral_u64()?;
@@ -1187,7 +2715,15 @@ impl<'ctx, B: BV> Model<'ctx, B> {
                 }
             } else if sort_kind == SortKind::Bool && Z3_is_numeral_ast(z3_ctx, z3_ast) {
                 Ok(Some(Exp::Bool(ast.get_bool_value().unwrap())))
-            } else if sort_kind == SortKind::Bool || sort_kind == SortKind::BV {
+            } else if sort_kind == SortKind::Int && Z3_is_numeral_ast(z3_ctx, z3_ast) {
+                Ok(Some(Exp::Int(ast.get_numeral_i128()?)))
+            } else if sort_kind == SortKind::Real && Z3_is_numeral_ast(z3_ctx, z3_ast) {
+                Ok(Some(ast.get_numeral_real()?))
+            } else if sort_kind == SortKind::Bool
+                || sort_kind == SortKind::BV
+                || sort_kind == SortKind::Int
+                || sort_kind == SortKind::Real
+            {
                 // Model did not need to assign an interpretation to this variable
                 println!("Model did not need to assign an interpretation to this variable");
                 Ok(None)
@@ -1199,7 +2735,7 @@ impl<'ctx, B: BV> Model<'ctx, B> {
 
                 // Scan all enumerations to find the enum_id (which is
                 // the index in the enums vector) and member number.
-                'outer: for (enum_id, enumeration) in self.solver.enums.enums.iter().enumerate() {
+                'outer: for (enum_id, enumeration) in self.enums.enums.iter().enumerate() {
                     for (i, member) in enumeration.consts.iter().enumerate() {
                         if Z3_is_eq_func_decl(z3_ctx, func_decl, *member) {
                             result = Ok(Some(Exp::Enum(EnumMember { enum_id, member: i })));
@@ -1220,41 +2756,70 @@ impl<'ctx, B: BV> Model<'ctx, B> {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum SmtResult {
-    Sat,
-    Unsat,
-    Unknown,
-}
+impl<'ctx> SolverBackend for Z3Backend<'ctx> {
+    fn add_def(&mut self, def: &Def) -> Result<(), ExecError> {
+        match def {
+            Def::Assert(exp) => self.assert(exp),
+            _ => self.declare(def),
+        }
+        Ok(())
+    }
 
-use SmtResult::*;
+    fn push(&mut self) -> Result<(), ExecError> {
+        unsafe { Z3_solver_push(self.ctx.z3_ctx, self.z3_solver) }
+        Ok(())
+    }
 
-impl SmtResult {
-    pub fn is_sat(self) -> Result<bool, ExecError> {
-        match self {
-            Sat => Ok(true),
-            Unsat => Ok(false),
-            Unknown => Err(ExecError::Z3Unknown),
-        }
+    fn pop(&mut self, n: u32) -> Result<(), ExecError> {
+        unsafe { Z3_solver_pop(self.ctx.z3_ctx, self.z3_solver, n) }
+        Ok(())
     }
 
-    pub fn is_unsat(self) -> Result<bool, ExecError> {
-        match self {
-            Sat => Ok(false),
-            Unsat => Ok(true),
-            Unknown => Err(ExecError::Z3Unknown),
-        }
+    fn check_sat(&mut self) -> Result<SmtResult, ExecError> {
+        Ok(smt_result(unsafe { Z3_solver_check(self.ctx.z3_ctx, self.z3_solver) }))
     }
 
-    pub fn is_unknown(self) -> bool {
-        self == Unknown
+    fn check_sat_with(&mut self, exp: &Exp) -> Result<SmtResult, ExecError> {
+        let ast = self.translate_exp(exp);
+        Ok(self.check_assumption(&ast))
+    }
+
+    fn get_value(&mut self, exp: &Exp) -> Result<Option<Exp>, ExecError> {
+        let ast = self.translate_exp(exp);
+        unsafe {
+            let z3_ctx = self.ctx.z3_ctx;
+            let z3_model = Z3_solver_get_model(z3_ctx, self.z3_solver);
+            if z3_model.is_null() {
+                return Err(ExecError::NoModel);
+            }
+            Z3_model_inc_ref(z3_ctx, z3_model);
+            let result = self.model_value(z3_model, ast, false);
+            Z3_model_dec_ref(z3_ctx, z3_model);
+            result
+        }
     }
 }
 
-static QFAUFBV_STR: &[u8] = b"qfaufbv\0";
+fn smt_result(result: Z3_lbool) -> SmtResult {
+    if result == Z3_L_TRUE {
+        Sat
+    } else if result == Z3_L_FALSE {
+        Unsat
+    } else {
+        Unknown
+    }
+}
 
 impl<'ctx, B: BV> Solver<'ctx, B> {
     pub fn new(ctx: &'ctx Context) -> Self {
+        Self::with_options(ctx, SolverOptions::default())
+    }
+
+    pub fn with_options(ctx: &'ctx Context, options: SolverOptions) -> Self {
+        Self::with_config(ctx, options, None)
+    }
+
+    fn with_config(ctx: &'ctx Context, options: SolverOptions, config: Option<&SolverConfig>) -> Self {
         unsafe {
             let mut major: c_uint = 0;
             let mut minor: c_uint = 0;
@@ -1262,24 +2827,26 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
             let mut revision: c_uint = 0;
             Z3_get_version(&mut major, &mut minor, &mut build, &mut revision);
 
-            // The QF_AUFBV solver has good performance on our problems, but we need to initialise it
-            // using a tactic rather than the logic name to ensure that the enumerations are supported,
-            // otherwise Z3 may crash.
-            let qfaufbv_tactic = Z3_mk_tactic(ctx.z3_ctx, CStr::from_bytes_with_nul_unchecked(QFAUFBV_STR).as_ptr());
-            Z3_tactic_inc_ref(ctx.z3_ctx, qfaufbv_tactic);
-            let z3_solver = Z3_mk_solver_from_tactic(ctx.z3_ctx, qfaufbv_tactic);
-            Z3_solver_inc_ref(ctx.z3_ctx, z3_solver);
-
             Solver {
                 ctx,
-                z3_solver,
+                z3: Z3Backend::with_config(ctx, options, config),
                 next_var: 0,
                 cycles: 0,
                 trace: Trace::new(),
-                decls: HashMap::new(),
-                func_decls: HashMap::new(),
-                enums: Enums::new(ctx),
-                enum_map: HashMap::new(),
+                options,
+                scopes: Vec::new(),
+                untraced_scopes: 0,
+                pending_label: None,
+                last_unsat: false,
+                head_hash: 0,
+                cache: None,
+                fork_location: None,
+                recorder: None,
+                portfolio: None,
+                stats: SolverStats::default(),
+                profile: None,
+                backend: None,
+                backend_failed: false,
             }
         }
     }
@@ -1291,110 +2858,177 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
     }
 
     fn translate_exp(&self, exp: &Exp) -> Ast<'ctx> {
+        self.z3.translate_exp(exp)
+    }
+
+    /// Find up to `limit` distinct values that `var` can take under
+    /// the current assertions, by repeatedly asking for a model and
+    /// blocking the value it assigns. Fewer than `limit` values means
+    /// all the possible values were found, and no values means the
+    /// current assertions are unsatisfiable. The blocking clauses are
+    /// removed afterwards and do not appear in the trace.
+    pub fn enumerate(&mut self, var: Sym, limit: usize) -> Result<Vec<Exp>, ExecError> {
+        let mut values = self.enumerate_tuple(&[var], limit)?;
+        Ok(values.drain(..).map(|mut tuple| tuple.remove(0)).collect())
+    }
+
+    /// Like `enumerate`, but finds distinct combinations of values
+    /// for several variables.
+    pub fn enumerate_tuple(&mut self, vars: &[Sym], limit: usize) -> Result<Vec<Vec<Exp>>, ExecError> {
+        let mut values = Vec::new();
+        self.push_untraced();
+        let result = self.enumerate_in_scope(vars, limit, &mut values);
+        self.pop_untraced();
+        result.map(|()| values)
+    }
+
+    fn enumerate_in_scope(&mut self, vars: &[Sym], limit: usize, values: &mut Vec<Vec<Exp>>) -> Result<(), ExecError> {
         use Exp::*;
-        match exp {
-            Var(v) => match self.decls.get(v) {
-                None => panic!("Could not get Z3 func_decl {}", *v),
-                Some(ast) => ast.clone(),
-            },
-            Bits(bv) => Ast::mk_bv(self.ctx, bv.len().try_into().unwrap(), &bv),
-            Bits64(bv, len) => Ast::mk_bv_u64(self.ctx, *len, *bv),
-            Enum(e) => Ast::mk_enum_member(&self.enums, e.enum_id, e.member),
-            Bool(b) => Ast::mk_bool(self.ctx, *b),
-            Not(exp) => Ast::mk_not(&self.translate_exp(exp)),
-            Eq(lhs, rhs) => Ast::mk_eq(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Neq(lhs, rhs) => Ast::mk_not(&Ast::mk_eq(&self.translate_exp(lhs), &self.translate_exp(rhs))),
-            And(lhs, rhs) => Ast::mk_and(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Or(lhs, rhs) => Ast::mk_or(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvnot(exp) => Ast::mk_bvnot(&self.translate_exp(exp)),
-            Bvand(lhs, rhs) => Ast::mk_bvand(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvor(lhs, rhs) => Ast::mk_bvor(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvxor(lhs, rhs) => Ast::mk_bvxor(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvnand(lhs, rhs) => Ast::mk_bvnand(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvnor(lhs, rhs) => Ast::mk_bvnor(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvxnor(lhs, rhs) => Ast::mk_bvxnor(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvneg(exp) => Ast::mk_bvneg(&self.translate_exp(exp)),
-            Bvadd(lhs, rhs) => Ast::mk_bvadd(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvsub(lhs, rhs) => Ast::mk_bvsub(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvmul(lhs, rhs) => Ast::mk_bvmul(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvudiv(lhs, rhs) => Ast::mk_bvudiv(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvsdiv(lhs, rhs) => Ast::mk_bvsdiv(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvurem(lhs, rhs) => Ast::mk_bvurem(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvsrem(lhs, rhs) => Ast::mk_bvsrem(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvsmod(lhs, rhs) => Ast::mk_bvsmod(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvult(lhs, rhs) => Ast::mk_bvult(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvslt(lhs, rhs) => Ast::mk_bvslt(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvule(lhs, rhs) => Ast::mk_bvule(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvsle(lhs, rhs) => Ast::mk_bvsle(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvuge(lhs, rhs) => Ast::mk_bvuge(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvsge(lhs, rhs) => Ast::mk_bvsge(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvugt(lhs, rhs) => Ast::mk_bvugt(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvsgt(lhs, rhs) => Ast::mk_bvsgt(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Extract(hi, lo, bv) => self.translate_exp(bv).extract(*hi, *lo),
-            ZeroExtend(i, bv) => self.translate_exp(bv).zero_extend(*i),
-            SignExtend(i, bv) => self.translate_exp(bv).sign_extend(*i),
-            Bvshl(lhs, rhs) => Ast::mk_bvshl(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvlshr(lhs, rhs) => Ast::mk_bvlshr(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Bvashr(lhs, rhs) => Ast::mk_bvashr(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Concat(lhs, rhs) => Ast::mk_concat(&self.translate_exp(lhs), &self.translate_exp(rhs)),
-            Ite(cond, t, f) => self.translate_exp(cond).ite(&self.translate_exp(t), &self.translate_exp(f)),
-            App(f, args) => {
-                let args_ast: Vec<_> = args.iter().map(|arg| self.translate_exp(arg)).collect();
-                match self.func_decls.get(f) {
-                    None => panic!("Could not get Z3 func_decl {}", *f),
-                    Some(fd) => Ast::mk_app(&fd, &args_ast),
-                }
-            }
-            Select(array, index) => Ast::mk_select(&self.translate_exp(array), &self.translate_exp(index)),
-            Store(array, index, val) => {
-                Ast::mk_store(&self.translate_exp(array), &self.translate_exp(index), &self.translate_exp(val))
-            }
+        while values.len() < limit && self.check_sat().is_sat()? {
+            let tuple = {
+                let mut model = Model::new(self);
+                vars.iter()
+                    .map(|var| {
+                        model
+                            .get_var_with_completion(*var, true)?
+                            .ok_or_else(|| ExecError::Z3Error(format!("No value for variable v{}", var)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            let block = vars.iter().zip(tuple.iter()).fold(Bool(false), |block, (var, value)| {
+                Or(Box::new(block), Box::new(Neq(Box::new(Var(*var)), Box::new(value.clone()))))
+            });
+            self.assert_untraced(&block);
+            values.push(tuple)
         }
+        Ok(())
     }
 
-    fn assert(&mut self, exp: &Exp) {
+    /// Find the smallest unsigned value the bitvector expression
+    /// `exp` can take under the current assertions, or `None` if
+    /// they are unsatisfiable.
+    pub fn minimize(&mut self, exp: &Exp) -> Result<Option<Exp>, ExecError> {
+        self.optimize(exp, false, false)
+    }
+
+    /// Find the largest unsigned value `exp` can take, as for `minimize`.
+    pub fn maximize(&mut self, exp: &Exp) -> Result<Option<Exp>, ExecError> {
+        self.optimize(exp, true, false)
+    }
+
+    /// Find the smallest signed value `exp` can take, as for `minimize`.
+    pub fn minimize_signed(&mut self, exp: &Exp) -> Result<Option<Exp>, ExecError> {
+        self.optimize(exp, false, true)
+    }
+
+    /// Find the largest signed value `exp` can take, as for `minimize`.
+    pub fn maximize_signed(&mut self, exp: &Exp) -> Result<Option<Exp>, ExecError> {
+        self.optimize(exp, true, true)
+    }
+
+    fn optimize(&mut self, exp: &Exp, maximize: bool, signed: bool) -> Result<Option<Exp>, ExecError> {
         let ast = self.translate_exp(exp);
-        unsafe {
-            Z3_solver_assert(self.ctx.z3_ctx, self.z3_solver, ast.z3_ast);
+        let width = unsafe {
+            let z3_ctx = self.ctx.z3_ctx;
+            let z3_sort = Z3_get_sort(z3_ctx, ast.z3_ast);
+            if Z3_get_sort_kind(z3_ctx, z3_sort) != SortKind::BV {
+                return Err(ExecError::Type("Can only minimize or maximize bitvectors".to_string()));
+            }
+            Z3_get_bv_sort_size(z3_ctx, z3_sort)
+        };
+        if !self.check_sat().is_sat()? {
+            return Ok(None);
+        }
+        let mut bits = vec![false; width as usize];
+        self.push_untraced();
+        let result = self.optimize_in_scope(exp, maximize, signed, &mut bits);
+        self.pop_untraced();
+        result?;
+        if width > 64 {
+            Ok(Some(Exp::Bits(bits)))
+        } else {
+            Ok(Some(Exp::Bits64(bits.iter().rev().fold(0, |acc, bit| (acc << 1) | *bit as u64), width)))
+        }
+    }
+
+    // Fix each bit from the most significant downwards, preferring 1
+    // when maximizing and 0 when minimizing (the other way round for
+    // the sign bit of a signed value).
+    fn optimize_in_scope(
+        &mut self,
+        exp: &Exp,
+        maximize: bool,
+        signed: bool,
+        bits: &mut [bool],
+    ) -> Result<(), ExecError> {
+        let bit_is = |i: u32, bit: bool| {
+            Exp::Eq(Box::new(Exp::Extract(i, i, Box::new(exp.clone()))), Box::new(Exp::Bits64(bit as u64, 1)))
+        };
+        for i in (0..bits.len()).rev() {
+            let preferred = if signed && i == bits.len() - 1 { !maximize } else { maximize };
+            let test = bit_is(i as u32, preferred);
+            let bit = match self.check_sat_with_uncached(&test) {
+                Sat => preferred,
+                Unsat => !preferred,
+                Unknown => return Err(ExecError::Z3Unknown),
+            };
+            self.assert_untraced(&bit_is(i as u32, bit));
+            bits[i] = bit
         }
+        Ok(())
     }
 
     pub fn get_enum(&mut self, size: usize) -> usize {
-        match self.enum_map.get(&size) {
+        match self.z3.enum_map.get(&size) {
             Some(enum_id) => *enum_id,
             None => {
                 let name = self.fresh();
                 self.add(Def::DefineEnum(name, size));
-                self.enums.enums.len() - 1
+                self.z3.enums.enums.len() - 1
             }
         }
     }
 
+    /// Update the hash of the definitions in the head of the trace,
+    /// which together with the checkpoint it continues from
+    /// identifies the solver state for the `SmtCache`.
+    fn hash_head(&mut self, def: &Def) {
+        let mut hasher = DefaultHasher::new();
+        self.head_hash.hash(&mut hasher);
+        def.hash(&mut hasher);
+        self.head_hash = hasher.finish()
+    }
+
     fn add_internal(&mut self, def: &Def) {
-        match &def {
-            Def::Assert(exp) => self.assert(exp),
-            Def::DeclareConst(v, ty) => {
-                let fd = FuncDecl::new(&self.ctx, *v, &self.enums, &[], ty);
-                self.decls.insert(*v, Ast::mk_constant(&fd));
-            }
-            Def::DeclareFun(v, arg_tys, result_ty) => {
-                let fd = FuncDecl::new(&self.ctx, *v, &self.enums, arg_tys, result_ty);
-                self.func_decls.insert(*v, fd);
-            }
-            Def::DefineConst(v, exp) => {
-                let ast = self.translate_exp(exp);
-                self.decls.insert(*v, ast);
+        let result = match def {
+            Def::Assert(exp) => {
+                self.stats.assertions += 1;
+                match self.pending_label.take() {
+                    // Only Z3 can produce unsat cores, see `set_backend`
+                    Some(label) if self.options.unsat_cores => {
+                        self.z3.assert_labelled(exp, label);
+                        Ok(())
+                    }
+                    _ => self.with_backend(|backend| backend.add_def(def)),
+                }
             }
-            Def::DefineEnum(name, size) => {
-                let members: Vec<Sym> = (0..*size).map(|_| self.fresh()).collect();
-                self.enums.add_enum(*name, &members);
-                self.enum_map.insert(*size, self.enums.enums.len() - 1);
+            // Declarations are always made in Z3, so it can give us the
+            // sorts of variables and evaluate closed expressions even
+            // when another backend answers the queries
+            _ => {
+                self.stats.declarations += 1;
+                self.z3.declare(def);
+                match &self.backend {
+                    Some(backend) => backend.borrow_mut().add_def(def),
+                    None => Ok(()),
+                }
             }
-        }
+        };
+        self.backend_result(result)
     }
 
-    pub fn length(&mut self, v: Sym) -> Option<u32> {
-        match self.decls.get(&v) {
+    pub fn length(&self, v: Sym) -> Option<u32> {
+        match self.z3.decls.get(&v) {
             Some(ast) => unsafe {
                 let z3_ctx = self.ctx.z3_ctx;
                 let z3_sort = Z3_get_sort(z3_ctx, ast.z3_ast);
@@ -1413,7 +3047,7 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
     }
 
     pub fn is_bitvector(&mut self, v: Sym) -> bool {
-        match self.decls.get(&v) {
+        match self.z3.decls.get(&v) {
             Some(ast) => unsafe {
                 let z3_ctx = self.ctx.z3_ctx;
                 let z3_sort = Z3_get_sort(z3_ctx, ast.z3_ast);
@@ -1426,8 +3060,13 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         }
     }
 
+    /// Add a definition to the solver, simplifying it first (see
+    /// [rewrite]) if the solver was created with the `simplify`
+    /// option.
     pub fn add(&mut self, def: Def) {
+        let def = if self.options.simplify { rewrite::simplify_def(def, &|v| self.length(v)) } else { def };
         self.add_internal(&def);
+        self.hash_head(&def);
         self.trace.head.push(Event::Smt(def))
     }
 
@@ -1443,6 +3082,14 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         sym
     }
 
+    /// Assert an expression, labelling it for unsat cores. The label
+    /// is not part of the trace, so is lost if a checkpoint is
+    /// replayed into a new solver.
+    pub fn add_labelled(&mut self, exp: Exp, label: String) {
+        self.pending_label = Some(label);
+        self.add(Def::Assert(exp))
+    }
+
     pub fn assert_eq(&mut self, lhs: Exp, rhs: Exp) {
         self.add(Def::Assert(Exp::Eq(Box::new(lhs), Box::new(rhs))))
     }
@@ -1456,62 +3103,415 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
         self.cycles
     }
 
-    fn add_event_internal(&mut self, event: &Event<B>) {
-        if let Event::Smt(def) = event {
-            self.add_internal(def)
-        };
+    fn add_event_internal(&mut self, event: &Event<B>) {
+        match event {
+            Event::Smt(def) => self.add_internal(def),
+            Event::Fork(_, _, loc) => {
+                self.pending_label = Some(loc.clone());
+                self.fork_location = Some(loc.clone())
+            }
+            _ => (),
+        }
+    }
+
+    pub fn add_event(&mut self, event: Event<B>) {
+        self.add_event_internal(&event);
+        if let Event::Smt(def) = &event {
+            self.hash_head(def)
+        }
+        self.trace.head.push(event)
+    }
+
+    fn replay(&mut self, num: usize, trace: Arc<Option<Trace<B>>>) {
+        self.replay_until(num, trace, || false);
+    }
+
+    /// Replay `trace`, giving up if `stop` returns true before any
+    /// event. Returns false if the replay was abandoned.
+    fn replay_until<F: Fn() -> bool>(&mut self, num: usize, trace: Arc<Option<Trace<B>>>, stop: F) -> bool {
+        // Some extra work would be required to replay on top of
+        // another trace, so until we need to do that we'll check it's
+        // empty:
+        assert!(self.trace.checkpoints == 0 && self.trace.head.is_empty());
+        let mut checkpoints: Vec<&[Event<B>]> = Vec::with_capacity(num);
+        let mut next = &*trace;
+        loop {
+            match next {
+                None => break,
+                Some(tr) => {
+                    checkpoints.push(&tr.head);
+                    next = &*tr.tail
+                }
+            }
+        }
+        assert!(checkpoints.len() == num);
+        let (declarations, assertions) = (self.stats.declarations, self.stats.assertions);
+        for events in checkpoints.iter().rev() {
+            for event in *events {
+                if stop() {
+                    return false;
+                }
+                self.add_event_internal(&event)
+            }
+        }
+        self.stats.replayed += self.stats.declarations - declarations + self.stats.assertions - assertions;
+        self.stats.declarations = declarations;
+        self.stats.assertions = assertions;
+        self.trace.checkpoints = num;
+        self.trace.tail = trace;
+        true
+    }
+
+    pub fn from_checkpoint(ctx: &'ctx Context, point: Checkpoint<B>) -> Self {
+        Self::from_checkpoint_with_options(ctx, point, SolverOptions::default())
+    }
+
+    pub fn from_checkpoint_with_options(
+        ctx: &'ctx Context,
+        Checkpoint { num, next_var, trace }: Checkpoint<B>,
+        options: SolverOptions,
+    ) -> Self {
+        let mut solver = Solver::with_options(ctx, options);
+        solver.replay(num, trace);
+        solver.next_var = next_var;
+        solver
+    }
+
+    /// Create a solver in `ctx` with the same definitions and options
+    /// as this one, by replaying its trace. This allows further
+    /// queries to be made, for example by a collector, without
+    /// changing a solver that only has been borrowed. The new solver
+    /// is not incremental and always uses Z3 rather than any backend
+    /// set with `set_backend`.
+    pub fn duplicate<'ctx2>(&self, ctx: &'ctx2 Context) -> Solver<'ctx2, B> {
+        let options = SolverOptions { incremental: false, ..self.options };
+        Solver::from_checkpoint_with_options(ctx, self.trace.to_checkpoint(self.next_var), options)
+    }
+
+    /// Check that the solver can reason about the SMT real theory,
+    /// which is needed before declaring any reals. The QF_AUFBV tactic
+    /// used by default does not support reals, so they need Z3's
+    /// general purpose solver, which is used when unbounded integers
+    /// or unsat cores are enabled, or a backend set with `set_backend`.
+    pub fn check_reals(&self) -> Result<(), ExecError> {
+        if self.z3.reals || self.backend.is_some() {
+            Ok(())
+        } else {
+            Err(ExecError::UnsupportedReals)
+        }
+    }
+
+    pub fn options(&self) -> SolverOptions {
+        self.options
+    }
+
+    fn push_scope(&mut self, point: &Checkpoint<B>) {
+        self.push_solver();
+        self.scopes.push(Scope {
+            point: point.trace.clone(),
+            enums: self.z3.enums.enums.len(),
+            fork_location: self.fork_location.clone(),
+        })
+    }
+
+    /// Return an incremental solver to the state it was in when it
+    /// created `point`, discarding everything added since. Returns
+    /// false if this is not possible, either because the checkpoint
+    /// was not created by this solver, or because enumerations were
+    /// declared after it, in which case the caller should fall back
+    /// to `from_checkpoint`.
+    pub fn restore_checkpoint(&mut self, point: &Checkpoint<B>) -> bool {
+        let i = match self.scopes.iter().rposition(|scope| Arc::ptr_eq(&scope.point, &point.trace)) {
+            Some(i) => i,
+            None => return false,
+        };
+        if self.scopes[i].enums != self.z3.enums.enums.len() {
+            return false;
+        }
+        // Pop back to the state before the scope was opened, then
+        // re-open it so the checkpoint can be restored again.
+        self.pop_solver((self.scopes.len() - i) as u32);
+        self.push_solver();
+        self.scopes.truncate(i + 1);
+        self.trace =
+            Trace { checkpoints: point.num, id: fresh_trace_id(), head: Vec::new(), tail: point.trace.clone() };
+        self.next_var = point.next_var;
+        self.cycles = 0;
+        self.head_hash = 0;
+        self.fork_location = self.scopes[i].fork_location.clone();
+        self.pending_label = match &*point.trace {
+            Some(Trace { head, .. }) => match head.last() {
+                Some(Event::Fork(_, _, loc)) => Some(loc.clone()),
+                _ => None,
+            },
+            None => None,
+        };
+        true
+    }
+
+    /// Returns the labels of the assertions in an unsat core for the
+    /// most recent call to `check_sat` or `check_sat_with`, or an
+    /// empty vector if that call did not return `Unsat` or unsat cores
+    /// are not enabled. Unlabelled assertions, and the assumption
+    /// given to `check_sat_with`, can also be part of the conflict but
+    /// are not reported.
+    pub fn unsat_core(&self) -> Vec<String> {
+        let mut core = Vec::new();
+        if !self.options.unsat_cores || !self.last_unsat {
+            return core;
+        }
+        unsafe {
+            let z3_ctx = self.ctx.z3_ctx;
+            let z3_core = Z3_solver_get_unsat_core(z3_ctx, self.z3.z3_solver);
+            Z3_ast_vector_inc_ref(z3_ctx, z3_core);
+            for i in 0..Z3_ast_vector_size(z3_ctx, z3_core) {
+                let id = Z3_get_ast_id(z3_ctx, Z3_ast_vector_get(z3_ctx, z3_core, i));
+                if let Some((_, label)) = self.z3.labels.get(&id) {
+                    core.push(label.clone())
+                }
+            }
+            Z3_ast_vector_dec_ref(z3_ctx, z3_core)
+        }
+        core
+    }
+
+    /// Use a cache for the results of `check_sat_with`. The cache is
+    /// not used when unsat cores are enabled, as a cached result has
+    /// no core, or while `enumerate` or `minimize` and friends have
+    /// assertions that are not in the trace. A cached result also has
+    /// no model, so callers should use `check_sat` rather than
+    /// `check_sat_with` before building a `Model`.
+    pub fn set_cache(&mut self, cache: Arc<SmtCache>) {
+        self.cache = Some(cache)
+    }
+
+    /// The definitions added since the last checkpoint, oldest first.
+    fn head_defs(&self) -> Vec<&Def> {
+        self.trace
+            .head
+            .iter()
+            .filter_map(|event| match event {
+                Event::Smt(def) => Some(def),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The definitions added to the solver, newest first.
+    fn defs(&self) -> Vec<&Def> {
+        self.trace
+            .to_vec()
+            .drain(..)
+            .filter_map(|event| match event {
+                Event::Smt(def) => Some(def),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Race the configurations in `portfolio` against this solver
+    /// when answering `check_sat_with` queries that it cannot answer
+    /// within the portfolio's head start. This is not done when unsat
+    /// cores are enabled, as the other solvers cannot produce them, or
+    /// during `enumerate` and `minimize` and friends, whose assertions
+    /// are not in the trace the other solvers replay.
+    pub fn set_portfolio(&mut self, portfolio: Arc<Portfolio>) {
+        self.portfolio = Some(portfolio)
+    }
+
+    /// Record every query this solver sends to Z3 using `recorder`,
+    /// tagged with the given task identifier.
+    pub fn set_recorder(&mut self, recorder: Arc<QueryRecorder>, task_id: usize) {
+        self.recorder = Some((recorder, task_id))
+    }
+
+    /// Collect statistics for every solver using this profile. Each
+    /// solver's totals are added to the profile when it is dropped.
+    pub fn set_profile(&mut self, profile: Arc<SolverProfile>) {
+        self.profile = Some(profile)
+    }
+
+    pub fn statistics(&self) -> &SolverStats {
+        &self.stats
+    }
+
+    /// Z3's own statistics for this solver, such as the number of
+    /// conflicts and decisions, and memory usage.
+    pub fn z3_statistics(&self) -> HashMap<String, f64> {
+        let mut result = HashMap::new();
+        unsafe {
+            let z3_ctx = self.ctx.z3_ctx;
+            let z3_stats = Z3_solver_get_statistics(z3_ctx, self.z3.z3_solver);
+            Z3_stats_inc_ref(z3_ctx, z3_stats);
+            for i in 0..Z3_stats_size(z3_ctx, z3_stats) {
+                let key = CStr::from_ptr(Z3_stats_get_key(z3_ctx, z3_stats, i)).to_string_lossy().to_string();
+                let value = if Z3_stats_is_uint(z3_ctx, z3_stats, i) {
+                    Z3_stats_get_uint_value(z3_ctx, z3_stats, i) as f64
+                } else {
+                    Z3_stats_get_double_value(z3_ctx, z3_stats, i)
+                };
+                result.insert(key, value);
+            }
+            Z3_stats_dec_ref(z3_ctx, z3_stats)
+        }
+        result
+    }
+
+    fn record_query(&mut self, assumption: Option<&Exp>, result: SmtResult, time: Duration) {
+        self.stats.record(result, time);
+        if let Some(profile) = &self.profile {
+            profile.record(&self.fork_location, result, time)
+        }
+        // The assertions are in the backend rather than Z3, so we
+        // cannot print the benchmark
+        if self.backend.is_some() {
+            return;
+        }
+        if let Some((recorder, task_id)) = &self.recorder {
+            let assumption = assumption.map(|exp| self.translate_exp(exp));
+            let benchmark = self.benchmark(assumption.as_ref(), result);
+            recorder.record(*task_id, self.fork_location.as_deref(), result, time, &benchmark)
+        }
+    }
+
+    /// Z3 keeps an assertion tracked for unsat cores as an implication
+    /// from its label, so return the original assertion.
+    unsafe fn untracked(&self, ast: Z3_ast) -> Z3_ast {
+        let z3_ctx = self.ctx.z3_ctx;
+        if !Z3_is_app(z3_ctx, ast) {
+            return ast;
+        }
+        let app = Z3_to_app(z3_ctx, ast);
+        if Z3_get_decl_kind(z3_ctx, Z3_get_app_decl(z3_ctx, app)) == DeclKind::IMPLIES
+            && Z3_get_app_num_args(z3_ctx, app) == 2
+            && self.z3.labels.contains_key(&Z3_get_ast_id(z3_ctx, Z3_get_app_arg(z3_ctx, app, 0)))
+        {
+            Z3_get_app_arg(z3_ctx, app, 1)
+        } else {
+            ast
+        }
     }
 
-    pub fn add_event(&mut self, event: Event<B>) {
-        self.add_event_internal(&event);
-        self.trace.head.push(event)
+    /// Print the current assertions, and optionally an assumption, as
+    /// an SMT-LIB2 benchmark including all the declarations they need.
+    /// Assertions labelled for unsat cores are printed without their
+    /// labels.
+    fn benchmark(&self, assumption: Option<&Ast<'ctx>>, status: SmtResult) -> String {
+        unsafe {
+            let z3_ctx = self.ctx.z3_ctx;
+            let assertions = Z3_solver_get_assertions(z3_ctx, self.z3.z3_solver);
+            Z3_ast_vector_inc_ref(z3_ctx, assertions);
+            let mut asts: Vec<Z3_ast> = (0..Z3_ast_vector_size(z3_ctx, assertions))
+                .map(|i| self.untracked(Z3_ast_vector_get(z3_ctx, assertions, i)))
+                .collect();
+            if let Some(ast) = assumption {
+                asts.push(ast.z3_ast)
+            }
+            let formula = asts.pop().unwrap_or_else(|| Z3_mk_true(z3_ctx));
+            let empty = CString::new("").unwrap();
+            let status = CString::new(status.status()).unwrap();
+            let s = Z3_benchmark_to_smtlib_string(
+                z3_ctx,
+                empty.as_ptr(),
+                empty.as_ptr(),
+                status.as_ptr(),
+                empty.as_ptr(),
+                asts.len() as c_uint,
+                asts.as_ptr(),
+                formula,
+            );
+            let benchmark = CStr::from_ptr(s).to_string_lossy().to_string();
+            Z3_ast_vector_dec_ref(z3_ctx, assertions);
+            benchmark
+        }
     }
 
-    fn replay(&mut self, num: usize, trace: Arc<Option<Trace<B>>>) {
-        // Some extra work would be required to replay on top of
-        // another trace, so until we need to do that we'll check it's
-        // empty:
-        assert!(self.trace.checkpoints == 0 && self.trace.head.is_empty());
-        let mut checkpoints: Vec<&[Event<B>]> = Vec::with_capacity(num);
-        let mut next = &*trace;
-        loop {
-            match next {
-                None => break,
-                Some(tr) => {
-                    checkpoints.push(&tr.head);
-                    next = &*tr.tail
-                }
+    /// Check satisfiability assuming `exp`, racing the solver's
+    /// `Portfolio` against it if the query is hard, see `set_portfolio`.
+    /// A result answered from the solver's cache (see `set_cache`)
+    /// does not query the solver, so it leaves no model, not even
+    /// when the result is `Sat`.
+    pub fn check_sat_with(&mut self, exp: &Exp) -> SmtResult {
+        // The cache key only covers the trace, not untraced assertions
+        let cache = if self.options.unsat_cores || self.untraced_scopes > 0 { None } else { self.cache.clone() };
+        let key = (self.trace.checkpoint_id(), self.head_hash);
+        if let Some(result) = cache.as_ref().and_then(|cache| cache.get(key, &self.head_defs(), exp)) {
+            self.stats.cached += 1;
+            if let Some(profile) = &self.profile {
+                profile.record_cached(&self.fork_location)
             }
+            self.last_unsat = false;
+            return result;
         }
-        assert!(checkpoints.len() == num);
-        for events in checkpoints.iter().rev() {
-            for event in *events {
-                self.add_event_internal(&event)
+        let result = self.check_sat_with_uncached(exp);
+        if let Some(cache) = cache {
+            if result != Unknown {
+                cache.insert(key, &self.head_defs(), exp, result)
             }
         }
-        self.trace.checkpoints = num;
-        self.trace.tail = trace
-    }
-
-    pub fn from_checkpoint(ctx: &'ctx Context, Checkpoint { num, next_var, trace }: Checkpoint<B>) -> Self {
-        let mut solver = Solver::new(ctx);
-        solver.replay(num, trace);
-        solver.next_var = next_var;
-        solver
+        result
+    }
+
+    /// Like `check_sat_with`, but never uses the cache. The query is
+    /// still recorded in the solver's statistics, profile, and query
+    /// recorder.
+    fn check_sat_with_uncached(&mut self, exp: &Exp) -> SmtResult {
+        // The other solvers in the portfolio replay the trace, so they
+        // would not have any untraced assertions
+        let portfolio = if self.options.unsat_cores || self.backend.is_some() || self.untraced_scopes > 0 {
+            None
+        } else {
+            self.portfolio.clone()
+        };
+        let start = Instant::now();
+        let result = match portfolio {
+            Some(portfolio) => self.check_portfolio(&portfolio, exp),
+            None => self.backend_check(Some(exp)),
+        };
+        self.last_unsat = result == Unsat;
+        self.record_query(Some(exp), result, start.elapsed());
+        result
     }
 
-    pub fn check_sat_with(&mut self, exp: &Exp) -> SmtResult {
+    fn check_portfolio(&mut self, portfolio: &Portfolio, exp: &Exp) -> SmtResult {
         let ast = self.translate_exp(exp);
-        unsafe {
-            let result = Z3_solver_check_assumptions(self.ctx.z3_ctx, self.z3_solver, 1, &ast.z3_ast);
-            if result == Z3_L_TRUE {
-                Sat
-            } else if result == Z3_L_FALSE {
-                Unsat
-            } else {
-                Unknown
+        // Only start the other solvers if this one does not answer
+        // within its head start
+        if portfolio.head_start.as_millis() > 0 {
+            let result = self.z3.check_assumption_timeout(&ast, portfolio.head_start);
+            if result != Unknown {
+                return result;
             }
         }
+        let point = self.trace.to_checkpoint(self.next_var);
+        let race = Race::new(portfolio.configs.len() + 1);
+        let (tx, rx) = mpsc::channel();
+        let options = self.options;
+        let race_ref = &race;
+        thread::scope(|scope| {
+            for (i, config) in portfolio.configs.iter().enumerate() {
+                let (point, tx) = (point.clone(), tx.clone());
+                scope.spawn(move |_| {
+                    let ctx = Context::new(Config::new());
+                    let mut solver = Solver::with_config(&ctx, options, Some(config));
+                    let Checkpoint { num, next_var, trace } = point;
+                    // Replaying can take longer than the query, so stop
+                    // if another solver has already answered it
+                    if !solver.replay_until(num, trace, || race_ref.finished()) {
+                        return;
+                    }
+                    solver.next_var = next_var;
+                    let ast = solver.translate_exp(exp);
+                    let _ = tx.send(race_ref.run(i + 1, &mut solver.z3, &ast));
+                });
+            }
+            drop(tx);
+            match race_ref.run(0, &mut self.z3, &ast) {
+                Unknown => rx.iter().find(|result| *result != Unknown).unwrap_or(Unknown),
+                result => result,
+            }
+        })
+        .unwrap()
     }
 
     pub fn trace(&self) -> &Trace<B> {
@@ -1519,22 +3519,17 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
     }
 
     pub fn check_sat(&mut self) -> SmtResult {
-        unsafe {
-            let result = Z3_solver_check(self.ctx.z3_ctx, self.z3_solver);
-            if result == Z3_L_TRUE {
-                Sat
-            } else if result == Z3_L_FALSE {
-                Unsat
-            } else {
-                Unknown
-            }
-        }
+        let start = Instant::now();
+        let result = self.backend_check(None);
+        self.last_unsat = result == Unsat;
+        self.record_query(None, result, start.elapsed());
+        result
     }
 
     pub fn dump_solver(&mut self, filename: &str) {
         let mut file = std::fs::File::create(filename).expect("Failed to open solver dump file");
         unsafe {
-            let s = Z3_solver_to_string(self.ctx.z3_ctx, self.z3_solver);
+            let s = Z3_solver_to_string(self.ctx.z3_ctx, self.z3.z3_solver);
             let cs = CStr::from_ptr(s);
             file.write_all(cs.to_bytes()).expect("Failed to write solver dump");
         }
@@ -1543,13 +3538,157 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
     pub fn dump_solver_with(&mut self, filename: &str, exp: &Exp) {
         let mut file = std::fs::File::create(filename).expect("Failed to open solver dump file");
         unsafe {
-            let s = Z3_solver_to_string(self.ctx.z3_ctx, self.z3_solver);
+            let s = Z3_solver_to_string(self.ctx.z3_ctx, self.z3.z3_solver);
             let cs = CStr::from_ptr(s);
             file.write_all(cs.to_bytes()).expect("Failed to write solver dump");
             writeln!(file, "{}", self.exp_to_str(exp)).expect("Failed to write exp");
         }
     }
 
+    /// Evaluate an expression containing no variables to a literal
+    /// using Z3's simplifier, without adding anything to the solver.
+    /// This is used to compute concrete results for operations, such
+    /// as floating point arithmetic, that are only implemented in SMT.
+    /// Real numerals are returned as an integer or a quotient of
+    /// integers converted with `IntToReal`.
+    pub fn eval_closed(&self, exp: &Exp) -> Result<Exp, ExecError> {
+        let ast = self.translate_exp(exp);
+        unsafe {
+            let z3_ctx = self.ctx.z3_ctx;
+            let simplify = |ast: &Ast<'ctx>| {
+                let z3_ast = Z3_simplify(z3_ctx, ast.z3_ast);
+                Z3_inc_ref(z3_ctx, z3_ast);
+                Ast { z3_ast, ctx: self.ctx }
+            };
+            let value = simplify(&ast);
+            let sort = Z3_get_sort(z3_ctx, value.z3_ast);
+            let not_closed = || ExecError::Type(format!("Expression {:?} is not closed", exp));
+            match Z3_get_sort_kind(z3_ctx, sort) {
+                SortKind::Bool => value.get_bool_value().map(Exp::Bool).ok_or_else(not_closed),
+                SortKind::BV if Z3_is_numeral_ast(z3_ctx, value.z3_ast) => {
+                    let sz = Z3_get_bv_sort_size(z3_ctx, sort);
+                    if sz <= 64 {
+                        Ok(Exp::Bits64(value.get_numeral_u64()?, sz))
+                    } else {
+                        let mut bits = Vec::with_capacity(sz as usize);
+                        for lo in (0..sz).step_by(64) {
+                            let hi = std::cmp::min(sz, lo + 64) - 1;
+                            let chunk = simplify(&value.extract(hi, lo)).get_numeral_u64()?;
+                            bits.extend((0..=(hi - lo)).map(|i| (chunk >> i) & 1 == 1))
+                        }
+                        Ok(Exp::Bits(bits))
+                    }
+                }
+                SortKind::Int if Z3_is_numeral_ast(z3_ctx, value.z3_ast) => Ok(Exp::Int(value.get_numeral_i128()?)),
+                SortKind::Real if Z3_is_numeral_ast(z3_ctx, value.z3_ast) => value.get_numeral_real(),
+                _ => Err(not_closed()),
+            }
+        }
+    }
+
+    /// Answer queries using `backend` rather than Z3. Every
+    /// definition already in the solver is sent to the backend, and
+    /// every later definition, query, and model is sent to it. Z3 is
+    /// still used to find the sorts of variables and to evaluate
+    /// closed expressions (see `eval_closed`). The backend cannot
+    /// produce unsat cores, is not raced against a portfolio, and
+    /// its queries are not recorded. Fails if the solver has
+    /// incremental scopes, as the backend would not have them.
+    pub fn set_backend(&mut self, mut backend: Box<dyn SolverBackend>) -> Result<(), ExecError> {
+        if self.options.unsat_cores {
+            return Err(ExecError::SolverProcess("Solver backends cannot produce unsat cores".to_string()));
+        }
+        if !self.scopes.is_empty() {
+            return Err(ExecError::SolverProcess("Cannot change the backend of a solver with scopes".to_string()));
+        }
+        for def in self.defs().iter().rev() {
+            backend.add_def(def)?
+        }
+        self.backend = Some(RefCell::new(backend));
+        self.backend_failed = false;
+        Ok(())
+    }
+
+    pub fn has_backend(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    /// A backend that fails to add a definition or open a scope no
+    /// longer agrees with this solver, so all its later queries
+    /// return `Unknown`.
+    fn backend_result(&mut self, result: Result<(), ExecError>) {
+        if let Err(err) = result {
+            log!(log::VERBOSE, &format!("Solver backend error: {}", err));
+            self.backend_failed = true
+        }
+    }
+
+    /// Run `f` with the backend answering queries, which is Z3
+    /// unless another backend was given to `set_backend`.
+    fn with_backend<T, F>(&mut self, f: F) -> Result<T, ExecError>
+    where
+        F: FnOnce(&mut dyn SolverBackend) -> Result<T, ExecError>,
+    {
+        match &self.backend {
+            Some(backend) => f(backend.borrow_mut().as_mut()),
+            None => f(&mut self.z3),
+        }
+    }
+
+    /// Check satisfiability, under an assumption if one is given.
+    fn backend_check(&mut self, assumption: Option<&Exp>) -> SmtResult {
+        if self.backend_failed {
+            return Unknown;
+        }
+        let result = self.with_backend(|backend| match assumption {
+            Some(exp) => backend.check_sat_with(exp),
+            None => backend.check_sat(),
+        });
+        result.unwrap_or_else(|err| {
+            log!(log::VERBOSE, &format!("Solver backend error: {}", err));
+            Unknown
+        })
+    }
+
+    fn backend_value(&self, exp: &Exp) -> Result<Option<Exp>, ExecError> {
+        match &self.backend {
+            Some(_) if self.backend_failed => Err(ExecError::NoModel),
+            Some(backend) => backend.borrow_mut().get_value(exp),
+            None => Err(ExecError::NoModel),
+        }
+    }
+
+    fn push_solver(&mut self) {
+        let result = self.with_backend(|backend| backend.push());
+        self.backend_result(result)
+    }
+
+    fn pop_solver(&mut self, n: u32) {
+        let result = self.with_backend(|backend| backend.pop(n));
+        self.backend_result(result)
+    }
+
+    /// Open a scope for assertions made with `assert_untraced`. While
+    /// it is open the trace no longer describes the solver's state, so
+    /// queries do not use the cache.
+    fn push_untraced(&mut self) {
+        self.push_solver();
+        self.untraced_scopes += 1
+    }
+
+    fn pop_untraced(&mut self) {
+        self.pop_solver(1);
+        self.untraced_scopes -= 1
+    }
+
+    /// Assert `exp` without adding it to the trace, for assertions
+    /// that will be removed by `pop_untraced`.
+    fn assert_untraced(&mut self, exp: &Exp) {
+        let def = Def::Assert(exp.clone());
+        let result = self.with_backend(|backend| backend.add_def(&def));
+        self.backend_result(result)
+    }
+
     pub fn exp_to_str(&mut self, exp: &Exp) -> String {
         let ast = self.translate_exp(exp);
         let cs;
@@ -1562,7 +3701,12 @@ impl<'ctx, B: BV> Solver<'ctx, B> {
 }
 
 pub fn checkpoint<B: BV>(solver: &mut Solver<B>) -> Checkpoint<B> {
-    solver.trace.checkpoint(solver.next_var)
+    let point = solver.trace.checkpoint(solver.next_var);
+    solver.head_hash = 0;
+    if solver.options.incremental {
+        solver.push_scope(&point)
+    }
+    point
 }
 
 /// This function just calls Z3_finalize_memory(). It's useful because
@@ -1592,8 +3736,6 @@ mod tests {
                     vec.push(true)
                 } else if c == '0' {
                     vec.push(false)
-                } else {
-                    ()
                 }
             }
             Bits(vec)
@@ -1711,4 +3853,719 @@ mod tests {
         )));
         assert!(solver.check_sat() == Unsat);
     }
+
+    #[test]
+    fn restore_checkpoint() {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let options = SolverOptions { incremental: true, ..SolverOptions::default() };
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        let x = solver.declare_const(Ty::Bool);
+        let point = checkpoint(&mut solver);
+        solver.add(Assert(Var(x)));
+        let y = solver.declare_const(Ty::BitVec(4));
+        let _ = checkpoint(&mut solver);
+        solver.add(Assert(Eq(Box::new(Var(y)), Box::new(bv!("0000")))));
+        assert!(solver.restore_checkpoint(&point));
+        assert!(solver.trace().to_vec().len() == 1);
+        assert!(solver.fresh() == y);
+        solver.add(Assert(Not(Box::new(Var(x)))));
+        assert!(solver.check_sat() == Sat);
+        assert!(solver.restore_checkpoint(&point));
+        assert!(solver.check_sat() == Sat);
+
+        let mut other = Solver::<B64>::with_options(&ctx, options);
+        let foreign = checkpoint(&mut other);
+        assert!(!solver.restore_checkpoint(&foreign));
+    }
+
+    #[test]
+    fn solver_backend() {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let backend_ctx: &'static Context = Box::leak(Box::new(Context::new(Config::new())));
+        let options = SolverOptions { incremental: true, ..SolverOptions::default() };
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        let x = solver.declare_const(Ty::BitVec(4));
+        solver.set_backend(Box::new(Z3Backend::new(backend_ctx))).unwrap();
+        assert!(solver.has_backend());
+
+        // Z3 does not see this assertion, so the answers must come
+        // from the backend
+        solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("0100")))));
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("1001")))) == Unsat);
+        assert!(solver.check_sat() == Sat);
+        match Model::new(&solver).get_var(x) {
+            Ok(Some(Bits64(value, 4))) => assert!(value < 4),
+            result => panic!("Unexpected model value {:?}", result),
+        }
+        assert!(solver.enumerate(x, 10).unwrap().len() == 4);
+        assert!(matches!(solver.maximize(&Var(x)), Ok(Some(Bits64(3, 4)))));
+
+        let point = checkpoint(&mut solver);
+        solver.add(Assert(Eq(Box::new(Var(x)), Box::new(bv!("0010")))));
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0001")))) == Unsat);
+        assert!(solver.restore_checkpoint(&point));
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0001")))) == Sat);
+        assert!(solver.set_backend(Box::new(Z3Backend::new(backend_ctx))).is_err());
+
+        let options = SolverOptions { unsat_cores: true, ..SolverOptions::default() };
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        assert!(solver.set_backend(Box::new(Z3Backend::new(backend_ctx))).is_err())
+    }
+
+    #[test]
+    fn unsat_core() {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let options = SolverOptions { unsat_cores: true, ..SolverOptions::default() };
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        let x = solver.declare_const(Ty::BitVec(4));
+        let y = solver.declare_const(Ty::Bool);
+        solver.add_event(Event::Fork(0, x, "first".to_string()));
+        solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("0100")))));
+        solver.add_event(Event::Fork(1, y, "second".to_string()));
+        solver.add(Assert(Var(y)));
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("1001")))) == Unsat);
+        assert!(solver.unsat_core() == vec!["first".to_string()]);
+        solver.add(Assert(Bvugt(Box::new(Var(x)), Box::new(bv!("1000")))));
+        assert!(solver.check_sat() == Unsat);
+        assert!(solver.unsat_core() == vec!["first".to_string()]);
+
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        let x = solver.declare_const(Ty::BitVec(4));
+        solver.add_labelled(Bvult(Box::new(Var(x)), Box::new(bv!("0100"))), "assertion".to_string());
+        solver.add(Assert(Bvugt(Box::new(Var(x)), Box::new(bv!("1000")))));
+        assert!(solver.check_sat() == Unsat);
+        assert!(solver.unsat_core() == vec!["assertion".to_string()]);
+
+        let mut solver = Solver::<B64>::new(&ctx);
+        solver.add_event(Event::Fork(0, x, "first".to_string()));
+        solver.add(Assert(Bool(false)));
+        assert!(solver.check_sat() == Unsat);
+        assert!(solver.unsat_core().is_empty())
+    }
+
+    #[test]
+    fn enumerate() {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(4));
+        let y = solver.declare_const(Ty::Bool);
+        solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("0011")))));
+
+        let mut values: Vec<u64> = solver
+            .enumerate(x, 10)
+            .unwrap()
+            .iter()
+            .map(|value| match value {
+                Bits64(bits, 4) => *bits,
+                _ => panic!("Unexpected value {:?}", value),
+            })
+            .collect();
+        values.sort_unstable();
+        assert!(values == vec![0, 1, 2]);
+        assert!(solver.enumerate(x, 2).unwrap().len() == 2);
+        assert!(solver.enumerate_tuple(&[x, y], 10).unwrap().len() == 6);
+
+        // The blocking clauses should have been removed
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0001")))) == Sat);
+        assert!(solver.trace().to_vec().len() == 3);
+
+        solver.add(Assert(Bool(false)));
+        assert!(solver.enumerate(x, 10).unwrap().is_empty())
+    }
+
+    #[test]
+    fn query_recorder_unsat_cores() {
+        let dir = std::env::temp_dir().join(format!("isla-query-recorder-cores-{}", std::process::id()));
+        let recorder = Arc::new(QueryRecorder::new(&dir).unwrap());
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let options = SolverOptions { unsat_cores: true, ..SolverOptions::default() };
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        solver.set_recorder(recorder.clone(), 0);
+        let x = solver.declare_const(Ty::BitVec(4));
+        solver.add_event(Event::Fork(0, x, "location".to_string()));
+        solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("0100")))));
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0101")))) == Unsat);
+
+        // The benchmark contains the assertion rather than the
+        // implication from its label
+        let benchmark = std::fs::read_to_string(dir.join("0_0.smt2")).unwrap();
+        assert!(!benchmark.contains("label"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Queries which cannot be written are counted
+        assert!(solver.check_sat() == Sat);
+        assert_eq!(recorder.queries(), 2);
+        assert_eq!(recorder.failures(), 1)
+    }
+
+    #[test]
+    fn optimize() {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(4));
+        solver.add(Assert(Bvugt(Box::new(Var(x)), Box::new(bv!("0010")))));
+        solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("1100")))));
+        assert!(matches!(solver.minimize(&Var(x)), Ok(Some(Bits64(3, 4)))));
+        // One query to check satisfiability, then one for each bit
+        assert!(solver.statistics().queries == 5);
+        assert!(matches!(solver.maximize(&Var(x)), Ok(Some(Bits64(11, 4)))));
+        assert!(matches!(solver.minimize(&Bvadd(Box::new(Var(x)), Box::new(bv!("1000")))), Ok(Some(Bits64(0, 4)))));
+
+        let y = solver.declare_const(Ty::BitVec(4));
+        solver.add(Assert(Bvsgt(Box::new(Var(y)), Box::new(bv!("1101")))));
+        solver.add(Assert(Bvslt(Box::new(Var(y)), Box::new(bv!("0010")))));
+        assert!(matches!(solver.minimize_signed(&Var(y)), Ok(Some(Bits64(0b1110, 4)))));
+        assert!(matches!(solver.maximize_signed(&Var(y)), Ok(Some(Bits64(1, 4)))));
+        assert!(matches!(solver.minimize(&Var(y)), Ok(Some(Bits64(0, 4)))));
+        assert!(matches!(solver.maximize(&Var(y)), Ok(Some(Bits64(15, 4)))));
+        let z = solver.declare_const(Ty::Bool);
+        assert!(solver.minimize(&Var(z)).is_err());
+
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0100")))) == Sat);
+        solver.add(Assert(Bool(false)));
+        assert!(matches!(solver.maximize(&Var(x)), Ok(None)))
+    }
+
+    #[test]
+    fn smt_cache() {
+        let cache = Arc::new(SmtCache::new());
+        let point = {
+            let cfg = Config::new();
+            let ctx = Context::new(cfg);
+            let mut solver = Solver::<B64>::new(&ctx);
+            solver.set_cache(cache.clone());
+            let x = solver.declare_const(Ty::BitVec(4));
+            solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("0100")))));
+            assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0101")))) == Unsat);
+            checkpoint(&mut solver)
+        };
+        assert!(cache.hits() == 0 && cache.misses() == 1);
+
+        // Solvers replaying the same checkpoint share results
+        let query = |solver: &mut Solver<B64>| {
+            solver.add(Assert(Bvugt(Box::new(var(0)), Box::new(bv!("0001")))));
+            solver.check_sat_with(&Eq(Box::new(var(0)), Box::new(bv!("0001"))))
+        };
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut first = Solver::from_checkpoint(&ctx, point.clone());
+        first.set_cache(cache.clone());
+        assert!(query(&mut first) == Unsat);
+        assert!(cache.hits() == 0 && cache.misses() == 2);
+        let mut second = Solver::from_checkpoint(&ctx, point);
+        second.set_cache(cache.clone());
+        assert!(query(&mut second) == Unsat);
+        assert!(cache.hits() == 1);
+        // Structurally different expressions are different queries
+        assert!(second.check_sat_with(&Eq(Box::new(var(0)), Box::new(Bits64(1, 4)))) == Unsat);
+        assert!(cache.misses() == 3);
+
+        // A different solver state does not reuse the result
+        second.add(Assert(Bool(true)));
+        assert!(second.check_sat_with(&Eq(Box::new(var(0)), Box::new(bv!("0001")))) == Unsat);
+        assert!(cache.hits() == 1 && cache.misses() == 4);
+
+        // Nor does a new checkpoint, even with the same definitions
+        let point = checkpoint(&mut first);
+        let mut third = Solver::from_checkpoint(&ctx, point);
+        third.set_cache(cache.clone());
+        assert!(third.check_sat_with(&Eq(Box::new(var(0)), Box::new(bv!("0001")))) == Unsat);
+        assert!(cache.hits() == 1 && cache.misses() == 5);
+
+        // Assertions which are not in the trace bypass the cache
+        let query = Eq(Box::new(var(0)), Box::new(bv!("0011")));
+        assert!(third.check_sat_with(&query) == Sat);
+        third.push_untraced();
+        third.assert_untraced(&Neq(Box::new(var(0)), Box::new(bv!("0011"))));
+        assert!(third.check_sat_with(&query) == Unsat);
+        third.pop_untraced();
+        assert!(third.check_sat_with(&query) == Sat);
+        assert!(cache.hits() == 2 && cache.misses() == 6)
+    }
+
+    #[test]
+    fn smt_cache_keys() {
+        let cache = SmtCache::with_capacity(2);
+        let x = Sym::from_u32(0);
+        let first = DeclareConst(x, Ty::BitVec(4));
+        let second = DeclareConst(x, Ty::Bool);
+        let exp = Var(x);
+
+        // States with the same hash are still compared in full
+        cache.insert((0, 0), &[&first], &exp, Sat);
+        assert!(cache.get((0, 0), &[&first], &exp) == Some(Sat));
+        assert!(cache.get((0, 0), &[&second], &exp).is_none());
+        assert!(cache.get((0, 0), &[&first, &first], &exp).is_none());
+        // States from different checkpoints are different
+        assert!(cache.get((1, 0), &[&first], &exp).is_none());
+
+        // The oldest state is evicted once the cache is full
+        cache.insert((0, 0), &[&second], &exp, Unsat);
+        cache.insert((0, 1), &[&first, &second], &exp, Sat);
+        assert!(cache.len() == 2);
+        assert!(cache.get((0, 0), &[&first], &exp).is_none());
+        assert!(cache.get((0, 0), &[&second], &exp) == Some(Unsat))
+    }
+
+    #[test]
+    fn query_recorder() {
+        let dir = std::env::temp_dir().join(format!("isla-query-recorder-{}", std::process::id()));
+        let recorder = Arc::new(QueryRecorder::new(&dir).unwrap());
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        solver.set_recorder(recorder.clone(), 7);
+        let x = solver.declare_const(Ty::BitVec(4));
+        let y = solver.declare_const(Ty::BitVec(4));
+        solver.add_event(Event::Fork(0, x, "location".to_string()));
+        solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("0100")))));
+        assert!(solver.check_sat() == Sat);
+        assert!(solver.check_sat_with(&Bvult(Box::new(Var(y)), Box::new(Var(x)))) == Sat);
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0101")))) == Unsat);
+        assert_eq!(recorder.queries(), 3);
+
+        // Each benchmark should be self-contained, and give the same
+        // result when replayed in a fresh context
+        for (n, expected) in ["sat", "sat", "unsat"].iter().enumerate() {
+            let benchmark = std::fs::read_to_string(dir.join(format!("7_{}.smt2", n))).unwrap();
+            assert!(benchmark.starts_with("; task: 7\n; location: location\n"));
+            assert!(benchmark.contains(&format!("; result: {}\n", expected)));
+            let fresh = Context::new(Config::new());
+            let output = unsafe {
+                let benchmark = CString::new(benchmark).unwrap();
+                CStr::from_ptr(Z3_eval_smtlib2_string(fresh.z3_ctx, benchmark.as_ptr())).to_string_lossy().to_string()
+            };
+            assert_eq!(output.trim(), *expected)
+        }
+        std::fs::remove_dir_all(&dir).unwrap()
+    }
+
+    #[test]
+    fn portfolio() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut portfolio = Portfolio::default();
+        portfolio.set_head_start(Duration::from_millis(0));
+        solver.set_portfolio(Arc::new(portfolio));
+        let x = solver.declare_const(Ty::BitVec(8));
+        let y = solver.declare_const(Ty::BitVec(8));
+        let e = solver.get_enum(3);
+        let z = solver.declare_const(Ty::Enum(e));
+        solver.add(Assert(Bvugt(Box::new(Var(x)), Box::new(Bits64(1, 8)))));
+        solver.add(Assert(Bvugt(Box::new(Var(y)), Box::new(Bits64(1, 8)))));
+        let product = Bvmul(Box::new(ZeroExtend(8, Box::new(Var(x)))), Box::new(ZeroExtend(8, Box::new(Var(y)))));
+        // 251 is prime
+        assert!(solver.check_sat_with(&Eq(Box::new(product.clone()), Box::new(Bits64(251, 16)))) == Unsat);
+        assert!(solver.check_sat_with(&Eq(Box::new(product), Box::new(Bits64(241 * 251, 16)))) == Sat);
+        assert!(solver.check_sat_with(&Neq(Box::new(Var(z)), Box::new(Var(z)))) == Unsat);
+        assert!(solver.statistics().queries == 3);
+        // Racing the portfolio does not checkpoint the solver's trace
+        assert!(solver.trace.checkpoints == 0 && solver.trace.head.len() == 6);
+        assert!(solver.check_sat() == Sat)
+    }
+
+    #[test]
+    fn portfolio_interrupts_solver() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(64));
+        let y = solver.declare_const(Ty::BitVec(64));
+        let z = solver.declare_const(Ty::BitVec(8));
+        // Factoring a 64-bit semiprime is hard
+        let wide = |v| ZeroExtend(64, Box::new(Var(v)));
+        let semiprime = ZeroExtend(64, Box::new(Bits64(4294967291 * 4294967279, 64)));
+        let factors = And(
+            Box::new(Eq(Box::new(Bvmul(Box::new(wide(x)), Box::new(wide(y)))), Box::new(semiprime))),
+            Box::new(And(
+                Box::new(Bvugt(Box::new(Var(x)), Box::new(Bits64(1, 64)))),
+                Box::new(Bvugt(Box::new(Var(y)), Box::new(Bits64(1, 64)))),
+            )),
+        );
+        let factors = solver.translate_exp(&factors);
+        // so the solver does not answer within its head start
+        assert!(solver.z3.check_assumption_timeout(&factors, Duration::from_millis(10)) == Unknown);
+        // and loses the race against a solver with an easy query
+        let race = Race::new(2);
+        let race_ref = &race;
+        thread::scope(|scope| {
+            scope.spawn(move |_| {
+                while race_ref.state.lock().unwrap().running[0].is_none() {
+                    std::thread::sleep(Duration::from_millis(1))
+                }
+                let ctx = Context::new(Config::new());
+                let mut other = Solver::<B64>::new(&ctx);
+                let ast = other.translate_exp(&Bool(true));
+                assert!(race_ref.run(1, &mut other.z3, &ast) == Sat)
+            });
+            assert!(race_ref.run(0, &mut solver.z3, &factors) == Unknown)
+        })
+        .unwrap();
+        // The interrupted solver can answer later queries itself
+        solver.add(Assert(Eq(Box::new(Var(z)), Box::new(Bits64(2, 8)))));
+        assert!(solver.check_sat() == Sat);
+        let mut model = Model::new(&solver);
+        assert!(matches!(model.get_var(z), Ok(Some(Bits64(2, 8)))))
+    }
+
+    #[test]
+    fn serialize_trace() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(4));
+        solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("0100")))));
+        solver.add_event(Event::ReadMem {
+            value: Val::Symbolic(x),
+            read_kind: Val::Enum(EnumMember { enum_id: 0, member: 1 }),
+            address: Val::Bits(B64::new(0x1000, 64)),
+            bytes: 1,
+            tag_value: None,
+            kind: "device".into(),
+        });
+        let point = checkpoint(&mut solver);
+        solver.add(Assert(Neq(Box::new(Var(x)), Box::new(bv!("0000")))));
+        solver.add_event(Event::Cycle);
+
+        let trace = bincode::serialize(solver.trace()).unwrap();
+        let trace: Trace<B64> = bincode::deserialize_from(&trace[..]).unwrap();
+        assert!(format!("{:?}", trace.to_vec()) == format!("{:?}", solver.trace().to_vec()));
+        assert!(matches!(trace.to_vec()[2], Event::ReadMem { kind, .. } if kind == "device"));
+
+        // Restoring a deserialized checkpoint drops the events after it
+        let point = bincode::serialize(&point).unwrap();
+        let mut bad_point = point.clone();
+        bad_point[0] += 1;
+        assert!(bincode::deserialize_from::<_, Checkpoint<B64>>(&bad_point[..]).is_err());
+        let point: Checkpoint<B64> = bincode::deserialize_from(&point[..]).unwrap();
+        let mut solver = Solver::from_checkpoint(&ctx, point);
+        assert!(solver.trace().to_vec().len() == 3);
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0000")))) == Sat)
+    }
+
+    #[test]
+    fn cache_trace() {
+        let dir = std::env::temp_dir().join(format!("isla-trace-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(8));
+        solver.add_event(Event::ReadMem {
+            value: Val::Symbolic(x),
+            read_kind: Val::Enum(EnumMember { enum_id: 0, member: 0 }),
+            address: Val::Bits(B64::new(0x2000, 64)),
+            bytes: 1,
+            tag_value: None,
+            kind: "symbolic".into(),
+        });
+        checkpoint(&mut solver);
+        solver.add_event(Event::Cycle);
+
+        solver.trace().cache(TraceKey("test".to_string()), &dir);
+        assert!(Trace::<B64>::from_cache(TraceKey("missing".to_string()), &dir).is_none());
+        let cached = Trace::<B64>::from_cache(TraceKey("test".to_string()), &dir).unwrap();
+        assert_eq!(format!("{:?}", cached.to_vec()), format!("{:?}", solver.trace().to_vec()));
+        assert!(matches!(cached.to_vec()[1], Event::ReadMem { kind, .. } if kind == "symbolic"));
+        std::fs::remove_dir_all(&dir).unwrap()
+    }
+
+    #[test]
+    fn solver_profile() {
+        let profile = Arc::new(SolverProfile::new());
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        {
+            let mut solver = Solver::<B64>::new(&ctx);
+            solver.set_profile(profile.clone());
+            let x = solver.declare_const(Ty::BitVec(4));
+            solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("0100")))));
+            assert!(solver.check_sat() == Sat);
+            solver.add_event(Event::Fork(0, x, "location".to_string()));
+            assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0101")))) == Unsat);
+            assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(bv!("0001")))) == Sat);
+
+            let stats = solver.statistics();
+            assert!(stats.declarations == 1 && stats.assertions == 1);
+            assert!(stats.queries == 3 && stats.sat == 2 && stats.unsat == 1 && stats.unknown == 0);
+            assert!(stats.max_time <= stats.time);
+            assert!(!solver.z3_statistics().is_empty());
+            // Totals are only added to the profile when the solver is dropped
+            assert!(profile.total().queries == 0)
+        }
+        assert!(profile.total().queries == 3);
+
+        let locations = profile.locations();
+        assert!(locations.len() == 2);
+        let location = locations.iter().find(|(loc, _)| loc.as_deref() == Some("location")).unwrap();
+        assert!(location.1.queries == 2 && location.1.unsat == 1)
+    }
+
+    #[test]
+    fn solver_stats_replayed() {
+        let cfg = Config::new();
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(4));
+        solver.add(Assert(Bvult(Box::new(Var(x)), Box::new(bv!("0100")))));
+        let point = checkpoint(&mut solver);
+        let mut solver = Solver::from_checkpoint(&ctx, point);
+        solver.add(Assert(Bvult(Box::new(bv!("0001")), Box::new(Var(x)))));
+        let stats = solver.statistics();
+        assert!(stats.declarations == 0 && stats.assertions == 1 && stats.replayed == 2);
+
+        let mut profile = SolverStats::default();
+        profile.merge(stats);
+        profile.merge(stats);
+        assert!(profile.assertions == 2 && profile.replayed == 4)
+    }
+
+    #[test]
+    fn eval_val() {
+        use crate::concrete::bitvector129::B129;
+        use crate::ir::{Name, UVal, Val};
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+        let mut solver = Solver::<B129>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(4));
+        let y = solver.declare_const(Ty::Bool);
+        let z = solver.declare_const(Ty::BitVec(100));
+        let unconstrained = solver.declare_const(Ty::BitVec(8));
+        solver.add(Assert(Eq(Box::new(Var(x)), Box::new(bv!("1010")))));
+        solver.add(Assert(Var(y)));
+        solver.add(Assert(Eq(Box::new(Var(z)), Box::new(SignExtend(96, Box::new(bv!("1001")))))));
+        assert!(solver.check_sat() == Sat);
+
+        let mut fields = HashMap::new();
+        fields.insert(Name::from_u32(0), Val::Symbolic(x));
+        fields.insert(Name::from_u32(1), Val::Vector(vec![Val::Symbolic(y), Val::I128(3)]));
+        let val = Val::Ctor(Name::from_u32(2), Box::new(Val::Struct(fields)));
+        let mut model = Model::new(&solver);
+        match model.eval_val(&val).unwrap() {
+            Val::Ctor(_, fields) => match *fields {
+                Val::Struct(fields) => {
+                    assert!(matches!(fields[&Name::from_u32(0)], Val::Bits(bv) if bv == B129::new(0b1010, 4)));
+                    let vector = &fields[&Name::from_u32(1)];
+                    assert!(matches!(vector, Val::Vector(v) if matches!(v[..], [Val::Bool(true), Val::I128(3)])))
+                }
+                _ => panic!("Expected struct"),
+            },
+            _ => panic!("Expected constructor"),
+        }
+        let expected = B129::new(0b1001, 4).sign_extend(100);
+        assert!(matches!(model.eval_val(&Val::Symbolic(z)).unwrap(), Val::Bits(bv) if bv == expected));
+
+        let mut bindings = HashMap::new();
+        bindings.insert(Name::from_u32(3), UVal::Init(Val::Symbolic(unconstrained)));
+        match &model.eval_bindings(&bindings, &HashMap::new()).unwrap()[&Name::from_u32(3)] {
+            UVal::Init(Val::Bits(bv)) => assert!(bv.len() == 8),
+            _ => panic!("Expected concrete bitvector"),
+        }
+    }
+
+    #[test]
+    fn eval_val_with_ty() {
+        use crate::ir;
+        use crate::ir::{Name, UVal, Val};
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+
+        // Without unbounded integers a symbolic Sail int is a 128-bit
+        // bitvector, which is wider than B64 can hold
+        let mut solver = Solver::<B64>::new(&ctx);
+        let i = solver.declare_const(Ty::BitVec(128));
+        let j = solver.declare_const(Ty::BitVec(64));
+        solver.add(Assert(Eq(Box::new(Var(i)), Box::new(SignExtend(64, Box::new(Bits64(-5i64 as u64, 64)))))));
+        solver.add(Assert(Eq(Box::new(Var(j)), Box::new(Bits64(-3i64 as u64, 64)))));
+        assert!(solver.check_sat() == Sat);
+        let mut model = Model::new(&solver);
+        assert!(model.eval_val(&Val::Symbolic(i)).is_err());
+        let ints = ir::Ty::Vector(Box::new(ir::Ty::I128));
+        let val = model.eval_val_with_ty(&Val::Vector(vec![Val::Symbolic(i), Val::I128(2)]), &ints).unwrap();
+        assert!(matches!(&val, Val::Vector(v) if matches!(v[..], [Val::I128(-5), Val::I128(2)])));
+        assert!(matches!(model.eval_val_with_ty(&Val::Symbolic(j), &ir::Ty::I64), Ok(Val::I64(-3))));
+
+        let options = SolverOptions { unbounded_ints: true, ..SolverOptions::default() };
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        let i = solver.declare_const(Ty::Int);
+        solver.add(Assert(Eq(Box::new(Var(i)), Box::new(Int(1 << 100)))));
+        assert!(solver.check_sat() == Sat);
+        let mut model = Model::new(&solver);
+        let mut bindings = HashMap::new();
+        bindings.insert(Name::from_u32(0), UVal::Init(Val::Symbolic(i)));
+        let mut types = HashMap::new();
+        types.insert(Name::from_u32(0), ir::Ty::I128);
+        match &model.eval_bindings(&bindings, &types).unwrap()[&Name::from_u32(0)] {
+            UVal::Init(Val::I128(n)) => assert!(*n == 1 << 100),
+            _ => panic!("Expected integer"),
+        }
+    }
+
+    #[test]
+    fn unbounded_ints() {
+        use crate::ir::Val;
+        use crate::primop::{binary_primops, unary_primops};
+        let mut cfg = Config::new();
+        cfg.set_param_value("model", "true");
+        let ctx = Context::new(cfg);
+        let options = SolverOptions { unbounded_ints: true, ..SolverOptions::default() };
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        let (unary, binary) = (unary_primops::<B64>(), binary_primops::<B64>());
+
+        // Adding to a large integer does not wrap around
+        let x = solver.declare_const(Ty::Int);
+        solver.add(Assert(IntGe(Box::new(Var(x)), Box::new(Int(i128::MAX - 1)))));
+        let sum = match binary["add_int"](Val::Symbolic(x), Val::I128(2), &mut solver).unwrap() {
+            Val::Symbolic(sum) => sum,
+            _ => panic!("Expected symbolic sum"),
+        };
+        assert!(solver.check_sat_with(&IntLt(Box::new(Var(sum)), Box::new(Int(0)))) == Unsat);
+        assert!(solver.check_sat() == Sat);
+        {
+            let mut model = Model::new(&solver);
+            assert!(matches!(model.get_var(x).unwrap(), Some(Int(_))));
+        }
+
+        // Conversions between bitvectors and integers round-trip
+        let bits = solver.declare_const(Ty::BitVec(8));
+        let n = unary["sail_signed"](Val::Symbolic(bits), &mut solver).unwrap();
+        let n = match n {
+            Val::Symbolic(n) => n,
+            _ => panic!("Expected symbolic integer"),
+        };
+        let negative = And(
+            Box::new(Eq(Box::new(Var(bits)), Box::new(bv!("11111111")))),
+            Box::new(Neq(Box::new(Var(n)), Box::new(Int(-1)))),
+        );
+        assert!(solver.check_sat_with(&negative) == Unsat);
+        let unsigned = match unary["sail_unsigned"](Val::Symbolic(bits), &mut solver).unwrap() {
+            Val::Symbolic(u) => u,
+            _ => panic!("Expected symbolic integer"),
+        };
+        let large = And(
+            Box::new(Eq(Box::new(Var(bits)), Box::new(bv!("11111111")))),
+            Box::new(Neq(Box::new(Var(unsigned)), Box::new(Int(255)))),
+        );
+        assert!(solver.check_sat_with(&large) == Unsat);
+
+        // Truncating division rounds towards zero
+        let q = match binary["tdiv_int"](Val::Symbolic(n), Val::I128(2), &mut solver).unwrap() {
+            Val::Symbolic(q) => q,
+            _ => panic!("Expected symbolic quotient"),
+        };
+        let rounding =
+            And(Box::new(Eq(Box::new(Var(n)), Box::new(Int(-3)))), Box::new(Neq(Box::new(Var(q)), Box::new(Int(-1)))));
+        assert!(solver.check_sat_with(&rounding) == Unsat);
+        let q = match crate::primop::udiv_int(Val::I128(7), Val::Symbolic(n), &mut solver).unwrap() {
+            Val::Symbolic(q) => q,
+            _ => panic!("Expected symbolic quotient"),
+        };
+        let rounding =
+            And(Box::new(Eq(Box::new(Var(n)), Box::new(Int(-2)))), Box::new(Neq(Box::new(Var(q)), Box::new(Int(-3)))));
+        assert!(solver.check_sat_with(&rounding) == Unsat);
+
+        // Shifts by a concrete amount do not wrap around, and shifting
+        // right rounds down
+        let shifted = match binary["shl_int"](Val::Symbolic(x), Val::I128(4), &mut solver).unwrap() {
+            Val::Symbolic(shifted) => shifted,
+            _ => panic!("Expected symbolic shift"),
+        };
+        assert!(solver.check_sat_with(&IntLt(Box::new(Var(shifted)), Box::new(Int(0)))) == Unsat);
+        let shifted = match binary["shr_int"](Val::Symbolic(n), Val::I128(1), &mut solver).unwrap() {
+            Val::Symbolic(shifted) => shifted,
+            _ => panic!("Expected symbolic shift"),
+        };
+        let rounding = And(
+            Box::new(Eq(Box::new(Var(n)), Box::new(Int(-3)))),
+            Box::new(Neq(Box::new(Var(shifted)), Box::new(Int(-2)))),
+        );
+        assert!(solver.check_sat_with(&rounding) == Unsat);
+        assert!(matches!(
+            binary["shl_int"](Val::I128(1), Val::Symbolic(n), &mut solver),
+            Err(ExecError::SymbolicExponent(_))
+        ))
+    }
+
+    #[test]
+    fn real_numbers() {
+        use crate::ir::Val;
+        use crate::primop::{binary_primops, unary_primops};
+        let ctx = Context::new(Config::new());
+        let (unary, binary) = (unary_primops::<B64>(), binary_primops::<B64>());
+        let string = |s: &str| Val::String(s.to_string());
+
+        // The default solver cannot declare reals
+        let mut solver = Solver::<B64>::new(&ctx);
+        assert!(matches!(unary["%string->%real"](string("1.5"), &mut solver), Err(ExecError::UnsupportedReals)));
+        assert!(matches!(unary["undefined_real"](Val::Unit, &mut solver), Err(ExecError::UnsupportedReals)));
+
+        // Reals need the general solver
+        let options = SolverOptions { unbounded_ints: true, ..SolverOptions::default() };
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        let real = |s: &str, solver: &mut Solver<B64>| unary["%string->%real"](string(s), solver).unwrap();
+        assert!(matches!(unary["%string->%real"](string("1.x"), &mut solver), Err(ExecError::Type(_))));
+        assert!(
+            matches!(unary["%string->%real"](string("one"), &mut solver), Err(ExecError::Type(msg)) if msg.contains("one"))
+        );
+
+        // Operations on reals built from literals have concrete results
+        let x = real("1.5", &mut solver);
+        let two = unary["to_real"](Val::I128(2), &mut solver).unwrap();
+        let product = binary["mult_real"](x.clone(), two.clone(), &mut solver).unwrap();
+        assert!(matches!(unary["round_down"](product, &mut solver), Ok(Val::I128(3))));
+        assert!(matches!(binary["lt_real"](x.clone(), two.clone(), &mut solver), Ok(Val::Bool(true))));
+        let y = real("-1.5", &mut solver);
+        assert!(matches!(unary["round_down"](y.clone(), &mut solver), Ok(Val::I128(-2))));
+        assert!(matches!(unary["round_up"](y.clone(), &mut solver), Ok(Val::I128(-1))));
+        let abs = unary["abs_real"](y, &mut solver).unwrap();
+        assert!(matches!(binary["eq_real"](abs, x.clone(), &mut solver), Ok(Val::Bool(true))));
+        let square = binary["real_power"](x.clone(), Val::I128(2), &mut solver).unwrap();
+        let expected = real("2.25", &mut solver);
+        assert!(matches!(binary["eq_real"](square, expected, &mut solver), Ok(Val::Bool(true))));
+        let inverse = binary["real_power"](two.clone(), Val::I128(-1), &mut solver).unwrap();
+        let quotient = binary["div_real"](x.clone(), two, &mut solver).unwrap();
+        let expected = real("0.75", &mut solver);
+        assert!(matches!(binary["eq_real"](quotient, expected, &mut solver), Ok(Val::Bool(true))));
+        let half = real("0.5", &mut solver);
+        assert!(matches!(binary["gteq_real"](inverse, half, &mut solver), Ok(Val::Bool(true))));
+
+        // Reasoning about symbolic reals
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        let x = unary["undefined_real"](Val::Unit, &mut solver).unwrap();
+        let root = match unary["sqrt_real"](x.clone(), &mut solver).unwrap() {
+            Val::Symbolic(root) => root,
+            _ => panic!("Expected symbolic square root"),
+        };
+        let (x, n) = match x {
+            Val::Symbolic(x) => (x, solver.declare_const(Ty::Int)),
+            _ => panic!("Expected symbolic real"),
+        };
+        let four = IntToReal(Box::new(Int(4)));
+        let wrong_root = And(
+            Box::new(Eq(Box::new(Var(x)), Box::new(four))),
+            Box::new(Neq(Box::new(Var(root)), Box::new(IntToReal(Box::new(Int(2)))))),
+        );
+        assert!(solver.check_sat_with(&wrong_root) == Unsat);
+        let floor = match unary["round_down"](Val::Symbolic(x), &mut solver).unwrap() {
+            Val::Symbolic(floor) => floor,
+            _ => panic!("Expected symbolic integer"),
+        };
+        let half = RealDiv(Box::new(IntToReal(Box::new(Int(-1)))), Box::new(IntToReal(Box::new(Int(2)))));
+        let rounding =
+            And(Box::new(Eq(Box::new(Var(x)), Box::new(half))), Box::new(Neq(Box::new(Var(floor)), Box::new(Int(-1)))));
+        assert!(solver.check_sat_with(&rounding) == Unsat);
+        assert!(matches!(binary["lt_real"](Val::Symbolic(x), Val::Symbolic(root), &mut solver), Ok(Val::Symbolic(_))));
+        assert!(matches!(
+            binary["real_power"](Val::Symbolic(x), Val::Symbolic(n), &mut solver),
+            Err(ExecError::SymbolicExponent(_))
+        ));
+        assert!(solver.check_sat() == Sat);
+        let mut model = Model::new(&solver);
+        assert!(matches!(model.get_var(x), Ok(Some(_))))
+    }
 }
@@ -0,0 +1,542 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2019, 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements [SolverBackend] for external SMT solvers
+//! that read SMT-LIB v2 commands on stdin and write their responses
+//! to stdout, such as cvc5, Bitwuzla, Boolector, or the z3 binary
+//! itself. Enumerations are declared as datatypes, so solvers without
+//! datatype support can only be used for traces that do not contain
+//! enumerations. They can replay recorded traces, or be used by the
+//! executor via `TaskState::set_solver_backend`, e.g. to compare
+//! solvers on the queries isla generates.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use super::smtlib::*;
+use super::{SmtResult, SolverBackend, Sym};
+use crate::error::ExecError;
+use crate::ir::EnumMember;
+use crate::simplify::{write_exp, WriteOpts};
+
+fn process_error<E: std::fmt::Display>(err: E) -> ExecError {
+    ExecError::SolverProcess(format!("{}", err))
+}
+
+/// Keeps track of the types of declared variables and functions so
+/// that definitions can be printed with their sorts.
+#[derive(Default)]
+struct Printer {
+    tcx: HashMap<Sym, Ty>,
+    ftcx: HashMap<Sym, (Vec<Ty>, Ty)>,
+    // Enumeration members are printed as e<enum_id>_<member>, so
+    // write_exp is given the enum identifiers in place of their sizes.
+    enum_ids: Vec<usize>,
+}
+
+impl Printer {
+    fn exp(&self, exp: &Exp) -> String {
+        let mut buf = Vec::new();
+        write_exp(&mut buf, exp, &WriteOpts::smtlib(), &self.enum_ids).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn def(&mut self, def: &Def) -> Result<String, ExecError> {
+        match def {
+            Def::DeclareConst(v, ty) => {
+                self.tcx.insert(*v, ty.clone());
+                Ok(format!("(declare-const v{} {})", v, ty))
+            }
+            Def::DeclareFun(v, arg_tys, result_ty) => {
+                self.ftcx.insert(*v, (arg_tys.clone(), result_ty.clone()));
+                let arg_tys: Vec<String> = arg_tys.iter().map(|ty| format!("{}", ty)).collect();
+                Ok(format!("(declare-fun v{} ({}) {})", v, arg_tys.join(" "), result_ty))
+            }
+            Def::DefineConst(v, exp) => {
+                let ty = exp
+                    .infer(&self.tcx, &self.ftcx)
+                    .ok_or_else(|| ExecError::Type(format!("Could not infer type of definition v{}", v)))?;
+                let s = format!("(define-fun v{} () {} {})", v, ty, self.exp(exp));
+                self.tcx.insert(*v, ty);
+                Ok(s)
+            }
+            Def::DefineEnum(_, size) => {
+                let enum_id = self.enum_ids.len();
+                self.enum_ids.push(enum_id);
+                let members: Vec<String> = (0..*size).map(|member| format!("(e{}_{})", enum_id, member)).collect();
+                Ok(format!("(declare-datatypes ((Enum{} 0)) (({})))", enum_id, members.join(" ")))
+            }
+            Def::Assert(exp) => Ok(format!("(assert {})", self.exp(exp))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+pub(crate) fn parse_sexp(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Sexp> {
+    while chars.peek()?.is_whitespace() {
+        chars.next();
+    }
+    match chars.next()? {
+        '(' => {
+            let mut items = Vec::new();
+            loop {
+                while chars.peek()?.is_whitespace() {
+                    chars.next();
+                }
+                if *chars.peek()? == ')' {
+                    chars.next();
+                    return Some(Sexp::List(items));
+                }
+                items.push(parse_sexp(chars)?)
+            }
+        }
+        ')' => None,
+        c => {
+            let mut atom = c.to_string();
+            if c == '"' || c == '|' {
+                loop {
+                    let next = chars.next()?;
+                    atom.push(next);
+                    if next == c {
+                        break;
+                    }
+                }
+            } else {
+                while let Some(next) = chars.peek() {
+                    if next.is_whitespace() || *next == '(' || *next == ')' {
+                        break;
+                    }
+                    atom.push(*next);
+                    chars.next();
+                }
+            }
+            Some(Sexp::Atom(atom))
+        }
+    }
+}
+
+fn bits_from_u64(bits: u64, len: u32) -> Exp {
+    if len > 64 {
+        let mut v: Vec<bool> = (0..64).map(|i| (bits >> i) & 1 == 1).collect();
+        v.resize(len as usize, false);
+        Exp::Bits(v)
+    } else {
+        Exp::Bits64(bits, len)
+    }
+}
+
+/// Convert a list of bits, least significant first, into an
+/// expression in the same form as `Model::get_var` returns.
+fn bits_from_vec(v: Vec<bool>) -> Exp {
+    if v.len() > 64 {
+        Exp::Bits(v)
+    } else {
+        let bits = v.iter().enumerate().fold(0, |acc, (i, bit)| acc | ((*bit as u64) << i));
+        Exp::Bits64(bits, v.len() as u32)
+    }
+}
+
+/// Convert a decimal numeral to `len` bits, least significant first.
+fn decimal_to_bits(numeral: &str, len: u32) -> Option<Vec<bool>> {
+    let mut digits: Vec<u32> = numeral.chars().map(|c| c.to_digit(10)).collect::<Option<_>>()?;
+    let mut bits = Vec::with_capacity(len as usize);
+    for _ in 0..len {
+        let mut rem = 0;
+        for digit in digits.iter_mut() {
+            let n = rem * 10 + *digit;
+            *digit = n / 2;
+            rem = n % 2;
+        }
+        bits.push(rem == 1)
+    }
+    Some(bits)
+}
+
+/// Parse an integer or real numeral, possibly negated with `(- n)`,
+/// as a numerator and a denominator.
+fn parse_numeral(value: &Sexp) -> Option<(i128, i128)> {
+    match value {
+        Sexp::Atom(a) if a.chars().next()?.is_ascii_digit() => match a.find('.') {
+            Some(point) => {
+                let fraction = &a[point + 1..];
+                let denominator = 10i128.checked_pow(fraction.len() as u32)?;
+                let numerator = a[..point].parse::<i128>().ok()?.checked_mul(denominator)?;
+                Some((numerator.checked_add(fraction.parse().ok()?)?, denominator))
+            }
+            None => Some((a.parse().ok()?, 1)),
+        },
+        Sexp::List(items) => match items.as_slice() {
+            [Sexp::Atom(minus), n] if minus == "-" => {
+                let (numerator, denominator) = parse_numeral(n)?;
+                Some((numerator.checked_neg()?, denominator))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn real_from_quotient(numerator: i128, denominator: i128) -> Exp {
+    let (mut a, mut b) = (numerator.abs(), denominator);
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r
+    }
+    let (numerator, denominator) = if a > 1 { (numerator / a, denominator / a) } else { (numerator, denominator) };
+    let numerator = Exp::IntToReal(Box::new(Exp::Int(numerator)));
+    if denominator == 1 {
+        numerator
+    } else {
+        Exp::RealDiv(Box::new(numerator), Box::new(Exp::IntToReal(Box::new(Exp::Int(denominator)))))
+    }
+}
+
+/// Parse a value from a model. Int values are returned as `Int`,
+/// and Real values in the same form as `Solver::eval_closed`.
+pub(crate) fn parse_value(value: &Sexp) -> Option<Exp> {
+    match value {
+        Sexp::Atom(a) if a.contains('.') => {
+            let (numerator, denominator) = parse_numeral(value)?;
+            Some(real_from_quotient(numerator, denominator))
+        }
+        Sexp::Atom(a) if a.chars().next()?.is_ascii_digit() => Some(Exp::Int(a.parse().ok()?)),
+        Sexp::Atom(a) if a == "true" => Some(Exp::Bool(true)),
+        Sexp::Atom(a) if a == "false" => Some(Exp::Bool(false)),
+        Sexp::Atom(a) if a.starts_with("#b") => Some(bits_from_vec(a[2..].chars().rev().map(|c| c == '1').collect())),
+        Sexp::Atom(a) if a.starts_with("#x") => {
+            let mut bits = Vec::with_capacity(4 * (a.len() - 2));
+            for c in a[2..].chars().rev() {
+                let nibble = c.to_digit(16)?;
+                bits.extend((0..4).map(|i| (nibble >> i) & 1 == 1))
+            }
+            Some(bits_from_vec(bits))
+        }
+        Sexp::Atom(a) if a.starts_with('e') => {
+            let mut parts = a[1..].splitn(2, '_');
+            let enum_id = parts.next()?.parse().ok()?;
+            let member = parts.next()?.parse().ok()?;
+            Some(Exp::Enum(EnumMember { enum_id, member }))
+        }
+        // Some solvers print bitvector values as (_ bvN len)
+        Sexp::List(items) => match items.as_slice() {
+            [Sexp::Atom(underscore), Sexp::Atom(numeral), Sexp::Atom(len)] if underscore == "_" => {
+                let len: u32 = len.parse().ok()?;
+                let numeral = numeral.strip_prefix("bv")?;
+                if len <= 64 {
+                    Some(bits_from_u64(numeral.parse().ok()?, len))
+                } else {
+                    Some(Exp::Bits(decimal_to_bits(numeral, len)?))
+                }
+            }
+            [Sexp::Atom(minus), n] if minus == "-" => match parse_value(n)? {
+                Exp::Int(n) => Some(Exp::Int(n.checked_neg()?)),
+                _ => {
+                    let (numerator, denominator) = parse_numeral(value)?;
+                    Some(real_from_quotient(numerator, denominator))
+                }
+            },
+            // Reals which are not decimals are printed as (/ n d)
+            [Sexp::Atom(slash), n, d] if slash == "/" => {
+                let (n_numerator, n_denominator) = parse_numeral(n)?;
+                let (d_numerator, d_denominator) = parse_numeral(d)?;
+                let numerator = n_numerator.checked_mul(d_denominator)?;
+                let denominator = d_numerator.checked_mul(n_denominator)?;
+                if denominator < 0 {
+                    Some(real_from_quotient(numerator.checked_neg()?, denominator.checked_neg()?))
+                } else {
+                    Some(real_from_quotient(numerator, denominator))
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// An SMT solver running as a separate process, driven over its
+/// standard input and output using SMT-LIB v2.
+///
+/// For example, using a z3 binary found on the `PATH`:
+/// ```no_run
+/// # use isla_lib::smt::smtlib::Exp::*;
+/// # use isla_lib::smt::smtlib::Def::*;
+/// # use isla_lib::smt::smtlib::*;
+/// # use isla_lib::smt::process::SmtLibProcess;
+/// # use isla_lib::smt::*;
+/// # let x = Sym::from_u32(0);
+/// let mut solver = SmtLibProcess::z3().unwrap();
+/// solver.add_def(&DeclareConst(x, Ty::BitVec(4))).unwrap();
+/// solver.add_def(&Assert(Bvugt(Box::new(Var(x)), Box::new(Bits64(14, 4))))).unwrap();
+/// assert!(solver.check_sat().unwrap() == SmtResult::Sat);
+/// assert!(matches!(solver.get_value(&Var(x)), Ok(Some(Bits64(15, 4)))))
+/// ```
+pub struct SmtLibProcess {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    printer: Printer,
+    // Set once the solver reports an error, after which we can no
+    // longer tell which response belongs to which command.
+    failed: bool,
+}
+
+impl SmtLibProcess {
+    /// Start a solver. The command should make the solver read
+    /// SMT-LIB v2 from stdin and accept multiple `(check-sat)`
+    /// commands, which for some solvers requires an incremental
+    /// mode flag.
+    pub fn new(mut cmd: Command) -> Result<Self, ExecError> {
+        let mut child = cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().map_err(process_error)?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut solver = SmtLibProcess { child, stdin, stdout, printer: Printer::default(), failed: false };
+        solver.send("(set-option :print-success false)")?;
+        solver.send("(set-option :produce-models true)")?;
+        Ok(solver)
+    }
+
+    pub fn z3() -> Result<Self, ExecError> {
+        let mut cmd = Command::new("z3");
+        cmd.args(["-in", "-smt2"]);
+        Self::new(cmd)
+    }
+
+    pub fn cvc5() -> Result<Self, ExecError> {
+        let mut cmd = Command::new("cvc5");
+        cmd.args(["--lang=smt2", "--incremental"]);
+        Self::new(cmd)
+    }
+
+    pub fn bitwuzla() -> Result<Self, ExecError> {
+        let mut cmd = Command::new("bitwuzla");
+        cmd.args(["--lang", "smt2"]);
+        Self::new(cmd)
+    }
+
+    pub fn boolector() -> Result<Self, ExecError> {
+        let mut cmd = Command::new("boolector");
+        cmd.args(["--smt2", "--incremental"]);
+        Self::new(cmd)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), ExecError> {
+        if self.failed {
+            return Err(ExecError::SolverProcess("Solver process failed on an earlier command".to_string()));
+        }
+        writeln!(self.stdin, "{}", command).map_err(process_error)
+    }
+
+    /// Read a single complete S-expression response from the
+    /// solver. If the response is an error, no further commands can
+    /// be sent.
+    fn receive(&mut self) -> Result<Sexp, ExecError> {
+        self.stdin.flush().map_err(process_error)?;
+        let mut response = String::new();
+        let mut depth: i32 = 0;
+        let mut quote: Option<char> = None;
+        loop {
+            let start = response.len();
+            if self.stdout.read_line(&mut response).map_err(process_error)? == 0 {
+                return Err(ExecError::SolverProcess("Solver process closed its output".to_string()));
+            }
+            for c in response[start..].chars() {
+                match quote {
+                    Some(q) if c == q => quote = None,
+                    Some(_) => (),
+                    None if c == '"' || c == '|' => quote = Some(c),
+                    None if c == '(' => depth += 1,
+                    None if c == ')' => depth -= 1,
+                    None => (),
+                }
+            }
+            if depth <= 0 && quote.is_none() && !response.trim().is_empty() {
+                break;
+            }
+        }
+        match parse_sexp(&mut response.chars().peekable()) {
+            Some(Sexp::List(items)) if matches!(items.first(), Some(Sexp::Atom(error)) if error == "error") => {
+                self.failed = true;
+                Err(ExecError::SolverProcess(response.trim().to_string()))
+            }
+            Some(sexp) => Ok(sexp),
+            None => Err(ExecError::SolverProcess(format!("Could not parse solver response: {}", response))),
+        }
+    }
+
+    fn check(&mut self, command: &str) -> Result<SmtResult, ExecError> {
+        self.send(command)?;
+        // As we turn off :print-success, any error from an earlier
+        // command will be read here before the result.
+        match self.receive()? {
+            Sexp::Atom(result) if result == "sat" => Ok(SmtResult::Sat),
+            Sexp::Atom(result) if result == "unsat" => Ok(SmtResult::Unsat),
+            Sexp::Atom(result) if result == "unknown" => Ok(SmtResult::Unknown),
+            response => Err(ExecError::SolverProcess(format!("Unexpected response to check-sat {:?}", response))),
+        }
+    }
+}
+
+impl Drop for SmtLibProcess {
+    fn drop(&mut self) {
+        if self.send("(exit)").and_then(|_| self.stdin.flush().map_err(process_error)).is_err() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+impl SolverBackend for SmtLibProcess {
+    fn add_def(&mut self, def: &Def) -> Result<(), ExecError> {
+        let command = self.printer.def(def)?;
+        self.send(&command)
+    }
+
+    fn push(&mut self) -> Result<(), ExecError> {
+        self.send("(push 1)")
+    }
+
+    fn pop(&mut self, n: u32) -> Result<(), ExecError> {
+        self.send(&format!("(pop {})", n))
+    }
+
+    fn check_sat(&mut self) -> Result<SmtResult, ExecError> {
+        self.check("(check-sat)")
+    }
+
+    fn check_sat_with(&mut self, exp: &Exp) -> Result<SmtResult, ExecError> {
+        let command = format!("(check-sat-assuming ({}))", self.printer.exp(exp));
+        self.check(&command)
+    }
+
+    fn get_value(&mut self, exp: &Exp) -> Result<Option<Exp>, ExecError> {
+        let command = format!("(get-value ({}))", self.printer.exp(exp));
+        self.send(&command)?;
+        match self.receive()? {
+            Sexp::List(items) => match items.as_slice() {
+                [Sexp::List(pair)] if pair.len() == 2 => Ok(parse_value(&pair[1])),
+                _ => Err(ExecError::SolverProcess("Unexpected response to get-value".to_string())),
+            },
+            Sexp::Atom(response) => Err(ExecError::SolverProcess(format!("Unexpected response {}", response))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Def::*;
+    use super::Exp::*;
+    use super::*;
+
+    fn parse(s: &str) -> Option<Exp> {
+        parse_value(&parse_sexp(&mut s.chars().peekable())?)
+    }
+
+    #[test]
+    fn print_defs() {
+        let mut printer = Printer::default();
+        let x = Sym::from_u32(0);
+        let y = Sym::from_u32(1);
+        assert_eq!(printer.def(&DeclareConst(x, Ty::BitVec(8))).unwrap(), "(declare-const v0 (_ BitVec 8))");
+        assert_eq!(
+            printer.def(&DefineConst(y, Bvadd(Box::new(Var(x)), Box::new(Bits64(1, 8))))).unwrap(),
+            "(define-fun v1 () (_ BitVec 8) (bvadd v0 #x01))"
+        );
+        assert_eq!(
+            printer.def(&DefineEnum(Sym::from_u32(2), 2)).unwrap(),
+            "(declare-datatypes ((Enum0 0)) (((e0_0) (e0_1))))"
+        );
+        assert_eq!(
+            printer
+                .def(&Assert(Eq(Box::new(Enum(EnumMember { enum_id: 0, member: 1 })), Box::new(Var(Sym::from_u32(3))))))
+                .unwrap(),
+            "(assert (= e0_1 v3))"
+        );
+        assert!(printer.def(&DefineConst(Sym::from_u32(4), Var(Sym::from_u32(5)))).is_err())
+    }
+
+    #[test]
+    fn parse_values() {
+        assert!(matches!(parse("true"), Some(Bool(true))));
+        assert!(matches!(parse("#b101"), Some(Bits64(5, 3))));
+        assert!(matches!(parse("#x2a"), Some(Bits64(42, 8))));
+        assert!(matches!(parse("(_ bv42 8)"), Some(Bits64(42, 8))));
+        assert!(matches!(parse("e3_1"), Some(Enum(EnumMember { enum_id: 3, member: 1 }))));
+        match parse("(_ bv36893488147419103232 72)") {
+            Some(Bits(bits)) => assert!(bits.len() == 72 && bits.iter().enumerate().all(|(i, b)| *b == (i == 65))),
+            _ => panic!("Expected a 72-bit value"),
+        }
+        assert!(matches!(parse("42"), Some(Int(42))));
+        assert!(matches!(parse("(- 42)"), Some(Int(-42))));
+        assert_eq!(parse("2.0"), Some(IntToReal(Box::new(Int(2)))));
+        assert_eq!(
+            parse("(- 1.5)"),
+            Some(RealDiv(Box::new(IntToReal(Box::new(Int(-3)))), Box::new(IntToReal(Box::new(Int(2))))))
+        );
+        assert_eq!(
+            parse("(/ 1.0 (- 3.0))"),
+            Some(RealDiv(Box::new(IntToReal(Box::new(Int(-1)))), Box::new(IntToReal(Box::new(Int(3))))))
+        );
+        assert!(parse("(- x)").is_none())
+    }
+
+    #[test]
+    fn check_sat_error() {
+        // A fake solver which rejects the first command it is sent
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo '(error \"unsupported\")'; cat > /dev/null"]);
+        let mut solver = SmtLibProcess::new(cmd).unwrap();
+        assert!(matches!(solver.check_sat(), Err(ExecError::SolverProcess(msg)) if msg.contains("unsupported")));
+        assert!(solver.check_sat().is_err())
+    }
+
+    #[test]
+    #[ignore] // Requires a z3 binary on the PATH, run with --ignored
+    fn z3_process() {
+        let mut solver = SmtLibProcess::z3().unwrap();
+        let x = Sym::from_u32(0);
+        solver.add_def(&DeclareConst(x, Ty::BitVec(4))).unwrap();
+        solver.add_def(&Assert(Bvugt(Box::new(Var(x)), Box::new(Bits64(14, 4))))).unwrap();
+        assert!(solver.check_sat().unwrap() == SmtResult::Sat);
+        assert!(matches!(solver.get_value(&Var(x)), Ok(Some(Bits64(15, 4)))));
+        assert!(solver.check_sat_with(&Eq(Box::new(Var(x)), Box::new(Bits64(3, 4)))).unwrap() == SmtResult::Unsat);
+
+        // An error from a bad command is reported by the next check,
+        // after which the solver cannot be used
+        solver.add_def(&Assert(Var(Sym::from_u32(1)))).unwrap();
+        assert!(matches!(solver.check_sat(), Err(ExecError::SolverProcess(_))));
+        assert!(solver.check_sat().is_err())
+    }
+}
@@ -0,0 +1,530 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2019, 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements a rewriting simplifier for SMT
+//! expressions. Primops frequently build terms over concrete
+//! operands, such as shifting a literal one or extracting from a
+//! concatenation, and folding these before they reach the solver (or
+//! an output trace) keeps both smaller. Every rewrite produces an
+//! equivalent expression of the same sort.
+
+use std::convert::TryFrom;
+
+use super::smtlib::*;
+use super::Sym;
+
+fn mask(width: u32) -> u128 {
+    if width >= 128 {
+        u128::MAX
+    } else {
+        (1 << width) - 1
+    }
+}
+
+fn signed(value: u128, width: u32) -> i128 {
+    let shift = 128 - width;
+    ((value << shift) as i128) >> shift
+}
+
+fn literal_bits(exp: &Exp) -> Option<Vec<bool>> {
+    match exp {
+        Exp::Bits64(bv, width) => Some((0..*width).map(|n| (bv >> n) & 1 == 1).collect()),
+        Exp::Bits(bv) => Some(bv.clone()),
+        _ => None,
+    }
+}
+
+fn from_bits(bits: Vec<bool>) -> Exp {
+    if bits.len() <= 64 {
+        Exp::Bits64(bits.iter().rev().fold(0, |acc, bit| (acc << 1) | *bit as u64), bits.len() as u32)
+    } else {
+        Exp::Bits(bits)
+    }
+}
+
+// Literals of up to 128 bits, which covers the results of the integer
+// primops, are folded arithmetically.
+fn literal(exp: &Exp) -> Option<(u128, u32)> {
+    match exp {
+        Exp::Bits64(bv, width) if *width > 0 => Some((*bv as u128 & mask(*width), *width)),
+        Exp::Bits(bv) if !bv.is_empty() && bv.len() <= 128 => {
+            Some((bv.iter().rev().fold(0, |acc, bit| (acc << 1) | *bit as u128), bv.len() as u32))
+        }
+        _ => None,
+    }
+}
+
+fn from_literal(value: u128, width: u32) -> Exp {
+    let value = value & mask(width);
+    if width <= 64 {
+        Exp::Bits64(value as u64, width)
+    } else {
+        Exp::Bits((0..width).map(|n| (value >> n) & 1 == 1).collect())
+    }
+}
+
+fn is_zero(exp: &Exp) -> bool {
+    match exp {
+        Exp::Bits64(bv, width) => *bv as u128 & mask(*width) == 0,
+        Exp::Bits(bv) => bv.iter().all(|bit| !bit),
+        _ => false,
+    }
+}
+
+fn width(exp: &Exp, var_width: &dyn Fn(Sym) -> Option<u32>) -> Option<u32> {
+    use Exp::*;
+    match exp {
+        Var(v) => var_width(*v),
+        Bits(bv) => Some(bv.len() as u32),
+        Bits64(_, sz) => Some(*sz),
+        Bvnot(exp) | Bvneg(exp) => width(exp, var_width),
+        Extract(i, j, _) => Some((i - j) + 1),
+        ZeroExtend(ext, exp) | SignExtend(ext, exp) => width(exp, var_width).map(|sz| sz + ext),
+        Bvand(lhs, rhs)
+        | Bvor(lhs, rhs)
+        | Bvxor(lhs, rhs)
+        | Bvnand(lhs, rhs)
+        | Bvnor(lhs, rhs)
+        | Bvxnor(lhs, rhs)
+        | Bvadd(lhs, rhs)
+        | Bvsub(lhs, rhs)
+        | Bvmul(lhs, rhs)
+        | Bvudiv(lhs, rhs)
+        | Bvsdiv(lhs, rhs)
+        | Bvurem(lhs, rhs)
+        | Bvsrem(lhs, rhs)
+        | Bvsmod(lhs, rhs)
+        | Bvshl(lhs, rhs)
+        | Bvlshr(lhs, rhs)
+        | Bvashr(lhs, rhs) => width(lhs, var_width).or_else(|| width(rhs, var_width)),
+        Concat(lhs, rhs) => Some(width(lhs, var_width)? + width(rhs, var_width)?),
+        Ite(_, then_exp, else_exp) => width(then_exp, var_width).or_else(|| width(else_exp, var_width)),
+        FPConvert(FPConversion::ToSigned(sz), _, _) | FPConvert(FPConversion::ToUnsigned(sz), _, _) => Some(*sz),
+        Int2BV(sz, _) => Some(*sz),
+        _ => None,
+    }
+}
+
+/// Decide equality between two expressions if they are structurally
+/// equal or both literals.
+fn decide_eq(lhs: &Exp, rhs: &Exp) -> Option<bool> {
+    use Exp::*;
+    if lhs == rhs {
+        return Some(true);
+    }
+    match (lhs, rhs) {
+        (Bool(x), Bool(y)) => Some(x == y),
+        (Enum(x), Enum(y)) => Some(x == y),
+        (Int(x), Int(y)) => Some(x == y),
+        _ => Some(literal_bits(lhs)? == literal_bits(rhs)?),
+    }
+}
+
+/// Check if `exp` is a disjunction (or conjunction) with `operand`
+/// as one side, so that it is absorbed by `operand`.
+fn absorbed_by(exp: &Exp, operand: &Exp, disjunction: bool) -> bool {
+    match exp {
+        Exp::Or(lhs, rhs) if disjunction => **lhs == *operand || **rhs == *operand,
+        Exp::And(lhs, rhs) if !disjunction => **lhs == *operand || **rhs == *operand,
+        _ => false,
+    }
+}
+
+fn fold_binary<F>(lhs: &Exp, rhs: &Exp, f: F) -> Option<Exp>
+where
+    F: Fn(u128, u128, u32) -> u128,
+{
+    let (x, sz) = literal(lhs)?;
+    let (y, _) = literal(rhs)?;
+    Some(from_literal(f(x, y, sz), sz))
+}
+
+fn fold_compare<F>(lhs: &Exp, rhs: &Exp, f: F) -> Option<Exp>
+where
+    F: Fn(u128, u128, u32) -> bool,
+{
+    let (x, sz) = literal(lhs)?;
+    let (y, _) = literal(rhs)?;
+    Some(Exp::Bool(f(x, y, sz)))
+}
+
+// Integer literals are only folded when the result fits in an i128
+fn fold_int<F>(lhs: &Exp, rhs: &Exp, f: F) -> Option<Exp>
+where
+    F: Fn(i128, i128) -> Option<i128>,
+{
+    match (lhs, rhs) {
+        (Exp::Int(x), Exp::Int(y)) => Some(Exp::Int(f(*x, *y)?)),
+        _ => None,
+    }
+}
+
+fn fold_int_compare<F>(lhs: &Exp, rhs: &Exp, f: F) -> Option<Exp>
+where
+    F: Fn(i128, i128) -> bool,
+{
+    match (lhs, rhs) {
+        (Exp::Int(x), Exp::Int(y)) => Some(Exp::Bool(f(*x, *y))),
+        _ => None,
+    }
+}
+
+fn fold_signed_compare<F>(lhs: &Exp, rhs: &Exp, f: F) -> Option<Exp>
+where
+    F: Fn(i128, i128) -> bool,
+{
+    fold_compare(lhs, rhs, |x, y, sz| f(signed(x, sz), signed(y, sz)))
+}
+
+/// Rewrite the top level of an expression (whose sub-expressions
+/// have already been simplified), returning `None` if no rule
+/// applies.
+fn rewrite(exp: &Exp, var_width: &dyn Fn(Sym) -> Option<u32>) -> Option<Exp> {
+    use Exp::*;
+    match exp {
+        Not(exp) => match &**exp {
+            Bool(b) => Some(Bool(!b)),
+            Not(exp) => Some((**exp).clone()),
+            _ => None,
+        },
+        And(lhs, rhs) => match (&**lhs, &**rhs) {
+            (Bool(false), _) | (_, Bool(false)) => Some(Bool(false)),
+            (Bool(true), exp) | (exp, Bool(true)) => Some(exp.clone()),
+            (x, y) if x == y || absorbed_by(y, x, true) => Some(x.clone()),
+            (x, y) if absorbed_by(x, y, true) => Some(y.clone()),
+            _ => None,
+        },
+        Or(lhs, rhs) => match (&**lhs, &**rhs) {
+            (Bool(true), _) | (_, Bool(true)) => Some(Bool(true)),
+            (Bool(false), exp) | (exp, Bool(false)) => Some(exp.clone()),
+            (x, y) if x == y || absorbed_by(y, x, false) => Some(x.clone()),
+            (x, y) if absorbed_by(x, y, false) => Some(y.clone()),
+            _ => None,
+        },
+        Eq(lhs, rhs) => {
+            if let Some(b) = decide_eq(lhs, rhs) {
+                return Some(Bool(b));
+            }
+            match (&**lhs, &**rhs) {
+                (Bool(true), exp) | (exp, Bool(true)) => Some(exp.clone()),
+                (Bool(false), exp) | (exp, Bool(false)) => Some(Not(Box::new(exp.clone()))),
+                (ZeroExtend(n, x), ZeroExtend(m, y)) if n == m => Some(Eq(x.clone(), y.clone())),
+                (ZeroExtend(n, x), lit) | (lit, ZeroExtend(n, x)) => {
+                    let bits = literal_bits(lit)?;
+                    let sz = bits.len().checked_sub(*n as usize)?;
+                    if bits[sz..].iter().any(|bit| *bit) {
+                        Some(Bool(false))
+                    } else {
+                        Some(Eq(x.clone(), Box::new(from_bits(bits[..sz].to_vec()))))
+                    }
+                }
+                _ => None,
+            }
+        }
+        Neq(lhs, rhs) => match rewrite(&Eq(lhs.clone(), rhs.clone()), var_width)? {
+            Bool(b) => Some(Bool(!b)),
+            exp => Some(Not(Box::new(exp))),
+        },
+        Ite(cond, then_exp, else_exp) => match (&**cond, &**then_exp, &**else_exp) {
+            (Bool(true), exp, _) | (Bool(false), _, exp) => Some(exp.clone()),
+            (_, x, y) if x == y => Some(x.clone()),
+            (cond, Bool(true), Bool(false)) => Some(cond.clone()),
+            (cond, Bool(false), Bool(true)) => Some(Not(Box::new(cond.clone()))),
+            _ => None,
+        },
+
+        Bvnot(exp) => match &**exp {
+            Bvnot(exp) => Some((**exp).clone()),
+            exp => Some(from_bits(literal_bits(exp)?.iter().map(|bit| !bit).collect())),
+        },
+        Bvneg(exp) => {
+            let (x, sz) = literal(exp)?;
+            Some(from_literal(x.wrapping_neg(), sz))
+        }
+        Bvand(lhs, rhs) => match (&**lhs, &**rhs) {
+            (zero, _) | (_, zero) if is_zero(zero) => Some(zero.clone()),
+            (lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| x & y),
+        },
+        Bvor(lhs, rhs) => match (&**lhs, &**rhs) {
+            (zero, exp) | (exp, zero) if is_zero(zero) => Some(exp.clone()),
+            (lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| x | y),
+        },
+        Bvxor(lhs, rhs) => match (&**lhs, &**rhs) {
+            (zero, exp) | (exp, zero) if is_zero(zero) => Some(exp.clone()),
+            (lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| x ^ y),
+        },
+        Bvnand(lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| !(x & y)),
+        Bvnor(lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| !(x | y)),
+        Bvxnor(lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| !(x ^ y)),
+        Bvadd(lhs, rhs) => match (&**lhs, &**rhs) {
+            (zero, exp) | (exp, zero) if is_zero(zero) => Some(exp.clone()),
+            (lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| x.wrapping_add(y)),
+        },
+        Bvsub(lhs, rhs) if is_zero(rhs) => Some((**lhs).clone()),
+        Bvsub(lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| x.wrapping_sub(y)),
+        Bvmul(lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| x.wrapping_mul(y)),
+        // Division by zero is defined in SMTLIB to return all ones,
+        // and remainder by zero to return the dividend.
+        Bvudiv(lhs, rhs) => fold_binary(lhs, rhs, |x, y, sz| x.checked_div(y).unwrap_or(mask(sz))),
+        Bvurem(lhs, rhs) => fold_binary(lhs, rhs, |x, y, _| x.checked_rem(y).unwrap_or(x)),
+        Bvshl(lhs, rhs) | Bvlshr(lhs, rhs) | Bvashr(lhs, rhs) if is_zero(rhs) => Some((**lhs).clone()),
+        Bvshl(lhs, rhs) => fold_binary(lhs, rhs, |x, y, sz| if y >= sz as u128 { 0 } else { x << y }),
+        Bvlshr(lhs, rhs) => fold_binary(lhs, rhs, |x, y, sz| if y >= sz as u128 { 0 } else { x >> y }),
+        Bvashr(lhs, rhs) => fold_binary(lhs, rhs, |x, y, sz| {
+            let x = signed(x, sz);
+            if y >= sz as u128 {
+                (x >> 127) as u128
+            } else {
+                (x >> y) as u128
+            }
+        }),
+        Bvult(lhs, rhs) => fold_compare(lhs, rhs, |x, y, _| x < y),
+        Bvule(lhs, rhs) => fold_compare(lhs, rhs, |x, y, _| x <= y),
+        Bvugt(lhs, rhs) => fold_compare(lhs, rhs, |x, y, _| x > y),
+        Bvuge(lhs, rhs) => fold_compare(lhs, rhs, |x, y, _| x >= y),
+        Bvslt(lhs, rhs) => fold_signed_compare(lhs, rhs, |x, y| x < y),
+        Bvsle(lhs, rhs) => fold_signed_compare(lhs, rhs, |x, y| x <= y),
+        Bvsgt(lhs, rhs) => fold_signed_compare(lhs, rhs, |x, y| x > y),
+        Bvsge(lhs, rhs) => fold_signed_compare(lhs, rhs, |x, y| x >= y),
+
+        Extract(i, j, exp) => {
+            if let Some(bits) = literal_bits(exp) {
+                return bits.get(*j as usize..=*i as usize).map(|bits| from_bits(bits.to_vec()));
+            }
+            if *j == 0 && width(exp, var_width) == Some(i + 1) {
+                return Some((**exp).clone());
+            }
+            match &**exp {
+                Extract(_, l, exp) => Some(Extract(i + l, j + l, exp.clone())),
+                Concat(hi, lo) => {
+                    let sz = width(lo, var_width)?;
+                    if *i < sz {
+                        Some(Extract(*i, *j, lo.clone()))
+                    } else if *j >= sz {
+                        Some(Extract(i - sz, j - sz, hi.clone()))
+                    } else {
+                        None
+                    }
+                }
+                ZeroExtend(_, exp) | SignExtend(_, exp) if *i < width(exp, var_width)? => {
+                    Some(Extract(*i, *j, exp.clone()))
+                }
+                ZeroExtend(_, exp) if *j >= width(exp, var_width)? => {
+                    Some(from_bits(vec![false; (i - j) as usize + 1]))
+                }
+                _ => None,
+            }
+        }
+        ZeroExtend(0, exp) | SignExtend(0, exp) => Some((**exp).clone()),
+        ZeroExtend(n, exp) => match &**exp {
+            ZeroExtend(m, exp) => Some(ZeroExtend(n + m, exp.clone())),
+            exp => {
+                let mut bits = literal_bits(exp)?;
+                bits.resize(bits.len() + *n as usize, false);
+                Some(from_bits(bits))
+            }
+        },
+        SignExtend(n, exp) => match &**exp {
+            SignExtend(m, exp) => Some(SignExtend(n + m, exp.clone())),
+            exp => {
+                let mut bits = literal_bits(exp)?;
+                let sign = *bits.last()?;
+                bits.resize(bits.len() + *n as usize, sign);
+                Some(from_bits(bits))
+            }
+        },
+        Concat(hi, lo) => match (literal_bits(hi), literal_bits(lo)) {
+            (Some(hi), Some(mut lo)) => {
+                lo.extend(hi);
+                Some(from_bits(lo))
+            }
+            (Some(hi), None) if hi.iter().all(|bit| !bit) => Some(ZeroExtend(hi.len() as u32, lo.clone())),
+            _ => None,
+        },
+
+        IntNeg(exp) => match &**exp {
+            Int(x) => Some(Int(x.checked_neg()?)),
+            IntNeg(exp) => Some((**exp).clone()),
+            _ => None,
+        },
+        IntAdd(lhs, rhs) => match (&**lhs, &**rhs) {
+            (Int(0), exp) | (exp, Int(0)) => Some(exp.clone()),
+            (lhs, rhs) => fold_int(lhs, rhs, i128::checked_add),
+        },
+        IntSub(lhs, rhs) => match (&**lhs, &**rhs) {
+            (exp, Int(0)) => Some(exp.clone()),
+            (lhs, rhs) => fold_int(lhs, rhs, i128::checked_sub),
+        },
+        IntMul(lhs, rhs) => fold_int(lhs, rhs, i128::checked_mul),
+        IntLt(lhs, rhs) => fold_int_compare(lhs, rhs, |x, y| x < y),
+        IntLe(lhs, rhs) => fold_int_compare(lhs, rhs, |x, y| x <= y),
+        IntGt(lhs, rhs) => fold_int_compare(lhs, rhs, |x, y| x > y),
+        IntGe(lhs, rhs) => fold_int_compare(lhs, rhs, |x, y| x >= y),
+        Int2BV(sz, exp) => match &**exp {
+            Int(x) if *sz <= 128 => Some(from_literal(*x as u128, *sz)),
+            BV2Nat(exp) if width(exp, var_width) == Some(*sz) => Some((**exp).clone()),
+            _ => None,
+        },
+        BV2Nat(exp) => {
+            let (x, _) = literal(exp)?;
+            i128::try_from(x).ok().map(Int)
+        }
+
+        _ => None,
+    }
+}
+
+/// Simplify an expression bottom-up, applying rewrites to each
+/// sub-expression until none apply. The `var_width` function gives
+/// the widths of bitvector variables where they are known, which some
+/// rewrites (such as extracting from a concatenation) require.
+pub fn simplify(mut exp: Exp, var_width: &dyn Fn(Sym) -> Option<u32>) -> Exp {
+    exp.modify(&|exp: &mut Exp| {
+        while let Some(simplified) = rewrite(exp, var_width) {
+            *exp = simplified
+        }
+    });
+    exp
+}
+
+/// Simplify the expressions within a definition
+pub fn simplify_def(def: Def, var_width: &dyn Fn(Sym) -> Option<u32>) -> Def {
+    match def {
+        Def::DefineConst(v, exp) => Def::DefineConst(v, simplify(exp, var_width)),
+        Def::Assert(exp) => Def::Assert(simplify(exp, var_width)),
+        _ => def,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Config, Context, SmtResult, Solver, SolverOptions};
+    use super::Exp::*;
+    use super::*;
+    use crate::concrete::bitvector64::B64;
+
+    fn bits64(bv: u64, sz: u32) -> Box<Exp> {
+        Box::new(Bits64(bv, sz))
+    }
+
+    fn no_widths(_: Sym) -> Option<u32> {
+        None
+    }
+
+    #[test]
+    fn constant_folding() {
+        assert_eq!(simplify(Bvshl(bits64(1, 8), bits64(3, 8)), &no_widths), Bits64(8, 8));
+        assert_eq!(simplify(Bvadd(bits64(0xFF, 8), bits64(2, 8)), &no_widths), Bits64(1, 8));
+        assert_eq!(simplify(Bvashr(bits64(0x80, 8), bits64(9, 8)), &no_widths), Bits64(0xFF, 8));
+        assert_eq!(simplify(Bvslt(bits64(0x80, 8), bits64(1, 8)), &no_widths), Bool(true));
+        assert_eq!(simplify(Bvudiv(bits64(7, 4), bits64(0, 4)), &no_widths), Bits64(0xF, 4));
+        assert_eq!(simplify(SignExtend(4, bits64(0b1010, 4)), &no_widths), Bits64(0xFA, 8));
+        let one = Box::new(Bits(vec![true; 1].into_iter().chain(vec![false; 127]).collect()));
+        let wide = simplify(Bvshl(one, Box::new(Bits64(100, 128))), &no_widths);
+        assert_eq!(wide, Bits((0..128).map(|n| n == 100).collect()))
+    }
+
+    #[test]
+    fn structural_rewrites() {
+        let x = Box::new(Var(Sym::from_u32(0)));
+        let widths = |v: Sym| if v.id == 0 { Some(8) } else { None };
+        let concat = Concat(x.clone(), bits64(0xAB, 8));
+        assert_eq!(simplify(Extract(15, 8, Box::new(concat.clone())), &widths), *x);
+        assert_eq!(simplify(Extract(7, 4, Box::new(concat)), &widths), Bits64(0xA, 4));
+        let ext = ZeroExtend(8, x.clone());
+        assert_eq!(simplify(Extract(15, 8, Box::new(ext.clone())), &widths), Bits64(0, 8));
+        assert_eq!(simplify(Eq(Box::new(ext.clone()), bits64(0x1FF, 16)), &widths), Bool(false));
+        assert_eq!(simplify(Eq(Box::new(ext), bits64(0x12, 16)), &widths), Eq(x.clone(), bits64(0x12, 8)));
+        assert_eq!(simplify(Concat(bits64(0, 4), x.clone()), &widths), ZeroExtend(4, x.clone()));
+
+        let p = Box::new(Var(Sym::from_u32(1)));
+        let q = Box::new(Var(Sym::from_u32(2)));
+        assert_eq!(simplify(And(p.clone(), Box::new(Or(q.clone(), p.clone()))), &widths), *p);
+        assert_eq!(simplify(Or(Box::new(And(p.clone(), q.clone())), p.clone()), &widths), *p);
+        assert_eq!(simplify(Ite(Box::new(Bvult(bits64(1, 4), bits64(2, 4))), p.clone(), q), &widths), *p);
+        assert_eq!(simplify(Neq(p.clone(), Box::new(Bool(false))), &widths), *p)
+    }
+
+    #[test]
+    fn rewrites_are_equivalent() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let x = solver.declare_const(Ty::BitVec(8));
+        let y = solver.declare_const(Ty::BitVec(16));
+        let x = || Box::new(Var(x));
+        let y = || Box::new(Var(y));
+        let exps = vec![
+            Eq(Box::new(Extract(11, 8, Box::new(Concat(x(), x())))), Box::new(Extract(3, 0, x()))),
+            Eq(Box::new(Extract(7, 0, Box::new(SignExtend(8, x())))), Box::new(Bvsub(x(), bits64(0, 8)))),
+            Eq(Box::new(ZeroExtend(8, x())), Box::new(Bvor(y(), bits64(0, 16)))),
+            Bvsle(Box::new(Bvneg(bits64(3, 8))), Box::new(Extract(7, 0, Box::new(Bvnot(y()))))),
+            Eq(Box::new(Bvsmod(x(), Box::new(Bvurem(bits64(9, 8), bits64(0, 8))))), x()),
+        ];
+        for exp in exps {
+            let simplified = simplify(exp.clone(), &|v| solver.length(v));
+            assert_ne!(exp, simplified);
+            let differ = Neq(Box::new(exp), Box::new(simplified));
+            assert_eq!(solver.check_sat_with(&differ), SmtResult::Unsat)
+        }
+    }
+
+    #[test]
+    fn simplify_option() {
+        use super::super::Event;
+        let ctx = Context::new(Config::new());
+        let shift = || Def::Assert(Eq(Box::new(Bvshl(bits64(1, 8), bits64(3, 8))), bits64(8, 8)));
+
+        let mut solver = Solver::<B64>::new(&ctx);
+        solver.add(shift());
+        assert!(matches!(solver.trace().to_vec()[..], [Event::Smt(Def::Assert(Eq(..)))]));
+
+        let options = SolverOptions { simplify: true, ..SolverOptions::default() };
+        let mut solver = Solver::<B64>::with_options(&ctx, options);
+        solver.add(shift());
+        assert!(matches!(solver.trace().to_vec()[..], [Event::Smt(Def::Assert(Bool(true)))]))
+    }
+
+    #[test]
+    fn simplify_trace() {
+        use super::super::Event;
+        use crate::simplify::simplify_smt;
+        let (x, v1, v2) = (Sym::from_u32(0), Sym::from_u32(1), Sym::from_u32(2));
+        let mut events: Vec<Event<B64>> = vec![
+            Event::Smt(Def::DeclareConst(x, Ty::BitVec(8))),
+            Event::Smt(Def::DefineConst(v1, Bits64(1, 8))),
+            Event::Smt(Def::DefineConst(v2, Bvshl(Box::new(Var(v1)), bits64(2, 8)))),
+            Event::Smt(Def::Assert(Eq(Box::new(Var(v2)), bits64(4, 8)))),
+            Event::Smt(Def::Assert(Bvult(Box::new(Var(v2)), Box::new(Var(x))))),
+        ];
+        simplify_smt(&mut events);
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[2], Event::Smt(Def::DefineConst(_, Bits64(4, 8)))));
+        assert!(matches!(&events[3], Event::Smt(Def::Assert(Bvult(lhs, _))) if **lhs == Bits64(4, 8)))
+    }
+}
@@ -0,0 +1,248 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2019, 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module provides snapshots of the final state of a
+//! [crate::memory::Memory], created by
+//! [crate::memory::Memory::snapshot]. Snapshots can be compared to
+//! find the bytes that differ between two paths (or between isla and
+//! a hardware run), and the concrete regions can be exported as raw
+//! binary or Intel HEX files.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::memory::Address;
+use crate::pages::Pages;
+use crate::smt::Event;
+
+#[derive(Clone, Debug)]
+pub struct RegionSnapshot {
+    pub range: Range<Address>,
+    pub kind: &'static str,
+    /// The contents of a concrete region. This is `None` for
+    /// symbolic and custom regions.
+    pub contents: Option<Pages>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MemorySnapshot<B> {
+    pub regions: Vec<RegionSnapshot>,
+    /// Bytes stored by write events with a concrete address and
+    /// data which are not reflected in the contents of a region,
+    /// e.g. writes to symbolic memory. Bytes which may have been
+    /// overwritten by a later unresolved write are not included.
+    pub written: BTreeMap<Address, u8>,
+    /// Write events with a symbolic address or data.
+    pub unresolved: Vec<Event<B>>,
+}
+
+/// A byte which differs between two snapshots. A byte is `None` if
+/// its value is not known in that snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteDiff {
+    pub address: Address,
+    pub left: Option<u8>,
+    pub right: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    Raw,
+    IntelHex,
+}
+
+impl<B> MemorySnapshot<B> {
+    /// The value of the byte at an address, if it is known.
+    pub fn byte(&self, address: Address) -> Option<u8> {
+        if let Some(byte) = self.written.get(&address) {
+            return Some(*byte);
+        }
+        self.regions
+            .iter()
+            .find(|region| region.range.contains(&address))
+            .and_then(|region| region.contents.as_ref())
+            .filter(|contents| contents.symbolic_byte(address).is_none())
+            .map(|contents| contents.read_byte(address))
+    }
+
+    /// Compare two snapshots. Region contents are only compared
+    /// between regions with the same range in both snapshots, along
+    /// with every byte written in either snapshot. Unresolved writes
+    /// are not compared.
+    pub fn diff(&self, other: &MemorySnapshot<B>) -> Vec<ByteDiff> {
+        let mut addresses: Vec<Address> = self.written.keys().chain(other.written.keys()).copied().collect();
+
+        for left in &self.regions {
+            let right = other.regions.iter().find(|right| right.range == left.range);
+            if let (Some(l), Some(r)) = (&left.contents, right.and_then(|right| right.contents.as_ref())) {
+                addresses.extend(l.differences(r).drain(..).filter(|address| left.range.contains(address)))
+            }
+        }
+
+        addresses.sort_unstable();
+        addresses.dedup();
+        addresses
+            .drain(..)
+            .filter_map(|address| {
+                let (left, right) = (self.byte(address), other.byte(address));
+                if left != right {
+                    Some(ByteDiff { address, left, right })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The bytes of a concrete region, including any bytes written
+    /// to it. Symbolic bytes are given as zero. Fails if there is no
+    /// such region, or it is not concrete.
+    pub fn region_bytes(&self, region: usize) -> io::Result<impl Iterator<Item = u8> + '_> {
+        let RegionSnapshot { range, contents, .. } = self
+            .regions
+            .get(region)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("No region {} in snapshot", region)))?;
+        let contents = contents.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Region {} in snapshot is not concrete", region))
+        })?;
+        Ok(range.clone().map(move |address| match self.written.get(&address) {
+            Some(byte) => *byte,
+            None => contents.read_byte(address),
+        }))
+    }
+
+    /// Write each concrete region to a separate file in `dir`, named
+    /// after its address range, returning the files written.
+    pub fn export<P: AsRef<Path>>(&self, dir: P, format: DumpFormat) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for (i, region) in self.regions.iter().enumerate() {
+            if region.contents.is_none() {
+                continue;
+            }
+            let bytes = self.region_bytes(i)?;
+            let extension = match format {
+                DumpFormat::Raw => "bin",
+                DumpFormat::IntelHex => "hex",
+            };
+            let file = dir.as_ref().join(format!("{:x}-{:x}.{}", region.range.start, region.range.end, extension));
+            let mut out = BufWriter::new(File::create(&file)?);
+            match format {
+                DumpFormat::Raw => {
+                    for byte in bytes {
+                        out.write_all(&[byte])?
+                    }
+                }
+                DumpFormat::IntelHex => write_intel_hex(&mut out, region.range.clone(), bytes)?,
+            }
+            out.flush()?;
+            files.push(file)
+        }
+        Ok(files)
+    }
+}
+
+fn hex_record(address: u16, record_type: u8, data: &[u8]) -> String {
+    let mut record = vec![data.len() as u8, (address >> 8) as u8, address as u8, record_type];
+    record.extend_from_slice(data);
+    let checksum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)).wrapping_neg();
+    record.push(checksum);
+    let mut line = ":".to_string();
+    for byte in record {
+        line.push_str(&format!("{:02X}", byte))
+    }
+    line.push('\n');
+    line
+}
+
+/// Encode bytes starting at `base` in Intel HEX format, using
+/// extended linear address records for addresses above 64K. Intel HEX
+/// can only represent 32-bit addresses, so this fails if the bytes
+/// do not fit below 4G.
+pub fn intel_hex(base: Address, bytes: &[u8]) -> io::Result<String> {
+    let mut hex = Vec::new();
+    let end = base
+        .checked_add(bytes.len() as u64)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Bytes extend past the end of the address space"))?;
+    write_intel_hex(&mut hex, base..end, bytes.iter().copied())?;
+    Ok(String::from_utf8(hex).unwrap())
+}
+
+/// Like [intel_hex], but writes the bytes in `range` from an iterator
+/// to `out` as they are encoded.
+pub fn write_intel_hex<W, I>(out: &mut W, range: Range<Address>, bytes: I) -> io::Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = u8>,
+{
+    if range.end > 1 << 32 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Intel HEX is limited to 32-bit addresses"));
+    }
+
+    let mut upper = None;
+    let mut address = range.start;
+    let mut bytes = bytes.into_iter().take((range.end - range.start) as usize).peekable();
+    while bytes.peek().is_some() {
+        // Records cannot cross a 64K boundary
+        let len = u64::min(16, 0x1_0000 - (address & 0xffff)) as usize;
+        let data: Vec<u8> = bytes.by_ref().take(len).collect();
+        if upper != Some(address >> 16) {
+            upper = Some(address >> 16);
+            out.write_all(hex_record(0, 4, &[(address >> 24) as u8, (address >> 16) as u8]).as_bytes())?
+        }
+        out.write_all(hex_record(address as u16, 0, &data).as_bytes())?;
+        address += data.len() as u64
+    }
+    out.write_all(hex_record(0, 1, &[]).as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intel_hex_records() {
+        let hex = intel_hex(0x1_fff8, &[0xaa; 12]).unwrap();
+        let lines: Vec<&str> = hex.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                ":020000040001F9",
+                ":08FFF800AAAAAAAAAAAAAAAAB1",
+                ":020000040002F8",
+                ":04000000AAAAAAAA54",
+                ":00000001FF"
+            ]
+        );
+        assert!(intel_hex(0xffff_fff0, &[0; 32]).is_err());
+        assert!(intel_hex(u64::MAX - 1, &[0; 4]).is_err())
+    }
+}
@@ -0,0 +1,844 @@
+// BSD 2-Clause License
+//
+// Copyright (c) 2019, 2020 Alasdair Armstrong
+//
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+// 1. Redistributions of source code must retain the above copyright
+// notice, this list of conditions and the following disclaimer.
+//
+// 2. Redistributions in binary form must reproduce the above copyright
+// notice, this list of conditions and the following disclaimer in the
+// documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// "AS IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// HOLDER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! This module implements the softfloat externs used by the Sail
+//! RISC-V model for its floating point instructions. Each extern
+//! takes a rounding mode and IEEE encoded bitvector operands, and
+//! writes its result and the accrued exception flags to the
+//! `float_result` and `float_fflags` registers, just like the C
+//! emulator does. The operations themselves are expressed using the
+//! SMT floating point theory. When every argument is concrete the
+//! result is computed by Z3 immediately, otherwise the results are
+//! defined as new symbolic variables.
+
+use std::collections::HashMap;
+
+use crate::concrete::BV;
+use crate::error::ExecError;
+use crate::executor::LocalFrame;
+use crate::ir::{UVal, Val, FLOAT_FFLAGS, FLOAT_RESULT};
+use crate::primop::{smt_value, Variadic};
+use crate::smt::smtlib::*;
+use crate::smt::{Event, Solver};
+
+/// An IEEE binary floating point format, given by the widths of its
+/// exponent and significand (including the hidden bit).
+#[derive(Clone, Copy, Debug)]
+struct Format {
+    ebits: u32,
+    sbits: u32,
+}
+
+const F16: Format = Format { ebits: 5, sbits: 11 };
+const F32: Format = Format { ebits: 8, sbits: 24 };
+const F64: Format = Format { ebits: 11, sbits: 53 };
+
+impl Format {
+    fn width(self) -> u32 {
+        self.ebits + self.sbits
+    }
+
+    /// A format with the same precision but at least `ebits` + 2
+    /// exponent bits. Rounding into this format behaves like rounding
+    /// with an unbounded exponent, which is how IEEE 754 defines
+    /// overflow and tininess.
+    fn widened(self, ebits: u32) -> Format {
+        Format { ebits: std::cmp::max(self.ebits, ebits) + 2, sbits: self.sbits }
+    }
+
+    fn bits(self, bits: u64) -> Exp {
+        Exp::Bits64(bits, self.width())
+    }
+
+    /// RISC-V requires NaN results to be the canonical quiet NaN
+    fn canonical_nan(self) -> Exp {
+        self.bits(((1 << self.ebits) - 1) << (self.sbits - 1) | 1 << (self.sbits - 2))
+    }
+
+    fn max_finite(self) -> Exp {
+        self.bits(((1 << self.ebits) - 2) << (self.sbits - 1) | ((1 << (self.sbits - 1)) - 1))
+    }
+
+    fn min_normal(self) -> Exp {
+        self.bits(1 << (self.sbits - 1))
+    }
+
+    fn decode(self, bits: Exp) -> Exp {
+        Exp::FPFromIEEE(self.ebits, self.sbits, Box::new(bits))
+    }
+
+    fn encode(self, fp: Exp) -> Exp {
+        Exp::Ite(
+            Box::new(fp_unary(FPUnop::IsNaN, &fp)),
+            Box::new(self.canonical_nan()),
+            Box::new(fp_unary(FPUnop::ToIEEE, &fp)),
+        )
+    }
+
+    /// Convert a value in another format into this one. Only used
+    /// for conversions that are exact, so the rounding mode is
+    /// irrelevant.
+    fn exact(self, fp: Exp) -> Exp {
+        convert(FPConversion::ToFP(self.ebits, self.sbits), &Exp::RoundingMode(FPRoundingMode::RNE), &fp)
+    }
+
+    /// Signalling NaNs cannot be distinguished from quiet NaNs in the
+    /// SMT floating point theory, so they are detected using the IEEE
+    /// encoding: an all ones exponent with a clear quiet bit and a
+    /// non-zero payload.
+    fn is_signalling_nan(self, bits: &Exp) -> Exp {
+        let w = self.width();
+        let s = self.sbits;
+        let exponent = Exp::Extract(w - 2, s - 1, Box::new(bits.clone()));
+        let quiet = Exp::Extract(s - 2, s - 2, Box::new(bits.clone()));
+        let payload = Exp::Extract(s - 3, 0, Box::new(bits.clone()));
+        and_all(vec![
+            Exp::Eq(Box::new(exponent), Box::new(Exp::Bits64((1 << self.ebits) - 1, self.ebits))),
+            Exp::Eq(Box::new(quiet), Box::new(Exp::Bits64(0, 1))),
+            Exp::Neq(Box::new(payload), Box::new(Exp::Bits64(0, s - 2))),
+        ])
+    }
+}
+
+fn fp_unary(op: FPUnop, x: &Exp) -> Exp {
+    Exp::FPUnary(op, Box::new(x.clone()))
+}
+
+fn fp_binary(op: FPBinop, x: &Exp, y: &Exp) -> Exp {
+    Exp::FPBinary(op, Box::new(x.clone()), Box::new(y.clone()))
+}
+
+fn convert(conversion: FPConversion, rm: &Exp, x: &Exp) -> Exp {
+    Exp::FPConvert(conversion, Box::new(rm.clone()), Box::new(x.clone()))
+}
+
+fn and(x: Exp, y: Exp) -> Exp {
+    Exp::And(Box::new(x), Box::new(y))
+}
+
+fn or(x: Exp, y: Exp) -> Exp {
+    Exp::Or(Box::new(x), Box::new(y))
+}
+
+fn not(x: Exp) -> Exp {
+    Exp::Not(Box::new(x))
+}
+
+fn and_all(xs: Vec<Exp>) -> Exp {
+    xs.into_iter().fold(Exp::Bool(true), and)
+}
+
+fn or_all(xs: Vec<Exp>) -> Exp {
+    xs.into_iter().fold(Exp::Bool(false), or)
+}
+
+fn is_finite(x: &Exp) -> Exp {
+    and(not(fp_unary(FPUnop::IsNaN, x)), not(fp_unary(FPUnop::IsInfinite, x)))
+}
+
+/// The rounding modes used by the softfloat interface are `0b000` =
+/// RNE, `0b001` = RTZ, `0b010` = RDN, `0b011` = RUP, and `0b100` =
+/// RMM. Any other value is treated as RNE.
+fn rounding_mode<B: BV>(rm: &Val<B>) -> Result<Exp, ExecError> {
+    use FPRoundingMode::*;
+    let mode = |bits: u64| match bits {
+        0b001 => RTZ,
+        0b010 => RTN,
+        0b011 => RTP,
+        0b100 => RNA,
+        _ => RNE,
+    };
+    match rm {
+        Val::Bits(bv) => Ok(Exp::RoundingMode(mode(bv.lower_u64()))),
+        Val::Symbolic(_) => {
+            let rm = smt_value(rm)?;
+            Ok([0b001, 0b010, 0b011, 0b100].iter().rev().fold(Exp::RoundingMode(RNE), |exp, bits| {
+                Exp::Ite(
+                    Box::new(Exp::Eq(Box::new(rm.clone()), Box::new(Exp::Bits64(*bits, 3)))),
+                    Box::new(Exp::RoundingMode(mode(*bits))),
+                    Box::new(exp),
+                )
+            }))
+        }
+        _ => Err(ExecError::Type(format!("softfloat rounding mode {:?}", rm))),
+    }
+}
+
+/// State shared by the implementation of each extern
+struct Operation<'a, 'ctx, B> {
+    solver: &'a mut Solver<'ctx, B>,
+    concrete: bool,
+}
+
+impl<'a, 'ctx, B: BV> Operation<'a, 'ctx, B> {
+    fn new(args: &[Val<B>], solver: &'a mut Solver<'ctx, B>) -> Self {
+        let concrete = args.iter().all(|arg| matches!(arg, Val::Bits(_) | Val::Bool(_)));
+        Operation { solver, concrete }
+    }
+
+    /// When executing symbolically intermediate results are bound to
+    /// variables, so they are shared rather than duplicated in each
+    /// expression that uses them.
+    fn bind(&mut self, exp: Exp) -> Exp {
+        if self.concrete {
+            exp
+        } else {
+            Exp::Var(self.solver.define_const(exp))
+        }
+    }
+
+    fn value(&mut self, exp: Exp) -> Result<Val<B>, ExecError> {
+        if self.concrete {
+            match self.solver.eval_closed(&exp)? {
+                Exp::Bits64(bits, len) => Ok(Val::Bits(B::new(bits, len))),
+                value => Err(ExecError::Type(format!("softfloat result {:?}", value))),
+            }
+        } else {
+            Ok(Val::Symbolic(self.solver.define_const(exp)))
+        }
+    }
+
+    /// Write the result and the exception flags, in the order NV
+    /// (invalid), DZ (divide by zero), OF (overflow), UF (underflow),
+    /// and NX (inexact) from most to least significant bit. Both
+    /// registers are `bits(64)` in the Sail model, so the result and
+    /// flags are zero-extended to 64 bits like the C emulator does.
+    fn finish(
+        mut self,
+        result: Exp,
+        width: u32,
+        flags: [Exp; 5],
+        frame: &mut LocalFrame<B>,
+    ) -> Result<Val<B>, ExecError> {
+        let flags = flags
+            .iter()
+            .map(|flag| Exp::Ite(Box::new(flag.clone()), Box::new(Exp::Bits64(1, 1)), Box::new(Exp::Bits64(0, 1))))
+            .fold(Exp::Bits64(0, 0), |bits, flag| {
+                if let Exp::Bits64(_, 0) = bits {
+                    flag
+                } else {
+                    Exp::Concat(Box::new(bits), Box::new(flag))
+                }
+            });
+        let result = self.value(Exp::ZeroExtend(64 - width, Box::new(result)))?;
+        let flags = self.value(Exp::ZeroExtend(64 - 5, Box::new(flags)))?;
+        for (reg, value) in [(FLOAT_RESULT, result), (FLOAT_FFLAGS, flags)].iter() {
+            self.solver.add_event(Event::WriteReg(*reg, vec![], value.clone()));
+            frame.regs_mut().insert(*reg, UVal::Init(value.clone()));
+        }
+        Ok(Val::Unit)
+    }
+}
+
+fn check_args<B: BV>(name: &str, args: &[Val<B>], arity: usize) -> Result<(), ExecError> {
+    if args.len() == arity {
+        Ok(())
+    } else {
+        Err(ExecError::Type(format!("softfloat_{} expects {} arguments", name, arity)))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Arith {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Sqrt,
+    MulAdd,
+}
+
+impl Arith {
+    fn arity(self) -> usize {
+        match self {
+            Arith::Sqrt => 1,
+            Arith::MulAdd => 3,
+            _ => 2,
+        }
+    }
+
+    fn apply(self, rm: &Exp, xs: &[Exp]) -> Exp {
+        use FPRoundingBinop::*;
+        let binary =
+            |op| Exp::FPRoundingBinary(op, Box::new(rm.clone()), Box::new(xs[0].clone()), Box::new(xs[1].clone()));
+        match self {
+            Arith::Add => binary(Add),
+            Arith::Sub => binary(Sub),
+            Arith::Mul => binary(Mul),
+            Arith::Div => binary(Div),
+            Arith::Sqrt => Exp::FPRoundingUnary(FPRoundingUnop::Sqrt, Box::new(rm.clone()), Box::new(xs[0].clone())),
+            Arith::MulAdd => Exp::FPfma(
+                Box::new(rm.clone()),
+                Box::new(xs[0].clone()),
+                Box::new(xs[1].clone()),
+                Box::new(xs[2].clone()),
+            ),
+        }
+    }
+
+    /// Invalid operations other than those on signalling NaNs
+    fn invalid(self, xs: &[Exp]) -> Exp {
+        use FPUnop::*;
+        let both = |op, x, y| and(fp_unary(op, x), fp_unary(op, y));
+        let same_sign = |x, y| Exp::Eq(Box::new(fp_unary(IsNegative, x)), Box::new(fp_unary(IsNegative, y)));
+        let zero_times_inf = |x, y| {
+            or(and(fp_unary(IsZero, x), fp_unary(IsInfinite, y)), and(fp_unary(IsInfinite, x), fp_unary(IsZero, y)))
+        };
+        match self {
+            Arith::Add => and(both(IsInfinite, &xs[0], &xs[1]), not(same_sign(&xs[0], &xs[1]))),
+            Arith::Sub => and(both(IsInfinite, &xs[0], &xs[1]), same_sign(&xs[0], &xs[1])),
+            Arith::Mul => zero_times_inf(&xs[0], &xs[1]),
+            Arith::Div => or(both(IsZero, &xs[0], &xs[1]), both(IsInfinite, &xs[0], &xs[1])),
+            Arith::Sqrt => and(fp_unary(IsNegative, &xs[0]), not(fp_unary(IsZero, &xs[0]))),
+            Arith::MulAdd => {
+                let infinite_times_nonzero =
+                    |x, y| and(fp_unary(IsInfinite, x), and(not(fp_unary(IsZero, y)), not(fp_unary(IsNaN, y))));
+                let product_infinite =
+                    or(infinite_times_nonzero(&xs[0], &xs[1]), infinite_times_nonzero(&xs[1], &xs[0]));
+                let product_negative =
+                    Exp::Neq(Box::new(fp_unary(IsNegative, &xs[0])), Box::new(fp_unary(IsNegative, &xs[1])));
+                let addend_opposite = Exp::Neq(Box::new(product_negative), Box::new(fp_unary(IsNegative, &xs[2])));
+                or(
+                    zero_times_inf(&xs[0], &xs[1]),
+                    and_all(vec![product_infinite, fp_unary(IsInfinite, &xs[2]), addend_opposite]),
+                )
+            }
+        }
+    }
+
+    fn divide_by_zero(self, xs: &[Exp]) -> Exp {
+        match self {
+            Arith::Div => {
+                and(fp_unary(FPUnop::IsZero, &xs[1]), and(is_finite(&xs[0]), not(fp_unary(FPUnop::IsZero, &xs[0]))))
+            }
+            _ => Exp::Bool(false),
+        }
+    }
+}
+
+fn arith<B: BV>(
+    op: Arith,
+    fmt: Format,
+    args: Vec<Val<B>>,
+    solver: &mut Solver<B>,
+    frame: &mut LocalFrame<B>,
+) -> Result<Val<B>, ExecError> {
+    use FPRoundingMode::*;
+    check_args(&format!("{:?}", op), &args, op.arity() + 1)?;
+    let mut operation = Operation::new(&args, solver);
+    let rm = rounding_mode(&args[0])?;
+    let bits = args[1..].iter().map(smt_value).collect::<Result<Vec<_>, _>>()?;
+    let xs: Vec<Exp> = bits.iter().map(|bits| operation.bind(fmt.decode(bits.clone()))).collect();
+
+    let r = operation.bind(op.apply(&rm, &xs));
+    let r_up = operation.bind(op.apply(&Exp::RoundingMode(RTP), &xs));
+    let r_down = operation.bind(op.apply(&Exp::RoundingMode(RTN), &xs));
+    let wide = fmt.widened(fmt.ebits);
+    let wide_xs: Vec<Exp> = xs.iter().map(|x| wide.exact(x.clone())).collect();
+    let r_wide = operation.bind(fp_unary(FPUnop::Abs, &op.apply(&rm, &wide_xs)));
+
+    let invalid = or(or_all(bits.iter().map(|bits| fmt.is_signalling_nan(bits)).collect()), op.invalid(&xs));
+    let inexact = and(not(fp_unary(FPUnop::IsNaN, &r)), not(fp_binary(FPBinop::Eq, &r_up, &r_down)));
+    let overflow = and(
+        not(fp_unary(FPUnop::IsInfinite, &r_wide)),
+        fp_binary(FPBinop::Gt, &r_wide, &wide.exact(fmt.decode(fmt.max_finite()))),
+    );
+    let underflow = and(inexact.clone(), fp_binary(FPBinop::Lt, &r_wide, &wide.exact(fmt.decode(fmt.min_normal()))));
+    let flags = [invalid, op.divide_by_zero(&xs), overflow, underflow, inexact];
+    operation.finish(fmt.encode(r), fmt.width(), flags, frame)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Compare {
+    Eq,
+    Lt,
+    Le,
+    LtQuiet,
+    LeQuiet,
+}
+
+fn compare<B: BV>(
+    op: Compare,
+    fmt: Format,
+    args: Vec<Val<B>>,
+    solver: &mut Solver<B>,
+    frame: &mut LocalFrame<B>,
+) -> Result<Val<B>, ExecError> {
+    check_args(&format!("{:?}", op), &args, 2)?;
+    let operation = Operation::new(&args, solver);
+    let bits = args.iter().map(smt_value).collect::<Result<Vec<_>, _>>()?;
+    let xs: Vec<Exp> = bits.iter().map(|bits| fmt.decode(bits.clone())).collect();
+    let cmp = match op {
+        Compare::Eq => FPBinop::Eq,
+        Compare::Lt | Compare::LtQuiet => FPBinop::Lt,
+        Compare::Le | Compare::LeQuiet => FPBinop::Leq,
+    };
+    // The signalling comparisons are invalid for any NaN operand,
+    // whereas the quiet ones only for signalling NaNs.
+    let invalid = match op {
+        Compare::Lt | Compare::Le => or_all(xs.iter().map(|x| fp_unary(FPUnop::IsNaN, x)).collect()),
+        _ => or_all(bits.iter().map(|bits| fmt.is_signalling_nan(bits)).collect()),
+    };
+    let result =
+        Exp::Ite(Box::new(fp_binary(cmp, &xs[0], &xs[1])), Box::new(Exp::Bits64(1, 1)), Box::new(Exp::Bits64(0, 1)));
+    let flags = [invalid, Exp::Bool(false), Exp::Bool(false), Exp::Bool(false), Exp::Bool(false)];
+    operation.finish(result, 1, flags, frame)
+}
+
+/// A bitvector literal for 2^n, using n + 1 bits
+fn power_of_two(n: u32) -> Exp {
+    if n < 64 {
+        Exp::Bits64(1 << n, n + 1)
+    } else {
+        let mut bits = vec![false; n as usize + 1];
+        bits[n as usize] = true;
+        Exp::Bits(bits)
+    }
+}
+
+fn to_int<B: BV>(
+    fmt: Format,
+    width: u32,
+    signed: bool,
+    args: Vec<Val<B>>,
+    solver: &mut Solver<B>,
+    frame: &mut LocalFrame<B>,
+) -> Result<Val<B>, ExecError> {
+    check_args("to_int", &args, 2)?;
+    let mut operation = Operation::new(&args, solver);
+    let rm = rounding_mode(&args[0])?;
+    let bits = smt_value(&args[1])?;
+    let x = operation.bind(fmt.decode(bits.clone()));
+    let integral = operation.bind(Exp::FPRoundingUnary(
+        FPRoundingUnop::RoundToIntegral,
+        Box::new(rm.clone()),
+        Box::new(x.clone()),
+    ));
+
+    let rne = Exp::RoundingMode(FPRoundingMode::RNE);
+    let from_unsigned = FPConversion::FromUnsigned(fmt.ebits, fmt.sbits);
+    let (lower, upper, min, max, conversion) = if signed {
+        let upper = convert(from_unsigned, &rne, &power_of_two(width - 1));
+        let lower = fp_unary(FPUnop::Neg, &upper);
+        (lower, upper, 1 << (width - 1), (1 << (width - 1)) - 1, FPConversion::ToSigned(width))
+    } else {
+        let upper = convert(from_unsigned, &rne, &power_of_two(width));
+        (fmt.decode(fmt.bits(0)), upper, 0, u64::MAX >> (64 - width), FPConversion::ToUnsigned(width))
+    };
+    let in_range = and(fp_binary(FPBinop::Geq, &integral, &lower), fp_binary(FPBinop::Lt, &integral, &upper));
+
+    // Out of range values saturate, with NaNs converting to the maximum value
+    let result = Exp::Ite(
+        Box::new(in_range.clone()),
+        Box::new(convert(conversion, &rm, &x)),
+        Box::new(Exp::Ite(
+            Box::new(fp_unary(FPUnop::IsNegative, &x)),
+            Box::new(Exp::Bits64(min, width)),
+            Box::new(Exp::Bits64(max, width)),
+        )),
+    );
+    let inexact = and(in_range.clone(), not(fp_binary(FPBinop::Eq, &integral, &x)));
+    let flags = [not(in_range), Exp::Bool(false), Exp::Bool(false), Exp::Bool(false), inexact];
+    operation.finish(result, width, flags, frame)
+}
+
+fn from_int<B: BV>(
+    fmt: Format,
+    width: u32,
+    signed: bool,
+    args: Vec<Val<B>>,
+    solver: &mut Solver<B>,
+    frame: &mut LocalFrame<B>,
+) -> Result<Val<B>, ExecError> {
+    use FPRoundingMode::*;
+    check_args("from_int", &args, 2)?;
+    let mut operation = Operation::new(&args, solver);
+    let rm = rounding_mode(&args[0])?;
+    let x = Exp::Extract(width - 1, 0, Box::new(smt_value(&args[1])?));
+    let conversion = |fmt: Format| {
+        if signed {
+            FPConversion::FromSigned(fmt.ebits, fmt.sbits)
+        } else {
+            FPConversion::FromUnsigned(fmt.ebits, fmt.sbits)
+        }
+    };
+
+    let r = operation.bind(convert(conversion(fmt), &rm, &x));
+    let r_up = operation.bind(convert(conversion(fmt), &Exp::RoundingMode(RTP), &x));
+    let r_down = operation.bind(convert(conversion(fmt), &Exp::RoundingMode(RTN), &x));
+    // Enough exponent range to represent any 64-bit integer
+    let wide = fmt.widened(8);
+    let r_wide = fp_unary(FPUnop::Abs, &convert(conversion(wide), &rm, &x));
+
+    let inexact = not(fp_binary(FPBinop::Eq, &r_up, &r_down));
+    let overflow = fp_binary(FPBinop::Gt, &r_wide, &wide.exact(fmt.decode(fmt.max_finite())));
+    let flags = [Exp::Bool(false), Exp::Bool(false), overflow, Exp::Bool(false), inexact];
+    operation.finish(fmt.encode(r), fmt.width(), flags, frame)
+}
+
+fn to_float<B: BV>(
+    from: Format,
+    to: Format,
+    args: Vec<Val<B>>,
+    solver: &mut Solver<B>,
+    frame: &mut LocalFrame<B>,
+) -> Result<Val<B>, ExecError> {
+    use FPRoundingMode::*;
+    check_args("to_float", &args, 2)?;
+    let mut operation = Operation::new(&args, solver);
+    let rm = rounding_mode(&args[0])?;
+    let bits = smt_value(&args[1])?;
+    let x = operation.bind(from.decode(bits.clone()));
+    let conversion = |fmt: Format| FPConversion::ToFP(fmt.ebits, fmt.sbits);
+
+    let r = operation.bind(convert(conversion(to), &rm, &x));
+    let r_up = operation.bind(convert(conversion(to), &Exp::RoundingMode(RTP), &x));
+    let r_down = operation.bind(convert(conversion(to), &Exp::RoundingMode(RTN), &x));
+    let wide = to.widened(from.ebits);
+    let r_wide = operation.bind(fp_unary(FPUnop::Abs, &convert(conversion(wide), &rm, &x)));
+
+    let inexact = and(not(fp_unary(FPUnop::IsNaN, &r)), not(fp_binary(FPBinop::Eq, &r_up, &r_down)));
+    let overflow = and(
+        not(fp_unary(FPUnop::IsInfinite, &r_wide)),
+        fp_binary(FPBinop::Gt, &r_wide, &wide.exact(to.decode(to.max_finite()))),
+    );
+    let underflow = and(inexact.clone(), fp_binary(FPBinop::Lt, &r_wide, &wide.exact(to.decode(to.min_normal()))));
+    let flags = [from.is_signalling_nan(&bits), Exp::Bool(false), overflow, underflow, inexact];
+    operation.finish(to.encode(r), to.width(), flags, frame)
+}
+
+fn round_to_int<B: BV>(
+    fmt: Format,
+    args: Vec<Val<B>>,
+    solver: &mut Solver<B>,
+    frame: &mut LocalFrame<B>,
+) -> Result<Val<B>, ExecError> {
+    check_args("roundToInt", &args, 3)?;
+    let mut operation = Operation::new(&args, solver);
+    let rm = rounding_mode(&args[0])?;
+    let bits = smt_value(&args[1])?;
+    let exact = smt_value(&args[2])?;
+    let x = operation.bind(fmt.decode(bits.clone()));
+
+    let r = operation.bind(Exp::FPRoundingUnary(FPRoundingUnop::RoundToIntegral, Box::new(rm), Box::new(x.clone())));
+    let inexact = and_all(vec![exact, not(fp_unary(FPUnop::IsNaN, &x)), not(fp_binary(FPBinop::Eq, &r, &x))]);
+    let flags = [fmt.is_signalling_nan(&bits), Exp::Bool(false), Exp::Bool(false), Exp::Bool(false), inexact];
+    operation.finish(fmt.encode(r), fmt.width(), flags, frame)
+}
+
+macro_rules! softfloat_primop {
+    ($name:ident, $implementation:ident, $($arg:expr),*) => {
+        fn $name<B: BV>(
+            args: Vec<Val<B>>,
+            solver: &mut Solver<B>,
+            frame: &mut LocalFrame<B>,
+        ) -> Result<Val<B>, ExecError> {
+            $implementation($($arg,)* args, solver, frame)
+        }
+    };
+}
+
+softfloat_primop!(f16add, arith, Arith::Add, F16);
+softfloat_primop!(f16sub, arith, Arith::Sub, F16);
+softfloat_primop!(f16mul, arith, Arith::Mul, F16);
+softfloat_primop!(f16div, arith, Arith::Div, F16);
+softfloat_primop!(f16muladd, arith, Arith::MulAdd, F16);
+softfloat_primop!(f16sqrt, arith, Arith::Sqrt, F16);
+softfloat_primop!(f32add, arith, Arith::Add, F32);
+softfloat_primop!(f32sub, arith, Arith::Sub, F32);
+softfloat_primop!(f32mul, arith, Arith::Mul, F32);
+softfloat_primop!(f32div, arith, Arith::Div, F32);
+softfloat_primop!(f32muladd, arith, Arith::MulAdd, F32);
+softfloat_primop!(f32sqrt, arith, Arith::Sqrt, F32);
+softfloat_primop!(f64add, arith, Arith::Add, F64);
+softfloat_primop!(f64sub, arith, Arith::Sub, F64);
+softfloat_primop!(f64mul, arith, Arith::Mul, F64);
+softfloat_primop!(f64div, arith, Arith::Div, F64);
+softfloat_primop!(f64muladd, arith, Arith::MulAdd, F64);
+softfloat_primop!(f64sqrt, arith, Arith::Sqrt, F64);
+
+softfloat_primop!(f16eq, compare, Compare::Eq, F16);
+softfloat_primop!(f16lt, compare, Compare::Lt, F16);
+softfloat_primop!(f16le, compare, Compare::Le, F16);
+softfloat_primop!(f16lt_quiet, compare, Compare::LtQuiet, F16);
+softfloat_primop!(f16le_quiet, compare, Compare::LeQuiet, F16);
+softfloat_primop!(f32eq, compare, Compare::Eq, F32);
+softfloat_primop!(f32lt, compare, Compare::Lt, F32);
+softfloat_primop!(f32le, compare, Compare::Le, F32);
+softfloat_primop!(f32lt_quiet, compare, Compare::LtQuiet, F32);
+softfloat_primop!(f32le_quiet, compare, Compare::LeQuiet, F32);
+softfloat_primop!(f64eq, compare, Compare::Eq, F64);
+softfloat_primop!(f64lt, compare, Compare::Lt, F64);
+softfloat_primop!(f64le, compare, Compare::Le, F64);
+softfloat_primop!(f64lt_quiet, compare, Compare::LtQuiet, F64);
+softfloat_primop!(f64le_quiet, compare, Compare::LeQuiet, F64);
+
+softfloat_primop!(f16toi32, to_int, F16, 32, true);
+softfloat_primop!(f16toui32, to_int, F16, 32, false);
+softfloat_primop!(f16toi64, to_int, F16, 64, true);
+softfloat_primop!(f16toui64, to_int, F16, 64, false);
+softfloat_primop!(f32toi32, to_int, F32, 32, true);
+softfloat_primop!(f32toui32, to_int, F32, 32, false);
+softfloat_primop!(f32toi64, to_int, F32, 64, true);
+softfloat_primop!(f32toui64, to_int, F32, 64, false);
+softfloat_primop!(f64toi32, to_int, F64, 32, true);
+softfloat_primop!(f64toui32, to_int, F64, 32, false);
+softfloat_primop!(f64toi64, to_int, F64, 64, true);
+softfloat_primop!(f64toui64, to_int, F64, 64, false);
+
+softfloat_primop!(i32tof16, from_int, F16, 32, true);
+softfloat_primop!(ui32tof16, from_int, F16, 32, false);
+softfloat_primop!(i64tof16, from_int, F16, 64, true);
+softfloat_primop!(ui64tof16, from_int, F16, 64, false);
+softfloat_primop!(i32tof32, from_int, F32, 32, true);
+softfloat_primop!(ui32tof32, from_int, F32, 32, false);
+softfloat_primop!(i64tof32, from_int, F32, 64, true);
+softfloat_primop!(ui64tof32, from_int, F32, 64, false);
+softfloat_primop!(i32tof64, from_int, F64, 32, true);
+softfloat_primop!(ui32tof64, from_int, F64, 32, false);
+softfloat_primop!(i64tof64, from_int, F64, 64, true);
+softfloat_primop!(ui64tof64, from_int, F64, 64, false);
+
+softfloat_primop!(f16tof32, to_float, F16, F32);
+softfloat_primop!(f16tof64, to_float, F16, F64);
+softfloat_primop!(f32tof16, to_float, F32, F16);
+softfloat_primop!(f32tof64, to_float, F32, F64);
+softfloat_primop!(f64tof16, to_float, F64, F16);
+softfloat_primop!(f64tof32, to_float, F64, F32);
+
+softfloat_primop!(f16round_to_int, round_to_int, F16);
+softfloat_primop!(f32round_to_int, round_to_int, F32);
+softfloat_primop!(f64round_to_int, round_to_int, F64);
+
+pub fn softfloat_primops<B: BV>() -> HashMap<String, Variadic<B>> {
+    let mut primops = HashMap::new();
+    primops.insert("softfloat_f16add".to_string(), f16add as Variadic<B>);
+    primops.insert("softfloat_f16sub".to_string(), f16sub as Variadic<B>);
+    primops.insert("softfloat_f16mul".to_string(), f16mul as Variadic<B>);
+    primops.insert("softfloat_f16div".to_string(), f16div as Variadic<B>);
+    primops.insert("softfloat_f16muladd".to_string(), f16muladd as Variadic<B>);
+    primops.insert("softfloat_f16sqrt".to_string(), f16sqrt as Variadic<B>);
+    primops.insert("softfloat_f32add".to_string(), f32add as Variadic<B>);
+    primops.insert("softfloat_f32sub".to_string(), f32sub as Variadic<B>);
+    primops.insert("softfloat_f32mul".to_string(), f32mul as Variadic<B>);
+    primops.insert("softfloat_f32div".to_string(), f32div as Variadic<B>);
+    primops.insert("softfloat_f32muladd".to_string(), f32muladd as Variadic<B>);
+    primops.insert("softfloat_f32sqrt".to_string(), f32sqrt as Variadic<B>);
+    primops.insert("softfloat_f64add".to_string(), f64add as Variadic<B>);
+    primops.insert("softfloat_f64sub".to_string(), f64sub as Variadic<B>);
+    primops.insert("softfloat_f64mul".to_string(), f64mul as Variadic<B>);
+    primops.insert("softfloat_f64div".to_string(), f64div as Variadic<B>);
+    primops.insert("softfloat_f64muladd".to_string(), f64muladd as Variadic<B>);
+    primops.insert("softfloat_f64sqrt".to_string(), f64sqrt as Variadic<B>);
+    primops.insert("softfloat_f16eq".to_string(), f16eq as Variadic<B>);
+    primops.insert("softfloat_f16lt".to_string(), f16lt as Variadic<B>);
+    primops.insert("softfloat_f16le".to_string(), f16le as Variadic<B>);
+    primops.insert("softfloat_f16lt_quiet".to_string(), f16lt_quiet as Variadic<B>);
+    primops.insert("softfloat_f16le_quiet".to_string(), f16le_quiet as Variadic<B>);
+    primops.insert("softfloat_f32eq".to_string(), f32eq as Variadic<B>);
+    primops.insert("softfloat_f32lt".to_string(), f32lt as Variadic<B>);
+    primops.insert("softfloat_f32le".to_string(), f32le as Variadic<B>);
+    primops.insert("softfloat_f32lt_quiet".to_string(), f32lt_quiet as Variadic<B>);
+    primops.insert("softfloat_f32le_quiet".to_string(), f32le_quiet as Variadic<B>);
+    primops.insert("softfloat_f64eq".to_string(), f64eq as Variadic<B>);
+    primops.insert("softfloat_f64lt".to_string(), f64lt as Variadic<B>);
+    primops.insert("softfloat_f64le".to_string(), f64le as Variadic<B>);
+    primops.insert("softfloat_f64lt_quiet".to_string(), f64lt_quiet as Variadic<B>);
+    primops.insert("softfloat_f64le_quiet".to_string(), f64le_quiet as Variadic<B>);
+    primops.insert("softfloat_f16toi32".to_string(), f16toi32 as Variadic<B>);
+    primops.insert("softfloat_f16toui32".to_string(), f16toui32 as Variadic<B>);
+    primops.insert("softfloat_f16toi64".to_string(), f16toi64 as Variadic<B>);
+    primops.insert("softfloat_f16toui64".to_string(), f16toui64 as Variadic<B>);
+    primops.insert("softfloat_f32toi32".to_string(), f32toi32 as Variadic<B>);
+    primops.insert("softfloat_f32toui32".to_string(), f32toui32 as Variadic<B>);
+    primops.insert("softfloat_f32toi64".to_string(), f32toi64 as Variadic<B>);
+    primops.insert("softfloat_f32toui64".to_string(), f32toui64 as Variadic<B>);
+    primops.insert("softfloat_f64toi32".to_string(), f64toi32 as Variadic<B>);
+    primops.insert("softfloat_f64toui32".to_string(), f64toui32 as Variadic<B>);
+    primops.insert("softfloat_f64toi64".to_string(), f64toi64 as Variadic<B>);
+    primops.insert("softfloat_f64toui64".to_string(), f64toui64 as Variadic<B>);
+    primops.insert("softfloat_i32tof16".to_string(), i32tof16 as Variadic<B>);
+    primops.insert("softfloat_ui32tof16".to_string(), ui32tof16 as Variadic<B>);
+    primops.insert("softfloat_i64tof16".to_string(), i64tof16 as Variadic<B>);
+    primops.insert("softfloat_ui64tof16".to_string(), ui64tof16 as Variadic<B>);
+    primops.insert("softfloat_i32tof32".to_string(), i32tof32 as Variadic<B>);
+    primops.insert("softfloat_ui32tof32".to_string(), ui32tof32 as Variadic<B>);
+    primops.insert("softfloat_i64tof32".to_string(), i64tof32 as Variadic<B>);
+    primops.insert("softfloat_ui64tof32".to_string(), ui64tof32 as Variadic<B>);
+    primops.insert("softfloat_i32tof64".to_string(), i32tof64 as Variadic<B>);
+    primops.insert("softfloat_ui32tof64".to_string(), ui32tof64 as Variadic<B>);
+    primops.insert("softfloat_i64tof64".to_string(), i64tof64 as Variadic<B>);
+    primops.insert("softfloat_ui64tof64".to_string(), ui64tof64 as Variadic<B>);
+    primops.insert("softfloat_f16tof32".to_string(), f16tof32 as Variadic<B>);
+    primops.insert("softfloat_f16tof64".to_string(), f16tof64 as Variadic<B>);
+    primops.insert("softfloat_f32tof16".to_string(), f32tof16 as Variadic<B>);
+    primops.insert("softfloat_f32tof64".to_string(), f32tof64 as Variadic<B>);
+    primops.insert("softfloat_f64tof16".to_string(), f64tof16 as Variadic<B>);
+    primops.insert("softfloat_f64tof32".to_string(), f64tof32 as Variadic<B>);
+    primops.insert("softfloat_f16roundToInt".to_string(), f16round_to_int as Variadic<B>);
+    primops.insert("softfloat_f32roundToInt".to_string(), f32round_to_int as Variadic<B>);
+    primops.insert("softfloat_f64roundToInt".to_string(), f64round_to_int as Variadic<B>);
+    primops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concrete::bitvector64::B64;
+    use crate::ir::Name;
+    use crate::smt::{Config, Context, SmtResult};
+
+    fn run(primop: Variadic<B64>, args: Vec<Val<B64>>, solver: &mut Solver<B64>) -> (Val<B64>, Val<B64>) {
+        let mut frame = LocalFrame::new(Name::from_u32(0), &[], None, &[]);
+        assert!(matches!(primop(args, solver, &mut frame).unwrap(), Val::Unit));
+        let read = |reg| match frame.regs().get(&reg) {
+            Some(UVal::Init(value)) => value.clone(),
+            _ => panic!("softfloat register not written"),
+        };
+        (read(FLOAT_RESULT), read(FLOAT_FFLAGS))
+    }
+
+    fn concrete(primop: Variadic<B64>, rm: Option<u64>, args: &[u64], width: u32) -> (u64, u64) {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let mut vals: Vec<Val<B64>> = rm.map(|rm| Val::Bits(B64::new(rm, 3))).into_iter().collect();
+        vals.extend(args.iter().map(|arg| Val::Bits(B64::new(*arg, width))));
+        match run(primop, vals, &mut solver) {
+            (Val::Bits(result), Val::Bits(flags)) => {
+                assert_eq!((result.len(), flags.len()), (64, 64));
+                (result.lower_u64(), flags.lower_u64())
+            }
+            _ => panic!("softfloat result not concrete"),
+        }
+    }
+
+    const RNE: u64 = 0b000;
+    const RTZ: u64 = 0b001;
+
+    const NV: u64 = 0b10000;
+    const DZ: u64 = 0b01000;
+    const OF: u64 = 0b00100;
+    const UF: u64 = 0b00010;
+    const NX: u64 = 0b00001;
+
+    /// Host NaNs are not canonical, so map them to the RISC-V NaN
+    fn host32(x: f32) -> u64 {
+        if x.is_nan() {
+            0x7fc00000
+        } else {
+            x.to_bits() as u64
+        }
+    }
+
+    fn host64(x: f64) -> u64 {
+        if x.is_nan() {
+            0x7ff8000000000000
+        } else {
+            x.to_bits()
+        }
+    }
+
+    #[test]
+    fn softfloat_matches_host() {
+        let values: [f64; 8] = [1.0, -2.5, 0.1, 3.0, 1e300, -0.0, 7.0e-310, 12345.678];
+        for x in values.iter() {
+            for y in values.iter() {
+                let (a, b) = (x.to_bits(), y.to_bits());
+                assert_eq!(concrete(f64add, Some(RNE), &[a, b], 64).0, host64(x + y));
+                assert_eq!(concrete(f64sub, Some(RNE), &[a, b], 64).0, host64(x - y));
+                assert_eq!(concrete(f64mul, Some(RNE), &[a, b], 64).0, host64(x * y));
+                if *y != 0.0 {
+                    assert_eq!(concrete(f64div, Some(RNE), &[a, b], 64).0, host64(x / y));
+                }
+                let (x, y) = (*x as f32, *y as f32);
+                let (a, b) = (x.to_bits() as u64, y.to_bits() as u64);
+                assert_eq!(concrete(f32add, Some(RNE), &[a, b], 32).0, host32(x + y));
+                assert_eq!(concrete(f32mul, Some(RNE), &[a, b], 32).0, host32(x * y));
+                assert_eq!(concrete(f32lt, None, &[a, b], 32).0, (x < y) as u64);
+            }
+            if *x >= 0.0 {
+                assert_eq!(concrete(f64sqrt, Some(RNE), &[x.to_bits()], 64).0, host64(x.sqrt()));
+            }
+            assert_eq!(concrete(f64tof32, Some(RNE), &[x.to_bits()], 64).0, host32(*x as f32))
+        }
+        let third = (1.0f32 / 3.0).to_bits() as u64;
+        assert_eq!(
+            concrete(f32muladd, Some(RNE), &[third, 3.0f32.to_bits() as u64, (-1.0f32).to_bits() as u64], 32).0,
+            { (1.0f32 / 3.0).mul_add(3.0, -1.0).to_bits() as u64 }
+        );
+        assert_eq!(concrete(i64tof64, Some(RNE), &[-3i64 as u64], 64).0, (-3.0f64).to_bits());
+        assert_eq!(concrete(f64toi32, Some(RTZ), &[(-7.9f64).to_bits()], 64).0, -7i32 as u32 as u64);
+    }
+
+    #[test]
+    fn softfloat_flags() {
+        let one = 1.0f32.to_bits() as u64;
+        let zero = 0.0f32.to_bits() as u64;
+        let max = f32::MAX.to_bits() as u64;
+        let snan = 0x7f800001;
+        assert_eq!(concrete(f32div, Some(RNE), &[one, zero], 32), (f32::INFINITY.to_bits() as u64, DZ));
+        assert_eq!(concrete(f32div, Some(RNE), &[zero, zero], 32), (0x7fc00000, NV));
+        assert_eq!(concrete(f32div, Some(RNE), &[one, 3.0f32.to_bits() as u64], 32).1, NX);
+        assert_eq!(concrete(f32add, Some(RNE), &[one, one], 32).1, 0);
+        assert_eq!(concrete(f32mul, Some(RNE), &[max, max], 32), (f32::INFINITY.to_bits() as u64, OF | NX));
+        assert_eq!(concrete(f32mul, Some(RTZ), &[max, max], 32), (max, OF | NX));
+        assert_eq!(concrete(f32mul, Some(RNE), &[f32::MIN_POSITIVE.to_bits() as u64, 0x3f000001], 32).1, UF | NX);
+        assert_eq!(concrete(f32add, Some(RNE), &[snan, one], 32), (0x7fc00000, NV));
+        assert_eq!(concrete(f32eq, None, &[0x7fc00000, one], 32), (0, 0));
+        assert_eq!(concrete(f32eq, None, &[snan, one], 32), (0, NV));
+        assert_eq!(concrete(f32lt, None, &[0x7fc00000, one], 32), (0, NV));
+        assert_eq!(concrete(f32toi32, Some(RNE), &[0x7fc00000], 32), (0x7fffffff, NV));
+        assert_eq!(concrete(f32toui32, Some(RNE), &[(-1.0f32).to_bits() as u64], 32), (0, NV));
+        assert_eq!(concrete(f32toi32, Some(RNE), &[1.5f32.to_bits() as u64], 32), (2, NX));
+        assert_eq!(concrete(ui64tof16, Some(RNE), &[u64::MAX], 64), (0x7c00, OF | NX));
+        assert_eq!(concrete(f64tof16, Some(RNE), &[1.0f64.to_bits()], 64), (0x3c00, 0));
+    }
+
+    #[test]
+    fn softfloat_symbolic() {
+        let ctx = Context::new(Config::new());
+        let mut solver = Solver::<B64>::new(&ctx);
+        let rm = solver.declare_const(Ty::BitVec(3));
+        let x = solver.declare_const(Ty::BitVec(64));
+        solver.add(Def::Assert(Exp::Eq(Box::new(Exp::Var(x)), Box::new(Exp::Bits64(1.5f64.to_bits(), 64)))));
+        let args = vec![Val::Symbolic(rm), Val::Symbolic(x), Val::Bits(B64::new(2.0f64.to_bits(), 64))];
+        let (result, flags) = run(f64mul, args, &mut solver);
+        let (result, flags) = match (result, flags) {
+            (Val::Symbolic(result), Val::Symbolic(flags)) => (result, flags),
+            _ => panic!("softfloat result not symbolic"),
+        };
+        assert!(solver.check_sat() == SmtResult::Sat);
+        assert_eq!((solver.length(result), solver.length(flags)), (Some(64), Some(64)));
+        let (result, flags) = (Exp::Var(result), Exp::Var(flags));
+        let wrong = Exp::Neq(Box::new(result), Box::new(Exp::Bits64(3.0f64.to_bits(), 64)));
+        assert!(solver.check_sat_with(&wrong) == SmtResult::Unsat);
+        let inexact = Exp::Neq(Box::new(flags), Box::new(Exp::Bits64(0, 64)));
+        assert!(solver.check_sat_with(&inexact) == SmtResult::Unsat)
+    }
+}